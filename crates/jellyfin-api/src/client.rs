@@ -1,4 +1,4 @@
-use crate::error::Error;
+use crate::error::JellyfinApiError;
 use crate::models::{
     AuthResponse, IncludeBaseItemFields, IncludeItemTypes, MediaFoldersResponse, User,
 };
@@ -51,7 +51,7 @@ impl std::hash::Hash for JellyfinClient {
 }
 
 impl JellyfinClient {
-    pub fn new(base_url: &str, client_info: ClientInfo) -> Result<Self, Error> {
+    pub fn new(base_url: &str, client_info: ClientInfo) -> Result<Self, JellyfinApiError> {
         let http_client = Client::builder()
             .timeout(std::time::Duration::from_secs(30))
             .build()?;
@@ -63,12 +63,12 @@ impl JellyfinClient {
         base_url: &str,
         client_info: ClientInfo,
         http_client: Client,
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, JellyfinApiError> {
         let mut url = Url::parse(base_url)?;
         // Ensure trailing slash for consistent joining
         if !url.path().ends_with('/') {
             url.path_segments_mut()
-                .map_err(|_| Error::UrlParse(url::ParseError::EmptyHost))?
+                .map_err(|_| JellyfinApiError::UrlParse(url::ParseError::EmptyHost))?
                 .push("");
         }
 
@@ -111,7 +111,7 @@ impl JellyfinClient {
         method: reqwest::Method,
         path: &str,
         body: Option<&serde_json::Value>,
-    ) -> Result<T, Error> {
+    ) -> Result<T, JellyfinApiError> {
         let mut request = self.request_builder(method, path).await?;
 
         if let Some(b) = body {
@@ -127,7 +127,7 @@ impl JellyfinClient {
         method: reqwest::Method,
         path: &str,
         body: Option<&serde_json::Value>,
-    ) -> Result<(), Error> {
+    ) -> Result<(), JellyfinApiError> {
         let mut request = self.request_builder(method, path).await?;
 
         if let Some(b) = body {
@@ -142,7 +142,7 @@ impl JellyfinClient {
         &self,
         method: reqwest::Method,
         path: &str,
-    ) -> Result<reqwest::RequestBuilder, Error> {
+    ) -> Result<reqwest::RequestBuilder, JellyfinApiError> {
         let url = self.base_url.join(path)?;
         let auth_header = self.build_auth_header().await;
         let user_agent = format!("Jellyswarrm API Client/{}", env!("CARGO_PKG_VERSION"));
@@ -154,15 +154,18 @@ impl JellyfinClient {
             .header(header::USER_AGENT, user_agent))
     }
 
-    async fn parse_response<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, Error> {
+    async fn parse_response<T: DeserializeOwned>(
+        response: reqwest::Response,
+    ) -> Result<T, JellyfinApiError> {
         if response.status().is_success() {
-            return Ok(response.json::<T>().await?);
+            let body = response.bytes().await?;
+            return Ok(serde_json::from_slice(&body)?);
         }
 
         Err(Self::response_error(response).await)
     }
 
-    async fn check_success(response: reqwest::Response) -> Result<(), Error> {
+    async fn check_success(response: reqwest::Response) -> Result<(), JellyfinApiError> {
         if response.status().is_success() {
             return Ok(());
         }
@@ -170,24 +173,23 @@ impl JellyfinClient {
         Err(Self::response_error(response).await)
     }
 
-    async fn response_error(response: reqwest::Response) -> Error {
+    async fn response_error(response: reqwest::Response) -> JellyfinApiError {
         let status = response.status();
         match status {
-            StatusCode::UNAUTHORIZED => Error::Unauthorized,
-            StatusCode::FORBIDDEN => Error::Forbidden,
-            StatusCode::NOT_FOUND => Error::NotFound,
-            _ => {
-                let text = response.text().await.unwrap_or_default();
-                Error::ServerError(format!("{} - {}", status, text))
-            }
+            StatusCode::UNAUTHORIZED => JellyfinApiError::Unauthorized,
+            StatusCode::FORBIDDEN => JellyfinApiError::Forbidden,
+            StatusCode::NOT_FOUND => JellyfinApiError::NotFound,
+            _ => JellyfinApiError::Server(status, response.text().await.unwrap_or_default()),
         }
     }
 
+    /// Log in with a username and password. Rejected credentials fail with
+    /// [`JellyfinApiError::Unauthorized`].
     pub async fn authenticate_by_name_typed<T: DeserializeOwned>(
         &self,
         username: &str,
         password: &str,
-    ) -> Result<T, Error> {
+    ) -> Result<T, JellyfinApiError> {
         let body = json!({
             "Username": username,
             "Pw": password
@@ -199,17 +201,13 @@ impl JellyfinClient {
             Some(&body),
         )
         .await
-        .map_err(|e| match e {
-            Error::Unauthorized => Error::AuthenticationFailed("Invalid credentials".to_string()),
-            _ => e,
-        })
     }
 
     pub async fn authenticate_by_name(
         &self,
         username: &str,
         password: &str,
-    ) -> Result<User, Error> {
+    ) -> Result<User, JellyfinApiError> {
         let response: AuthResponse = self.authenticate_by_name_typed(username, password).await?;
 
         let mut write_guard = self.auth_token.write().await;
@@ -218,51 +216,150 @@ impl JellyfinClient {
         Ok(response.user)
     }
 
-    pub async fn logout(&self) -> Result<(), Error> {
+    pub async fn logout(&self) -> Result<(), JellyfinApiError> {
         self.request_no_content(reqwest::Method::POST, "Sessions/Logout", None)
             .await?;
         *self.auth_token.write().await = None;
         Ok(())
     }
 
-    pub async fn get_me(&self) -> Result<User, Error> {
+    pub async fn get_me(&self) -> Result<User, JellyfinApiError> {
         self.request(reqwest::Method::GET, "Users/Me", None).await
     }
 
     pub async fn get_media_folders(
         &self,
         user_id: Option<&str>,
-    ) -> Result<Vec<crate::models::MediaFolder>, Error> {
-        let path = if let Some(uid) = user_id {
-            format!("Users/{}/Views", uid)
-        } else {
-            "Library/MediaFolders".to_string()
+    ) -> Result<Vec<crate::models::MediaFolder>, JellyfinApiError> {
+        let Some(user_id) = user_id else {
+            let response: MediaFoldersResponse = self
+                .request(reqwest::Method::GET, "Library/MediaFolders", None)
+                .await?;
+            return Ok(response.items);
         };
 
+        self.get_views(user_id).await
+    }
+
+    /// Fetch the libraries (views) `user_id` can see. Folders with mixed content
+    /// have no collection type; servers send it as `null` or leave it out.
+    pub async fn get_views(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<crate::models::MediaFolder>, JellyfinApiError> {
+        let path = format!("Users/{}/Views", user_id);
         let response: MediaFoldersResponse =
             self.request(reqwest::Method::GET, &path, None).await?;
         Ok(response.items)
     }
 
-    pub async fn get_public_system_info(&self) -> Result<crate::models::PublicSystemInfo, Error> {
-        self.request(reqwest::Method::GET, "System/Info/Public", None)
-            .await
+    /// Fetch the system info a server shares without authentication. A server that
+    /// can't be reached fails with [`JellyfinApiError::Network`], one that answers with
+    /// something other than Jellyfin's system info with [`JellyfinApiError::NotJellyfin`].
+    pub async fn get_public_system_info(
+        &self,
+    ) -> Result<crate::models::PublicSystemInfo, JellyfinApiError> {
+        let response = self
+            .request_builder(reqwest::Method::GET, "System/Info/Public")
+            .await?
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(match Self::response_error(response).await {
+                JellyfinApiError::NotFound => {
+                    JellyfinApiError::NotJellyfin("System/Info/Public not found".to_string())
+                }
+                e => e,
+            });
+        }
+
+        let body = response.bytes().await?;
+        let info: crate::models::PublicSystemInfo = serde_json::from_slice(&body)
+            .map_err(|e| JellyfinApiError::NotJellyfin(e.to_string()))?;
+        if info.version.is_none() {
+            return Err(JellyfinApiError::NotJellyfin(
+                "system info has no server version".to_string(),
+            ));
+        }
+        Ok(info)
     }
 
     pub async fn get_branding_configuration(
         &self,
-    ) -> Result<crate::models::BrandingConfiguration, Error> {
+    ) -> Result<crate::models::BrandingConfiguration, JellyfinApiError> {
         self.request(reqwest::Method::GET, "Branding/Configuration", None)
             .await
     }
 
+    // Quick Connect methods
+
+    pub async fn quick_connect_enabled(&self) -> Result<bool, JellyfinApiError> {
+        self.request(reqwest::Method::GET, "QuickConnect/Enabled", None)
+            .await
+    }
+
+    pub async fn quick_connect_initiate<T: DeserializeOwned>(&self) -> Result<T, JellyfinApiError> {
+        self.request(reqwest::Method::POST, "QuickConnect/Initiate", None)
+            .await
+    }
+
+    pub async fn quick_connect_connect<T: DeserializeOwned>(
+        &self,
+        secret: &str,
+    ) -> Result<T, JellyfinApiError> {
+        let response = self
+            .request_builder(reqwest::Method::GET, "QuickConnect/Connect")
+            .await?
+            .query(&[("Secret", secret)])
+            .send()
+            .await?;
+
+        Self::parse_response(response).await
+    }
+
+    /// Authorizes a pending Quick Connect code for `user_id`. Requires an authenticated client.
+    pub async fn quick_connect_authorize(
+        &self,
+        code: &str,
+        user_id: &str,
+    ) -> Result<bool, JellyfinApiError> {
+        let response = self
+            .request_builder(reqwest::Method::POST, "QuickConnect/Authorize")
+            .await?
+            .query(&[("Code", code), ("UserId", user_id)])
+            .send()
+            .await?;
+
+        Self::parse_response(response).await
+    }
+
+    pub async fn authenticate_with_quick_connect_typed<T: DeserializeOwned>(
+        &self,
+        secret: &str,
+    ) -> Result<T, JellyfinApiError> {
+        let body = json!({ "Secret": secret });
+
+        self.request(
+            reqwest::Method::POST,
+            "Users/AuthenticateWithQuickConnect",
+            Some(&body),
+        )
+        .await
+    }
+
     // Admin methods
 
-    pub async fn get_users(&self) -> Result<Vec<User>, Error> {
+    pub async fn get_users(&self) -> Result<Vec<User>, JellyfinApiError> {
         self.request(reqwest::Method::GET, "Users", None).await
     }
 
-    pub async fn create_user(&self, username: &str, password: Option<&str>) -> Result<User, Error> {
+    /// Create a user. Jellyfin refuses names that are already taken with a `400`,
+    /// which surfaces as [`JellyfinApiError::Server`].
+    pub async fn create_user(
+        &self,
+        username: &str,
+        password: Option<&str>,
+    ) -> Result<User, JellyfinApiError> {
         let body = json!({
             "Name": username,
             "Password": password
@@ -275,12 +372,40 @@ impl JellyfinClient {
         Ok(user)
     }
 
-    pub async fn delete_user(&self, user_id: &str) -> Result<(), Error> {
+    /// Set the password of `user_id`. Administrators may leave out the current
+    /// password of other users.
+    pub async fn update_user_password(
+        &self,
+        user_id: &str,
+        current_password: Option<&str>,
+        new_password: &str,
+    ) -> Result<(), JellyfinApiError> {
+        let body = json!({
+            "CurrentPw": current_password,
+            "NewPw": new_password,
+            "ResetPassword": false
+        });
+        let path = format!("Users/{}/Password", user_id);
+        self.request_no_content(reqwest::Method::POST, &path, Some(&body))
+            .await
+    }
+
+    pub async fn delete_user(&self, user_id: &str) -> Result<(), JellyfinApiError> {
         let path = format!("Users/{}", user_id);
         self.request_no_content(reqwest::Method::DELETE, &path, None)
             .await
     }
 
+    /// Fetch a single item as seen by the authenticated user. An id the server
+    /// doesn't know fails with [`JellyfinApiError::NotFound`].
+    pub async fn get_item(
+        &self,
+        item_id: &str,
+    ) -> Result<crate::models::BaseItem, JellyfinApiError> {
+        self.request(reqwest::Method::GET, &format!("Items/{}", item_id), None)
+            .await
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub async fn get_items(
         &self,
@@ -293,7 +418,7 @@ impl JellyfinClient {
         sort_by: Option<String>,
         sort_order: Option<String>,
         include_fields: Option<Vec<IncludeBaseItemFields>>,
-    ) -> Result<crate::models::ItemsResponse, Error> {
+    ) -> Result<crate::models::ItemsResponse, JellyfinApiError> {
         let mut query = vec![
             ("Recursive", recursive.to_string()),
             //("Fields", "PrimaryImageAspectRatio,CanDelete,BasicSyncInfo,ProductionYear,RunTimeTicks,CommunityRating".to_string()),
@@ -357,6 +482,57 @@ mod tests {
     use wiremock::matchers::{header as header_matcher, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
+    #[tokio::test]
+    async fn test_public_system_info_distinguishes_unreachable_and_non_jellyfin_servers() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/System/Info/Public"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ServerName": "Media",
+                "Version": "10.10.7",
+                "Id": "server_id"
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = JellyfinClient::new(&mock_server.uri(), ClientInfo::default()).unwrap();
+        let info = client.get_public_system_info().await.unwrap();
+        assert_eq!(info.version.as_deref(), Some("10.10.7"));
+
+        let other_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/System/Info/Public"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "ok" })))
+            .mount(&other_server)
+            .await;
+        let client = JellyfinClient::new(&other_server.uri(), ClientInfo::default()).unwrap();
+        assert!(matches!(
+            client.get_public_system_info().await,
+            Err(JellyfinApiError::NotJellyfin(_))
+        ));
+
+        let web_server = MockServer::start().await;
+        let client = JellyfinClient::new(&web_server.uri(), ClientInfo::default()).unwrap();
+        assert!(matches!(
+            client.get_public_system_info().await,
+            Err(JellyfinApiError::NotJellyfin(_))
+        ));
+
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = JellyfinClient::new(
+            &format!("http://127.0.0.1:{closed_port}"),
+            ClientInfo::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            client.get_public_system_info().await,
+            Err(JellyfinApiError::Network(_))
+        ));
+    }
+
     #[tokio::test]
     async fn test_authenticate_success() {
         let mock_server = MockServer::start().await;
@@ -388,6 +564,119 @@ mod tests {
         assert_eq!(client.get_token().await.as_deref(), Some("test_token"));
     }
 
+    #[tokio::test]
+    async fn test_admin_methods_report_typed_errors() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/Users/AuthenticateByName"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Users"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "Items": [] })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/Users/New"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .set_body_string("A user with the name 'alice' already exists."),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/Users/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+
+        let client = JellyfinClient::new(&mock_server.uri(), ClientInfo::default()).unwrap();
+
+        assert!(matches!(
+            client.authenticate_by_name("alice", "wrong").await,
+            Err(JellyfinApiError::Unauthorized)
+        ));
+        assert!(matches!(
+            client.get_users().await,
+            Err(JellyfinApiError::Deserialize(_))
+        ));
+        match client.create_user("alice", Some("password")).await {
+            Err(JellyfinApiError::Server(status, body)) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert!(body.contains("already exists"));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        assert!(matches!(
+            client.delete_user("missing").await,
+            Err(JellyfinApiError::NotFound)
+        ));
+
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = JellyfinClient::new(
+            &format!("http://127.0.0.1:{closed_port}"),
+            ClientInfo::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            client.get_users().await,
+            Err(JellyfinApiError::Network(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_update_user_password_posts_current_and_new_password() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/Users/user_id/Password"))
+            .and(wiremock::matchers::body_json(json!({
+                "CurrentPw": "old",
+                "NewPw": "new",
+                "ResetPassword": false
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = JellyfinClient::new(&mock_server.uri(), ClientInfo::default()).unwrap();
+        let client = client.with_token("test_token".to_string()).await;
+
+        client
+            .update_user_password("user_id", Some("old"), "new")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quick_connect_authorize_sends_code_and_user() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/QuickConnect/Authorize"))
+            .and(wiremock::matchers::query_param("Code", "123456"))
+            .and(wiremock::matchers::query_param("UserId", "user_id"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(true))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = JellyfinClient::new(&mock_server.uri(), ClientInfo::default()).unwrap();
+        let client = client.with_token("test_token".to_string()).await;
+
+        assert!(client
+            .quick_connect_authorize("123456", "user_id")
+            .await
+            .unwrap());
+    }
+
     #[tokio::test]
     async fn test_get_media_folders() {
         let mock_server = MockServer::start().await;
@@ -423,6 +712,76 @@ mod tests {
         assert_eq!(folders[0].name, "Movies");
     }
 
+    #[tokio::test]
+    async fn test_get_views_parses_captured_response() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/Users/user_id/Views"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                include_str!("tests/files/userviews.json"),
+                "application/json",
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Users/other_user/Views"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let client = JellyfinClient::new(&mock_server.uri(), ClientInfo::default()).unwrap();
+        let client = client.with_token("test_token".to_string()).await;
+
+        let views = client.get_views("user_id").await.unwrap();
+        let views: Vec<_> = views
+            .iter()
+            .map(|view| (view.name.as_str(), view.collection_type.as_deref()))
+            .collect();
+        assert_eq!(
+            views,
+            [
+                ("Filme", Some("movies")),
+                ("Serien", Some("tvshows")),
+                ("Gemischt", None)
+            ]
+        );
+        assert!(matches!(
+            client.get_views("other_user").await,
+            Err(JellyfinApiError::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_item_reports_unknown_ids_as_not_found() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/Items/item_1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "Name": "Heat",
+                "Id": "item_1",
+                "Type": "Movie"
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Items/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+
+        let client = JellyfinClient::new(&mock_server.uri(), ClientInfo::default()).unwrap();
+        let client = client.with_token("test_token".to_string()).await;
+
+        let item = client.get_item("item_1").await.unwrap();
+        assert_eq!(item.name, "Heat");
+        assert!(matches!(
+            client.get_item("missing").await,
+            Err(JellyfinApiError::NotFound)
+        ));
+    }
+
     #[tokio::test]
     async fn test_get_branding_configuration() {
         let mock_server = MockServer::start().await;
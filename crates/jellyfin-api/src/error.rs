@@ -1,23 +1,28 @@
+use reqwest::StatusCode;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-pub enum Error {
+pub enum JellyfinApiError {
+    /// The server couldn't be reached or the connection broke off.
     #[error("Network error: {0}")]
     Network(#[from] reqwest::Error),
-    #[error("Serialization error: {0}")]
-    Serialization(#[from] serde_json::Error),
+    /// The server answered with a body that doesn't have the expected shape.
+    #[error("Deserialization error: {0}")]
+    Deserialize(#[from] serde_json::Error),
     #[error("URL parse error: {0}")]
     UrlParse(#[from] url::ParseError),
-    #[error("Authentication failed: {0}")]
-    AuthenticationFailed(String),
+    /// `401`: missing or invalid token, or rejected credentials when logging in.
     #[error("Unauthorized")]
     Unauthorized,
     #[error("Forbidden")]
     Forbidden,
     #[error("Not found")]
     NotFound,
-    #[error("Server error: {0}")]
-    ServerError(String),
+    /// Any other unsuccessful status, with the body the server sent.
+    #[error("Server error: {0} - {1}")]
+    Server(StatusCode, String),
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
+    #[error("Not a Jellyfin server: {0}")]
+    NotJellyfin(String),
 }
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crate::{error::Error, ClientInfo, JellyfinClient};
+use crate::{error::JellyfinApiError, ClientInfo, JellyfinClient};
 use moka::future::Cache;
 use tracing::info;
 use url::Url;
@@ -34,12 +34,12 @@ impl JellyfinClientStorage {
         base_url: &str,
         client_info: ClientInfo,
         id: Option<&str>,
-    ) -> Result<Arc<JellyfinClient>, Error> {
+    ) -> Result<Arc<JellyfinClient>, JellyfinApiError> {
         info!("Requesting JellyfinClient for URL: {}", base_url);
         let mut url = Url::parse(base_url)?;
         if url.path().ends_with('/') {
             url.path_segments_mut()
-                .map_err(|_| Error::UrlParse(url::ParseError::EmptyHost))?
+                .map_err(|_| JellyfinApiError::UrlParse(url::ParseError::EmptyHost))?
                 .pop_if_empty();
         }
         let normalized_url = url.to_string();
@@ -1,6 +1,9 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, Data, DeriveInput, Expr, ExprLit, ExprMethodCall, Fields, Lit};
+use syn::{
+    ext::IdentExt, parse_macro_input, punctuated::Punctuated, Data, DeriveInput, Expr, ExprLit,
+    ExprMethodCall, Fields, Lit, Meta, Token,
+};
 
 /// A procedural macro that adds serde rename/alias attributes to struct fields
 /// with support for multiple case conversions simultaneously.
@@ -25,6 +28,14 @@ use syn::{parse_macro_input, Data, DeriveInput, Expr, ExprLit, ExprMethodCall, F
 ///     pub extra: std::collections::HashMap<String, serde_json::Value>,
 ///     // Preserves existing serde attributes
 /// }
+///
+/// // Every listed case after the first becomes its own alias
+/// #[multi_case_struct(pascal, camel, snake)]
+/// #[derive(Debug, Serialize, Deserialize, Clone)]
+/// pub struct MediaSourceQuery {
+///     pub media_source_id: String,
+///     // Generates: #[serde(rename = "MediaSourceId", alias = "mediaSourceId", alias = "media_source_id")]
+/// }
 /// ```
 ///
 /// Supported cases: `pascal`, `camel`, `snake`, `kebab`, `screaming`
@@ -55,24 +66,16 @@ pub fn multi_case_struct(args: TokenStream, input: TokenStream) -> TokenStream {
                     let field_vis = &field.vis;
                     let mut field_attrs = field.attrs.clone();
 
-                    // Check if field already has serde rename or alias attributes (syn v2.0 API)
-                    let has_serde_rename_or_alias = field_attrs.iter().any(|attr| {
-                        if attr.path().is_ident("serde") {
-                            if let syn::Meta::List(meta_list) = &attr.meta {
-                                let tokens = meta_list.tokens.to_string();
-                                tokens.contains("rename") || tokens.contains("alias")
-                            } else {
-                                false
-                            }
-                        } else {
-                            false
-                        }
-                    });
+                    // Only an explicit `rename`/`alias` opts a field out. Every other serde
+                    // attribute (`default`, `skip`, `with`, ...) is re-emitted untouched next
+                    // to the generated one.
+                    let has_serde_rename_or_alias =
+                        field_attrs.iter().any(has_serde_rename_or_alias);
 
                     // Always add rename/alias unless rename/alias is already present
                     if !has_serde_rename_or_alias {
                         if let Some(field_name) = field_name {
-                            let field_name_str = field_name.to_string();
+                            let field_name_str = field_name.unraw().to_string();
 
                             if case_types.len() == 1 {
                                 // Single case - use rename
@@ -84,14 +87,19 @@ pub fn multi_case_struct(args: TokenStream, input: TokenStream) -> TokenStream {
                             } else {
                                 // Multiple cases - use rename for first, alias for others
                                 let primary_name = convert_case(&field_name_str, &case_types[0]);
-                                let alias_names: Vec<String> = case_types
-                                    .iter()
-                                    .skip(1)
-                                    .map(|case_type| convert_case(&field_name_str, case_type))
-                                    .collect();
-
+                                let mut alias_names: Vec<String> = Vec::new();
+                                for case_type in case_types.iter().skip(1) {
+                                    let alias = convert_case(&field_name_str, case_type);
+                                    // Single-word fields convert to the same name in several
+                                    // cases; serde rejects a duplicate alias.
+                                    if alias != primary_name && !alias_names.contains(&alias) {
+                                        alias_names.push(alias);
+                                    }
+                                }
+
+                                // serde takes one `alias = "..."` entry per alias.
                                 let serde_attr: syn::Attribute = syn::parse_quote! {
-                                    #[serde(rename = #primary_name, alias = #(#alias_names),*)]
+                                    #[serde(rename = #primary_name #(, alias = #alias_names)*)]
                                 };
                                 field_attrs.push(serde_attr);
                             }
@@ -120,6 +128,20 @@ pub fn multi_case_struct(args: TokenStream, input: TokenStream) -> TokenStream {
     TokenStream::from(quote! { #input })
 }
 
+fn has_serde_rename_or_alias(attr: &syn::Attribute) -> bool {
+    if !attr.path().is_ident("serde") {
+        return false;
+    }
+
+    attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+        .map(|metas| {
+            metas
+                .iter()
+                .any(|meta| meta.path().is_ident("rename") || meta.path().is_ident("alias"))
+        })
+        .unwrap_or(false)
+}
+
 #[derive(Debug, Clone)]
 enum CaseType {
     Pascal,
@@ -165,7 +187,7 @@ fn convert_case(input: &str, case_type: &CaseType) -> String {
         CaseType::Camel => snake_to_camel_case(input),
         CaseType::Snake => input.to_string(),
         CaseType::Kebab => snake_to_kebab_case(input),
-        CaseType::ScreamingSnake => input.to_uppercase(),
+        CaseType::ScreamingSnake => snake_to_screaming_snake_case(input),
     }
 }
 
@@ -199,6 +221,15 @@ fn snake_to_kebab_case(input: &str) -> String {
     input.replace('_', "-")
 }
 
+fn snake_to_screaming_snake_case(input: &str) -> String {
+    input
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_uppercase)
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
 #[proc_macro]
 pub fn lowercase_routes(input: TokenStream) -> TokenStream {
     let mut expr = parse_macro_input!(input as Expr);
@@ -319,3 +350,55 @@ fn extract_routes(expr: &Expr) -> Vec<String> {
     // Not a method call we track
     Vec::new()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_case_single_word() {
+        assert_eq!(convert_case("id", &CaseType::Pascal), "Id");
+        assert_eq!(convert_case("id", &CaseType::Camel), "id");
+        assert_eq!(convert_case("id", &CaseType::Snake), "id");
+        assert_eq!(convert_case("id", &CaseType::Kebab), "id");
+        assert_eq!(convert_case("id", &CaseType::ScreamingSnake), "ID");
+    }
+
+    #[test]
+    fn test_convert_case_multi_word() {
+        let field = "media_source_id";
+        assert_eq!(convert_case(field, &CaseType::Pascal), "MediaSourceId");
+        assert_eq!(convert_case(field, &CaseType::Camel), "mediaSourceId");
+        assert_eq!(convert_case(field, &CaseType::Snake), "media_source_id");
+        assert_eq!(convert_case(field, &CaseType::Kebab), "media-source-id");
+        assert_eq!(
+            convert_case(field, &CaseType::ScreamingSnake),
+            "MEDIA_SOURCE_ID"
+        );
+    }
+
+    #[test]
+    fn test_screaming_snake_keeps_word_boundaries() {
+        assert_eq!(snake_to_screaming_snake_case("user_id"), "USER_ID");
+        assert_eq!(snake_to_screaming_snake_case("user__id_"), "USER_ID");
+        assert_eq!(
+            snake_to_screaming_snake_case("max_streaming_bitrate"),
+            "MAX_STREAMING_BITRATE"
+        );
+    }
+
+    #[test]
+    fn test_detects_only_explicit_rename_or_alias() {
+        let renamed: syn::Attribute = syn::parse_quote!(#[serde(rename = "Custom")]);
+        let aliased: syn::Attribute = syn::parse_quote!(#[serde(default, alias = "other")]);
+        let with_helper: syn::Attribute = syn::parse_quote!(#[serde(with = "alias_helper")]);
+        let default: syn::Attribute = syn::parse_quote!(#[serde(default)]);
+        let doc: syn::Attribute = syn::parse_quote!(#[doc = "rename me"]);
+
+        assert!(has_serde_rename_or_alias(&renamed));
+        assert!(has_serde_rename_or_alias(&aliased));
+        assert!(!has_serde_rename_or_alias(&with_helper));
+        assert!(!has_serde_rename_or_alias(&default));
+        assert!(!has_serde_rename_or_alias(&doc));
+    }
+}
@@ -0,0 +1,129 @@
+use jellyswarrm_macros::multi_case_struct;
+use serde::{Deserialize, Serialize};
+
+#[multi_case_struct(pascal, camel, snake)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct MediaSourceQuery {
+    media_source_id: String,
+    item_id: Option<String>,
+    id: u32,
+}
+
+#[multi_case_struct(pascal, camel, snake, kebab)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct KebabQuery {
+    audio_stream_index: i32,
+}
+
+#[test]
+fn test_deserializes_every_listed_case() {
+    for json in [
+        r#"{"MediaSourceId":"source","ItemId":"item","Id":1}"#,
+        r#"{"mediaSourceId":"source","itemId":"item","id":1}"#,
+        r#"{"media_source_id":"source","item_id":"item","id":1}"#,
+    ] {
+        let query: MediaSourceQuery = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            query,
+            MediaSourceQuery {
+                media_source_id: "source".to_string(),
+                item_id: Some("item".to_string()),
+                id: 1,
+            }
+        );
+    }
+}
+
+#[test]
+fn test_mixed_casing_in_one_payload() {
+    let query: MediaSourceQuery =
+        serde_json::from_str(r#"{"media_source_id":"source","itemId":"item","Id":2}"#).unwrap();
+    assert_eq!(query.media_source_id, "source");
+    assert_eq!(query.item_id.as_deref(), Some("item"));
+    assert_eq!(query.id, 2);
+}
+
+#[test]
+fn test_serializes_with_primary_case() {
+    let json = serde_json::to_value(MediaSourceQuery {
+        media_source_id: "source".to_string(),
+        item_id: None,
+        id: 3,
+    })
+    .unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({"MediaSourceId":"source","ItemId":null,"Id":3})
+    );
+}
+
+#[test]
+fn test_kebab_alias_alongside_snake() {
+    for json in [
+        r#"{"AudioStreamIndex":1}"#,
+        r#"{"audioStreamIndex":1}"#,
+        r#"{"audio_stream_index":1}"#,
+        r#"{"audio-stream-index":1}"#,
+    ] {
+        let query: KebabQuery = serde_json::from_str(json).unwrap();
+        assert_eq!(query.audio_stream_index, 1);
+    }
+}
+
+mod string_as_number {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[multi_case_struct(pascal, camel, screaming)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct AttributedQuery {
+    #[serde(default)]
+    start_index: u32,
+    #[serde(skip)]
+    cached_total: Option<u32>,
+    #[serde(with = "string_as_number")]
+    max_bitrate: u32,
+    #[serde(rename = "customName")]
+    display_name: Option<String>,
+    r#type: Option<String>,
+}
+
+#[test]
+fn test_preserves_existing_serde_attributes() {
+    let query: AttributedQuery =
+        serde_json::from_str(r#"{"MaxBitrate":"8000","cachedTotal":5,"customName":"x"}"#).unwrap();
+    assert_eq!(query.start_index, 0);
+    assert_eq!(query.cached_total, None);
+    assert_eq!(query.max_bitrate, 8000);
+    assert_eq!(query.display_name.as_deref(), Some("x"));
+
+    let json = serde_json::to_value(&query).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "StartIndex": 0,
+            "MaxBitrate": "8000",
+            "customName": "x",
+            "Type": null
+        })
+    );
+}
+
+#[test]
+fn test_screaming_snake_alias_keeps_underscores() {
+    let query: AttributedQuery =
+        serde_json::from_str(r#"{"START_INDEX":3,"MAX_BITRATE":"1","TYPE":"Movie"}"#).unwrap();
+    assert_eq!(query.start_index, 3);
+    assert_eq!(query.max_bitrate, 1);
+    assert_eq!(query.r#type.as_deref(), Some("Movie"));
+}
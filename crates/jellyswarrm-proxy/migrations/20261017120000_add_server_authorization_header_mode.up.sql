@@ -0,0 +1,3 @@
+ALTER TABLE servers
+ADD COLUMN authorization_header_mode TEXT NOT NULL DEFAULT 'Normalize'
+CHECK (authorization_header_mode IN ('Normalize', 'Preserve'));
@@ -0,0 +1,3 @@
+ALTER TABLE servers
+ADD COLUMN max_connections INTEGER NULL
+CHECK (max_connections IS NULL OR max_connections >= 0);
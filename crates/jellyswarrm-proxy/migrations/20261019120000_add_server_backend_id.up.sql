@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN backend_id TEXT NULL;
@@ -0,0 +1 @@
+ALTER TABLE library_group_members DROP COLUMN priority;
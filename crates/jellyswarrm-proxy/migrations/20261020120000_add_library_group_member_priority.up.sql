@@ -0,0 +1 @@
+ALTER TABLE library_group_members ADD COLUMN priority INTEGER NULL;
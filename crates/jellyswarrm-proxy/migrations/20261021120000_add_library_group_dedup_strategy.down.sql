@@ -0,0 +1 @@
+ALTER TABLE library_groups DROP COLUMN dedup_strategy;
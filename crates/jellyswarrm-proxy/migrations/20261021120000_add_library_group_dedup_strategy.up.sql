@@ -0,0 +1 @@
+ALTER TABLE library_groups ADD COLUMN dedup_strategy TEXT NULL;
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 use serde_default::DefaultFromSerde;
 use sqlx::migrate::Migrator;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::sync::LazyLock;
@@ -14,7 +16,9 @@ use jellyfin_api::ClientInfo;
 
 use base64::prelude::*;
 
+use crate::duplicate_policy::{DuplicatePolicy, DEFAULT_TITLE_ARTICLES};
 use crate::encryption::Password;
+use crate::server_url::ServerUrl;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MediaStreamingMode {
@@ -43,6 +47,310 @@ impl fmt::Display for MediaStreamingMode {
     }
 }
 
+/// Which header carries the session token when a request is forwarded to a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AuthorizationHeaderMode {
+    /// Send `X-Emby-Authorization` as the standard `Authorization` header.
+    Normalize,
+    /// Keep `X-Emby-Authorization` if the client used it.
+    Preserve,
+}
+
+impl std::str::FromStr for AuthorizationHeaderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "normalize" => Ok(AuthorizationHeaderMode::Normalize),
+            "preserve" => Ok(AuthorizationHeaderMode::Preserve),
+            _ => Err(format!("Invalid authorization header mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AuthorizationHeaderMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthorizationHeaderMode::Normalize => write!(f, "Normalize"),
+            AuthorizationHeaderMode::Preserve => write!(f, "Preserve"),
+        }
+    }
+}
+
+/// How the ids in proxied JSON responses are rewritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum JsonRewriteMode {
+    /// Parse the whole document and run every response rewrite.
+    Full,
+    /// Rewrite known id fields in place without building the document.
+    Streaming,
+}
+
+impl std::str::FromStr for JsonRewriteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "full" => Ok(JsonRewriteMode::Full),
+            "streaming" => Ok(JsonRewriteMode::Streaming),
+            _ => Err(format!("Invalid JSON rewrite mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for JsonRewriteMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonRewriteMode::Full => write!(f, "Full"),
+            JsonRewriteMode::Streaming => write!(f, "Streaming"),
+        }
+    }
+}
+
+/// Order of the seasons and episodes returned by `/Shows/{id}/Seasons` and
+/// `/Shows/{id}/Episodes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ShowChildOrder {
+    /// Keep the order the server returned.
+    Server,
+    /// Sort by season number, then episode number.
+    Index,
+}
+
+impl std::str::FromStr for ShowChildOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "server" => Ok(ShowChildOrder::Server),
+            "index" => Ok(ShowChildOrder::Index),
+            _ => Err(format!("Invalid show child order: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ShowChildOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShowChildOrder::Server => write!(f, "Server"),
+            ShowChildOrder::Index => write!(f, "Index"),
+        }
+    }
+}
+
+/// How `/QuickConnect/*` requests are answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum QuickConnectMode {
+    /// Codes are issued and authorized by the proxy itself.
+    Local,
+    /// Codes are issued by the best available backend server and authorized there.
+    Passthrough,
+    /// Quick Connect is reported as disabled.
+    Disabled,
+}
+
+impl std::str::FromStr for QuickConnectMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(QuickConnectMode::Local),
+            "passthrough" => Ok(QuickConnectMode::Passthrough),
+            "disabled" => Ok(QuickConnectMode::Disabled),
+            _ => Err(format!("Invalid quick connect mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for QuickConnectMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuickConnectMode::Local => write!(f, "Local"),
+            QuickConnectMode::Passthrough => write!(f, "Passthrough"),
+            QuickConnectMode::Disabled => write!(f, "Disabled"),
+        }
+    }
+}
+
+/// What to do with a media id in a request that no mapping or virtual library knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UnmappedMediaIdMode {
+    /// The id is sent unchanged to whichever server the request resolves to.
+    PassThrough,
+    /// The user's servers are asked for the item in priority order and the first one
+    /// that has it gets a mapping for it.
+    ProbeServers,
+    /// The request is answered with 404.
+    NotFound,
+}
+
+impl std::str::FromStr for UnmappedMediaIdMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace(['_', '-'], "").as_str() {
+            "passthrough" => Ok(UnmappedMediaIdMode::PassThrough),
+            "probeservers" => Ok(UnmappedMediaIdMode::ProbeServers),
+            "notfound" => Ok(UnmappedMediaIdMode::NotFound),
+            _ => Err(format!("Invalid unmapped media id mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for UnmappedMediaIdMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnmappedMediaIdMode::PassThrough => write!(f, "PassThrough"),
+            UnmappedMediaIdMode::ProbeServers => write!(f, "ProbeServers"),
+            UnmappedMediaIdMode::NotFound => write!(f, "NotFound"),
+        }
+    }
+}
+
+/// When federated media names get the ` [ServerName]` suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerNameSuffixMode {
+    Always,
+    /// Only items whose name shows up on more than one backend are suffixed.
+    OnCollision,
+    Never,
+}
+
+impl std::str::FromStr for ServerNameSuffixMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace(['_', '-'], "").as_str() {
+            "always" | "true" => Ok(ServerNameSuffixMode::Always),
+            "oncollision" => Ok(ServerNameSuffixMode::OnCollision),
+            "never" | "false" => Ok(ServerNameSuffixMode::Never),
+            _ => Err(format!("Invalid server name suffix mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ServerNameSuffixMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerNameSuffixMode::Always => write!(f, "always"),
+            ServerNameSuffixMode::OnCollision => write!(f, "on_collision"),
+            ServerNameSuffixMode::Never => write!(f, "never"),
+        }
+    }
+}
+
+// Older configs store `include_server_name_in_media` as a boolean.
+impl<'de> Deserialize<'de> for ServerNameSuffixMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawMode {
+            Flag(bool),
+            Name(String),
+        }
+
+        match RawMode::deserialize(deserializer)? {
+            RawMode::Flag(true) => Ok(ServerNameSuffixMode::Always),
+            RawMode::Flag(false) => Ok(ServerNameSuffixMode::Never),
+            RawMode::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// How merged libraries decide that items on different backends are versions of the
+/// same title.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeduplicationStrategy {
+    Off,
+    /// Items sharing a Tmdb, Imdb or Tvdb id.
+    #[default]
+    ProviderIds,
+    /// Provider ids where an item has them, otherwise normalized title plus production year.
+    NameYear,
+}
+
+impl std::str::FromStr for DeduplicationStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace(['_', '-'], "").as_str() {
+            "off" | "false" => Ok(DeduplicationStrategy::Off),
+            "providerids" | "true" => Ok(DeduplicationStrategy::ProviderIds),
+            "nameyear" => Ok(DeduplicationStrategy::NameYear),
+            _ => Err(format!("Invalid deduplication strategy: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for DeduplicationStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeduplicationStrategy::Off => write!(f, "off"),
+            DeduplicationStrategy::ProviderIds => write!(f, "provider_ids"),
+            DeduplicationStrategy::NameYear => write!(f, "name_year"),
+        }
+    }
+}
+
+// `merge_library_versions` started out as a boolean that enabled provider id matching.
+impl<'de> Deserialize<'de> for DeduplicationStrategy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawStrategy {
+            Flag(bool),
+            Name(String),
+        }
+
+        match RawStrategy::deserialize(deserializer)? {
+            RawStrategy::Flag(true) => Ok(DeduplicationStrategy::ProviderIds),
+            RawStrategy::Flag(false) => Ok(DeduplicationStrategy::Off),
+            RawStrategy::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// What to do with requests that reach user-scoped endpoints without a resolvable user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UnauthenticatedAuditMode {
+    Off,
+    /// Log the request and let it through.
+    Log,
+    /// Log the request and answer `401 Unauthorized`.
+    Block,
+}
+
+impl std::str::FromStr for UnauthenticatedAuditMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(UnauthenticatedAuditMode::Off),
+            "log" => Ok(UnauthenticatedAuditMode::Log),
+            "block" => Ok(UnauthenticatedAuditMode::Block),
+            _ => Err(format!("Invalid unauthenticated audit mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for UnauthenticatedAuditMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnauthenticatedAuditMode::Off => write!(f, "Off"),
+            UnauthenticatedAuditMode::Log => write!(f, "Log"),
+            UnauthenticatedAuditMode::Block => write!(f, "Block"),
+        }
+    }
+}
+
 pub static MIGRATOR: Migrator = sqlx::migrate!();
 
 pub static CLIENT_INFO: LazyLock<ClientInfo> = LazyLock::new(|| ClientInfo {
@@ -101,8 +409,8 @@ fn default_port() -> u16 {
     3000
 }
 
-fn default_include_server_name_in_media() -> bool {
-    true
+fn default_include_server_name_in_media() -> ServerNameSuffixMode {
+    ServerNameSuffixMode::Always
 }
 
 fn default_username() -> String {
@@ -113,6 +421,13 @@ fn default_password() -> Password {
     "jellyswarrm".to_string().into()
 }
 
+fn default_title_articles() -> BTreeMap<String, Vec<String>> {
+    BTreeMap::from([(
+        "en".to_string(),
+        DEFAULT_TITLE_ARTICLES.map(str::to_string).to_vec(),
+    )])
+}
+
 fn default_session_key() -> Vec<u8> {
     Key::generate().master().to_vec()
 }
@@ -129,18 +444,210 @@ fn default_media_streaming_mode() -> MediaStreamingMode {
     MediaStreamingMode::Proxy
 }
 
+fn default_secure_cookies() -> bool {
+    false
+}
+
+fn default_trust_forwarded_headers() -> bool {
+    false
+}
+
+fn default_startup_self_check() -> bool {
+    true
+}
+
 fn default_server_background_check_interval_secs() -> u64 {
     30
 }
 
+fn default_media_mapping_cache_capacity() -> u64 {
+    100_000
+}
+
+fn default_media_mapping_cache_ttl_secs() -> u64 {
+    60 * 30
+}
+
+fn default_play_session_ttl_secs() -> u64 {
+    12 * 60 * 60
+}
+
+fn default_play_session_capacity() -> u64 {
+    10_000
+}
+
+fn default_session_lifetime_secs() -> u64 {
+    0
+}
+
+fn default_session_refresh_window_secs() -> u64 {
+    60 * 60
+}
+
+fn default_backup_dir() -> String {
+    "backups".to_string()
+}
+
+fn default_backup_retention() -> u32 {
+    7
+}
+
 fn default_auto_create_users_on_login() -> bool {
     true
 }
 
+fn default_enrich_user_me() -> bool {
+    false
+}
+
 fn default_merge_libraries() -> bool {
     true
 }
 
+fn default_merge_box_sets() -> bool {
+    false
+}
+
+fn default_merge_library_versions() -> DeduplicationStrategy {
+    DeduplicationStrategy::Off
+}
+
+fn default_dedup_strategy() -> DeduplicationStrategy {
+    DeduplicationStrategy::default()
+}
+
+fn default_refresh_all_copies() -> bool {
+    false
+}
+
+fn default_rate_all_copies() -> bool {
+    false
+}
+
+fn default_mark_all_copies() -> bool {
+    false
+}
+
+fn default_report_backend_version() -> bool {
+    true
+}
+
+fn default_upstream_error_details() -> bool {
+    false
+}
+
+fn default_sanitize_buffered_ranges() -> bool {
+    false
+}
+
+fn default_box_set_duplicate_policy() -> DuplicatePolicy {
+    DuplicatePolicy::ShowAll
+}
+
+fn default_per_user_max_bitrate() -> u64 {
+    0
+}
+
+fn default_upstream_retries() -> u32 {
+    0
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    0
+}
+
+fn default_pool_max_idle_per_host() -> u64 {
+    0
+}
+
+fn default_pool_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_tcp_keepalive_secs() -> u64 {
+    15
+}
+
+fn default_http2_prior_knowledge() -> bool {
+    false
+}
+
+fn default_allow_invalid_upstream_certs() -> bool {
+    false
+}
+
+fn default_upstream_ca_bundle() -> String {
+    String::new()
+}
+
+fn default_show_child_order() -> ShowChildOrder {
+    ShowChildOrder::Server
+}
+
+fn default_json_full_parse_limit() -> u64 {
+    8_388_608
+}
+
+fn default_json_rewrite_mode() -> JsonRewriteMode {
+    JsonRewriteMode::Full
+}
+
+fn default_tag_source_server() -> bool {
+    false
+}
+
+fn default_proxy_unknown_paths() -> bool {
+    true
+}
+
+fn default_quick_connect_mode() -> QuickConnectMode {
+    QuickConnectMode::Local
+}
+
+fn default_client_rate_limit_per_second() -> f64 {
+    0.0
+}
+
+fn default_client_rate_limit_burst() -> u32 {
+    20
+}
+
+fn default_dedup_mutations_window_ms() -> u64 {
+    0
+}
+
+fn default_max_connections_per_server() -> u32 {
+    0
+}
+
+fn default_upstream_queue_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_disable_id_remapping() -> bool {
+    false
+}
+
+fn default_expose_federation_status() -> bool {
+    false
+}
+
+fn default_unmapped_media_id() -> UnmappedMediaIdMode {
+    UnmappedMediaIdMode::PassThrough
+}
+
+fn default_audit_unauthenticated() -> UnauthenticatedAuditMode {
+    UnauthenticatedAuditMode::Off
+}
+
+fn default_enable_legacy_emby_auth() -> bool {
+    false
+}
+
+fn default_cors_allow_credentials() -> bool {
+    false
+}
+
 mod base64_serde {
     use super::*;
     use serde::de::Error as DeError;
@@ -213,27 +720,235 @@ define_fallback_deserializer!(deserialize_port, u16, default_port);
 define_fallback_deserializer!(deserialize_host, String, default_host);
 define_fallback_deserializer!(
     deserialize_include_server_name_in_media,
-    bool,
+    ServerNameSuffixMode,
     default_include_server_name_in_media
 );
-define_fallback_deserializer!(deserialize_timeout, u64, default_timeout);
-define_fallback_deserializer!(deserialize_ui_route, UrlSegment, default_ui_route);
+define_fallback_deserializer!(deserialize_timeout, u64, default_timeout);
+define_fallback_deserializer!(deserialize_ui_route, UrlSegment, default_ui_route);
+define_fallback_deserializer!(
+    deserialize_media_streaming_mode,
+    MediaStreamingMode,
+    default_media_streaming_mode
+);
+define_fallback_deserializer!(deserialize_secure_cookies, bool, default_secure_cookies);
+define_fallback_deserializer!(
+    deserialize_trust_forwarded_headers,
+    bool,
+    default_trust_forwarded_headers
+);
+define_fallback_deserializer!(
+    deserialize_startup_self_check,
+    bool,
+    default_startup_self_check
+);
+define_fallback_deserializer!(
+    deserialize_server_background_check_interval_secs,
+    u64,
+    default_server_background_check_interval_secs
+);
+define_fallback_deserializer!(
+    deserialize_media_mapping_cache_capacity,
+    u64,
+    default_media_mapping_cache_capacity
+);
+define_fallback_deserializer!(
+    deserialize_media_mapping_cache_ttl_secs,
+    u64,
+    default_media_mapping_cache_ttl_secs
+);
+define_fallback_deserializer!(
+    deserialize_play_session_ttl_secs,
+    u64,
+    default_play_session_ttl_secs
+);
+define_fallback_deserializer!(
+    deserialize_play_session_capacity,
+    u64,
+    default_play_session_capacity
+);
+define_fallback_deserializer!(
+    deserialize_session_lifetime_secs,
+    u64,
+    default_session_lifetime_secs
+);
+define_fallback_deserializer!(
+    deserialize_session_refresh_window_secs,
+    u64,
+    default_session_refresh_window_secs
+);
+define_fallback_deserializer!(deserialize_backup_dir, String, default_backup_dir);
+define_fallback_deserializer!(deserialize_backup_retention, u32, default_backup_retention);
+define_fallback_deserializer!(
+    deserialize_auto_create_users_on_login,
+    bool,
+    default_auto_create_users_on_login
+);
+define_fallback_deserializer!(deserialize_enrich_user_me, bool, default_enrich_user_me);
+define_fallback_deserializer!(deserialize_merge_libraries, bool, default_merge_libraries);
+define_fallback_deserializer!(deserialize_merge_box_sets, bool, default_merge_box_sets);
+define_fallback_deserializer!(
+    deserialize_merge_library_versions,
+    DeduplicationStrategy,
+    default_merge_library_versions
+);
+define_fallback_deserializer!(
+    deserialize_default_dedup_strategy,
+    DeduplicationStrategy,
+    default_dedup_strategy
+);
+define_fallback_deserializer!(
+    deserialize_refresh_all_copies,
+    bool,
+    default_refresh_all_copies
+);
+define_fallback_deserializer!(deserialize_rate_all_copies, bool, default_rate_all_copies);
+define_fallback_deserializer!(deserialize_mark_all_copies, bool, default_mark_all_copies);
+define_fallback_deserializer!(
+    deserialize_report_backend_version,
+    bool,
+    default_report_backend_version
+);
+define_fallback_deserializer!(
+    deserialize_upstream_error_details,
+    bool,
+    default_upstream_error_details
+);
+define_fallback_deserializer!(
+    deserialize_sanitize_buffered_ranges,
+    bool,
+    default_sanitize_buffered_ranges
+);
+define_fallback_deserializer!(
+    deserialize_box_set_duplicate_policy,
+    DuplicatePolicy,
+    default_box_set_duplicate_policy
+);
+define_fallback_deserializer!(
+    deserialize_per_user_max_bitrate,
+    u64,
+    default_per_user_max_bitrate
+);
+define_fallback_deserializer!(deserialize_upstream_retries, u32, default_upstream_retries);
+define_fallback_deserializer!(
+    deserialize_connect_timeout_secs,
+    u64,
+    default_connect_timeout_secs
+);
+define_fallback_deserializer!(
+    deserialize_pool_max_idle_per_host,
+    u64,
+    default_pool_max_idle_per_host
+);
+define_fallback_deserializer!(
+    deserialize_pool_idle_timeout_secs,
+    u64,
+    default_pool_idle_timeout_secs
+);
+define_fallback_deserializer!(
+    deserialize_tcp_keepalive_secs,
+    u64,
+    default_tcp_keepalive_secs
+);
+define_fallback_deserializer!(
+    deserialize_http2_prior_knowledge,
+    bool,
+    default_http2_prior_knowledge
+);
+define_fallback_deserializer!(
+    deserialize_allow_invalid_upstream_certs,
+    bool,
+    default_allow_invalid_upstream_certs
+);
+define_fallback_deserializer!(
+    deserialize_upstream_ca_bundle,
+    String,
+    default_upstream_ca_bundle
+);
+define_fallback_deserializer!(
+    deserialize_show_child_order,
+    ShowChildOrder,
+    default_show_child_order
+);
+define_fallback_deserializer!(
+    deserialize_json_full_parse_limit,
+    u64,
+    default_json_full_parse_limit
+);
+define_fallback_deserializer!(
+    deserialize_json_rewrite_mode,
+    JsonRewriteMode,
+    default_json_rewrite_mode
+);
+define_fallback_deserializer!(
+    deserialize_tag_source_server,
+    bool,
+    default_tag_source_server
+);
+define_fallback_deserializer!(
+    deserialize_proxy_unknown_paths,
+    bool,
+    default_proxy_unknown_paths
+);
+define_fallback_deserializer!(
+    deserialize_quick_connect_mode,
+    QuickConnectMode,
+    default_quick_connect_mode
+);
+define_fallback_deserializer!(
+    deserialize_client_rate_limit_per_second,
+    f64,
+    default_client_rate_limit_per_second
+);
+define_fallback_deserializer!(
+    deserialize_client_rate_limit_burst,
+    u32,
+    default_client_rate_limit_burst
+);
+define_fallback_deserializer!(
+    deserialize_dedup_mutations_window_ms,
+    u64,
+    default_dedup_mutations_window_ms
+);
+define_fallback_deserializer!(
+    deserialize_max_connections_per_server,
+    u32,
+    default_max_connections_per_server
+);
+define_fallback_deserializer!(
+    deserialize_upstream_queue_timeout_ms,
+    u64,
+    default_upstream_queue_timeout_ms
+);
+define_fallback_deserializer!(
+    deserialize_disable_id_remapping,
+    bool,
+    default_disable_id_remapping
+);
+define_fallback_deserializer!(
+    deserialize_expose_federation_status,
+    bool,
+    default_expose_federation_status
+);
+define_fallback_deserializer!(
+    deserialize_unmapped_media_id,
+    UnmappedMediaIdMode,
+    default_unmapped_media_id
+);
 define_fallback_deserializer!(
-    deserialize_media_streaming_mode,
-    MediaStreamingMode,
-    default_media_streaming_mode
+    deserialize_audit_unauthenticated,
+    UnauthenticatedAuditMode,
+    default_audit_unauthenticated
 );
 define_fallback_deserializer!(
-    deserialize_server_background_check_interval_secs,
-    u64,
-    default_server_background_check_interval_secs
+    deserialize_enable_legacy_emby_auth,
+    bool,
+    default_enable_legacy_emby_auth
 );
 define_fallback_deserializer!(
-    deserialize_auto_create_users_on_login,
+    deserialize_cors_allow_credentials,
     bool,
-    default_auto_create_users_on_login
+    default_cors_allow_credentials
 );
-define_fallback_deserializer!(deserialize_merge_libraries, bool, default_merge_libraries);
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PreconfiguredServer {
@@ -244,6 +959,20 @@ pub struct PreconfiguredServer {
     pub media_streaming_mode: MediaStreamingMode,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PinnedLibrary {
+    pub library: String,
+    pub server: String,
+}
+
+/// Servers holding the same library. Only `display` is browsed, while watch state
+/// is kept in sync with the `sync` servers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WatchStateMirror {
+    pub display: String,
+    pub sync: Vec<String>,
+}
+
 #[derive(Clone, Deserialize, Serialize, DefaultFromSerde)]
 pub struct AppConfig {
     #[serde(default = "default_server_id")]
@@ -260,7 +989,7 @@ pub struct AppConfig {
         default = "default_include_server_name_in_media",
         deserialize_with = "deserialize_include_server_name_in_media"
     )]
-    pub include_server_name_in_media: bool,
+    pub include_server_name_in_media: ServerNameSuffixMode,
 
     #[serde(default = "default_username")]
     pub username: String,
@@ -270,6 +999,15 @@ pub struct AppConfig {
     #[serde(default)]
     pub preconfigured_servers: Vec<PreconfiguredServer>,
 
+    #[serde(default)]
+    pub pinned_libraries: Vec<PinnedLibrary>,
+
+    #[serde(default)]
+    pub watch_state_mirrors: Vec<WatchStateMirror>,
+
+    #[serde(default = "default_title_articles")]
+    pub title_articles: BTreeMap<String, Vec<String>>,
+
     #[serde(default = "default_session_key", with = "base64_serde")]
     pub session_key: Vec<u8>,
 
@@ -285,29 +1023,478 @@ pub struct AppConfig {
     #[serde(default)]
     pub url_prefix: Option<UrlSegment>,
 
+    #[serde(default)]
+    pub public_base_url: Option<ServerUrl>,
+
+    /// Build absolute URLs from `X-Forwarded-Proto`/`X-Forwarded-Host` when no
+    /// `public_base_url` is set. Only safe behind a reverse proxy that overwrites them.
+    #[serde(
+        default = "default_trust_forwarded_headers",
+        deserialize_with = "deserialize_trust_forwarded_headers"
+    )]
+    pub trust_forwarded_headers: bool,
+
     #[serde(
         default = "default_media_streaming_mode",
         deserialize_with = "deserialize_media_streaming_mode"
     )]
     pub media_streaming_mode: MediaStreamingMode,
 
+    #[serde(
+        default = "default_secure_cookies",
+        deserialize_with = "deserialize_secure_cookies"
+    )]
+    pub secure_cookies: bool,
+
+    #[serde(
+        default = "default_startup_self_check",
+        deserialize_with = "deserialize_startup_self_check"
+    )]
+    pub startup_self_check: bool,
+
     #[serde(
         default = "default_server_background_check_interval_secs",
         deserialize_with = "deserialize_server_background_check_interval_secs"
     )]
     pub server_background_check_interval_secs: u64,
 
+    #[serde(
+        default = "default_media_mapping_cache_capacity",
+        deserialize_with = "deserialize_media_mapping_cache_capacity"
+    )]
+    pub media_mapping_cache_capacity: u64,
+
+    #[serde(
+        default = "default_media_mapping_cache_ttl_secs",
+        deserialize_with = "deserialize_media_mapping_cache_ttl_secs"
+    )]
+    pub media_mapping_cache_ttl_secs: u64,
+
+    #[serde(
+        default = "default_play_session_ttl_secs",
+        deserialize_with = "deserialize_play_session_ttl_secs"
+    )]
+    pub play_session_ttl_secs: u64,
+
+    #[serde(
+        default = "default_play_session_capacity",
+        deserialize_with = "deserialize_play_session_capacity"
+    )]
+    pub play_session_capacity: u64,
+
+    /// Lifetime of the backend sessions created at login, `0` keeps them forever.
+    #[serde(
+        default = "default_session_lifetime_secs",
+        deserialize_with = "deserialize_session_lifetime_secs"
+    )]
+    pub session_lifetime_secs: u64,
+
+    /// Backend sessions expiring within this window are logged in again, `0` disables it.
+    #[serde(
+        default = "default_session_refresh_window_secs",
+        deserialize_with = "deserialize_session_refresh_window_secs"
+    )]
+    pub session_refresh_window_secs: u64,
+
+    /// Directory for database backups, relative paths are resolved against `DATA_DIR`.
+    #[serde(
+        default = "default_backup_dir",
+        deserialize_with = "deserialize_backup_dir"
+    )]
+    pub backup_dir: String,
+
+    /// Number of database backups to keep, `0` keeps all of them.
+    #[serde(
+        default = "default_backup_retention",
+        deserialize_with = "deserialize_backup_retention"
+    )]
+    pub backup_retention: u32,
+
     #[serde(
         default = "default_auto_create_users_on_login",
         deserialize_with = "deserialize_auto_create_users_on_login"
     )]
     pub auto_create_users_on_login: bool,
 
+    #[serde(
+        default = "default_enrich_user_me",
+        deserialize_with = "deserialize_enrich_user_me"
+    )]
+    pub enrich_user_me: bool,
+
     #[serde(
         default = "default_merge_libraries",
         deserialize_with = "deserialize_merge_libraries"
     )]
     pub merge_libraries: bool,
+
+    #[serde(
+        default = "default_merge_box_sets",
+        deserialize_with = "deserialize_merge_box_sets"
+    )]
+    pub merge_box_sets: bool,
+
+    #[serde(
+        default = "default_merge_library_versions",
+        deserialize_with = "deserialize_merge_library_versions"
+    )]
+    pub merge_library_versions: DeduplicationStrategy,
+
+    #[serde(
+        default = "default_dedup_strategy",
+        deserialize_with = "deserialize_default_dedup_strategy"
+    )]
+    pub default_dedup_strategy: DeduplicationStrategy,
+
+    #[serde(
+        default = "default_refresh_all_copies",
+        deserialize_with = "deserialize_refresh_all_copies"
+    )]
+    pub refresh_all_copies: bool,
+
+    #[serde(
+        default = "default_rate_all_copies",
+        deserialize_with = "deserialize_rate_all_copies"
+    )]
+    pub rate_all_copies: bool,
+
+    #[serde(
+        default = "default_mark_all_copies",
+        deserialize_with = "deserialize_mark_all_copies"
+    )]
+    pub mark_all_copies: bool,
+
+    #[serde(
+        default = "default_report_backend_version",
+        deserialize_with = "deserialize_report_backend_version"
+    )]
+    pub report_backend_version: bool,
+
+    #[serde(
+        default = "default_upstream_error_details",
+        deserialize_with = "deserialize_upstream_error_details"
+    )]
+    pub upstream_error_details: bool,
+
+    #[serde(
+        default = "default_sanitize_buffered_ranges",
+        deserialize_with = "deserialize_sanitize_buffered_ranges"
+    )]
+    pub sanitize_buffered_ranges: bool,
+
+    #[serde(
+        default = "default_box_set_duplicate_policy",
+        deserialize_with = "deserialize_box_set_duplicate_policy"
+    )]
+    pub box_set_duplicate_policy: DuplicatePolicy,
+
+    #[serde(
+        default = "default_per_user_max_bitrate",
+        deserialize_with = "deserialize_per_user_max_bitrate"
+    )]
+    pub per_user_max_bitrate: u64,
+
+    #[serde(
+        default = "default_upstream_retries",
+        deserialize_with = "deserialize_upstream_retries"
+    )]
+    pub upstream_retries: u32,
+
+    #[serde(
+        default = "default_connect_timeout_secs",
+        deserialize_with = "deserialize_connect_timeout_secs"
+    )]
+    pub connect_timeout_secs: u64,
+
+    #[serde(
+        default = "default_pool_max_idle_per_host",
+        deserialize_with = "deserialize_pool_max_idle_per_host"
+    )]
+    pub pool_max_idle_per_host: u64,
+
+    #[serde(
+        default = "default_pool_idle_timeout_secs",
+        deserialize_with = "deserialize_pool_idle_timeout_secs"
+    )]
+    pub pool_idle_timeout_secs: u64,
+
+    #[serde(
+        default = "default_tcp_keepalive_secs",
+        deserialize_with = "deserialize_tcp_keepalive_secs"
+    )]
+    pub tcp_keepalive_secs: u64,
+
+    #[serde(
+        default = "default_http2_prior_knowledge",
+        deserialize_with = "deserialize_http2_prior_knowledge"
+    )]
+    pub http2_prior_knowledge: bool,
+
+    #[serde(
+        default = "default_allow_invalid_upstream_certs",
+        deserialize_with = "deserialize_allow_invalid_upstream_certs"
+    )]
+    pub allow_invalid_upstream_certs: bool,
+
+    #[serde(
+        default = "default_upstream_ca_bundle",
+        deserialize_with = "deserialize_upstream_ca_bundle"
+    )]
+    pub upstream_ca_bundle: String,
+
+    #[serde(
+        default = "default_show_child_order",
+        deserialize_with = "deserialize_show_child_order"
+    )]
+    pub show_child_order: ShowChildOrder,
+
+    #[serde(
+        default = "default_json_full_parse_limit",
+        deserialize_with = "deserialize_json_full_parse_limit"
+    )]
+    pub json_full_parse_limit: u64,
+
+    #[serde(
+        default = "default_json_rewrite_mode",
+        deserialize_with = "deserialize_json_rewrite_mode"
+    )]
+    pub json_rewrite_mode: JsonRewriteMode,
+
+    #[serde(
+        default = "default_tag_source_server",
+        deserialize_with = "deserialize_tag_source_server"
+    )]
+    pub tag_source_server: bool,
+
+    #[serde(
+        default = "default_proxy_unknown_paths",
+        deserialize_with = "deserialize_proxy_unknown_paths"
+    )]
+    pub proxy_unknown_paths: bool,
+
+    #[serde(
+        default = "default_quick_connect_mode",
+        deserialize_with = "deserialize_quick_connect_mode"
+    )]
+    pub quick_connect_mode: QuickConnectMode,
+
+    #[serde(
+        default = "default_client_rate_limit_per_second",
+        deserialize_with = "deserialize_client_rate_limit_per_second"
+    )]
+    pub client_rate_limit_per_second: f64,
+
+    #[serde(
+        default = "default_client_rate_limit_burst",
+        deserialize_with = "deserialize_client_rate_limit_burst"
+    )]
+    pub client_rate_limit_burst: u32,
+
+    #[serde(
+        default = "default_dedup_mutations_window_ms",
+        deserialize_with = "deserialize_dedup_mutations_window_ms"
+    )]
+    pub dedup_mutations_window_ms: u64,
+
+    #[serde(
+        default = "default_max_connections_per_server",
+        deserialize_with = "deserialize_max_connections_per_server"
+    )]
+    pub max_connections_per_server: u32,
+
+    #[serde(
+        default = "default_upstream_queue_timeout_ms",
+        deserialize_with = "deserialize_upstream_queue_timeout_ms"
+    )]
+    pub upstream_queue_timeout_ms: u64,
+
+    #[serde(
+        default = "default_disable_id_remapping",
+        deserialize_with = "deserialize_disable_id_remapping"
+    )]
+    pub disable_id_remapping: bool,
+
+    #[serde(
+        default = "default_expose_federation_status",
+        deserialize_with = "deserialize_expose_federation_status"
+    )]
+    pub expose_federation_status: bool,
+
+    #[serde(
+        default = "default_unmapped_media_id",
+        deserialize_with = "deserialize_unmapped_media_id"
+    )]
+    pub unmapped_media_id: UnmappedMediaIdMode,
+
+    /// Query parameters (`Name` or `Name=Value`) that keep an item listing on one server.
+    #[serde(default)]
+    pub single_server_query_params: Vec<String>,
+
+    #[serde(
+        default = "default_audit_unauthenticated",
+        deserialize_with = "deserialize_audit_unauthenticated"
+    )]
+    pub audit_unauthenticated: UnauthenticatedAuditMode,
+
+    /// Accept `Authorization` headers with the legacy `Emby ` prefix on proxied requests.
+    #[serde(
+        default = "default_enable_legacy_emby_auth",
+        deserialize_with = "deserialize_enable_legacy_emby_auth"
+    )]
+    pub enable_legacy_emby_auth: bool,
+
+    /// Origins browsers may call the API from. Empty or `*` allows any origin.
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+
+    #[serde(
+        default = "default_cors_allow_credentials",
+        deserialize_with = "deserialize_cors_allow_credentials"
+    )]
+    pub cors_allow_credentials: bool,
+
+    /// Request headers browsers may send. Empty or `*` allows any header.
+    #[serde(default)]
+    pub cors_allowed_headers: Vec<String>,
+}
+
+/// Settings that are only read at startup. A reload keeps their running values, so
+/// changing them takes a restart.
+pub const RESTART_REQUIRED_SETTINGS: &[&str] = &[
+    "host",
+    "port",
+    "url_prefix",
+    "ui_route",
+    "session_key",
+    "secure_cookies",
+    "connect_timeout_secs",
+    "pool_max_idle_per_host",
+    "pool_idle_timeout_secs",
+    "tcp_keepalive_secs",
+    "http2_prior_knowledge",
+    "allow_invalid_upstream_certs",
+    "upstream_ca_bundle",
+    "server_background_check_interval_secs",
+    "media_mapping_cache_capacity",
+    "media_mapping_cache_ttl_secs",
+    "play_session_ttl_secs",
+    "play_session_capacity",
+    "session_lifetime_secs",
+    "preconfigured_servers",
+    "startup_self_check",
+];
+
+/// The settings a reload changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadSummary {
+    /// Changed settings that are in effect now.
+    pub applied: Vec<String>,
+    /// Changed settings that keep their running value until a restart.
+    pub requires_restart: Vec<String>,
+}
+
+/// Shortest `session_key` the cookie signer accepts.
+pub const MIN_SESSION_KEY_LEN: usize = 64;
+
+/// A setting that can't work, found by [`AppConfig::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConfigError {
+    #[error("`host` '{0}' is not an IP address. Use e.g. `0.0.0.0` to listen on all interfaces or `127.0.0.1` for local access only.")]
+    InvalidHost(String),
+    #[error("`port` must not be 0.")]
+    InvalidPort,
+    #[error("`session_key` is {0} bytes long but at least {MIN_SESSION_KEY_LEN} are required. Remove `session_key` to have a new one generated.")]
+    SessionKeyTooShort(usize),
+    #[error(
+        "`url_prefix` '{0}' must be a single path segment without slashes, e.g. `jellyswarrm`."
+    )]
+    InvalidUrlPrefix(String),
+    #[error("Preconfigured server '{name}' has an invalid URL '{url}': {reason}")]
+    InvalidServerUrl {
+        name: String,
+        url: String,
+        reason: String,
+    },
+    #[error("`timeout` must be at least 1 second.")]
+    ZeroTimeout,
+}
+
+impl AppConfig {
+    /// The address to listen on, from `host` and `port`.
+    pub fn bind_address(&self) -> Result<SocketAddr, ConfigError> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let ip = host
+            .parse::<IpAddr>()
+            .map_err(|_| ConfigError::InvalidHost(self.host.clone()))?;
+        Ok(SocketAddr::new(ip, self.port))
+    }
+
+    /// Take over the settings of `reloaded` that are read while running. Settings in
+    /// [`RESTART_REQUIRED_SETTINGS`] keep their current value.
+    pub fn reload_from(&mut self, reloaded: AppConfig) -> Result<ReloadSummary, serde_json::Error> {
+        let current = serde_json::to_value(&*self)?;
+        let mut reloaded = serde_json::to_value(reloaded)?;
+        let mut summary = ReloadSummary::default();
+
+        if let (Some(current), Some(reloaded)) = (current.as_object(), reloaded.as_object_mut()) {
+            for (key, value) in reloaded.iter_mut() {
+                let Some(running) = current.get(key) else {
+                    continue;
+                };
+                if running == value {
+                    continue;
+                }
+                if RESTART_REQUIRED_SETTINGS.contains(&key.as_str()) {
+                    *value = running.clone();
+                    summary.requires_restart.push(key.clone());
+                } else {
+                    summary.applied.push(key.clone());
+                }
+            }
+        }
+
+        *self = serde_json::from_value(reloaded)?;
+        Ok(summary)
+    }
+
+    /// Check the settings that would otherwise fail late or be silently replaced.
+    /// Returns every problem found, so they can all be fixed at once.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+
+        if let Err(e) = self.bind_address() {
+            errors.push(e);
+        }
+        if self.port == 0 {
+            errors.push(ConfigError::InvalidPort);
+        }
+        if self.session_key.len() < MIN_SESSION_KEY_LEN {
+            errors.push(ConfigError::SessionKeyTooShort(self.session_key.len()));
+        }
+        if let Some(prefix) = &self.url_prefix {
+            if prefix.contains('/') {
+                errors.push(ConfigError::InvalidUrlPrefix(prefix.to_string()));
+            }
+        }
+        for server in &self.preconfigured_servers {
+            if let Err(e) = ServerUrl::parse(&server.url) {
+                errors.push(ConfigError::InvalidServerUrl {
+                    name: server.name.clone(),
+                    url: server.url.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+        if self.timeout == 0 {
+            errors.push(ConfigError::ZeroTimeout);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
 impl fmt::Debug for AppConfig {
@@ -326,19 +1513,96 @@ impl fmt::Debug for AppConfig {
             .field("username", &self.username)
             .field("password", &self.password)
             .field("preconfigured_servers", &self.preconfigured_servers)
+            .field("pinned_libraries", &self.pinned_libraries)
+            .field("watch_state_mirrors", &self.watch_state_mirrors)
+            .field("title_articles", &self.title_articles)
             .field("session_key", &session_key)
             .field("timeout", &self.timeout)
             .field("ui_route", &self.ui_route)
             .field("url_prefix", &self.url_prefix)
+            .field("public_base_url", &self.public_base_url)
+            .field("trust_forwarded_headers", &self.trust_forwarded_headers)
             .field("media_streaming_mode", &self.media_streaming_mode)
+            .field("secure_cookies", &self.secure_cookies)
+            .field("startup_self_check", &self.startup_self_check)
             .field(
                 "server_background_check_interval_secs",
                 &self.server_background_check_interval_secs,
             )
+            .field(
+                "media_mapping_cache_capacity",
+                &self.media_mapping_cache_capacity,
+            )
+            .field(
+                "media_mapping_cache_ttl_secs",
+                &self.media_mapping_cache_ttl_secs,
+            )
+            .field("play_session_ttl_secs", &self.play_session_ttl_secs)
+            .field("play_session_capacity", &self.play_session_capacity)
+            .field("session_lifetime_secs", &self.session_lifetime_secs)
+            .field(
+                "session_refresh_window_secs",
+                &self.session_refresh_window_secs,
+            )
+            .field("backup_dir", &self.backup_dir)
+            .field("backup_retention", &self.backup_retention)
             .field(
                 "auto_create_users_on_login",
                 &self.auto_create_users_on_login,
             )
+            .field("enrich_user_me", &self.enrich_user_me)
+            .field("merge_libraries", &self.merge_libraries)
+            .field("merge_box_sets", &self.merge_box_sets)
+            .field("merge_library_versions", &self.merge_library_versions)
+            .field("default_dedup_strategy", &self.default_dedup_strategy)
+            .field("refresh_all_copies", &self.refresh_all_copies)
+            .field("rate_all_copies", &self.rate_all_copies)
+            .field("mark_all_copies", &self.mark_all_copies)
+            .field("report_backend_version", &self.report_backend_version)
+            .field("upstream_error_details", &self.upstream_error_details)
+            .field("sanitize_buffered_ranges", &self.sanitize_buffered_ranges)
+            .field("box_set_duplicate_policy", &self.box_set_duplicate_policy)
+            .field("per_user_max_bitrate", &self.per_user_max_bitrate)
+            .field("upstream_retries", &self.upstream_retries)
+            .field("connect_timeout_secs", &self.connect_timeout_secs)
+            .field("pool_max_idle_per_host", &self.pool_max_idle_per_host)
+            .field("pool_idle_timeout_secs", &self.pool_idle_timeout_secs)
+            .field("tcp_keepalive_secs", &self.tcp_keepalive_secs)
+            .field("http2_prior_knowledge", &self.http2_prior_knowledge)
+            .field(
+                "allow_invalid_upstream_certs",
+                &self.allow_invalid_upstream_certs,
+            )
+            .field("upstream_ca_bundle", &self.upstream_ca_bundle)
+            .field("show_child_order", &self.show_child_order)
+            .field("json_full_parse_limit", &self.json_full_parse_limit)
+            .field("json_rewrite_mode", &self.json_rewrite_mode)
+            .field("tag_source_server", &self.tag_source_server)
+            .field("proxy_unknown_paths", &self.proxy_unknown_paths)
+            .field("quick_connect_mode", &self.quick_connect_mode)
+            .field(
+                "client_rate_limit_per_second",
+                &self.client_rate_limit_per_second,
+            )
+            .field("client_rate_limit_burst", &self.client_rate_limit_burst)
+            .field("dedup_mutations_window_ms", &self.dedup_mutations_window_ms)
+            .field(
+                "max_connections_per_server",
+                &self.max_connections_per_server,
+            )
+            .field("upstream_queue_timeout_ms", &self.upstream_queue_timeout_ms)
+            .field("disable_id_remapping", &self.disable_id_remapping)
+            .field("expose_federation_status", &self.expose_federation_status)
+            .field("unmapped_media_id", &self.unmapped_media_id)
+            .field(
+                "single_server_query_params",
+                &self.single_server_query_params,
+            )
+            .field("audit_unauthenticated", &self.audit_unauthenticated)
+            .field("enable_legacy_emby_auth", &self.enable_legacy_emby_auth)
+            .field("cors_allowed_origins", &self.cors_allowed_origins)
+            .field("cors_allow_credentials", &self.cors_allow_credentials)
+            .field("cors_allowed_headers", &self.cors_allowed_headers)
             .finish()
     }
 }
@@ -377,16 +1641,21 @@ pub fn load_config() -> AppConfig {
             .add_source(config::Environment::with_prefix("JELLYSWARRM").separator("_"))
     };
 
-    let config = match builder.build() {
-        Ok(c) => c.try_deserialize().unwrap_or_default(),
+    let (config, generated_server_id) = match builder.build() {
+        Ok(c) => {
+            let generated_server_id = c.get_string("server_id").is_err();
+            (c.try_deserialize().unwrap_or_default(), generated_server_id)
+        }
         Err(e) => {
             let config = AppConfig::default();
             eprintln!("Failed to load config using defaults: {e}");
-            config
+            (config, false)
         }
     };
 
-    if !path.exists() {
+    // Clients key their sessions off the server id, so a generated one is written
+    // back instead of being replaced by a new one on the next start.
+    if !path.exists() || generated_server_id {
         if let Err(e) = save_config(&config) {
             eprintln!("Failed to save default config to {path:?}: {e}");
         }
@@ -395,6 +1664,12 @@ pub fn load_config() -> AppConfig {
     config
 }
 
+/// Whether the admin password comes from `JELLYSWARRM_PASSWORD`, which overrides the
+/// config file on every start.
+pub fn admin_password_from_env() -> bool {
+    std::env::var_os("JELLYSWARRM_PASSWORD").is_some()
+}
+
 /// Persist configuration to the first existing file or the primary default file.
 pub fn save_config(cfg: &AppConfig) -> std::io::Result<()> {
     let toml_str = toml::to_string_pretty(cfg).map_err(std::io::Error::other)?;
@@ -489,3 +1764,154 @@ impl<'de> serde::Deserialize<'de> for UrlSegment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invalid(config: AppConfig, expected: ConfigError) {
+        assert_eq!(config.validate(), Err(vec![expected]));
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(AppConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn host_must_be_an_ip_address() {
+        assert_invalid(
+            AppConfig {
+                host: "localhost:8096".to_string(),
+                ..AppConfig::default()
+            },
+            ConfigError::InvalidHost("localhost:8096".to_string()),
+        );
+
+        let config = AppConfig {
+            host: "::".to_string(),
+            port: 8096,
+            ..AppConfig::default()
+        };
+        assert_eq!(config.bind_address(), Ok("[::]:8096".parse().unwrap()));
+    }
+
+    #[test]
+    fn port_must_not_be_zero() {
+        assert_invalid(
+            AppConfig {
+                port: 0,
+                ..AppConfig::default()
+            },
+            ConfigError::InvalidPort,
+        );
+    }
+
+    #[test]
+    fn session_key_must_be_long_enough_for_cookies() {
+        assert_invalid(
+            AppConfig {
+                session_key: vec![7; 32],
+                ..AppConfig::default()
+            },
+            ConfigError::SessionKeyTooShort(32),
+        );
+        assert_invalid(
+            AppConfig {
+                session_key: Vec::new(),
+                ..AppConfig::default()
+            },
+            ConfigError::SessionKeyTooShort(0),
+        );
+    }
+
+    #[test]
+    fn url_prefix_must_be_a_single_segment() {
+        assert_invalid(
+            AppConfig {
+                url_prefix: Some("media/jellyswarrm".into()),
+                ..AppConfig::default()
+            },
+            ConfigError::InvalidUrlPrefix("media/jellyswarrm".to_string()),
+        );
+
+        let config = AppConfig {
+            url_prefix: Some("/jellyswarrm/".into()),
+            ..AppConfig::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn preconfigured_server_urls_must_parse() {
+        let config = AppConfig {
+            preconfigured_servers: vec![PreconfiguredServer {
+                url: "movies.local".to_string(),
+                name: "Movies".to_string(),
+                priority: 100,
+                media_streaming_mode: MediaStreamingMode::Redirect,
+            }],
+            ..AppConfig::default()
+        };
+        let errors = config.validate().unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [ConfigError::InvalidServerUrl { name, .. }] if name == "Movies"
+        ));
+    }
+
+    #[test]
+    fn timeout_must_be_positive() {
+        assert_invalid(
+            AppConfig {
+                timeout: 0,
+                ..AppConfig::default()
+            },
+            ConfigError::ZeroTimeout,
+        );
+    }
+
+    #[test]
+    fn all_problems_are_reported_together() {
+        let config = AppConfig {
+            port: 0,
+            timeout: 0,
+            ..AppConfig::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(vec![ConfigError::InvalidPort, ConfigError::ZeroTimeout])
+        );
+    }
+
+    #[test]
+    fn reloads_keep_settings_that_need_a_restart() {
+        let mut running = AppConfig::default();
+        let reloaded = AppConfig {
+            include_server_name_in_media: ServerNameSuffixMode::Never,
+            cors_allowed_origins: vec!["https://app.example".to_string()],
+            port: running.port + 1,
+            url_prefix: Some("media".into()),
+            ..running.clone()
+        };
+
+        let summary = running.reload_from(reloaded).unwrap();
+        assert_eq!(
+            summary,
+            ReloadSummary {
+                applied: vec![
+                    "cors_allowed_origins".to_string(),
+                    "include_server_name_in_media".to_string(),
+                ],
+                requires_restart: vec!["port".to_string(), "url_prefix".to_string()],
+            }
+        );
+        assert_eq!(
+            running.include_server_name_in_media,
+            ServerNameSuffixMode::Never
+        );
+        assert_eq!(running.cors_allowed_origins, ["https://app.example"]);
+        assert_eq!(running.port, AppConfig::default().port);
+        assert_eq!(running.url_prefix, None);
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::Mutex;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer, ExposeHeaders};
+use tracing::warn;
+
+use crate::{config::AppConfig, AppState};
+
+/// The `cors_*` settings a [`CorsLayer`] was built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CorsSettings {
+    allowed_origins: Vec<String>,
+    allowed_headers: Vec<String>,
+    allow_credentials: bool,
+}
+
+impl CorsSettings {
+    fn of(config: &AppConfig) -> Self {
+        Self {
+            allowed_origins: config.cors_allowed_origins.clone(),
+            allowed_headers: config.cors_allowed_headers.clone(),
+            allow_credentials: config.cors_allow_credentials,
+        }
+    }
+}
+
+/// The CORS layer of the current settings, rebuilt when a config reload changed them.
+#[derive(Debug, Default)]
+pub struct CorsCache(Mutex<Option<(CorsSettings, CorsLayer)>>);
+
+impl CorsCache {
+    fn layer(&self, config: &AppConfig) -> CorsLayer {
+        let settings = CorsSettings::of(config);
+        let mut cached = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match cached.as_ref() {
+            Some((built_from, layer)) if *built_from == settings => layer.clone(),
+            _ => {
+                let layer = cors_layer(config);
+                *cached = Some((settings, layer.clone()));
+                layer
+            }
+        }
+    }
+}
+
+/// Apply the CORS settings of the running config, so reloading them takes effect
+/// without a restart.
+pub async fn reloadable_cors(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let layer = state.cors.layer(&*state.config.read().await);
+    match layer.layer(next).oneshot(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+/// Build the CORS layer from the `cors_*` settings. The defaults behave like
+/// [`CorsLayer::permissive`].
+///
+/// Browsers reject a wildcard together with credentials, so when credentials are
+/// allowed every wildcard is replaced by echoing what the request asked for.
+pub fn cors_layer(config: &AppConfig) -> CorsLayer {
+    let credentials = config.cors_allow_credentials;
+
+    let origins = parse_list::<HeaderValue>(&config.cors_allowed_origins, "origin");
+    let allow_origin = match origins {
+        Some(origins) => AllowOrigin::list(origins),
+        None if credentials => AllowOrigin::mirror_request(),
+        None => AllowOrigin::any(),
+    };
+
+    let headers = parse_list::<HeaderName>(&config.cors_allowed_headers, "header");
+    let allow_headers = match headers {
+        Some(headers) => AllowHeaders::list(headers),
+        None if credentials => AllowHeaders::mirror_request(),
+        None => AllowHeaders::any(),
+    };
+
+    let layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_headers(allow_headers)
+        .allow_credentials(credentials);
+    if credentials {
+        layer.allow_methods(AllowMethods::mirror_request())
+    } else {
+        layer
+            .allow_methods(Any)
+            .expose_headers(ExposeHeaders::any())
+    }
+}
+
+/// The configured values, or `None` when the list is empty or contains `*`.
+/// Values that aren't valid in a header are skipped.
+fn parse_list<T>(values: &[String], kind: &str) -> Option<Vec<T>>
+where
+    T: TryFrom<String>,
+{
+    if values.is_empty() || values.iter().any(|value| value.trim() == "*") {
+        return None;
+    }
+
+    let parsed = values
+        .iter()
+        .filter_map(|value| {
+            T::try_from(value.trim().to_string())
+                .inspect_err(|_| warn!("Ignoring invalid CORS {}: '{}'", kind, value))
+                .ok()
+        })
+        .collect();
+    Some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    async fn preflight(config: &AppConfig, origin: &str) -> axum::http::HeaderMap {
+        let router = Router::new()
+            .route("/Items", get(|| async { "ok" }))
+            .layer(cors_layer(config));
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .method(Method::OPTIONS)
+                    .uri("/Items")
+                    .header(header::ORIGIN, origin)
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                    .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-emby-token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.headers().clone()
+    }
+
+    #[tokio::test]
+    async fn defaults_allow_any_origin() {
+        let headers = preflight(&AppConfig::default(), "https://app.example").await;
+
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS], "*");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+
+    #[tokio::test]
+    async fn credentials_echo_the_request_origin() {
+        let config = AppConfig {
+            cors_allow_credentials: true,
+            ..AppConfig::default()
+        };
+        let headers = preflight(&config, "https://app.example").await;
+
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "x-emby-token"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET");
+    }
+
+    #[tokio::test]
+    async fn only_listed_origins_are_allowed() {
+        let config = AppConfig {
+            cors_allowed_origins: vec!["https://app.example".to_string()],
+            cors_allowed_headers: vec!["X-Emby-Token".to_string(), "Content-Type".to_string()],
+            ..AppConfig::default()
+        };
+
+        let headers = preflight(&config, "https://app.example").await;
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "x-emby-token,content-type"
+        );
+
+        let headers = preflight(&config, "https://other.example").await;
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
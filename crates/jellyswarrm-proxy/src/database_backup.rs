@@ -0,0 +1,154 @@
+//! Database backups, written to `backup_dir` with a bounded number kept.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+
+use crate::{config::DATA_DIR, server_storage::ServerStorageService};
+
+const BACKUP_PREFIX: &str = "jellyswarrm-";
+const BACKUP_SUFFIX: &str = ".db";
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("Failed to prepare the backup directory: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to write the backup: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// The backup directory configured as `backup_dir`.
+pub fn backup_dir(configured: &str) -> PathBuf {
+    DATA_DIR.join(configured)
+}
+
+/// Backup file names sort by the time they were taken.
+pub fn backup_file_name(taken_at: DateTime<Utc>) -> String {
+    format!(
+        "{BACKUP_PREFIX}{}{BACKUP_SUFFIX}",
+        taken_at.format("%Y%m%d-%H%M%S%.3f")
+    )
+}
+
+fn is_backup_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_SUFFIX))
+}
+
+/// Snapshot the database into a new file in `dir`, then delete all but the newest
+/// `retention` backups there (`0` keeps all). Returns the path of the new backup.
+pub async fn create_backup(
+    server_storage: &ServerStorageService,
+    dir: &Path,
+    retention: u32,
+) -> Result<PathBuf, BackupError> {
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(backup_file_name(Utc::now()));
+    server_storage.backup_database(&path).await?;
+    info!("Wrote database backup to {}", path.display());
+
+    if retention > 0 {
+        match prune_backups(dir, retention as usize).await {
+            Ok(removed) if !removed.is_empty() => {
+                info!("Removed {} old database backups", removed.len())
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to remove old database backups: {}", e),
+        }
+    }
+    Ok(path)
+}
+
+/// Delete all but the newest `keep` backups in `dir`. Other files are left alone.
+pub async fn prune_backups(dir: &Path, keep: usize) -> std::io::Result<Vec<PathBuf>> {
+    let mut backups = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if is_backup_file(&path) {
+            backups.push(path);
+        }
+    }
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(keep);
+    let mut removed = Vec::new();
+    for path in backups.into_iter().take(excess) {
+        tokio::fs::remove_file(&path).await?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MediaStreamingMode, MIGRATOR};
+    use sqlx::{
+        sqlite::{SqliteConnectOptions, SqliteJournalMode},
+        SqlitePool,
+    };
+
+    #[tokio::test]
+    async fn backups_are_readable_snapshots_of_the_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(dir.path().join("jellyswarrm.db"))
+                .create_if_missing(true)
+                .journal_mode(SqliteJournalMode::Wal),
+        )
+        .await
+        .unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        let server_storage = ServerStorageService::new(pool);
+        server_storage
+            .add_server(
+                "Movies",
+                "http://movies.local:8096",
+                100,
+                MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+
+        let path = create_backup(&server_storage, &dir.path().join("backups"), 7)
+            .await
+            .unwrap();
+
+        let backup = SqlitePool::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        let servers = ServerStorageService::new(backup)
+            .list_servers()
+            .await
+            .unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].name, "Movies");
+    }
+
+    #[tokio::test]
+    async fn pruning_keeps_the_newest_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = Utc::now();
+        let mut names = Vec::new();
+        for minutes in 0..4 {
+            let name = backup_file_name(start + chrono::Duration::minutes(minutes));
+            std::fs::write(dir.path().join(&name), b"").unwrap();
+            names.push(name);
+        }
+        std::fs::write(dir.path().join("notes.txt"), b"").unwrap();
+
+        let removed = prune_backups(dir.path(), 2).await.unwrap();
+        assert_eq!(
+            removed,
+            vec![dir.path().join(&names[0]), dir.path().join(&names[1])]
+        );
+        for name in &names[2..] {
+            assert!(dir.path().join(name).exists());
+        }
+        assert!(dir.path().join("notes.txt").exists());
+    }
+}
@@ -1,11 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 
 use crate::{
-    models::{enums::BaseItemKind, MediaItem},
+    config::DeduplicationStrategy,
+    models::{enums::BaseItemKind, MediaItem, UserData},
     server_id::ServerId,
     server_storage::Server,
 };
@@ -60,6 +62,10 @@ impl fmt::Display for DuplicatePolicy {
     }
 }
 
+/// Prefix of the merge key under which identically named box sets share a
+/// merged library entry.
+pub const BOX_SET_MERGE_KEY_PREFIX: &str = "boxset:";
+
 #[derive(Debug, Clone)]
 pub struct DuplicatePolicyConfig {
     pub policy: DuplicatePolicy,
@@ -75,11 +81,12 @@ pub struct TaggedMediaItem {
 pub fn apply_duplicate_policy(
     items: Vec<TaggedMediaItem>,
     config: &DuplicatePolicyConfig,
+    titles: &TitleNormalizer,
 ) -> Vec<MediaItem> {
     let mut group_indexes: HashMap<String, usize> = HashMap::new();
     let mut groups: Vec<Vec<TaggedMediaItem>> = Vec::new();
     for tagged in items {
-        let key = duplicate_key(&tagged.item);
+        let key = duplicate_key(&tagged.item, titles);
         if let Some(&index) = group_indexes.get(&key) {
             groups[index].push(tagged);
         } else {
@@ -94,6 +101,264 @@ pub fn apply_duplicate_policy(
         .collect()
 }
 
+/// Collapse playable items that `strategy` considers the same title into one entry
+/// whose `MediaSources` hold every backend's versions.
+///
+/// The canonical item, with its runtime and streams, comes from the highest-priority
+/// server with a playable copy rather than a placeholder; the other copies only
+/// contribute their media sources, which are labelled with their server's name so
+/// clients can tell the versions apart.
+pub fn merge_duplicate_versions(
+    items: Vec<TaggedMediaItem>,
+    strategy: DeduplicationStrategy,
+    titles: &TitleNormalizer,
+) -> Vec<TaggedMediaItem> {
+    if strategy == DeduplicationStrategy::Off {
+        return items;
+    }
+
+    group_versions(items, strategy, titles)
+        .into_iter()
+        .map(merge_version_group)
+        .collect()
+}
+
+/// What [`merge_duplicate_versions`] would make of `items`, without merging them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionMergePreview {
+    pub total_items: usize,
+    pub merged_items: usize,
+    pub collapsed_items: usize,
+    /// Names of titles that several copies would be merged into, at most the
+    /// requested sample size.
+    pub sample: Vec<String>,
+}
+
+pub fn preview_version_merge(
+    items: Vec<TaggedMediaItem>,
+    strategy: DeduplicationStrategy,
+    titles: &TitleNormalizer,
+    sample_size: usize,
+) -> VersionMergePreview {
+    let total_items = items.len();
+    let groups = if strategy == DeduplicationStrategy::Off {
+        items.into_iter().map(|tagged| vec![tagged]).collect()
+    } else {
+        group_versions(items, strategy, titles)
+    };
+
+    VersionMergePreview {
+        total_items,
+        merged_items: groups.len(),
+        collapsed_items: total_items - groups.len(),
+        sample: groups
+            .into_iter()
+            .filter(|group| group.len() > 1)
+            .filter_map(|group| merge_version_group(group).item.name)
+            .take(sample_size)
+            .collect(),
+    }
+}
+
+/// `items` grouped by the titles `strategy` considers the same, in order of first
+/// appearance.
+fn group_versions(
+    items: Vec<TaggedMediaItem>,
+    strategy: DeduplicationStrategy,
+    titles: &TitleNormalizer,
+) -> Vec<Vec<TaggedMediaItem>> {
+    let mut group_indexes: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<Vec<TaggedMediaItem>> = Vec::new();
+    for tagged in items {
+        let keys = version_keys(&tagged.item, strategy, titles);
+        match keys.iter().find_map(|key| group_indexes.get(key).copied()) {
+            Some(index) => {
+                for key in keys {
+                    group_indexes.entry(key).or_insert(index);
+                }
+                groups[index].push(tagged);
+            }
+            None => {
+                for key in keys {
+                    group_indexes.insert(key, groups.len());
+                }
+                groups.push(vec![tagged]);
+            }
+        }
+    }
+
+    groups
+}
+
+/// Whether `strategy` treats `left` and `right` as versions of the same title.
+/// Provider id matching is used when `strategy` is `Off`.
+pub fn is_same_version(
+    left: &MediaItem,
+    right: &MediaItem,
+    strategy: DeduplicationStrategy,
+    titles: &TitleNormalizer,
+) -> bool {
+    let strategy = match strategy {
+        DeduplicationStrategy::Off => DeduplicationStrategy::ProviderIds,
+        strategy => strategy,
+    };
+    let right_keys = version_keys(right, strategy, titles);
+    version_keys(left, strategy, titles)
+        .iter()
+        .any(|key| right_keys.contains(key))
+}
+
+fn merge_version_group(mut group: Vec<TaggedMediaItem>) -> TaggedMediaItem {
+    if group.len() == 1 {
+        return group.remove(0);
+    }
+
+    group.sort_by(|left, right| {
+        has_playable_source(&right.item)
+            .cmp(&has_playable_source(&left.item))
+            .then_with(|| right.server.priority.cmp(&left.server.priority))
+            .then_with(|| left.server.name.cmp(&right.server.name))
+            .then_with(|| left.server.id.as_i64().cmp(&right.server.id.as_i64()))
+    });
+
+    let mut media_sources = Vec::new();
+    for tagged in &group {
+        for source in tagged.item.media_sources.iter().flatten() {
+            let mut source = source.clone();
+            source.name = Some(match source.name.as_deref() {
+                Some(name) if !name.is_empty() => format!("{name} [{}]", tagged.server.name),
+                _ => tagged.server.name.clone(),
+            });
+            media_sources.push(source);
+        }
+    }
+
+    let user_data = merged_user_data(group.iter().map(|tagged| &tagged.item));
+    let mut canonical = group.remove(0);
+    if !media_sources.is_empty() {
+        canonical.item.media_sources = Some(media_sources);
+    }
+    canonical.item.user_data = user_data;
+    canonical
+}
+
+/// The watch state of copies of the same title on several servers, as one: favorite
+/// or played on any server counts, the furthest position and latest play win and the
+/// play counts add up. `Key` and `ItemId` stay those of `left`, so the copy whose
+/// identity the result keeps goes first.
+pub fn merge_user_data(left: &UserData, right: &UserData) -> UserData {
+    let furthest = if right.playback_position_ticks > left.playback_position_ticks {
+        right
+    } else {
+        left
+    };
+    UserData {
+        playback_position_ticks: furthest.playback_position_ticks,
+        play_count: left.play_count.saturating_add(right.play_count),
+        is_favorite: left.is_favorite || right.is_favorite,
+        played: left.played || right.played,
+        key: left.key.clone(),
+        item_id: left.item_id.clone(),
+        played_percentage: furthest
+            .played_percentage
+            .or(left.played_percentage)
+            .or(right.played_percentage),
+        last_played_date: left
+            .last_played_date
+            .clone()
+            .max(right.last_played_date.clone()),
+        unplayed_item_count: match (left.unplayed_item_count, right.unplayed_item_count) {
+            (Some(left), Some(right)) => Some(left.min(right)),
+            (left, right) => left.or(right),
+        },
+    }
+}
+
+fn merged_user_data<'a>(items: impl Iterator<Item = &'a MediaItem>) -> Option<UserData> {
+    items
+        .filter_map(|item| item.user_data.as_ref())
+        .fold(None, |merged, user_data| {
+            Some(match merged {
+                Some(merged) => merge_user_data(&merged, user_data),
+                None => user_data.clone(),
+            })
+        })
+}
+
+fn version_keys(
+    item: &MediaItem,
+    strategy: DeduplicationStrategy,
+    titles: &TitleNormalizer,
+) -> Vec<String> {
+    if !matches!(
+        item.item_type,
+        BaseItemKind::Movie
+            | BaseItemKind::Episode
+            | BaseItemKind::Video
+            | BaseItemKind::MusicVideo
+    ) {
+        return Vec::new();
+    }
+
+    let keys = provider_version_keys(item);
+    if !keys.is_empty() || strategy != DeduplicationStrategy::NameYear {
+        return keys;
+    }
+
+    let name = normalized_name(item, titles);
+    if name.is_empty() {
+        return Vec::new();
+    }
+    let key = match item.item_type {
+        BaseItemKind::Episode => {
+            let series = item
+                .series_name
+                .as_deref()
+                .map(|series| titles.normalize(series))
+                .filter(|series| !series.is_empty())
+                .unwrap_or(name);
+            format!(
+                "title:{series}:s{}:e{}:{:?}",
+                episode_number(item, "ParentIndexNumber"),
+                episode_number(item, "IndexNumber"),
+                item.item_type
+            )
+        }
+        _ => format!(
+            "title:{name}:{}:{:?}",
+            production_year(item).unwrap_or_default(),
+            item.item_type
+        ),
+    };
+    vec![key]
+}
+
+fn provider_version_keys(item: &MediaItem) -> Vec<String> {
+    let Some(provider_ids) = item
+        .provider_ids
+        .as_ref()
+        .and_then(serde_json::Value::as_object)
+    else {
+        return Vec::new();
+    };
+
+    ["Tmdb", "Imdb", "Tvdb"]
+        .into_iter()
+        .filter_map(|provider| {
+            let id = provider_ids
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(provider))
+                .and_then(|(_, value)| value.as_str())
+                .filter(|id| !id.is_empty())?;
+            Some(format!(
+                "{}:{id}:{:?}",
+                provider.to_ascii_lowercase(),
+                item.item_type
+            ))
+        })
+        .collect()
+}
+
 fn select_from_duplicate_group(
     group: Vec<TaggedMediaItem>,
     config: &DuplicatePolicyConfig,
@@ -106,13 +371,40 @@ fn select_from_duplicate_group(
         return group.into_iter().map(item_with_server_suffix).collect();
     }
 
-    group
-        .into_iter()
-        .max_by(|left, right| {
-            compare_for_policy(config, left, right).then_with(|| left.item.id.cmp(&right.item.id))
+    let mut group = group;
+    let Some(survivor) = group
+        .iter()
+        .enumerate()
+        .max_by(|(_, left), (_, right)| {
+            has_playable_source(&left.item)
+                .cmp(&has_playable_source(&right.item))
+                .then_with(|| compare_for_policy(config, left, right))
+                .then_with(|| left.item.id.cmp(&right.item.id))
         })
-        .map(|tagged| vec![tagged.item])
-        .unwrap_or_default()
+        .map(|(index, _)| index)
+    else {
+        return Vec::new();
+    };
+    let mut item = group.swap_remove(survivor).item;
+    // The survivor goes first so the merged watch state keeps its `Key` and `ItemId`.
+    let user_data =
+        merged_user_data(std::iter::once(&item).chain(group.iter().map(|tagged| &tagged.item)));
+    item.user_data = user_data;
+    vec![item]
+}
+
+/// Whether `item` is backed by a file on its server, as opposed to a placeholder for
+/// something missing. Copies that are win over placeholders whatever the policy, so
+/// the surviving item's runtime and streams describe something playable.
+fn has_playable_source(item: &MediaItem) -> bool {
+    let is_virtual = ["LocationType", "locationType"]
+        .iter()
+        .any(|key| item.extra.get(*key).and_then(|value| value.as_str()) == Some("Virtual"));
+    !is_virtual
+        && item
+            .media_sources
+            .as_ref()
+            .is_none_or(|sources| !sources.is_empty())
 }
 
 fn compare_for_policy(
@@ -133,10 +425,20 @@ fn compare_for_policy(
             .server
             .priority
             .cmp(&right.server.priority)
-            .then_with(|| left.server.id.as_i64().cmp(&right.server.id.as_i64())),
+            .then_with(|| prefer_by_server_name(left, right)),
     }
 }
 
+/// Tie-break between equal priorities: the server whose name sorts first wins, so the
+/// choice stays put however the backends happen to answer.
+fn prefer_by_server_name(left: &TaggedMediaItem, right: &TaggedMediaItem) -> std::cmp::Ordering {
+    right
+        .server
+        .name
+        .cmp(&left.server.name)
+        .then_with(|| left.server.id.as_i64().cmp(&right.server.id.as_i64()))
+}
+
 fn prefer_server(
     left: &TaggedMediaItem,
     right: &TaggedMediaItem,
@@ -147,7 +449,7 @@ fn prefer_server(
             .server
             .priority
             .cmp(&right.server.priority)
-            .then_with(|| left.server.id.as_i64().cmp(&right.server.id.as_i64()));
+            .then_with(|| prefer_by_server_name(left, right));
     };
 
     let left_matches = left.server.id == preferred;
@@ -163,29 +465,72 @@ fn prefer_server(
     }
 }
 
-fn duplicate_key(item: &MediaItem) -> String {
+fn duplicate_key(item: &MediaItem, titles: &TitleNormalizer) -> String {
     if item.item_type == BaseItemKind::Episode {
-        return episode_duplicate_key(item);
+        return episode_duplicate_key(item, titles);
     }
 
     if let Some(provider) = provider_identity(item) {
         return format!("content:provider:{provider}:{:?}", item.item_type);
     }
 
-    let name = normalized_name(item);
-    let year = item
-        .production_year
-        .map(i64::from)
-        .or_else(|| {
-            item.extra
-                .get("ProductionYear")
-                .or_else(|| item.extra.get("productionYear"))
-                .and_then(serde_json::Value::as_i64)
-        })
-        .unwrap_or_default();
+    let name = normalized_name(item, titles);
+    let year = production_year(item).unwrap_or_default();
     format!("content:title:{name}:{year}:{:?}", item.item_type)
 }
 
+fn production_year(item: &MediaItem) -> Option<i64> {
+    item.production_year.map(i64::from).or_else(|| {
+        item.extra
+            .get("ProductionYear")
+            .or_else(|| item.extra.get("productionYear"))
+            .and_then(serde_json::Value::as_i64)
+    })
+}
+
+/// Merge key for box sets (collections) so identically named sets on different
+/// servers collapse into one entry. Returns `None` for every other item type.
+pub fn box_set_merge_key(item: &MediaItem, titles: &TitleNormalizer) -> Option<String> {
+    if item.item_type != BaseItemKind::BoxSet {
+        return None;
+    }
+
+    let name = item
+        .name
+        .as_deref()
+        .map(|name| titles.normalize(name))
+        .unwrap_or_default();
+    if name.is_empty() {
+        return None;
+    }
+    Some(format!("{BOX_SET_MERGE_KEY_PREFIX}{name}"))
+}
+
+/// Identities of a box set's children, used to tell whether two box sets with the
+/// same name hold the same collection: every provider id of a child plus its
+/// normalized title and production year.
+pub fn box_set_member_keys<'a>(
+    children: impl IntoIterator<Item = &'a MediaItem>,
+    titles: &TitleNormalizer,
+) -> HashSet<String> {
+    let mut keys = HashSet::new();
+    for child in children {
+        if let Some(provider_ids) = child.provider_ids.as_ref().and_then(|ids| ids.as_object()) {
+            for (provider, id) in provider_ids {
+                if let Some(id) = id.as_str().filter(|id| !id.is_empty()) {
+                    keys.insert(format!("provider:{}:{id}", provider.to_ascii_lowercase()));
+                }
+            }
+        }
+        let name = normalized_name(child, titles);
+        if !name.is_empty() {
+            let year = production_year(child).unwrap_or_default();
+            keys.insert(format!("title:{name}:{year}"));
+        }
+    }
+    keys
+}
+
 fn item_with_server_suffix(mut tagged: TaggedMediaItem) -> MediaItem {
     if matches!(
         tagged.item.item_type,
@@ -198,7 +543,7 @@ fn item_with_server_suffix(mut tagged: TaggedMediaItem) -> MediaItem {
     tagged.item
 }
 
-fn episode_duplicate_key(item: &MediaItem) -> String {
+fn episode_duplicate_key(item: &MediaItem, titles: &TitleNormalizer) -> String {
     if let Some(user_key) = item.user_data.as_ref().and_then(|data| {
         let key = data.key.trim();
         if key.is_empty() || key.chars().all(|character| character == '0') {
@@ -219,9 +564,9 @@ fn episode_duplicate_key(item: &MediaItem) -> String {
     let series = item
         .series_name
         .as_deref()
-        .map(normalize_title)
+        .map(|series| titles.normalize(series))
         .filter(|name| !name.is_empty())
-        .unwrap_or_else(|| normalized_name(item));
+        .unwrap_or_else(|| normalized_name(item, titles));
     let season = episode_number(item, "ParentIndexNumber");
     let episode = episode_number(item, "IndexNumber");
     format!("episode:fallback:{series}:s{season}:e{episode}")
@@ -256,38 +601,84 @@ fn provider_identity(item: &MediaItem) -> Option<String> {
     None
 }
 
-fn normalized_name(item: &MediaItem) -> String {
+fn normalized_name(item: &MediaItem, titles: &TitleNormalizer) -> String {
     let raw = item
         .sort_name
         .as_deref()
         .or(item.original_title.as_deref())
         .or(item.name.as_deref())
         .unwrap_or("");
-    normalize_title(raw)
+    titles.normalize(raw)
 }
 
-fn normalize_title(value: &str) -> String {
-    let value = value.trim();
-    let value = value
-        .rsplit_once('[')
-        .filter(|(_, suffix)| suffix.ends_with(']'))
-        .map(|(prefix, _)| prefix.trim_end())
-        .unwrap_or(value);
+/// Leading articles dropped from titles when no other list is configured.
+pub const DEFAULT_TITLE_ARTICLES: [&str; 3] = ["the", "a", "an"];
 
+/// Reduces titles to the comparison keys used to match items across servers.
+#[derive(Debug, Clone)]
+pub struct TitleNormalizer {
+    articles: HashSet<String>,
+}
+
+impl Default for TitleNormalizer {
+    fn default() -> Self {
+        Self::new(DEFAULT_TITLE_ARTICLES)
+    }
+}
+
+impl TitleNormalizer {
+    /// Build a normalizer that drops any of `articles`. They are folded like titles,
+    /// so `Él` and `l'` match `el` and `l`.
+    pub fn new<S: AsRef<str>>(articles: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            articles: articles
+                .into_iter()
+                .map(|article| fold(article.as_ref()).trim().to_string())
+                .filter(|article| !article.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Reduce a title to a comparison key: a trailing ` [ServerName]` suffix is
+    /// removed, accents are folded, case and punctuation are ignored, and a leading
+    /// article is dropped, including the library-sorted `Matrix, The` form.
+    pub fn normalize(&self, value: &str) -> String {
+        let value = value.trim();
+        let value = value
+            .rsplit_once('[')
+            .filter(|(_, suffix)| suffix.ends_with(']'))
+            .map(|(prefix, _)| prefix.trim_end())
+            .unwrap_or(value);
+        let value = value
+            .rsplit_once(',')
+            .filter(|(_, article)| self.articles.contains(fold(article).trim()))
+            .map(|(title, _)| title)
+            .unwrap_or(value);
+
+        let folded = fold(value);
+        let mut words = folded.split_whitespace().collect::<Vec<_>>();
+        if words.len() > 1 && self.articles.contains(words[0]) {
+            words.remove(0);
+        }
+        words.join(" ")
+    }
+}
+
+/// NFKD-fold `value`, dropping accents, lowercasing it and turning everything but
+/// letters and digits into spaces.
+fn fold(value: &str) -> String {
     value
-        .to_ascii_lowercase()
-        .chars()
+        .nfkd()
+        .filter(|character| !is_combining_mark(*character))
+        .flat_map(char::to_lowercase)
         .map(|character| {
-            if character.is_ascii_alphanumeric() {
+            if character.is_alphanumeric() {
                 character
             } else {
                 ' '
             }
         })
-        .collect::<String>()
-        .split_whitespace()
-        .collect::<Vec<_>>()
-        .join(" ")
+        .collect()
 }
 
 fn media_size(item: &MediaItem) -> i64 {
@@ -348,7 +739,10 @@ fn quality_score(item: &MediaItem) -> i64 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{config::MediaStreamingMode, server_url::ServerUrl};
+    use crate::{
+        config::{AuthorizationHeaderMode, MediaStreamingMode},
+        server_url::ServerUrl,
+    };
 
     fn tagged(
         server_id: i64,
@@ -374,12 +768,152 @@ mod tests {
                 url: ServerUrl::parse("http://example:8096").unwrap(),
                 priority,
                 media_streaming_mode: MediaStreamingMode::Redirect,
+                authorization_header_mode: AuthorizationHeaderMode::Normalize,
+                max_connections: None,
+                backend_id: None,
                 created_at: chrono::Utc::now(),
                 updated_at: chrono::Utc::now(),
             },
         }
     }
 
+    fn user_data(value: serde_json::Value) -> UserData {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn box_sets_share_members_by_provider_id_or_title() {
+        let titles = TitleNormalizer::default();
+        let child = |value: serde_json::Value| serde_json::from_value::<MediaItem>(value).unwrap();
+
+        let first = box_set_member_keys(
+            &[
+                child(
+                    serde_json::json!({ "Id": "1", "Name": "Alien", "Type": "Movie", "ProviderIds": { "Tmdb": "348" } }),
+                ),
+                child(
+                    serde_json::json!({ "Id": "2", "Name": "Aliens", "Type": "Movie", "ProductionYear": 1986 }),
+                ),
+            ],
+            &titles,
+        );
+        let by_provider = box_set_member_keys(
+            &[child(
+                serde_json::json!({ "Id": "3", "Name": "Alien (1979)", "Type": "Movie", "ProviderIds": { "tmdb": "348" } }),
+            )],
+            &titles,
+        );
+        let by_title = box_set_member_keys(
+            &[child(
+                serde_json::json!({ "Id": "4", "Name": "Aliens", "Type": "Movie", "ProductionYear": 1986 }),
+            )],
+            &titles,
+        );
+        let unrelated = box_set_member_keys(
+            &[child(
+                serde_json::json!({ "Id": "5", "Name": "Alien Nation", "Type": "Movie", "ProviderIds": { "Tmdb": "10128" } }),
+            )],
+            &titles,
+        );
+
+        assert!(!first.is_disjoint(&by_provider));
+        assert!(!first.is_disjoint(&by_title));
+        assert!(first.is_disjoint(&unrelated));
+    }
+
+    #[test]
+    fn user_data_of_duplicates_is_merged() {
+        let left = user_data(serde_json::json!({
+            "PlaybackPositionTicks": 1_000,
+            "PlayCount": 1,
+            "IsFavorite": false,
+            "Played": true,
+            "Key": "left-key",
+            "ItemId": "left",
+            "PlayedPercentage": 10.0,
+            "LastPlayedDate": "2024-01-01T00:00:00Z",
+            "UnplayedItemCount": 3
+        }));
+        let right = user_data(serde_json::json!({
+            "PlaybackPositionTicks": 5_000,
+            "PlayCount": 2,
+            "IsFavorite": true,
+            "Played": false,
+            "Key": "right-key",
+            "ItemId": "right",
+            "PlayedPercentage": 50.0,
+            "LastPlayedDate": "2024-03-01T00:00:00Z",
+            "UnplayedItemCount": 1
+        }));
+
+        let merged = merge_user_data(&left, &right);
+
+        assert!(merged.is_favorite);
+        assert!(merged.played);
+        assert_eq!(merged.playback_position_ticks, 5_000);
+        assert_eq!(merged.played_percentage, Some(50.0));
+        assert_eq!(merged.play_count, 3);
+        assert_eq!(
+            merged.last_played_date.as_deref(),
+            Some("2024-03-01T00:00:00Z")
+        );
+        assert_eq!(merged.unplayed_item_count, Some(1));
+        assert_eq!(merged.key, "left-key");
+        assert_eq!(merged.item_id, "left");
+
+        let unwatched = user_data(serde_json::json!({
+            "PlaybackPositionTicks": 0,
+            "PlayCount": 0,
+            "IsFavorite": false,
+            "Played": false,
+            "Key": "key",
+            "ItemId": "id"
+        }));
+        let merged = merge_user_data(&unwatched, &unwatched);
+        assert!(!merged.is_favorite);
+        assert!(!merged.played);
+        assert_eq!(merged.play_count, 0);
+    }
+
+    #[test]
+    fn surviving_duplicate_carries_the_merged_user_data() {
+        let mut small = tagged(1, 100, "Movie", 10, "1");
+        let mut large = tagged(2, 50, "Movie", 20, "1");
+        small.item.user_data = Some(user_data(serde_json::json!({
+            "PlaybackPositionTicks": 0,
+            "PlayCount": 1,
+            "IsFavorite": true,
+            "Played": true,
+            "Key": "small",
+            "ItemId": "1-Movie"
+        })));
+        large.item.user_data = Some(user_data(serde_json::json!({
+            "PlaybackPositionTicks": 0,
+            "PlayCount": 0,
+            "IsFavorite": false,
+            "Played": false,
+            "Key": "large",
+            "ItemId": "2-Movie"
+        })));
+        let config = DuplicatePolicyConfig {
+            policy: DuplicatePolicy::LargestSize,
+            preferred_server_id: None,
+        };
+
+        let items =
+            apply_duplicate_policy(vec![small, large], &config, &TitleNormalizer::default());
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, "2-Movie");
+        let user_data = items[0].user_data.as_ref().unwrap();
+        assert!(user_data.is_favorite);
+        assert!(user_data.played);
+        assert_eq!(user_data.play_count, 1);
+        // The survivor is the second copy; its watch state keeps its own identity.
+        assert_eq!(user_data.key, "large");
+        assert_eq!(user_data.item_id, "2-Movie");
+    }
+
     #[test]
     fn keeps_largest_duplicate() {
         let items = vec![
@@ -392,6 +926,7 @@ mod tests {
                 policy: DuplicatePolicy::LargestSize,
                 preferred_server_id: None,
             },
+            &TitleNormalizer::default(),
         );
         assert_eq!(result.len(), 1);
         assert_eq!(result[0].id, "2-Movie");
@@ -430,6 +965,7 @@ mod tests {
                 policy: DuplicatePolicy::ShowAll,
                 preferred_server_id: None,
             },
+            &TitleNormalizer::default(),
         );
         assert_eq!(result.len(), 2);
         assert_eq!(
@@ -452,6 +988,7 @@ mod tests {
                 policy: DuplicatePolicy::ServerPriority,
                 preferred_server_id: None,
             },
+            &TitleNormalizer::default(),
         );
 
         assert_eq!(result.len(), 2);
@@ -472,6 +1009,7 @@ mod tests {
                 policy: DuplicatePolicy::LargestSize,
                 preferred_server_id: None,
             },
+            &TitleNormalizer::default(),
         );
 
         assert_eq!(result.len(), 2);
@@ -490,8 +1028,264 @@ mod tests {
                 policy: DuplicatePolicy::LargestSize,
                 preferred_server_id: None,
             },
+            &TitleNormalizer::default(),
         );
 
         assert_eq!(result[0].id, "2-Movie");
     }
+
+    fn versioned(server: Server, id: &str, provider_ids: serde_json::Value) -> TaggedMediaItem {
+        let item: MediaItem = serde_json::from_value(serde_json::json!({
+            "Id": id,
+            "Name": "Heat",
+            "Type": "Movie",
+            "ProviderIds": provider_ids,
+            "MediaSources": [{ "Id": id, "Name": "Heat" }]
+        }))
+        .unwrap();
+        TaggedMediaItem { item, server }
+    }
+
+    #[test]
+    fn shared_imdb_id_collapses_into_one_item_with_every_media_source() {
+        let low = tagged(1, 50, "x", 1, "unused").server;
+        let high = tagged(2, 100, "x", 1, "unused").server;
+        let other = tagged(3, 10, "x", 1, "unused").server;
+
+        let merged = merge_duplicate_versions(
+            vec![
+                versioned(low, "low-heat", serde_json::json!({ "Imdb": "tt0113277" })),
+                versioned(
+                    high,
+                    "high-heat",
+                    serde_json::json!({ "Tmdb": "949", "imdb": "tt0113277" }),
+                ),
+                versioned(other, "remake", serde_json::json!({ "Imdb": "tt9999999" })),
+            ],
+            DeduplicationStrategy::ProviderIds,
+            &TitleNormalizer::default(),
+        );
+
+        assert_eq!(merged.len(), 2);
+        let heat = &merged[0];
+        assert_eq!(heat.item.id, "high-heat");
+        assert_eq!(heat.server.id, ServerId::new(2));
+        let sources = heat
+            .item
+            .media_sources
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|source| (source.id.as_str(), source.name.as_deref().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                ("high-heat", "Heat [Server 2]"),
+                ("low-heat", "Heat [Server 1]")
+            ]
+        );
+        assert_eq!(merged[1].item.id, "remake");
+    }
+
+    fn normalize_title(value: &str) -> String {
+        TitleNormalizer::default().normalize(value)
+    }
+
+    #[test]
+    fn normalize_title_ignores_articles_accents_and_punctuation() {
+        assert_eq!(normalize_title("The Matrix"), "matrix");
+        assert_eq!(normalize_title("Matrix, The"), "matrix");
+        assert_eq!(normalize_title("  THE MATRIX!  "), "matrix");
+        assert_eq!(normalize_title("Amélie"), "amelie");
+        assert_eq!(normalize_title("Pokémon: The Movie"), "pokemon the movie");
+        assert_eq!(normalize_title("Léon [Server 2]"), "leon");
+        assert_eq!(normalize_title("An American Werewolf"), "american werewolf");
+        assert_eq!(normalize_title("The"), "the");
+        assert_eq!(
+            normalize_title("Crouching Tiger, Hidden Dragon"),
+            "crouching tiger hidden dragon"
+        );
+    }
+
+    #[test]
+    fn configured_articles_match_titles_across_locales() {
+        let titles = TitleNormalizer::new(["the", "der", "die", "das", "le", "la", "l'", "el"]);
+        assert_eq!(titles.normalize("Das Boot"), titles.normalize("Boot, Das"));
+        assert_eq!(titles.normalize("L'Odyssée"), "odyssee");
+        assert_eq!(titles.normalize("La Haine"), titles.normalize("HAINE, LA"));
+        assert_eq!(
+            titles.normalize("El laberinto del fauno"),
+            titles.normalize("Laberinto del Fauno")
+        );
+        assert_eq!(titles.normalize("The Matrix"), "matrix");
+        assert_eq!(titles.normalize("A Quiet Place"), "a quiet place");
+    }
+
+    #[test]
+    fn accented_and_punctuated_copies_share_a_duplicate_key() {
+        let titles = TitleNormalizer::default();
+        let with_name = |name: &str| -> MediaItem {
+            serde_json::from_value(serde_json::json!({
+                "Id": name,
+                "Name": name,
+                "Type": "Movie",
+                "ProductionYear": 2001
+            }))
+            .unwrap()
+        };
+
+        assert_eq!(
+            duplicate_key(&with_name("Amélie"), &titles),
+            duplicate_key(&with_name("Amelie"), &titles)
+        );
+        assert_eq!(
+            duplicate_key(&with_name("Crouching Tiger, Hidden Dragon"), &titles),
+            duplicate_key(&with_name("Crouching Tiger: Hidden Dragon!"), &titles)
+        );
+        assert_eq!(
+            duplicate_key(&with_name("The Others"), &titles),
+            duplicate_key(&with_name("Others, The"), &titles)
+        );
+    }
+
+    fn unidentified(server: Server, id: &str, name: &str, year: i32) -> TaggedMediaItem {
+        let item: MediaItem = serde_json::from_value(serde_json::json!({
+            "Id": id,
+            "Name": name,
+            "Type": "Movie",
+            "ProductionYear": year,
+            "MediaSources": [{ "Id": id, "Name": name }]
+        }))
+        .unwrap();
+        TaggedMediaItem { item, server }
+    }
+
+    #[test]
+    fn name_year_strategy_merges_titles_without_provider_ids() {
+        let items = || {
+            vec![
+                unidentified(
+                    tagged(1, 50, "x", 1, "unused").server,
+                    "low",
+                    "The Matrix",
+                    1999,
+                ),
+                unidentified(
+                    tagged(2, 100, "x", 1, "unused").server,
+                    "high",
+                    "Matrix, The",
+                    1999,
+                ),
+                unidentified(
+                    tagged(3, 10, "x", 1, "unused").server,
+                    "sequel",
+                    "The Matrix",
+                    2021,
+                ),
+            ]
+        };
+
+        let titles = TitleNormalizer::default();
+        let by_provider =
+            merge_duplicate_versions(items(), DeduplicationStrategy::ProviderIds, &titles);
+        assert_eq!(by_provider.len(), 3);
+
+        let merged = merge_duplicate_versions(items(), DeduplicationStrategy::NameYear, &titles);
+        let ids = merged
+            .iter()
+            .map(|tagged| tagged.item.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["high", "sequel"]);
+        assert_eq!(merged[0].item.media_sources.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn playable_copy_wins_over_a_stub_on_a_higher_priority_server() {
+        let stub: MediaItem = serde_json::from_value(serde_json::json!({
+            "Id": "stub",
+            "Name": "Heat",
+            "Type": "Movie",
+            "LocationType": "Virtual",
+            "ProviderIds": { "Tmdb": "949" },
+            "MediaSources": []
+        }))
+        .unwrap();
+        let full: MediaItem = serde_json::from_value(serde_json::json!({
+            "Id": "full",
+            "Name": "Heat",
+            "Type": "Movie",
+            "RunTimeTicks": 102_000_000_000_i64,
+            "ProviderIds": { "Tmdb": "949" },
+            "MediaSources": [{ "Id": "full", "Size": 4_000 }],
+            "MediaStreams": [{ "Type": "Video", "Index": 0 }]
+        }))
+        .unwrap();
+        let items = || {
+            vec![
+                TaggedMediaItem {
+                    item: stub.clone(),
+                    server: tagged(1, 100, "x", 1, "unused").server,
+                },
+                TaggedMediaItem {
+                    item: full.clone(),
+                    server: tagged(2, 10, "x", 1, "unused").server,
+                },
+            ]
+        };
+        let titles = TitleNormalizer::default();
+
+        let merged = merge_duplicate_versions(items(), DeduplicationStrategy::ProviderIds, &titles);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].item.id, "full");
+        assert_eq!(merged[0].item.run_time_ticks, Some(102_000_000_000));
+        assert_eq!(merged[0].item.media_streams.as_ref().unwrap().len(), 1);
+        assert_eq!(merged[0].item.media_sources.as_ref().unwrap().len(), 1);
+
+        let selected = apply_duplicate_policy(
+            items(),
+            &DuplicatePolicyConfig {
+                policy: DuplicatePolicy::ServerPriority,
+                preferred_server_id: None,
+            },
+            &titles,
+        );
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].id, "full");
+    }
+
+    #[test]
+    fn preview_counts_what_each_strategy_would_collapse() {
+        let items = || {
+            vec![
+                tagged(1, 100, "Heat", 1, "949"),
+                tagged(2, 50, "Heat", 1, "949"),
+                unidentified(tagged(1, 100, "x", 1, "unused").server, "a", "Alien", 1979),
+                unidentified(tagged(2, 50, "x", 1, "unused").server, "b", "Alien", 1979),
+                unidentified(tagged(2, 50, "x", 1, "unused").server, "c", "Aliens", 1986),
+            ]
+        };
+        let titles = TitleNormalizer::default();
+
+        let off = preview_version_merge(items(), DeduplicationStrategy::Off, &titles, 10);
+        assert_eq!(
+            (off.total_items, off.merged_items, off.collapsed_items),
+            (5, 5, 0)
+        );
+
+        let by_provider =
+            preview_version_merge(items(), DeduplicationStrategy::ProviderIds, &titles, 10);
+        assert_eq!(by_provider.merged_items, 4);
+        assert_eq!(by_provider.collapsed_items, 1);
+        assert_eq!(by_provider.sample, ["Heat"]);
+
+        let by_name = preview_version_merge(items(), DeduplicationStrategy::NameYear, &titles, 1);
+        assert_eq!(by_name.merged_items, 3);
+        assert_eq!(by_name.collapsed_items, 2);
+        assert_eq!(by_name.sample, ["Heat"]);
+        assert_eq!(
+            by_name.merged_items,
+            merge_duplicate_versions(items(), DeduplicationStrategy::NameYear, &titles).len()
+        );
+    }
 }
@@ -0,0 +1,377 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+
+use crate::{AppState, REQUEST_ID_HEADER};
+
+/// Status a backend answered with, added to error responses when
+/// `upstream_error_details` is enabled.
+pub const UPSTREAM_STATUS_HEADER: &str = "x-jellyswarrm-upstream-status";
+/// Name of the server an error response came from.
+pub const UPSTREAM_SERVER_HEADER: &str = "x-jellyswarrm-server";
+
+/// Longest backend error message included in a problem `detail`.
+const MAX_UPSTREAM_DETAIL_LEN: usize = 512;
+
+/// Marks a response whose status and body came from a backend, so its error
+/// body reaches the client unchanged.
+#[derive(Clone, Copy)]
+pub struct UpstreamResponse;
+
+/// A backend error a handler turned into one of its own error statuses.
+#[derive(Debug, Clone)]
+pub struct UpstreamFailure {
+    pub status: reqwest::StatusCode,
+    pub url: url::Url,
+    pub body: String,
+}
+
+type UpstreamFailureSlot = Arc<Mutex<Option<UpstreamFailure>>>;
+
+tokio::task_local! {
+    static UPSTREAM_FAILURE: UpstreamFailureSlot;
+}
+
+/// Remember the backend error behind the response being built, so
+/// [`jellyfin_error_bodies`] can report it. Does nothing outside of a request.
+pub fn record_upstream_failure(failure: UpstreamFailure) {
+    let _ = UPSTREAM_FAILURE.try_with(|slot| {
+        if let Ok(mut slot) = slot.lock() {
+            *slot = Some(failure);
+        }
+    });
+}
+
+/// Add the diagnostic headers naming the server and status behind an error.
+pub fn insert_upstream_headers(headers: &mut header::HeaderMap, status: u16, server_name: &str) {
+    headers.insert(UPSTREAM_STATUS_HEADER, HeaderValue::from(status));
+    if let Ok(value) = HeaderValue::from_str(server_name) {
+        headers.insert(UPSTREAM_SERVER_HEADER, value);
+    }
+}
+
+/// Error body in the `ProblemDetails` shape Jellyfin uses for its own errors.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProblemDetails<'a> {
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+    trace_id: &'a str,
+}
+
+/// Build a Jellyfin-style JSON error response for an error raised by the proxy.
+pub fn problem_response(status: StatusCode, request_id: &str) -> Response {
+    problem_response_with_detail(status, request_id, problem_detail(status))
+}
+
+pub fn problem_response_with_detail(
+    status: StatusCode,
+    request_id: &str,
+    detail: &str,
+) -> Response {
+    let problem = ProblemDetails {
+        title: status.canonical_reason().unwrap_or("Error"),
+        status: status.as_u16(),
+        detail,
+        trace_id: request_id,
+    };
+    let body = serde_json::to_vec(&problem).unwrap_or_default();
+
+    let mut response = (status, body).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+fn problem_detail(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "Jellyswarrm could not process the request.",
+        StatusCode::UNAUTHORIZED => "The request is not authenticated with Jellyswarrm.",
+        StatusCode::FORBIDDEN => "The user is not allowed to access this resource.",
+        StatusCode::NOT_FOUND => "The requested resource was not found on any server.",
+        StatusCode::BAD_GATEWAY => "The server holding this item could not be reached.",
+        StatusCode::SERVICE_UNAVAILABLE => "No server is available to handle the request.",
+        StatusCode::GATEWAY_TIMEOUT => "The server holding this item did not answer in time.",
+        _ if status.is_server_error() => "Jellyswarrm failed to handle the request.",
+        _ => "The request could not be completed.",
+    }
+}
+
+/// Middleware that gives the bodiless error responses of proxy handlers a
+/// Jellyfin-style JSON body. Backend responses and the web UI are left alone.
+pub async fn jellyfin_error_bodies(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let ui_prefix = format!("/{}", state.config.read().await.ui_route);
+    let is_ui_request = req
+        .uri()
+        .path()
+        .to_ascii_lowercase()
+        .starts_with(&ui_prefix.to_ascii_lowercase());
+
+    let failure_slot = UpstreamFailureSlot::default();
+    let mut response = UPSTREAM_FAILURE
+        .scope(failure_slot.clone(), next.run(req))
+        .await;
+    if is_ui_request {
+        return response;
+    }
+
+    let failure = if state.upstream_error_details_enabled().await {
+        failure_slot.lock().ok().and_then(|mut slot| slot.take())
+    } else {
+        None
+    };
+    if !is_bare_error(&response) {
+        return response;
+    }
+    let failure = match failure {
+        Some(failure) => Some((server_name_for(&state, &failure.url).await, failure)),
+        None => None,
+    };
+
+    let request_id = response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    let mut problem = match &failure {
+        Some((server_name, failure)) => {
+            let detail = format!(
+                "{server_name} answered with {}: {}",
+                failure.status,
+                sanitize_upstream_body(&failure.body)
+            );
+            insert_upstream_headers(response.headers_mut(), failure.status.as_u16(), server_name);
+            problem_response_with_detail(response.status(), &request_id, &detail)
+        }
+        None if response.status() == StatusCode::SERVICE_UNAVAILABLE
+            && !has_servers(&state).await =>
+        {
+            let detail = format!(
+                "No server is configured in Jellyswarrm yet. Add one in the web UI at /{}.",
+                state.config.read().await.ui_route
+            );
+            problem_response_with_detail(response.status(), &request_id, &detail)
+        }
+        None => problem_response(response.status(), &request_id),
+    };
+    let (parts, _) = response.into_parts();
+    for (name, value) in &parts.headers {
+        if !problem.headers().contains_key(name) {
+            problem.headers_mut().insert(name, value.clone());
+        }
+    }
+    problem
+}
+
+async fn has_servers(state: &AppState) -> bool {
+    state
+        .server_storage
+        .list_servers()
+        .await
+        .map(|servers| !servers.is_empty())
+        .unwrap_or(true)
+}
+
+/// Name of the server `url` points into, or its host when no server matches.
+async fn server_name_for(state: &AppState, url: &url::Url) -> String {
+    let servers = state
+        .server_storage
+        .list_servers()
+        .await
+        .unwrap_or_default();
+    servers
+        .into_iter()
+        .find(|server| {
+            url.as_str()
+                .starts_with(server.url.as_str().trim_end_matches('/'))
+        })
+        .map(|server| server.name)
+        .unwrap_or_else(|| url.host_str().unwrap_or_default().to_string())
+}
+
+/// Collapse a backend error body into one line, cut it short and redact
+/// anything that looks like a token or an id.
+fn sanitize_upstream_body(body: &str) -> String {
+    let mut sanitized = String::new();
+    let mut run = String::new();
+    let flush = |sanitized: &mut String, run: &mut String| {
+        if run.len() >= 32 {
+            sanitized.push_str("<redacted>");
+        } else {
+            sanitized.push_str(run);
+        }
+        run.clear();
+    };
+    for c in body
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+    {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            run.push(c);
+        } else {
+            flush(&mut sanitized, &mut run);
+            if !c.is_control() {
+                sanitized.push(c);
+            }
+        }
+    }
+    flush(&mut sanitized, &mut run);
+
+    if sanitized.chars().count() > MAX_UPSTREAM_DETAIL_LEN {
+        sanitized = sanitized.chars().take(MAX_UPSTREAM_DETAIL_LEN).collect();
+        sanitized.push('…');
+    }
+    sanitized
+}
+
+fn is_bare_error(response: &Response<Body>) -> bool {
+    (response.status().is_client_error() || response.status().is_server_error())
+        && response.extensions().get::<UpstreamResponse>().is_none()
+        && !response.headers().contains_key(header::CONTENT_TYPE)
+        && response.body().size_hint().exact() == Some(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_test_app_state;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    async fn request(uri: &str) -> Response {
+        request_with_state(create_test_app_state().await, uri).await
+    }
+
+    async fn request_with_state(state: AppState, uri: &str) -> Response {
+        let router = axum::Router::new()
+            .route(
+                "/Items/{id}",
+                get(|| async { Err::<(), _>(StatusCode::BAD_GATEWAY) }),
+            )
+            .route(
+                "/Videos/{id}",
+                get(|| async {
+                    let mut response = (StatusCode::NOT_FOUND, "Item not found").into_response();
+                    response.extensions_mut().insert(UpstreamResponse);
+                    response
+                }),
+            )
+            .route(
+                "/Users/{id}",
+                get(|| async {
+                    record_upstream_failure(UpstreamFailure {
+                        status: reqwest::StatusCode::FORBIDDEN,
+                        url: "http://main:8096/Users/abc".parse().unwrap(),
+                        body: "User is not allowed.\n Token 0123456789abcdef0123456789abcdef"
+                            .to_string(),
+                    });
+                    StatusCode::UNAUTHORIZED
+                }),
+            )
+            .route("/ui/login", get(|| async { StatusCode::UNAUTHORIZED }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                jellyfin_error_bodies,
+            ))
+            .with_state(state);
+
+        router
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body_bytes(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn proxy_errors_get_a_problem_details_body() {
+        let response = request("/Items/abc").await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        let request_id = response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(body["status"], 502);
+        assert_eq!(body["title"], "Bad Gateway");
+        assert_eq!(
+            body["detail"],
+            "The server holding this item could not be reached."
+        );
+        assert_eq!(body["traceId"], request_id);
+    }
+
+    #[tokio::test]
+    async fn upstream_and_ui_errors_are_passed_through() {
+        let upstream = request("/Videos/abc").await;
+        assert_eq!(upstream.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body_bytes(upstream).await, b"Item not found");
+
+        let ui = request("/ui/login").await;
+        assert_eq!(ui.status(), StatusCode::UNAUTHORIZED);
+        assert!(body_bytes(ui).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn handler_errors_report_the_backend_error_when_enabled() {
+        let state = create_test_app_state().await;
+        state
+            .server_storage
+            .add_server(
+                "Main",
+                "http://main:8096",
+                100,
+                crate::config::MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+
+        let response = request_with_state(state.clone(), "/Users/abc").await;
+        assert!(!response.headers().contains_key(UPSTREAM_SERVER_HEADER));
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(
+            body["detail"],
+            "The request is not authenticated with Jellyswarrm."
+        );
+
+        state.config.write().await.upstream_error_details = true;
+        let response = request_with_state(state, "/Users/abc").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[UPSTREAM_SERVER_HEADER], "Main");
+        assert_eq!(response.headers()[UPSTREAM_STATUS_HEADER], "403");
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(
+            body["detail"],
+            "Main answered with 403 Forbidden: User is not allowed. Token <redacted>"
+        );
+    }
+}
@@ -3,7 +3,7 @@ use hyper::StatusCode;
 use tracing::error;
 
 use crate::{
-    request_preprocessing::{preprocess_request, PreprocessedRequest},
+    request_preprocessing::{preprocess_error_status, preprocess_request, PreprocessedRequest},
     user_authorization_service::{AuthorizationSession, User},
     AppState,
 };
@@ -16,7 +16,7 @@ impl FromRequest<AppState> for Preprocessed {
     async fn from_request(req: Request, state: &AppState) -> Result<Self, Self::Rejection> {
         preprocess_request(req, state).await.map(Self).map_err(|e| {
             error!("Failed to preprocess request: {}", e);
-            StatusCode::BAD_REQUEST
+            preprocess_error_status(&e)
         })
     }
 }
@@ -4,11 +4,13 @@ use tracing::{error, info, warn};
 
 use crate::{
     encryption::{decrypt_password, HashedPassword, Password},
-    server_storage::ServerStorageService,
-    user_authorization_service::UserAuthorizationService,
+    server_id::ServerId,
+    server_storage::{Server, ServerStorageService},
+    user_authorization_service::{ServerMapping, User, UserAuthorizationService},
     AppState,
 };
-use jellyfin_api::JellyfinClient;
+use jellyfin_api::{error::JellyfinApiError, JellyfinClient};
+use reqwest::StatusCode;
 
 #[derive(Debug, Clone)]
 pub enum SyncStatus {
@@ -19,6 +21,7 @@ pub enum SyncStatus {
     Skipped,
     Deleted,
     NotFound,
+    PasswordUpdated,
 }
 
 #[derive(Debug, Clone)]
@@ -28,6 +31,13 @@ pub struct ServerSyncResult {
     pub message: Option<String>,
 }
 
+/// Outcome of provisioning one proxy user onto a server.
+#[derive(Debug, Clone)]
+pub struct UserSyncResult {
+    pub username: String,
+    pub result: ServerSyncResult,
+}
+
 #[derive(Clone)]
 pub struct FederatedUserService {
     server_storage: Arc<ServerStorageService>,
@@ -74,214 +84,451 @@ impl FederatedUserService {
             }
         };
 
-        let config = self.config.read().await;
-        let admin_password: HashedPassword = config.password.clone().into();
+        for server in servers {
+            let (client, remote_users) = match self.admin_session(&server).await {
+                Ok(session) => session,
+                Err(result) => {
+                    results.push(result);
+                    continue;
+                }
+            };
 
-        drop(config);
+            if let Some(result) = self
+                .provision_user(&client, &remote_users, &server, username, password, user_id)
+                .await
+            {
+                results.push(result);
+            }
+        }
 
-        for server in servers {
-            // Check if we have admin credentials for this server
-            if let Some(admin) = match self.server_storage.get_server_admin(server.id).await {
-                Ok(a) => a,
-                Err(e) => {
-                    results.push(ServerSyncResult {
+        results
+    }
+
+    /// Provisions every proxy user onto the server `server_id`, e.g. after it was
+    /// added with admin credentials. Users need their own password on the new server,
+    /// which is recovered from their existing server mappings; users whose password
+    /// can't be decrypted from any of them are skipped. Users already mapped to the
+    /// server are left alone.
+    ///
+    /// Returns the outcome per user, or the reason the server couldn't be synced at all.
+    pub async fn sync_all_users_to_server(
+        &self,
+        server_id: ServerId,
+    ) -> Result<Vec<UserSyncResult>, ServerSyncResult> {
+        let server = match self.server_storage.get_server_by_id(server_id).await {
+            Ok(Some(server)) => server,
+            Ok(None) => {
+                return Err(ServerSyncResult {
+                    server_name: server_id.to_string(),
+                    status: SyncStatus::NotFound,
+                    message: Some("Server not found".to_string()),
+                })
+            }
+            Err(e) => {
+                return Err(ServerSyncResult {
+                    server_name: server_id.to_string(),
+                    status: SyncStatus::Failed,
+                    message: Some(format!("Failed to get server: {}", e)),
+                })
+            }
+        };
+        let users = self.user_authorization.list_users().await.map_err(|e| {
+            error!("Failed to list users for sync: {}", e);
+            ServerSyncResult {
+                server_name: server.name.clone(),
+                status: SyncStatus::Failed,
+                message: Some(format!("Failed to list users: {}", e)),
+            }
+        })?;
+        let (client, remote_users) = self.admin_session(&server).await?;
+        let admin_password: HashedPassword = self.config.read().await.password.clone().into();
+
+        let mut results = Vec::new();
+        for user in users {
+            let username = user.original_username.clone();
+            let result = match self
+                .user_sync_password(&user, &server, &admin_password)
+                .await
+            {
+                Ok(password) => self
+                    .provision_user(
+                        &client,
+                        &remote_users,
+                        &server,
+                        &username,
+                        &password,
+                        &user.id,
+                    )
+                    .await
+                    .unwrap_or_else(|| ServerSyncResult {
                         server_name: server.name.clone(),
                         status: SyncStatus::Failed,
-                        message: Some(format!("Failed to get admin creds: {}", e)),
-                    });
+                        message: Some("Client error".to_string()),
+                    }),
+                Err(result) => result,
+            };
+            results.push(UserSyncResult { username, result });
+        }
+
+        Ok(results)
+    }
+
+    /// Change the password of `user_id` on the backends where it is federated, i.e.
+    /// where the user is mapped with their proxy password. Call this after the proxy
+    /// password itself was updated, so the mappings are encrypted with
+    /// `new_password`; mappings of backends that accepted it are updated to it.
+    /// Mappings with their own credentials are left alone.
+    pub async fn propagate_password_change(
+        &self,
+        user_id: &str,
+        old_password: &Password,
+        new_password: &Password,
+    ) -> Vec<ServerSyncResult> {
+        let mut results = Vec::new();
+        let mappings = match self.user_authorization.list_server_mappings(user_id).await {
+            Ok(mappings) => mappings,
+            Err(e) => {
+                error!("Failed to list mappings for password change: {}", e);
+                return results;
+            }
+        };
+
+        let new_password_hash: HashedPassword = new_password.into();
+        for mapping in mappings {
+            let mapped_password = decrypt_password(&mapping.mapped_password, &new_password_hash)
+                .unwrap_or_else(|_| Password::from(mapping.mapped_password.as_str()));
+            if mapped_password != *old_password {
+                continue;
+            }
+
+            let server = match self
+                .server_storage
+                .get_server_by_id(mapping.server_id)
+                .await
+            {
+                Ok(Some(server)) => server,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("Failed to get server {}: {}", mapping.server_id, e);
                     continue;
                 }
-            } {
-                // Decrypt admin password
-                let decrypted_admin_password =
-                    match decrypt_password(&admin.password, &admin_password) {
-                        Ok(p) => p,
-                        Err(e) => {
-                            error!(
-                                "Failed to decrypt admin password for server {}: {}",
-                                server.name, e
-                            );
-                            results.push(ServerSyncResult {
-                                server_name: server.name.clone(),
-                                status: SyncStatus::Failed,
-                                message: Some("Failed to decrypt admin password".to_string()),
-                            });
-                            continue;
-                        }
-                    };
-
-                let client_info = crate::config::CLIENT_INFO.clone();
-
-                let client = match JellyfinClient::new(server.url.as_str(), client_info.clone()) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        error!("Failed to create jellyfin client: {}", e);
-                        results.push(ServerSyncResult {
-                            server_name: server.name.clone(),
-                            status: SyncStatus::Failed,
-                            message: Some(format!("Client error: {}", e)),
-                        });
-                        continue;
-                    }
-                };
+            };
+            let (client, remote_users) = match self.admin_session(&server).await {
+                Ok(session) => session,
+                Err(result) => {
+                    results.push(result);
+                    continue;
+                }
+            };
 
-                // Authenticate as admin to get token
-                match client
-                    .authenticate_by_name(&admin.username, decrypted_admin_password.as_str())
-                    .await
-                {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!(
-                            "Failed to authenticate as admin on server {}: {}",
-                            server.name, e
-                        );
-                        results.push(ServerSyncResult {
-                            server_name: server.name.clone(),
-                            status: SyncStatus::Failed,
-                            message: Some(format!("Admin auth failed: {}", e)),
-                        });
-                        continue;
-                    }
-                };
+            let Some(remote_user) = remote_users
+                .iter()
+                .find(|u| u.name.eq_ignore_ascii_case(&mapping.mapped_username))
+            else {
+                results.push(ServerSyncResult {
+                    server_name: server.name.clone(),
+                    status: SyncStatus::NotFound,
+                    message: Some(format!("User {} not found", mapping.mapped_username)),
+                });
+                continue;
+            };
+
+            if let Err(e) = client
+                .update_user_password(
+                    &remote_user.id,
+                    Some(old_password.as_str()),
+                    new_password.as_str(),
+                )
+                .await
+            {
+                warn!(
+                    "Failed to change password of {} on server {}: {}",
+                    mapping.mapped_username, server.name, e
+                );
+                results.push(ServerSyncResult {
+                    server_name: server.name.clone(),
+                    status: SyncStatus::Failed,
+                    message: Some(format!("Password change failed: {}", e)),
+                });
+                continue;
+            }
 
-                // Check if user exists
-                let users = match client.get_users().await {
-                    Ok(u) => u,
-                    Err(e) => {
-                        error!("Failed to list users on server {}: {}", server.name, e);
-                        results.push(ServerSyncResult {
-                            server_name: server.name.clone(),
-                            status: SyncStatus::Failed,
-                            message: Some(format!("Failed to list users: {}", e)),
-                        });
-                        continue;
-                    }
-                };
-
-                let existing_user = users.iter().find(|u| u.name.eq_ignore_ascii_case(username));
-
-                if let Some(remote_user) = existing_user {
-                    // User exists. Check if password matches.
-                    // We need a new client to check user password
-                    let user_client =
-                        match JellyfinClient::new(server.url.as_str(), client_info.clone()) {
-                            Ok(c) => c,
-                            Err(_) => continue,
-                        };
-
-                    let (status, should_map) = match user_client
-                        .authenticate_by_name(username, password.as_str())
-                        .await
-                    {
-                        Ok(_) => (SyncStatus::AlreadyExists, true),
-                        Err(_) => (SyncStatus::ExistsWithDifferentPassword, false),
-                    };
-
-                    info!(
-                        "Synced user {} to server {} (Remote ID: {}, Status: {:?})",
-                        username, server.name, remote_user.id, status
-                    );
-
-                    if should_map {
-                        if let Err(e) = self
-                            .user_authorization
-                            .add_server_mapping(
-                                user_id,
-                                &server,
-                                username,
-                                password,
-                                Some(&password.into()), // Encrypt with their own password so they can use it
-                            )
-                            .await
-                        {
-                            error!(
-                                "Failed to create local mapping for synced user on server {}: {}",
-                                server.name, e
-                            );
-                            results.push(ServerSyncResult {
-                                server_name: server.name.clone(),
-                                status: SyncStatus::Failed,
-                                message: Some(format!("Failed to save local mapping: {}", e)),
-                            });
-                        } else {
-                            results.push(ServerSyncResult {
-                                server_name: server.name.clone(),
-                                status,
-                                message: None,
-                            });
-                        }
-                    } else {
-                        results.push(ServerSyncResult {
-                            server_name: server.name.clone(),
-                            status,
-                            message: Some("User exists with different password".to_string()),
-                        });
-                    }
-                } else {
-                    // Create user
-                    match client.create_user(username, Some(password.as_str())).await {
-                        Ok(new_user) => {
-                            info!(
-                                "Synced user {} to server {} (Remote ID: {}, Status: Created)",
-                                username, server.name, new_user.id
-                            );
-
-                            if let Err(e) = self
-                                .user_authorization
-                                .add_server_mapping(
-                                    user_id,
-                                    &server,
-                                    username,
-                                    password,
-                                    Some(&password.into()), // Encrypt with their own password so they can use it
-                                )
-                                .await
-                            {
-                                error!(
-                                    "Failed to create local mapping for synced user on server {}: {}",
-                                    server.name, e
-                                );
-                                results.push(ServerSyncResult {
-                                    server_name: server.name.clone(),
-                                    status: SyncStatus::Failed,
-                                    message: Some(format!("Failed to save local mapping: {}", e)),
-                                });
-                            } else {
-                                results.push(ServerSyncResult {
-                                    server_name: server.name.clone(),
-                                    status: SyncStatus::Created,
-                                    message: None,
-                                });
-                            }
-                        }
-                        Err(e) => {
-                            warn!(
-                                "Failed to sync user {} to server {}: {}",
-                                username, server.name, e
-                            );
-                            results.push(ServerSyncResult {
-                                server_name: server.name.clone(),
-                                status: SyncStatus::Failed,
-                                message: Some(format!("Sync failed: {}", e)),
-                            });
-                        }
-                    }
-                }
-            } else {
+            info!(
+                "Changed password of {} on server {}",
+                mapping.mapped_username, server.name
+            );
+            results.push(
+                self.map_synced_user(
+                    &server,
+                    &mapping.mapped_username,
+                    new_password,
+                    user_id,
+                    SyncStatus::PasswordUpdated,
+                )
+                .await,
+            );
+        }
+
+        results
+    }
+
+    /// The password `user` is provisioned with on `server`, or the result to report
+    /// when they are skipped.
+    async fn user_sync_password(
+        &self,
+        user: &User,
+        server: &Server,
+        admin_password: &HashedPassword,
+    ) -> Result<Password, ServerSyncResult> {
+        let skipped = |status: SyncStatus, message: &str| ServerSyncResult {
+            server_name: server.name.clone(),
+            status,
+            message: Some(message.to_string()),
+        };
+
+        let mappings = self
+            .user_authorization
+            .list_server_mappings(&user.id)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to list mappings of user {}: {}",
+                    user.original_username, e
+                );
+                skipped(SyncStatus::Failed, "Failed to list server mappings")
+            })?;
+        if mappings
+            .iter()
+            .any(|mapping| mapping.server_id == server.id)
+        {
+            return Err(skipped(
+                SyncStatus::AlreadyExists,
+                "Already mapped to this server",
+            ));
+        }
+
+        recover_user_password(user, &mappings, admin_password).ok_or_else(|| {
+            warn!(
+                "Skipping sync of user {} to server {}: password can't be decrypted",
+                user.original_username, server.name
+            );
+            skipped(
+                SyncStatus::Skipped,
+                "Password can't be decrypted from any server mapping",
+            )
+        })
+    }
+
+    /// Authenticate as the admin of `server` and list its users. Fails with the
+    /// result to report for the server when that is not possible.
+    async fn admin_session(
+        &self,
+        server: &Server,
+    ) -> Result<(JellyfinClient, Vec<jellyfin_api::models::User>), ServerSyncResult> {
+        let failed = |message: String| ServerSyncResult {
+            server_name: server.name.clone(),
+            status: SyncStatus::Failed,
+            message: Some(message),
+        };
+
+        // Check if we have admin credentials for this server
+        let admin = match self.server_storage.get_server_admin(server.id).await {
+            Ok(Some(admin)) => admin,
+            Ok(None) => {
                 warn!(
-                    "Skipping sync for server {}: No admin credentials configured",
+                    "Skipping server {}: No admin credentials configured",
                     server.name
                 );
-                results.push(ServerSyncResult {
+                return Err(ServerSyncResult {
                     server_name: server.name.clone(),
                     status: SyncStatus::Skipped,
                     message: Some("No admin credentials".to_string()),
                 });
             }
+            Err(e) => return Err(failed(format!("Failed to get admin creds: {}", e))),
+        };
+
+        // Decrypt admin password
+        let admin_password: HashedPassword = self.config.read().await.password.clone().into();
+        let decrypted_admin_password =
+            decrypt_password(&admin.password, &admin_password).map_err(|e| {
+                error!(
+                    "Failed to decrypt admin password for server {}: {}",
+                    server.name, e
+                );
+                failed("Failed to decrypt admin password".to_string())
+            })?;
+
+        let client = JellyfinClient::new(server.url.as_str(), crate::config::CLIENT_INFO.clone())
+            .map_err(|e| {
+            error!("Failed to create jellyfin client: {}", e);
+            failed(format!("Client error: {}", e))
+        })?;
+
+        // Authenticate as admin to get token
+        client
+            .authenticate_by_name(&admin.username, decrypted_admin_password.as_str())
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to authenticate as admin on server {}: {}",
+                    server.name, e
+                );
+                match e {
+                    JellyfinApiError::Unauthorized => {
+                        failed("Admin credentials were rejected".to_string())
+                    }
+                    e => failed(format!("Admin auth failed: {}", e)),
+                }
+            })?;
+
+        let users = client.get_users().await.map_err(|e| {
+            error!("Failed to list users on server {}: {}", server.name, e);
+            match e {
+                JellyfinApiError::Unauthorized | JellyfinApiError::Forbidden => {
+                    failed("Admin account is not allowed to list users".to_string())
+                }
+                e => failed(format!("Failed to list users: {}", e)),
+            }
+        })?;
+
+        Ok((client, users))
+    }
+
+    /// Create `username` on `server` unless one of its `remote_users` already has that
+    /// name, and map the proxy user `user_id` to it when its password is `password`.
+    /// Returns `None` if no client could be created to check an existing user.
+    async fn provision_user(
+        &self,
+        client: &JellyfinClient,
+        remote_users: &[jellyfin_api::models::User],
+        server: &Server,
+        username: &str,
+        password: &Password,
+        user_id: &str,
+    ) -> Option<ServerSyncResult> {
+        if remote_users
+            .iter()
+            .any(|u| u.name.eq_ignore_ascii_case(username))
+        {
+            return self
+                .map_existing_user(server, username, password, user_id)
+                .await;
         }
 
-        results
+        // Create user
+        match client.create_user(username, Some(password.as_str())).await {
+            Ok(new_user) => {
+                info!(
+                    "Synced user {} to server {} (Remote ID: {}, Status: Created)",
+                    username, server.name, new_user.id
+                );
+                Some(
+                    self.map_synced_user(server, username, password, user_id, SyncStatus::Created)
+                        .await,
+                )
+            }
+            // Created in the meantime, e.g. by another sync.
+            Err(JellyfinApiError::Server(StatusCode::BAD_REQUEST, body))
+                if body.contains("already exists") =>
+            {
+                self.map_existing_user(server, username, password, user_id)
+                    .await
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to sync user {} to server {}: {}",
+                    username, server.name, e
+                );
+                Some(ServerSyncResult {
+                    server_name: server.name.clone(),
+                    status: SyncStatus::Failed,
+                    message: Some(format!("Sync failed: {}", e)),
+                })
+            }
+        }
+    }
+
+    /// Map `user_id` to the existing `username` on `server` if it accepts `password`.
+    /// Returns `None` if no client could be created to check the password.
+    async fn map_existing_user(
+        &self,
+        server: &Server,
+        username: &str,
+        password: &Password,
+        user_id: &str,
+    ) -> Option<ServerSyncResult> {
+        let user_client =
+            JellyfinClient::new(server.url.as_str(), crate::config::CLIENT_INFO.clone()).ok()?;
+
+        let result = match user_client
+            .authenticate_by_name(username, password.as_str())
+            .await
+        {
+            Ok(_) => {
+                self.map_synced_user(
+                    server,
+                    username,
+                    password,
+                    user_id,
+                    SyncStatus::AlreadyExists,
+                )
+                .await
+            }
+            Err(JellyfinApiError::Unauthorized) => ServerSyncResult {
+                server_name: server.name.clone(),
+                status: SyncStatus::ExistsWithDifferentPassword,
+                message: Some("User exists with different password".to_string()),
+            },
+            Err(e) => ServerSyncResult {
+                server_name: server.name.clone(),
+                status: SyncStatus::Failed,
+                message: Some(format!("Failed to check password of existing user: {}", e)),
+            },
+        };
+
+        info!(
+            "Synced user {} to server {} (Status: {:?})",
+            username, server.name, result.status
+        );
+        Some(result)
+    }
+
+    async fn map_synced_user(
+        &self,
+        server: &Server,
+        username: &str,
+        password: &Password,
+        user_id: &str,
+        status: SyncStatus,
+    ) -> ServerSyncResult {
+        if let Err(e) = self
+            .user_authorization
+            .add_server_mapping(
+                user_id,
+                server,
+                username,
+                password,
+                Some(&password.into()), // Encrypt with their own password so they can use it
+            )
+            .await
+        {
+            error!(
+                "Failed to create local mapping for synced user on server {}: {}",
+                server.name, e
+            );
+            return ServerSyncResult {
+                server_name: server.name.clone(),
+                status: SyncStatus::Failed,
+                message: Some(format!("Failed to save local mapping: {}", e)),
+            };
+        }
+
+        ServerSyncResult {
+            server_name: server.name.clone(),
+            status,
+            message: None,
+        }
     }
 
     pub async fn delete_user_from_all_servers(&self, username: &str) -> Vec<ServerSyncResult> {
@@ -294,128 +541,58 @@ impl FederatedUserService {
             }
         };
 
-        let config = self.config.read().await;
-        let admin_password = &config.password;
-
         for server in servers {
-            if let Some(admin) = match self.server_storage.get_server_admin(server.id).await {
-                Ok(a) => a,
-                Err(e) => {
-                    results.push(ServerSyncResult {
-                        server_name: server.name.clone(),
-                        status: SyncStatus::Failed,
-                        message: Some(format!("Failed to get admin creds: {}", e)),
-                    });
+            let (client, remote_users) = match self.admin_session(&server).await {
+                Ok(session) => session,
+                Err(result) => {
+                    results.push(result);
                     continue;
                 }
-            } {
-                let decrypted_admin_password =
-                    match decrypt_password(&admin.password, &admin_password.into()) {
-                        Ok(p) => p,
-                        Err(e) => {
-                            error!(
-                                "Failed to decrypt admin password for server {}: {}",
-                                server.name, e
-                            );
-                            results.push(ServerSyncResult {
-                                server_name: server.name.clone(),
-                                status: SyncStatus::Failed,
-                                message: Some("Failed to decrypt admin password".to_string()),
-                            });
-                            continue;
-                        }
-                    };
-
-                let client_info = crate::config::CLIENT_INFO.clone();
-
-                let client = match JellyfinClient::new(server.url.as_str(), client_info.clone()) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        error!("Failed to create jellyfin client: {}", e);
+            };
+
+            // Find user ID
+            let user_id = remote_users
+                .iter()
+                .find(|u| u.name.eq_ignore_ascii_case(username))
+                .map(|u| u.id.clone());
+
+            if let Some(id) = user_id {
+                match client.delete_user(&id).await {
+                    Ok(_) => {
+                        info!(
+                            "Deleted user {} from server {} (Deleted: true)",
+                            username, server.name
+                        );
                         results.push(ServerSyncResult {
                             server_name: server.name.clone(),
-                            status: SyncStatus::Failed,
-                            message: Some(format!("Client error: {}", e)),
+                            status: SyncStatus::Deleted,
+                            message: None,
                         });
-                        continue;
                     }
-                };
-
-                match client
-                    .authenticate_by_name(&admin.username, decrypted_admin_password.as_str())
-                    .await
-                {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!(
-                            "Failed to authenticate as admin on server {}: {}",
-                            server.name, e
-                        );
+                    Err(JellyfinApiError::NotFound) => {
                         results.push(ServerSyncResult {
                             server_name: server.name.clone(),
-                            status: SyncStatus::Failed,
-                            message: Some(format!("Admin auth failed: {}", e)),
+                            status: SyncStatus::NotFound,
+                            message: Some("User was already deleted".to_string()),
                         });
-                        continue;
                     }
-                };
-
-                // Find user ID
-                let users = match client.get_users().await {
-                    Ok(u) => u,
                     Err(e) => {
-                        error!("Failed to list users on server {}: {}", server.name, e);
+                        warn!(
+                            "Failed to delete user {} from server {}: {}",
+                            username, server.name, e
+                        );
                         results.push(ServerSyncResult {
                             server_name: server.name.clone(),
                             status: SyncStatus::Failed,
-                            message: Some(format!("Failed to list users: {}", e)),
+                            message: Some(format!("Delete failed: {}", e)),
                         });
-                        continue;
-                    }
-                };
-
-                let user_id = users
-                    .iter()
-                    .find(|u| u.name.eq_ignore_ascii_case(username))
-                    .map(|u| u.id.clone());
-
-                if let Some(id) = user_id {
-                    match client.delete_user(&id).await {
-                        Ok(_) => {
-                            info!(
-                                "Deleted user {} from server {} (Deleted: true)",
-                                username, server.name
-                            );
-                            results.push(ServerSyncResult {
-                                server_name: server.name.clone(),
-                                status: SyncStatus::Deleted,
-                                message: None,
-                            });
-                        }
-                        Err(e) => {
-                            warn!(
-                                "Failed to delete user {} from server {}: {}",
-                                username, server.name, e
-                            );
-                            results.push(ServerSyncResult {
-                                server_name: server.name.clone(),
-                                status: SyncStatus::Failed,
-                                message: Some(format!("Delete failed: {}", e)),
-                            });
-                        }
                     }
-                } else {
-                    results.push(ServerSyncResult {
-                        server_name: server.name.clone(),
-                        status: SyncStatus::NotFound,
-                        message: None,
-                    });
                 }
             } else {
                 results.push(ServerSyncResult {
                     server_name: server.name.clone(),
-                    status: SyncStatus::Skipped,
-                    message: Some("No admin credentials".to_string()),
+                    status: SyncStatus::NotFound,
+                    message: None,
                 });
             }
         }
@@ -423,3 +600,404 @@ impl FederatedUserService {
         results
     }
 }
+
+/// The proxy password of `user`, recovered from one of their server mappings.
+/// Mappings created by federation hold it encrypted with the user's password hash,
+/// others may be encrypted with the admin password or stored in plain text. A
+/// candidate only counts if it matches the user's password hash.
+fn recover_user_password(
+    user: &User,
+    mappings: &[ServerMapping],
+    admin_password: &HashedPassword,
+) -> Option<Password> {
+    mappings.iter().find_map(|mapping| {
+        [
+            decrypt_password(&mapping.mapped_password, &user.original_password_hash).ok(),
+            decrypt_password(&mapping.mapped_password, admin_password).ok(),
+            Some(Password::from(mapping.mapped_password.as_str())),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|candidate| user.original_password_hash.verify(candidate.as_str()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{AppConfig, MediaStreamingMode, MIGRATOR},
+        encryption::encrypt_password,
+    };
+    use sqlx::SqlitePool;
+    use wiremock::{
+        matchers::{body_partial_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    struct TestContext {
+        service: FederatedUserService,
+        server_storage: Arc<ServerStorageService>,
+        user_authorization: Arc<UserAuthorizationService>,
+        admin_password: HashedPassword,
+    }
+
+    async fn create_test_context() -> TestContext {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        let server_storage = Arc::new(ServerStorageService::new(pool.clone()));
+        let user_authorization = Arc::new(UserAuthorizationService::new(pool));
+        let config = AppConfig::default();
+        let admin_password: HashedPassword = config.password.clone().into();
+        let service = FederatedUserService::new_from_components(
+            server_storage.clone(),
+            user_authorization.clone(),
+            Arc::new(tokio::sync::RwLock::new(config)),
+        );
+        TestContext {
+            service,
+            server_storage,
+            user_authorization,
+            admin_password,
+        }
+    }
+
+    /// Add `upstream` as a server with admin credentials whose user list is `users`.
+    async fn add_admin_server(
+        context: &TestContext,
+        upstream: &MockServer,
+        users: serde_json::Value,
+    ) -> Server {
+        Mock::given(method("POST"))
+            .and(path("/Users/AuthenticateByName"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "AccessToken": "admin-token",
+                "User": { "Id": "admin-id", "Name": "admin", "ServerId": "new-server" }
+            })))
+            .mount(upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Users"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(users))
+            .mount(upstream)
+            .await;
+
+        let server_id = context
+            .server_storage
+            .add_server("New", &upstream.uri(), 50, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+        context
+            .server_storage
+            .add_server_admin(
+                server_id,
+                "admin",
+                &encrypt_password(&"admin-pw".into(), &context.admin_password).unwrap(),
+            )
+            .await
+            .unwrap();
+        context
+            .server_storage
+            .get_server_by_id(server_id)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn all_users_are_synced_to_a_new_server_unless_their_password_is_unknown() {
+        let context = create_test_context().await;
+        let TestContext {
+            service,
+            server_storage,
+            user_authorization,
+            ..
+        } = &context;
+        let new_server = MockServer::start().await;
+        let new_id = add_admin_server(
+            &context,
+            &new_server,
+            serde_json::json!([{ "Id": "admin-id", "Name": "admin", "ServerId": "new-server" }]),
+        )
+        .await
+        .id;
+        Mock::given(method("POST"))
+            .and(path("/Users/New"))
+            .and(body_partial_json(
+                serde_json::json!({ "Name": "alice", "Password": "alice-pw" }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Id": "alice-id", "Name": "alice", "ServerId": "new-server"
+            })))
+            .expect(1)
+            .mount(&new_server)
+            .await;
+
+        let old_id = server_storage
+            .add_server("Old", "http://old.invalid", 100, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+        let old = server_storage
+            .get_server_by_id(old_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Mapped by federation: the password is encrypted with the user's own.
+        let alice_password: Password = "alice-pw".into();
+        let alice = user_authorization
+            .create_user("alice", &alice_password)
+            .await
+            .unwrap();
+        user_authorization
+            .add_server_mapping(
+                &alice.id,
+                &old,
+                "alice",
+                &alice_password,
+                Some(&(&alice_password).into()),
+            )
+            .await
+            .unwrap();
+        // Mapped by hand with other credentials, which don't reveal the password.
+        let bob = user_authorization
+            .create_user("bob", &"bob-pw".into())
+            .await
+            .unwrap();
+        user_authorization
+            .add_server_mapping(
+                &bob.id,
+                &old,
+                "robert",
+                &"robert-pw".into(),
+                Some(&HashedPassword::from_password("unrelated")),
+            )
+            .await
+            .unwrap();
+
+        let results = service.sync_all_users_to_server(new_id).await.unwrap();
+
+        let status = |username: &str| {
+            results
+                .iter()
+                .find(|result| result.username == username)
+                .map(|result| result.result.status.clone())
+        };
+        assert!(matches!(status("alice"), Some(SyncStatus::Created)));
+        assert!(matches!(status("bob"), Some(SyncStatus::Skipped)));
+        let mapping = user_authorization
+            .get_server_mapping_by_server_id(&alice.id, new_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(mapping.mapped_username, "alice");
+        assert!(user_authorization
+            .get_server_mapping_by_server_id(&bob.id, new_id)
+            .await
+            .unwrap()
+            .is_none());
+
+        let skipped = service.sync_all_users_to_server(old_id).await.unwrap_err();
+        assert!(matches!(skipped.status, SyncStatus::Skipped));
+    }
+
+    #[tokio::test]
+    async fn users_created_in_the_meantime_are_mapped_if_their_password_matches() {
+        let context = create_test_context().await;
+        let upstream = MockServer::start().await;
+        let server = add_admin_server(
+            &context,
+            &upstream,
+            serde_json::json!([{ "Id": "admin-id", "Name": "admin", "ServerId": "new-server" }]),
+        )
+        .await;
+        Mock::given(method("POST"))
+            .and(path("/Users/New"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .set_body_string("A user with the name 'alice' already exists."),
+            )
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let alice_password: Password = "alice-pw".into();
+        let alice = context
+            .user_authorization
+            .create_user("alice", &alice_password)
+            .await
+            .unwrap();
+        let results = context
+            .service
+            .sync_user_to_all_servers("alice", &alice_password, &alice.id)
+            .await;
+
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0].status, SyncStatus::AlreadyExists));
+        assert!(context
+            .user_authorization
+            .get_server_mapping_by_server_id(&alice.id, server.id)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn deletes_report_missing_users_and_rejected_admins() {
+        let context = create_test_context().await;
+        let upstream = MockServer::start().await;
+        add_admin_server(
+            &context,
+            &upstream,
+            serde_json::json!([
+                { "Id": "admin-id", "Name": "admin", "ServerId": "new-server" },
+                { "Id": "alice-id", "Name": "alice", "ServerId": "new-server" }
+            ]),
+        )
+        .await;
+        Mock::given(method("DELETE"))
+            .and(path("/Users/alice-id"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let rejecting = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/Users/AuthenticateByName"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&rejecting)
+            .await;
+        let rejecting_id = context
+            .server_storage
+            .add_server("Rejecting", &rejecting.uri(), 10, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+        context
+            .server_storage
+            .add_server_admin(
+                rejecting_id,
+                "admin",
+                &encrypt_password(&"old-admin-pw".into(), &context.admin_password).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let results = context.service.delete_user_from_all_servers("alice").await;
+
+        let result = |server_name: &str| {
+            results
+                .iter()
+                .find(|result| result.server_name == server_name)
+                .unwrap()
+        };
+        assert!(matches!(result("New").status, SyncStatus::NotFound));
+        let rejected = result("Rejecting");
+        assert!(matches!(rejected.status, SyncStatus::Failed));
+        assert_eq!(
+            rejected.message.as_deref(),
+            Some("Admin credentials were rejected")
+        );
+    }
+
+    #[tokio::test]
+    async fn password_changes_reach_the_backends_the_user_is_federated_to() {
+        let context = create_test_context().await;
+        let TestContext {
+            service,
+            server_storage,
+            user_authorization,
+            ..
+        } = &context;
+        let upstream = MockServer::start().await;
+        let federated = add_admin_server(
+            &context,
+            &upstream,
+            serde_json::json!([
+                { "Id": "admin-id", "Name": "admin", "ServerId": "new-server" },
+                { "Id": "alice-id", "Name": "Alice", "ServerId": "new-server" }
+            ]),
+        )
+        .await;
+        Mock::given(method("POST"))
+            .and(path("/Users/alice-id/Password"))
+            .and(body_partial_json(
+                serde_json::json!({ "CurrentPw": "old-pw", "NewPw": "new-pw" }),
+            ))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let own_id = server_storage
+            .add_server("Own", "http://own.invalid", 100, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+
+        let old_password: Password = "old-pw".into();
+        let new_password: Password = "new-pw".into();
+        let alice = user_authorization
+            .create_user("alice", &old_password)
+            .await
+            .unwrap();
+        user_authorization
+            .add_server_mapping(
+                &alice.id,
+                &federated,
+                "alice",
+                &old_password,
+                Some(&(&old_password).into()),
+            )
+            .await
+            .unwrap();
+        // Own credentials on another server aren't touched.
+        user_authorization
+            .add_server_mapping(
+                &alice.id,
+                "http://own.invalid",
+                "alice-own",
+                &"own-pw".into(),
+                Some(&(&old_password).into()),
+            )
+            .await
+            .unwrap();
+
+        user_authorization
+            .update_user_password(
+                &alice.id,
+                &old_password,
+                &new_password,
+                &AppConfig::default().password,
+            )
+            .await
+            .unwrap();
+        let results = service
+            .propagate_password_change(&alice.id, &old_password, &new_password)
+            .await;
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].server_name, "New");
+        assert!(matches!(results[0].status, SyncStatus::PasswordUpdated));
+        let new_hash: HashedPassword = (&new_password).into();
+        let mapping = |server_id| {
+            let user_authorization = user_authorization.clone();
+            let alice_id = alice.id.clone();
+            async move {
+                user_authorization
+                    .get_server_mapping_by_server_id(&alice_id, server_id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+            }
+        };
+        let federated_mapping = mapping(federated.id).await;
+        assert_eq!(
+            decrypt_password(&federated_mapping.mapped_password, &new_hash).unwrap(),
+            new_password
+        );
+        let own_mapping = mapping(own_id).await;
+        assert_eq!(
+            decrypt_password(&own_mapping.mapped_password, &new_hash).unwrap(),
+            Password::from("own-pw")
+        );
+    }
+}
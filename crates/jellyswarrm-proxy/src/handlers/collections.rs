@@ -0,0 +1,341 @@
+use std::collections::BTreeSet;
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use tracing::{error, warn};
+
+use crate::{
+    extractors::Preprocessed,
+    handlers::common::execute_processed_json_request,
+    processors::{response_processor::ResponseProcessingProfile, url_processor::find_query_value},
+    request_preprocessing::PreprocessedRequest,
+    server_storage::Server,
+    AppState,
+};
+
+pub static COLLECTION_ITEM_IDS_QUERY_TAGS: &[&str] = &["Ids"];
+
+fn collection_error(message: String) -> Response {
+    warn!("Rejecting collection request: {message}");
+    (StatusCode::BAD_REQUEST, message).into_response()
+}
+
+/// Collections live on a single backend, so every item added to one has to come
+/// from the server the request is routed to.
+async fn validate_collection_items(
+    state: &AppState,
+    preprocessed: &PreprocessedRequest,
+) -> Result<(), Response> {
+    let Some(ids) = find_query_value(
+        preprocessed.original_request.url(),
+        COLLECTION_ITEM_IDS_QUERY_TAGS,
+    ) else {
+        return Ok(());
+    };
+    let target = &preprocessed.server;
+
+    let mut foreign_servers = BTreeSet::new();
+    for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        let mapping = state
+            .media_storage
+            .get_media_mapping_with_server(id)
+            .await
+            .map_err(|e| {
+                error!("Failed to look up collection item {id}: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            })?;
+        match mapping {
+            Some((_, server)) if server.id == target.id => {}
+            Some((_, server)) => {
+                foreign_servers.insert(server.name);
+            }
+            None => return Err(collection_error(format!("Unknown item id '{id}'"))),
+        }
+    }
+
+    if foreign_servers.is_empty() {
+        return Ok(());
+    }
+
+    Err(collection_error(format!(
+        "Collections cannot span servers: this collection belongs to '{}' but items come from {}",
+        target.name,
+        foreign_servers
+            .into_iter()
+            .map(|name| format!("'{name}'"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )))
+}
+
+async fn forward_without_body(
+    state: &AppState,
+    request: reqwest::Request,
+    server: &Server,
+) -> Result<StatusCode, Response> {
+    let response = state.reqwest_client.execute(request).await.map_err(|e| {
+        error!(
+            "Failed to forward collection request to {}: {e}",
+            server.name
+        );
+        StatusCode::BAD_GATEWAY.into_response()
+    })?;
+    StatusCode::from_u16(response.status().as_u16())
+        .map_err(|_| StatusCode::BAD_GATEWAY.into_response())
+}
+
+//http://localhost:3000/Collections?Name=Marvel&Ids=430c368c5eb34534bf98363d5adbb92f,5f7e146c44d84b479cafecd3280be4ea
+/// Create a collection on the server that owns its initial items, or on the
+/// user's primary (highest priority) server when it starts out empty.
+pub async fn create_collection(
+    State(state): State<AppState>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<Json<serde_json::Value>, Response> {
+    validate_collection_items(&state, &preprocessed).await?;
+
+    let proxy_api_key = preprocessed
+        .user
+        .as_ref()
+        .map(|user| user.virtual_key.clone());
+    execute_processed_json_request(
+        &state,
+        preprocessed.request,
+        &preprocessed.server,
+        ResponseProcessingProfile::Media,
+        false,
+        proxy_api_key.as_deref(),
+    )
+    .await
+    .map(Json)
+    .map_err(IntoResponse::into_response)
+}
+
+//http://localhost:3000/Collections/430c368c5eb34534bf98363d5adbb92f/Items?Ids=5f7e146c44d84b479cafecd3280be4ea
+pub async fn add_collection_items(
+    State(state): State<AppState>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<StatusCode, Response> {
+    validate_collection_items(&state, &preprocessed).await?;
+    forward_without_body(&state, preprocessed.request, &preprocessed.server).await
+}
+
+pub async fn remove_collection_items(
+    State(state): State<AppState>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<StatusCode, Response> {
+    validate_collection_items(&state, &preprocessed).await?;
+    forward_without_body(&state, preprocessed.request, &preprocessed.server).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{add_server_with_session, create_test_app_state};
+    use crate::{
+        models::Authorization, request_preprocessing::preprocess_request,
+        user_authorization_service::User,
+    };
+    use axum::body::Body;
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn preprocessed_post(state: &AppState, user: &User, uri: &str) -> PreprocessedRequest {
+        let auth_header = Authorization {
+            client: "Jellyfin Web".to_string(),
+            device: "Firefox".to_string(),
+            device_id: "web-device-id".to_string(),
+            version: "10.10.7".to_string(),
+            token: Some(user.virtual_key.clone()),
+        }
+        .to_header_value();
+        let uri: axum::http::Uri = uri.parse().unwrap();
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header)
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+
+        preprocess_request(request, state).await.unwrap()
+    }
+
+    struct Fixture {
+        state: AppState,
+        user: User,
+        first_upstream: MockServer,
+        first_items: [String; 2],
+        second_item: String,
+    }
+
+    async fn fixture() -> Fixture {
+        let state = create_test_app_state().await;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let first = add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+        let second = add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+        state.server_storage.check_servers_health().await;
+
+        let virtual_id = |original: &'static str, server: Server| {
+            let state = state.clone();
+            async move {
+                state
+                    .media_storage
+                    .get_or_create_media_mapping(original, &server)
+                    .await
+                    .unwrap()
+                    .virtual_media_id
+            }
+        };
+        let first_items = [
+            virtual_id("11111111111111111111111111111111", first.clone()).await,
+            virtual_id("22222222222222222222222222222222", first).await,
+        ];
+        let second_item = virtual_id("33333333333333333333333333333333", second).await;
+
+        Fixture {
+            state,
+            user,
+            first_upstream,
+            first_items,
+            second_item,
+        }
+    }
+
+    #[tokio::test]
+    async fn create_collection_routes_to_the_items_backend() {
+        let fixture = fixture().await;
+        Mock::given(method("POST"))
+            .and(path("/Collections"))
+            .and(query_param(
+                "Ids",
+                "11111111111111111111111111111111,22222222222222222222222222222222",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Id": "44444444444444444444444444444444"
+            })))
+            .expect(1)
+            .mount(&fixture.first_upstream)
+            .await;
+
+        let preprocessed = preprocessed_post(
+            &fixture.state,
+            &fixture.user,
+            &format!(
+                "/Collections?Name=Trilogy&Ids={},{}",
+                fixture.first_items[0], fixture.first_items[1]
+            ),
+        )
+        .await;
+        let Json(response) =
+            create_collection(State(fixture.state.clone()), Preprocessed(preprocessed))
+                .await
+                .unwrap();
+
+        let collection_id = response["Id"].as_str().unwrap();
+        let mapping = fixture
+            .state
+            .media_storage
+            .get_media_mapping_by_virtual(collection_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            mapping.original_media_id,
+            "44444444444444444444444444444444"
+        );
+    }
+
+    #[tokio::test]
+    async fn cross_backend_collection_is_rejected() {
+        let fixture = fixture().await;
+        Mock::given(method("POST"))
+            .and(path("/Collections"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&fixture.first_upstream)
+            .await;
+
+        let preprocessed = preprocessed_post(
+            &fixture.state,
+            &fixture.user,
+            &format!(
+                "/Collections?Name=Mixed&Ids={},{}",
+                fixture.first_items[0], fixture.second_item
+            ),
+        )
+        .await;
+        let response = create_collection(State(fixture.state.clone()), Preprocessed(preprocessed))
+            .await
+            .unwrap_err();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let message = String::from_utf8(body.to_vec()).unwrap();
+        assert!(message.contains("'First'"), "{message}");
+        assert!(message.contains("'Second'"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn adding_items_from_another_backend_is_rejected() {
+        let fixture = fixture().await;
+        let collection = fixture
+            .state
+            .media_storage
+            .get_or_create_media_mapping(
+                "44444444444444444444444444444444",
+                &fixture.state.server_storage.list_servers().await.unwrap()[0],
+            )
+            .await
+            .unwrap();
+        Mock::given(method("POST"))
+            .and(path("/Collections/44444444444444444444444444444444/Items"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&fixture.first_upstream)
+            .await;
+
+        let same_backend = preprocessed_post(
+            &fixture.state,
+            &fixture.user,
+            &format!(
+                "/Collections/{}/Items?Ids={}",
+                collection.virtual_media_id, fixture.first_items[1]
+            ),
+        )
+        .await;
+        let status = add_collection_items(State(fixture.state.clone()), Preprocessed(same_backend))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let other_backend = preprocessed_post(
+            &fixture.state,
+            &fixture.user,
+            &format!(
+                "/Collections/{}/Items?Ids={}",
+                collection.virtual_media_id, fixture.second_item
+            ),
+        )
+        .await;
+        let response =
+            add_collection_items(State(fixture.state.clone()), Preprocessed(other_backend))
+                .await
+                .unwrap_err();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -4,11 +4,18 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use hyper::StatusCode;
 use reqwest::header::{HeaderValue, CONTENT_LENGTH, TRANSFER_ENCODING};
 use serde::Serialize;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
+    error_response::{record_upstream_failure, UpstreamFailure},
     models::{MediaSource, PlaybackRequest, PlaybackResponse},
-    processors::response_processor::ResponseProcessingProfile,
+    processors::{
+        response_processor::ResponseProcessingProfile,
+        url_processor::{
+            find_query_value, matches_case_insensitive, MAX_STREAMING_BITRATE_QUERY_TAGS,
+            PLAY_SESSION_ID_QUERY_TAGS,
+        },
+    },
     server_storage::Server,
     session_storage::PlaybackSession,
     user_authorization_service::AuthorizationSession,
@@ -37,6 +44,47 @@ where
     }
 }
 
+/// `PlaybackRequest` fields that hold ids, which stay strings even when they look
+/// like numbers.
+const PLAYBACK_QUERY_STRING_FIELDS: &[&str] =
+    &["MediaSourceId", "UserId", "LiveStreamId", "PlaySessionId"];
+
+/// Read a `PlaybackRequest` from the query of a `GET .../PlaybackInfo` request.
+/// Query values are untyped, so booleans and numbers are recognized by their text.
+pub fn playback_request_from_query(
+    request: &reqwest::Request,
+) -> Result<PlaybackRequest, StatusCode> {
+    let pairs: Vec<(String, String)> =
+        serde_urlencoded::from_str(request.url().query().unwrap_or_default()).map_err(|e| {
+            error!("Failed to parse PlaybackInfo query: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+
+    let fields = pairs
+        .into_iter()
+        .map(|(key, value)| {
+            let value = if PLAYBACK_QUERY_STRING_FIELDS
+                .iter()
+                .any(|field| field.eq_ignore_ascii_case(&key))
+            {
+                serde_json::Value::String(value)
+            } else if let Ok(flag) = value.to_ascii_lowercase().parse::<bool>() {
+                serde_json::Value::Bool(flag)
+            } else if let Ok(number) = value.parse::<i64>() {
+                serde_json::Value::from(number)
+            } else {
+                serde_json::Value::String(value)
+            };
+            (key, value)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    serde_json::from_value(serde_json::Value::Object(fields)).map_err(|e| {
+        error!("Failed to read PlaybackRequest from query: {}", e);
+        StatusCode::BAD_REQUEST
+    })
+}
+
 pub fn set_json_body<T>(request: &mut reqwest::Request, payload: &T) -> Result<(), StatusCode>
 where
     T: Serialize,
@@ -68,18 +116,26 @@ pub async fn execute_json_request<T>(
 where
     T: serde::de::DeserializeOwned,
 {
-    let response = client
-        .execute(request)
-        .await
-        .map_err(|e| {
-            error!("Failed to execute request: {}", e);
-            StatusCode::BAD_GATEWAY
-        })?
-        .error_for_status()
-        .map_err(|e| {
-            error!("Request failed with status: {}", e);
-            StatusCode::UNAUTHORIZED
-        })?;
+    let response = client.execute(request).await.map_err(|e| {
+        error!("Failed to execute request: {}", e);
+        StatusCode::BAD_GATEWAY
+    })?;
+    json_response(response).await
+}
+
+/// Parse the JSON body of a successful upstream response.
+pub async fn json_response<T>(response: reqwest::Response) -> Result<T, StatusCode>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        let url = response.url().clone();
+        error!("Request to {} failed with status: {}", url, status);
+        let body = response.text().await.unwrap_or_default();
+        record_upstream_failure(UpstreamFailure { status, url, body });
+        return Err(StatusCode::UNAUTHORIZED);
+    }
 
     let response_text = response.text().await.map_err(|e| {
         error!("Failed to get response text: {}", e);
@@ -179,9 +235,11 @@ pub async fn execute_processed_json_request(
     should_change_name: bool,
     proxy_api_key: Option<&str>,
 ) -> Result<serde_json::Value, StatusCode> {
+    let upstream_slot = state.acquire_upstream_slot(server).await?;
     let mut response = execute_json_request::<serde_json::Value>(&state.reqwest_client, request)
         .await
         .inspect_err(|e| error!("Failed to get upstream JSON: {:?}", e))?;
+    drop(upstream_slot);
 
     state
         .process_response_json(
@@ -246,11 +304,126 @@ pub async fn remap_playback_request(
     Ok(())
 }
 
+/// Clamp a playback request to the bitrate the user has left under
+/// `per_user_max_bitrate`, taking their other active streams into account.
+///
+/// Jellyfin clients send `MaxStreamingBitrate` in the body, the query or both, so the
+/// upstream request's query is clamped as well. Returns the bitrate the stream reserves
+/// once its playback starts; a request without a bitrate gets, and reserves, whatever
+/// is left of the cap.
+pub async fn apply_user_bitrate_cap(
+    payload: &mut PlaybackRequest,
+    request: &mut reqwest::Request,
+    state: &AppState,
+    session: &AuthorizationSession,
+) -> Result<Option<i64>, StatusCode> {
+    let query_bitrate = find_query_value(request.url(), MAX_STREAMING_BITRATE_QUERY_TAGS)
+        .and_then(|value| value.parse::<i64>().ok());
+    let requested = match (payload.max_streaming_bitrate, query_bitrate) {
+        (Some(body), Some(query)) => Some(body.min(query)),
+        (body, query) => body.or(query),
+    };
+
+    let Some(cap) = state.per_user_max_bitrate().await else {
+        return Ok(requested);
+    };
+
+    // A client renegotiating an existing play session replaces that stream.
+    let play_session_id = find_query_value(request.url(), PLAY_SESSION_ID_QUERY_TAGS)
+        .or_else(|| find_play_session_id(&payload.extra));
+    let in_use = state
+        .play_sessions
+        .active_bitrate_for_user(&session.user_id, play_session_id.as_deref())
+        .await;
+    let available = cap - in_use;
+    if available <= 0 {
+        warn!(
+            "User {} already streams {} of their {} bps bitrate cap; rejecting new stream",
+            session.user_id, in_use, cap
+        );
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    let bitrate = requested.map_or(available, |requested| requested.min(available));
+    if requested.is_some_and(|requested| requested != bitrate) {
+        info!(
+            "Clamping stream bitrate for user {} to {} bps ({} of {} bps in use)",
+            session.user_id, bitrate, in_use, cap
+        );
+    }
+
+    payload.max_streaming_bitrate = Some(bitrate);
+    if query_bitrate.is_some() {
+        let pairs: Vec<(String, String)> = request
+            .url()
+            .query_pairs()
+            .map(|(key, value)| {
+                if matches_case_insensitive(&key, MAX_STREAMING_BITRATE_QUERY_TAGS) {
+                    (key.to_string(), bitrate.to_string())
+                } else {
+                    (key.to_string(), value.to_string())
+                }
+            })
+            .collect();
+        request
+            .url_mut()
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(pairs);
+    }
+
+    Ok(Some(bitrate))
+}
+
+/// Play session id of a JSON playback body. Only `PlaySessionId` is considered here:
+/// report bodies also carry the unrelated Jellyfin `SessionId`.
+fn find_play_session_id<'a>(
+    body: impl IntoIterator<Item = (&'a String, &'a serde_json::Value)>,
+) -> Option<String> {
+    body.into_iter()
+        .find(|(key, _)| matches_case_insensitive(key, &["PlaySessionId"]))
+        .and_then(|(_, value)| value.as_str().map(str::to_string))
+}
+
+/// Keep the bandwidth accounting in sync with the client's playback reports:
+/// start and progress reports activate or refresh a stream, a stop report releases it.
+/// Legacy `/Users/{id}/PlayingItems/{id}` reports stop playback with `DELETE`.
+pub async fn update_stream_activity(state: &AppState, request: &reqwest::Request) {
+    let path = request.url().path().to_ascii_lowercase();
+    let legacy_report = path.contains("/playingitems/");
+    let stopped = path.ends_with("/sessions/playing/stopped")
+        || (legacy_report && request.method() == reqwest::Method::DELETE);
+    if !stopped
+        && !legacy_report
+        && !path.ends_with("/sessions/playing")
+        && !path.ends_with("/sessions/playing/progress")
+    {
+        return;
+    }
+
+    let play_session_id =
+        find_query_value(request.url(), PLAY_SESSION_ID_QUERY_TAGS).or_else(|| {
+            let body = request.body()?.as_bytes()?;
+            let report = serde_json::from_slice::<serde_json::Map<_, _>>(body).ok()?;
+            find_play_session_id(&report)
+        });
+    let Some(play_session_id) = play_session_id else {
+        return;
+    };
+
+    if stopped {
+        state.play_sessions.release_stream(&play_session_id).await;
+    } else {
+        state.play_sessions.start_stream(&play_session_id).await;
+    }
+}
+
 pub async fn process_playback_response(
     response: &mut PlaybackResponse,
     state: &AppState,
     server: &Server,
     session: &AuthorizationSession,
+    stream_bitrate: Option<i64>,
 ) -> Result<(), StatusCode> {
     let proxy_user = state
         .user_authorization
@@ -268,6 +441,12 @@ pub async fn process_playback_response(
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
+    let original_source_ids: Vec<String> = response
+        .media_sources
+        .iter()
+        .map(|source| source.id.clone())
+        .collect();
+
     let mut response_json = serde_json::to_value(&*response).map_err(|e| {
         error!("Failed to serialize playback response JSON: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
@@ -285,24 +464,37 @@ pub async fn process_playback_response(
 
     *response = response_json_to_payload(response_json)?;
 
-    for item in &response.media_sources {
+    for (item, original_id) in response.media_sources.iter().zip(&original_source_ids) {
         track_play_session(
             item,
+            original_id,
             &response.play_session_id,
-            &session.user_id,
+            session,
             server,
             state,
         )
         .await?;
     }
 
+    if let Some(bitrate) = stream_bitrate {
+        state
+            .play_sessions
+            .plan_stream(&session.user_id, &response.play_session_id, bitrate)
+            .await;
+    }
+
     Ok(())
 }
 
+/// Remember which backend, backend user and backend media source a play session
+/// belongs to, so later reports and stream requests carrying its `PlaySessionId` or
+/// one of its ids are routed back to it. `item` is the already remapped source and
+/// `original_item_id` its id on `server`.
 pub async fn track_play_session(
     item: &MediaSource,
+    original_item_id: &str,
     session_id: &str,
-    user_id: &str,
+    session: &AuthorizationSession,
     server: &Server,
     state: &AppState,
 ) -> Result<(), StatusCode> {
@@ -319,6 +511,11 @@ pub async fn track_play_session(
         &mut session_ids,
         &mut item_ids,
     );
+    collect_delivery_url_tracking_values(
+        item.direct_stream_url.as_deref(),
+        &mut session_ids,
+        &mut item_ids,
+    );
 
     if let Some(media_streams) = &item.media_streams {
         for stream in media_streams {
@@ -344,7 +541,19 @@ pub async fn track_play_session(
 
     for tracked_session_id in session_ids {
         for item_id in &item_ids {
-            add_tracked_play_session(item_id, &tracked_session_id, user_id, server, state).await;
+            add_tracked_play_session(
+                PlaybackSession {
+                    session_id: tracked_session_id.clone(),
+                    item_id: item_id.clone(),
+                    user_id: session.user_id.clone(),
+                    server_id: server.id,
+                    original_user_id: session.original_user_id.clone(),
+                    original_item_id: original_item_id.to_string(),
+                },
+                server,
+                state,
+            )
+            .await;
         }
     }
 
@@ -375,26 +584,12 @@ fn push_unique(values: &mut Vec<String>, value: String) {
     }
 }
 
-async fn add_tracked_play_session(
-    item_id: &str,
-    session_id: &str,
-    user_id: &str,
-    server: &Server,
-    state: &AppState,
-) {
+async fn add_tracked_play_session(session: PlaybackSession, server: &Server, state: &AppState) {
     info!(
         "Tracking play session for item: {}, server: {}",
-        item_id, server.name
+        session.item_id, server.name
     );
-    state
-        .play_sessions
-        .add_session(PlaybackSession {
-            item_id: item_id.to_string(),
-            session_id: session_id.to_string(),
-            user_id: user_id.to_string(),
-            server_id: server.id,
-        })
-        .await;
+    state.play_sessions.add_session(session).await;
 }
 
 fn extract_media_id_from_delivery_url(value: &str) -> Option<String> {
@@ -424,86 +619,40 @@ fn extract_play_session_id_from_delivery_url(value: &str) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
-
     use serde_json::json;
-    use sqlx::SqlitePool;
 
     use super::*;
+    use crate::test_support::create_test_app_state_with_config;
     use crate::{
-        config::{AppConfig, MediaStreamingMode, MIGRATOR},
-        media_storage_service::MediaStorageService,
+        config::{AppConfig, MediaStreamingMode},
         server_id::ServerId,
-        server_storage::ServerStorageService,
         server_url::ServerUrl,
-        session_storage::SessionStorage,
-        user_authorization_service::UserAuthorizationService,
-        virtual_library_service::VirtualLibraryService,
-        DataContext, ProxyProcessors,
     };
 
     async fn create_test_state() -> (AppState, Server) {
-        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&pool).await.unwrap();
-
-        let now = chrono::Utc::now();
-        let result = sqlx::query(
-            r#"
-            INSERT INTO servers (name, url, priority, created_at, updated_at)
-            VALUES (?, ?, ?, ?, ?)
-            "#,
-        )
-        .bind("People Server")
-        .bind("http://people.example:8096")
-        .bind(100)
-        .bind(now)
-        .bind(now)
-        .execute(&pool)
-        .await
-        .unwrap();
-
-        let server = Server {
-            id: ServerId::new(result.last_insert_rowid()),
-            name: "People Server".to_string(),
-            url: ServerUrl::parse("http://people.example:8096").unwrap(),
-            priority: 100,
-            media_streaming_mode: MediaStreamingMode::Redirect,
-            created_at: now,
-            updated_at: now,
-        };
-
-        let config = AppConfig {
+        let state = create_test_app_state_with_config(AppConfig {
             server_id: "proxy-server".to_string(),
             ..AppConfig::default()
-        };
-        let server_storage = ServerStorageService::new(pool.clone());
-        let media_storage = MediaStorageService::new(pool.clone());
-
-        let data_context = DataContext {
-            user_authorization: Arc::new(UserAuthorizationService::new(pool.clone())),
-            server_storage: Arc::new(server_storage.clone()),
-            media_storage: Arc::new(media_storage.clone()),
-            virtual_library_service: Arc::new(VirtualLibraryService::new(
-                pool,
-                server_storage,
-                media_storage,
-            )),
-            play_sessions: Arc::new(SessionStorage::new()),
-            config: Arc::new(tokio::sync::RwLock::new(config)),
-        };
-
-        let processors = ProxyProcessors::new(data_context.clone());
+        })
+        .await;
+        let server_id = state
+            .server_storage
+            .add_server(
+                "People Server",
+                "http://people.example:8096",
+                100,
+                MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+        let server = state
+            .server_storage
+            .get_server_by_id(server_id)
+            .await
+            .unwrap()
+            .unwrap();
 
-        (
-            AppState::new(
-                reqwest::Client::new(),
-                reqwest::Client::new(),
-                data_context,
-                processors,
-                crate::handlers::quick_connect::QuickConnectStorage::new(),
-            ),
-            server,
-        )
+        (state, server)
     }
 
     #[tokio::test]
@@ -638,6 +787,137 @@ mod tests {
             .contains("api_key=proxy-token"));
     }
 
+    #[tokio::test]
+    async fn response_processor_routes_subtitle_delivery_urls_through_the_proxy() {
+        let (state, server) = create_test_state().await;
+        let item_id = "41414141414141414141414141414141";
+        let source_id = "42424242424242424242424242424242";
+        let external = "https://subtitles.example/heat.srt";
+        let mut media_source = json!({
+            "Id": source_id,
+            "MediaStreams": [
+                {
+                    "Index": 2,
+                    "Type": "Subtitle",
+                    "Codec": "srt",
+                    "IsExternal": true,
+                    "DeliveryMethod": "External",
+                    "DeliveryUrl": format!(
+                        "/Videos/{item_id}/{source_id}/Subtitles/2/Stream.srt?api_key=upstream-token"
+                    )
+                },
+                {
+                    "Index": 3,
+                    "Type": "Subtitle",
+                    "Codec": "ass",
+                    "DeliveryMethod": "External",
+                    "DeliveryUrl": format!(
+                        "http://people.example:8096/Videos/{item_id}/{source_id}/Subtitles/3/0/Stream.ass?api_key=upstream-token"
+                    )
+                },
+                {
+                    "Index": 4,
+                    "Type": "Subtitle",
+                    "Codec": "srt",
+                    "IsExternal": true,
+                    "IsExternalUrl": true,
+                    "DeliveryMethod": "External",
+                    "DeliveryUrl": external
+                }
+            ]
+        });
+
+        state
+            .process_response_json(
+                &mut media_source,
+                &server,
+                ResponseProcessingProfile::Media,
+                false,
+                Some("proxy-token"),
+            )
+            .await
+            .unwrap();
+
+        let virtual_item_id = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &server)
+            .await
+            .unwrap()
+            .virtual_media_id;
+        let virtual_source_id = state
+            .media_storage
+            .get_or_create_media_mapping(source_id, &server)
+            .await
+            .unwrap()
+            .virtual_media_id;
+        let streams = &media_source["MediaStreams"];
+        assert_eq!(
+            streams[0]["DeliveryUrl"],
+            format!(
+                "/Videos/{virtual_item_id}/{virtual_source_id}/Subtitles/2/Stream.srt?api_key=proxy-token"
+            )
+        );
+        assert_eq!(
+            streams[1]["DeliveryUrl"],
+            format!(
+                "/Videos/{virtual_item_id}/{virtual_source_id}/Subtitles/3/0/Stream.ass?api_key=proxy-token"
+            )
+        );
+        assert_eq!(streams[2]["DeliveryUrl"], external);
+    }
+
+    #[tokio::test]
+    async fn browse_responses_store_mappings_for_every_rewritten_id() {
+        let (state, server) = create_test_state().await;
+        let episode_id = "51515151515151515151515151515151";
+        let series_id = "52525252525252525252525252525252";
+        let season_id = "53535353535353535353535353535353";
+        let mut browse = json!({
+            "Items": [
+                {
+                    "Id": episode_id,
+                    "Type": "Episode",
+                    "Name": "Pilot",
+                    "SeriesId": series_id,
+                    "SeasonId": season_id,
+                    "ParentId": season_id
+                }
+            ],
+            "TotalRecordCount": 1
+        });
+
+        state
+            .process_response_json(
+                &mut browse,
+                &server,
+                ResponseProcessingProfile::Media,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let item = &browse["Items"][0];
+        for (field, original_id) in [
+            ("Id", episode_id),
+            ("SeriesId", series_id),
+            ("SeasonId", season_id),
+            ("ParentId", season_id),
+        ] {
+            let virtual_id = item[field].as_str().unwrap();
+            assert_ne!(virtual_id, original_id, "{field} was not rewritten");
+            let (mapping, mapped_server) = state
+                .media_storage
+                .get_media_mapping_with_server(virtual_id)
+                .await
+                .unwrap()
+                .unwrap_or_else(|| panic!("no mapping stored for {field}"));
+            assert_eq!(mapping.original_media_id, original_id);
+            assert_eq!(mapped_server.id, server.id);
+        }
+        assert_eq!(item["SeasonId"], item["ParentId"]);
+    }
+
     #[tokio::test]
     async fn response_processor_remaps_top_level_item_arrays() {
         let (state, server) = create_test_state().await;
@@ -679,6 +959,130 @@ mod tests {
         assert_ne!(media_items[1]["People"][0]["Id"].as_str(), Some(person_id));
     }
 
+    #[tokio::test]
+    async fn response_processor_tags_items_with_their_source_server_when_enabled() {
+        let (state, server) = create_test_state().await;
+        let items = json!({
+            "Items": [
+                {
+                    "Id": "34343434343434343434343434343434",
+                    "Type": "Movie",
+                    "Name": "Tagged",
+                    "UserData": {
+                        "ItemId": "34343434343434343434343434343434"
+                    }
+                }
+            ],
+            "TotalRecordCount": 1
+        });
+
+        let mut untagged = items.clone();
+        state
+            .process_response_json(
+                &mut untagged,
+                &server,
+                ResponseProcessingProfile::Media,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(untagged["Items"][0].get("JellyswarrmServer").is_none());
+        assert!(untagged.get("JellyswarrmServer").is_none());
+
+        state.config.write().await.tag_source_server = true;
+        let mut tagged = items.clone();
+        state
+            .process_response_json(
+                &mut tagged,
+                &server,
+                ResponseProcessingProfile::Media,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            tagged["Items"][0]["JellyswarrmServer"],
+            json!({ "Name": "People Server", "Id": server.id.to_string() })
+        );
+        assert_eq!(tagged["Items"][0]["Name"], "Tagged");
+        assert!(tagged["Items"][0]["UserData"]
+            .get("JellyswarrmServer")
+            .is_none());
+        assert!(tagged.get("JellyswarrmServer").is_none());
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_are_capped_per_backend() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let (state, server) = create_test_state().await;
+        {
+            let mut config = state.config.write().await;
+            config.max_connections_per_server = 1;
+            config.upstream_queue_timeout_ms = 50;
+        }
+        let limited_upstream = MockServer::start().await;
+        let unlimited_upstream = MockServer::start().await;
+        for upstream in [&limited_upstream, &unlimited_upstream] {
+            Mock::given(method("GET"))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(json!({}))
+                        .set_delay(std::time::Duration::from_millis(300)),
+                )
+                .mount(upstream)
+                .await;
+        }
+        let limited = Server {
+            url: ServerUrl::parse(&limited_upstream.uri()).unwrap(),
+            ..server.clone()
+        };
+        let unlimited = Server {
+            id: ServerId::new(server.id.as_i64() + 1),
+            url: ServerUrl::parse(&unlimited_upstream.uri()).unwrap(),
+            max_connections: Some(0),
+            backend_id: None,
+            ..server
+        };
+
+        let fetch = |server: &Server| {
+            let state = state.clone();
+            let server = server.clone();
+            async move {
+                let request = reqwest::Request::new(
+                    reqwest::Method::GET,
+                    format!("{}/Items", server.url).parse().unwrap(),
+                );
+                execute_processed_json_request(
+                    &state,
+                    request,
+                    &server,
+                    ResponseProcessingProfile::Media,
+                    false,
+                    None,
+                )
+                .await
+            }
+        };
+        let (first, second, third, fourth) = tokio::join!(
+            fetch(&limited),
+            fetch(&limited),
+            fetch(&unlimited),
+            fetch(&unlimited),
+        );
+
+        let mut limited_statuses = [first.err(), second.err()];
+        limited_statuses.sort();
+        assert_eq!(
+            limited_statuses,
+            [None, Some(StatusCode::SERVICE_UNAVAILABLE)]
+        );
+        assert!(third.is_ok());
+        assert!(fourth.is_ok());
+    }
+
     #[tokio::test]
     async fn best_effort_response_profile_remaps_media_like_fields() {
         let (state, server) = create_test_state().await;
@@ -780,9 +1184,16 @@ mod tests {
         }))
         .unwrap();
 
-        track_play_session(&source, "session-1", "user-1", &server, &state)
-            .await
-            .unwrap();
+        track_play_session(
+            &source,
+            "upstream-source-id",
+            "session-1",
+            &test_session("user-1"),
+            &server,
+            &state,
+        )
+        .await
+        .unwrap();
 
         let source_session = state
             .play_sessions
@@ -797,6 +1208,8 @@ mod tests {
 
         assert_eq!(source_session.server_id, server.id);
         assert_eq!(video_session.server_id, server.id);
+        assert_eq!(source_session.original_user_id, "upstream-user");
+        assert_eq!(source_session.original_item_id, "upstream-source-id");
     }
 
     #[tokio::test]
@@ -820,9 +1233,16 @@ mod tests {
         }))
         .unwrap();
 
-        track_play_session(&source, "response-session", "user-1", &server, &state)
-            .await
-            .unwrap();
+        track_play_session(
+            &source,
+            "upstream-source-id",
+            "response-session",
+            &test_session("user-1"),
+            &server,
+            &state,
+        )
+        .await
+        .unwrap();
 
         for session_id in [
             "response-session",
@@ -847,4 +1267,138 @@ mod tests {
             }
         }
     }
+
+    fn test_session(user_id: &str) -> AuthorizationSession {
+        let now = chrono::Utc::now();
+        AuthorizationSession {
+            id: 1,
+            user_id: user_id.to_string(),
+            mapping_id: 1,
+            server_url: "http://people.example:8096".to_string(),
+            device: crate::user_authorization_service::Device {
+                client: "Jellyfin Web".to_string(),
+                device: "Firefox".to_string(),
+                device_id: "device-1".to_string(),
+                version: "10.10.0".to_string(),
+            },
+            jellyfin_token: "token".to_string(),
+            original_user_id: "upstream-user".to_string(),
+            expires_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn playback_request(max_streaming_bitrate: i64) -> PlaybackRequest {
+        serde_json::from_value(json!({ "MaxStreamingBitrate": max_streaming_bitrate })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn second_concurrent_stream_is_clamped_to_user_bitrate_cap() {
+        let (state, _server) = create_test_state().await;
+        state.config.write().await.per_user_max_bitrate = 10_000_000;
+        let session = test_session("user-1");
+
+        let mut first = playback_request(8_000_000);
+        let mut first_request = reqwest::Request::new(
+            reqwest::Method::POST,
+            "http://people.example:8096/Items/item-1/PlaybackInfo"
+                .parse()
+                .unwrap(),
+        );
+        let first_bitrate =
+            apply_user_bitrate_cap(&mut first, &mut first_request, &state, &session)
+                .await
+                .unwrap();
+        assert_eq!(first_bitrate, Some(8_000_000));
+        state
+            .play_sessions
+            .track_stream(&session.user_id, "play-1", first_bitrate.unwrap())
+            .await;
+
+        let mut second = playback_request(8_000_000);
+        let mut second_request = reqwest::Request::new(
+            reqwest::Method::POST,
+            "http://people.example:8096/Items/item-2/PlaybackInfo?MaxStreamingBitrate=8000000"
+                .parse()
+                .unwrap(),
+        );
+        let second_bitrate =
+            apply_user_bitrate_cap(&mut second, &mut second_request, &state, &session)
+                .await
+                .unwrap();
+        assert_eq!(second_bitrate, Some(2_000_000));
+        assert_eq!(second.max_streaming_bitrate, Some(2_000_000));
+        assert_eq!(
+            find_query_value(second_request.url(), MAX_STREAMING_BITRATE_QUERY_TAGS).as_deref(),
+            Some("2000000")
+        );
+        state
+            .play_sessions
+            .track_stream(&session.user_id, "play-2", second_bitrate.unwrap())
+            .await;
+        assert!(
+            state
+                .play_sessions
+                .active_bitrate_for_user(&session.user_id, None)
+                .await
+                <= 10_000_000
+        );
+
+        let mut third = playback_request(1_000_000);
+        let mut third_request = reqwest::Request::new(
+            reqwest::Method::POST,
+            "http://people.example:8096/Items/item-3/PlaybackInfo"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            apply_user_bitrate_cap(&mut third, &mut third_request, &state, &session).await,
+            Err(StatusCode::TOO_MANY_REQUESTS)
+        );
+
+        // Other users have their own budget.
+        let mut other = playback_request(8_000_000);
+        assert_eq!(
+            apply_user_bitrate_cap(
+                &mut other,
+                &mut third_request,
+                &state,
+                &test_session("user-2")
+            )
+            .await
+            .unwrap(),
+            Some(8_000_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_without_a_bitrate_reserve_what_is_left_of_the_cap() {
+        let (state, _server) = create_test_state().await;
+        state.config.write().await.per_user_max_bitrate = 10_000_000;
+        let session = test_session("user-1");
+        let mut request = reqwest::Request::new(
+            reqwest::Method::POST,
+            "http://people.example:8096/Items/item-1/PlaybackInfo"
+                .parse()
+                .unwrap(),
+        );
+
+        let mut first: PlaybackRequest = serde_json::from_value(json!({})).unwrap();
+        let first_bitrate = apply_user_bitrate_cap(&mut first, &mut request, &state, &session)
+            .await
+            .unwrap();
+        assert_eq!(first_bitrate, Some(10_000_000));
+        assert_eq!(first.max_streaming_bitrate, Some(10_000_000));
+        state
+            .play_sessions
+            .track_stream(&session.user_id, "play-1", first_bitrate.unwrap())
+            .await;
+
+        let mut second: PlaybackRequest = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(
+            apply_user_bitrate_cap(&mut second, &mut request, &state, &session).await,
+            Err(StatusCode::TOO_MANY_REQUESTS)
+        );
+    }
 }
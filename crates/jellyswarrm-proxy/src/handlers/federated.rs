@@ -1,24 +1,43 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
 
-use axum::{extract::State, Json};
+use axum::{
+    extract::State,
+    http::HeaderValue,
+    response::{IntoResponse, Response},
+    Json,
+};
 use hyper::StatusCode;
 use tokio::task::JoinSet;
 use tracing::{debug, error, trace, warn};
 
 use crate::{
-    duplicate_policy::{DuplicatePolicy, DuplicatePolicyConfig, TaggedMediaItem},
+    duplicate_policy::{
+        box_set_member_keys, box_set_merge_key, merge_duplicate_versions, DuplicatePolicy,
+        DuplicatePolicyConfig, TaggedMediaItem, TitleNormalizer,
+    },
     extractors::Preprocessed,
     handlers::{
-        common::{execute_json_request, response_json_to_payload},
-        items::get_items,
+        common::{
+            execute_json_request, execute_processed_json_request, json_response,
+            response_json_to_payload,
+        },
+        items::{get_items, session_request},
+        watch_state::without_sync_mirrors,
     },
     models::{
         enums::{BaseItemKind, CollectionType},
         ItemsResponseVariants, ItemsResponseWithCount, MediaItem,
     },
-    processors::response_processor::ResponseProcessingProfile,
+    processors::{
+        response_processor::ResponseProcessingProfile,
+        url_processor::{find_query_value, matches_case_insensitive, PARENT_ID_QUERY_TAGS},
+    },
     request_preprocessing::{apply_to_request, JellyfinAuthorization, PreprocessedRequest},
     server_storage::Server,
+    url_helper::join_server_url,
     user_authorization_service::AuthorizationSession,
     virtual_library_service::{
         normalize_library_id, LibraryAssignment, LibraryGrouping, ResolvedVirtualLibrary,
@@ -29,12 +48,53 @@ use crate::{
 
 mod postprocessing;
 
-use postprocessing::{FederatedItems, MergeStrategy, Pagination, ResponseShape, ServerItems};
+use postprocessing::{
+    CatalogPaging, FederatedItems, MergeStrategy, Pagination, ResponseShape, ServerItems,
+    ServerNameSuffixes,
+};
+
+/// Query keys filtering by genre or studio ids, each with the key filtering by their
+/// names instead. Ids are specific to a server while names are the same on all of them.
+const NAMED_FILTER_QUERY_TAGS: &[(&str, &str)] =
+    &[("GenreIds", "Genres"), ("StudioIds", "Studios")];
+
+/// Header reporting how many servers were left out of a merged response because
+/// they failed to answer. It is only set on partial responses.
+pub const SKIPPED_SERVERS_HEADER: &str = "x-jellyswarrm-skipped-servers";
+
+/// A merged items response, served with `200` even when some servers were skipped.
+pub struct FederatedResponse {
+    body: serde_json::Value,
+    skipped_servers: usize,
+}
+
+impl From<Json<serde_json::Value>> for FederatedResponse {
+    fn from(Json(body): Json<serde_json::Value>) -> Self {
+        Self {
+            body,
+            skipped_servers: 0,
+        }
+    }
+}
+
+impl IntoResponse for FederatedResponse {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.body).into_response();
+        if self.skipped_servers > 0 {
+            response.headers_mut().insert(
+                SKIPPED_SERVERS_HEADER,
+                HeaderValue::from(self.skipped_servers),
+            );
+        }
+        response
+    }
+}
 
 struct RawFederatedCatalog {
     server_items: Vec<ServerItems>,
     failures: usize,
     response_shape: ResponseShape,
+    paging: CatalogPaging,
 }
 
 struct AutomaticGroupPresentation {
@@ -52,27 +112,29 @@ struct ServerMediaItem {
     server: Server,
 }
 
-fn unique_server_sessions(
+/// One session per server, leaving out the servers that only mirror the watch
+/// state of another listed server.
+async fn unique_server_sessions(
+    state: &AppState,
     sessions: Vec<(AuthorizationSession, Server)>,
 ) -> Vec<(AuthorizationSession, Server)> {
     let mut seen_servers = HashSet::new();
-    sessions
+    let sessions = sessions
         .into_iter()
         .filter(|(_, server)| seen_servers.insert(server.id))
-        .collect()
+        .collect();
+    without_sync_mirrors(sessions, &state.watch_state_mirrors().await)
 }
 
 fn extract_parent_id(url: &url::Url) -> Option<String> {
-    url.query_pairs()
-        .find(|(key, _)| key.eq_ignore_ascii_case("ParentId"))
-        .map(|(_, value)| value.into_owned())
+    find_query_value(url, PARENT_ID_QUERY_TAGS)
 }
 
 fn replace_parent_id(url: &url::Url, new_id: &str) -> url::Url {
     let pairs = url
         .query_pairs()
         .map(|(key, value)| {
-            let value = if key.eq_ignore_ascii_case("ParentId") {
+            let value = if matches_case_insensitive(&key, PARENT_ID_QUERY_TAGS) {
                 new_id.to_string()
             } else {
                 value.into_owned()
@@ -86,14 +148,50 @@ fn replace_parent_id(url: &url::Url, new_id: &str) -> url::Url {
     new_url
 }
 
+/// Query parameters that scope a listing to one item of one server, so it is never
+/// fanned out. Virtual library `ParentId`s are decided later, since that needs a lookup.
+const SINGLE_SERVER_QUERY_PARAMS: &[&str] = &["SeriesId", "SeasonId"];
+
+/// Whether a listing request goes to a single server instead of all of them. Besides
+/// [`SINGLE_SERVER_QUERY_PARAMS`], `single_server_params` may add entries from the
+/// config: `Name` restricts when the parameter is present, `Name=Value` when one of its
+/// comma-separated values equals `Value`. Names and values are case-insensitive.
+pub fn should_restrict_federation(url: &url::Url, single_server_params: &[String]) -> bool {
+    if has_query_key(url, SINGLE_SERVER_QUERY_PARAMS) {
+        return true;
+    }
+
+    single_server_params.iter().any(|param| {
+        let (name, expected) = match param.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (param.trim(), None),
+        };
+        url.query_pairs()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .any(|(_, value)| match expected {
+                Some(expected) => value
+                    .split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case(expected)),
+                None => true,
+            })
+    })
+}
+
 pub async fn get_items_from_all_servers_if_not_restricted(
     State(state): State<AppState>,
     Preprocessed(preprocessed): Preprocessed,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<FederatedResponse, StatusCode> {
     let original_request = &preprocessed.original_request;
 
-    if has_query_key(original_request.url(), &["SeriesId"]) {
-        return get_items(State(state), Preprocessed(preprocessed)).await;
+    let single_server_params = state.config.read().await.single_server_query_params.clone();
+    if should_restrict_federation(original_request.url(), &single_server_params) {
+        debug!(
+            "Not fanning out {}: query restricts it to one server",
+            original_request.url().path()
+        );
+        return get_items(State(state), Preprocessed(preprocessed))
+            .await
+            .map(FederatedResponse::from);
     }
 
     get_items_from_all_servers_preprocessed(&state, preprocessed).await
@@ -114,10 +212,26 @@ async fn get_virtual_library_items(
     state: &AppState,
     preprocessed: PreprocessedRequest,
     resolved: ResolvedVirtualLibrary,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    let duplicate_config = resolved.library.duplicate_config();
+) -> Result<FederatedResponse, StatusCode> {
+    let duplicate_config = if resolved.library.is_box_set() {
+        DuplicatePolicyConfig {
+            policy: state.box_set_duplicate_policy().await,
+            preferred_server_id: None,
+        }
+    } else {
+        resolved.library.duplicate_config()
+    };
+    let dedup_strategy = match resolved.library.dedup_strategy() {
+        Some(strategy) => strategy,
+        None => state.library_deduplication_strategy().await,
+    };
+    let access_scope = preprocessed.access_scope;
     let original_request = preprocessed.original_request;
-    let sessions = unique_server_sessions(preprocessed.sessions.ok_or(StatusCode::UNAUTHORIZED)?);
+    let sessions = unique_server_sessions(
+        state,
+        preprocessed.sessions.ok_or(StatusCode::UNAUTHORIZED)?,
+    )
+    .await;
     if sessions.is_empty() {
         return Err(StatusCode::UNAUTHORIZED);
     }
@@ -185,7 +299,7 @@ async fn get_virtual_library_items(
         });
     }
 
-    let (indexed_results, failures) = collect_federated_results(join_set, failures).await?;
+    let (mut indexed_results, failures) = collect_federated_results(join_set, failures).await?;
 
     if failures > 0 {
         warn!(
@@ -194,6 +308,17 @@ async fn get_virtual_library_items(
         );
     }
 
+    collapse_box_sets(
+        state,
+        &original_request,
+        &sessions,
+        indexed_results
+            .iter_mut()
+            .map(|(_, fetch)| &mut fetch.server_items),
+        access_scope.as_ref(),
+    )
+    .await?;
+
     let response_shape = ResponseShape::from_responses(
         indexed_results
             .iter()
@@ -223,7 +348,9 @@ async fn get_virtual_library_items(
         })
         .collect();
 
-    let items = FederatedItems::from_tagged_items(tagged_items, &duplicate_config);
+    let titles = state.title_normalizer().await;
+    let tagged_items = merge_duplicate_versions(tagged_items, dedup_strategy, &titles);
+    let items = FederatedItems::from_tagged_items(tagged_items, &duplicate_config, &titles);
 
     let total_count = estimate_merged_library_total(
         items.len(),
@@ -231,24 +358,29 @@ async fn get_virtual_library_items(
         upstream_total_sum,
         all_fully_fetched,
     );
-    items_response_to_json(items.with_reported_total(total_count).into_response(
-        original_request.url(),
-        pagination,
-        response_shape,
-    ))
+    items_response_to_json(
+        items.with_reported_total(total_count).into_response(
+            original_request.url(),
+            pagination,
+            response_shape,
+        ),
+        failures,
+    )
 }
 
 pub async fn get_items_from_all_servers(
     State(state): State<AppState>,
     Preprocessed(preprocessed): Preprocessed,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<FederatedResponse, StatusCode> {
     get_items_from_all_servers_preprocessed(&state, preprocessed).await
 }
 
 async fn get_items_from_all_servers_preprocessed(
     state: &AppState,
-    preprocessed: PreprocessedRequest,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+    mut preprocessed: PreprocessedRequest,
+) -> Result<FederatedResponse, StatusCode> {
+    translate_named_filters(state, &mut preprocessed).await;
+
     if let Some(parent_id) = extract_parent_id(preprocessed.original_request.url()) {
         let resolution = state
             .virtual_library_service
@@ -285,15 +417,20 @@ async fn get_items_from_all_servers_preprocessed(
                 } else {
                     ResponseShape::Counted
                 };
-                return items_response_to_json(FederatedItems::default().into_response(
-                    preprocessed.original_request.url(),
-                    pagination,
-                    response_shape,
-                ));
+                return items_response_to_json(
+                    FederatedItems::default().into_response(
+                        preprocessed.original_request.url(),
+                        pagination,
+                        response_shape,
+                    ),
+                    0,
+                );
             }
             VirtualLibraryResolution::Unknown => {
                 if is_single_virtual_library_parent(state, &parent_id).await {
-                    return get_items(State(state.clone()), Preprocessed(preprocessed)).await;
+                    return get_items(State(state.clone()), Preprocessed(preprocessed))
+                        .await
+                        .map(FederatedResponse::from);
                 }
             }
         }
@@ -314,62 +451,356 @@ async fn get_items_from_all_servers_preprocessed(
     }
 }
 
-async fn get_interleaved_root(
-    state: &AppState,
-    preprocessed: PreprocessedRequest,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+/// `/Genres` and `/Studios`: the entries of every server, with the ones of the same
+/// name merged, so browsing into one covers the items of all servers.
+pub async fn get_named_items_from_all_servers(
+    State(state): State<AppState>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<FederatedResponse, StatusCode> {
     let original_request = preprocessed.original_request;
-    let sessions = unique_server_sessions(preprocessed.sessions.ok_or(StatusCode::UNAUTHORIZED)?);
+    let sessions = unique_server_sessions(
+        &state,
+        preprocessed.sessions.ok_or(StatusCode::UNAUTHORIZED)?,
+    )
+    .await;
     if sessions.is_empty() {
         return Err(StatusCode::UNAUTHORIZED);
     }
     let pagination = Pagination::from_url(original_request.url());
-    let mut join_set = JoinSet::new();
-    let mut failures = 0;
+    let RawFederatedCatalog {
+        mut server_items,
+        failures,
+        response_shape,
+        paging,
+    } = fetch_raw_federated_catalog(&state, &original_request, sessions, pagination).await?;
 
-    for (index, (session, server)) in sessions.into_iter().enumerate() {
-        let Some(request) = original_request.try_clone() else {
-            error!("Failed to clone request for server: {}", server.name);
-            failures += 1;
+    for items in &mut server_items {
+        process_items_response_json(&mut items.response, &state, &items.server, false).await?;
+    }
+    let titles = state.title_normalizer().await;
+    let items =
+        FederatedItems::default().merge_server_items(server_items, MergeStrategy::ByName(&titles));
+
+    items_response_to_json(
+        items.into_paged_response(original_request.url(), pagination, response_shape, paging),
+        failures,
+    )
+}
+
+/// `/Genres/{name}` and `/Studios/{name}`: the entry of the highest priority server
+/// that has one of that name.
+pub async fn get_named_item(
+    State(state): State<AppState>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let proxy_api_key = preprocessed
+        .user
+        .as_ref()
+        .map(|user| user.virtual_key.clone());
+    let mut sessions = unique_server_sessions(
+        &state,
+        preprocessed.sessions.ok_or(StatusCode::UNAUTHORIZED)?,
+    )
+    .await;
+    sessions.sort_by(|(_, left), (_, right)| right.priority.cmp(&left.priority));
+
+    let mut last_error = StatusCode::NOT_FOUND;
+    for (session, server) in sessions {
+        let Some(mut request) = preprocessed.original_request.try_clone() else {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        };
+        let auth = JellyfinAuthorization::Authorization(session.to_authorization());
+        apply_to_request(
+            &mut request,
+            &server,
+            &Some(session),
+            &Some(auth),
+            &state,
+            preprocessed.access_scope.as_ref(),
+        )
+        .await;
+        match execute_processed_json_request(
+            &state,
+            request,
+            &server,
+            ResponseProcessingProfile::Media,
+            false,
+            proxy_api_key.as_deref(),
+        )
+        .await
+        {
+            Ok(item) => return Ok(Json(item)),
+            Err(status) => {
+                debug!(
+                    "Server '{}' has no entry for {}: {}",
+                    server.name,
+                    preprocessed.original_request.url().path(),
+                    status
+                );
+                last_error = status;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// Replace the genre and studio ids a client filters by with their names, which every
+/// server understands, so a merged genre or studio finds the items of all servers.
+/// Filters whose ids can't all be resolved are left as they are.
+async fn translate_named_filters(state: &AppState, preprocessed: &mut PreprocessedRequest) {
+    let url = preprocessed.original_request.url().clone();
+    let mut translated = Vec::new();
+    for (key, value) in url.query_pairs() {
+        let Some((_, names_key)) = NAMED_FILTER_QUERY_TAGS
+            .iter()
+            .find(|(ids_key, _)| key.eq_ignore_ascii_case(ids_key))
+        else {
             continue;
         };
+        if let Some(names) = resolve_item_names(state, preprocessed, &value).await {
+            translated.push((key.into_owned(), (*names_key, names.join("|"))));
+        }
+    }
+    if translated.is_empty() {
+        return;
+    }
 
-        let state_clone = state.clone();
-        join_set.spawn(async move {
-            let result = fetch_items_from_server(
-                index,
-                state_clone,
-                request,
-                session,
-                server,
-                pagination,
-                true,
+    for request_url in [
+        preprocessed.original_request.url_mut(),
+        preprocessed.request.url_mut(),
+    ] {
+        let pairs = request_url
+            .query_pairs()
+            .map(
+                |(key, value)| match translated.iter().find(|(ids_key, _)| *ids_key == key) {
+                    Some((_, (names_key, names))) => (names_key.to_string(), names.clone()),
+                    None => (key.into_owned(), value.into_owned()),
+                },
             )
-            .await;
-            (index, result)
-        });
+            .collect::<Vec<_>>();
+        request_url.query_pairs_mut().clear().extend_pairs(pairs);
     }
+}
 
-    let (indexed_results, failures) = collect_federated_results(join_set, failures).await?;
-    let response_shape =
-        ResponseShape::from_responses(indexed_results.iter().map(|(_, items)| &items.response));
-    let server_count = indexed_results.len();
-    let server_items = indexed_results
+/// The names of the items with the virtual ids in `ids`, asked from the servers
+/// they belong to.
+async fn resolve_item_names(
+    state: &AppState,
+    preprocessed: &PreprocessedRequest,
+    ids: &str,
+) -> Option<Vec<String>> {
+    let sessions = preprocessed.sessions.as_ref()?;
+    let mut names = Vec::new();
+    for id in ids
+        .split([',', '|'])
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+    {
+        let mapping = state
+            .media_storage
+            .get_media_mapping_by_virtual(id)
+            .await
+            .ok()
+            .flatten()?;
+        let (session, server) = sessions
+            .iter()
+            .find(|(_, server)| server.id == mapping.server_id)?;
+        let mut url = join_server_url(
+            &server.url,
+            &format!("/Items/{}", mapping.original_media_id),
+        );
+        url.query_pairs_mut()
+            .append_pair("userId", &session.original_user_id);
+        let item: MediaItem = match execute_json_request(
+            &state.reqwest_client,
+            session_request(state, reqwest::Method::GET, url, session).await,
+        )
+        .await
+        {
+            Ok(item) => item,
+            Err(status) => {
+                warn!(
+                    "Failed to look up the name of {} on server '{}': {}",
+                    id, server.name, status
+                );
+                return None;
+            }
+        };
+        names.push(item.name?);
+    }
+    (!names.is_empty()).then_some(names)
+}
+
+async fn get_interleaved_root(
+    state: &AppState,
+    preprocessed: PreprocessedRequest,
+) -> Result<FederatedResponse, StatusCode> {
+    let access_scope = preprocessed.access_scope;
+    let original_request = preprocessed.original_request;
+    let sessions = unique_server_sessions(
+        state,
+        preprocessed.sessions.ok_or(StatusCode::UNAUTHORIZED)?,
+    )
+    .await;
+    if sessions.is_empty() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let pagination = Pagination::from_url(original_request.url());
+    let RawFederatedCatalog {
+        mut server_items,
+        failures,
+        response_shape,
+        paging,
+    } = fetch_raw_federated_catalog(state, &original_request, sessions.clone(), pagination).await?;
+
+    let suffixes = ServerNameSuffixes::detect(
+        state.server_name_suffix_mode().await,
+        state.title_normalizer().await,
+        server_items.iter().flat_map(|items| {
+            items
+                .response
+                .items()
+                .iter()
+                .map(|item| (item, items.server.id))
+        }),
+    );
+    for items in &mut server_items {
+        let raw_items = std::mem::take(items.response.items_mut());
+        *items.response.items_mut() =
+            process_media_items_for_server(raw_items, state, &items.server, &suffixes).await?;
+    }
+
+    collapse_box_sets(
+        state,
+        &original_request,
+        &sessions,
+        server_items.iter_mut(),
+        access_scope.as_ref(),
+    )
+    .await?;
+    let server_count = server_items.len();
+    let server_items = server_items
         .into_iter()
-        .map(|(_, items)| items.response)
+        .map(|items| items.response)
         .collect::<Vec<_>>();
     let items = FederatedItems::interleaved(server_items);
 
     debug!("Combined items from {server_count} servers");
 
+    items_response_to_json(
+        items.into_paged_response(original_request.url(), pagination, response_shape, paging),
+        failures,
+    )
+}
+
+/// Largest `StartIndex + Limit` window a federated catalog fetches from every server
+/// so it can sort and page across them. Deeper pages are served per server instead.
+const MAX_CROSS_SERVER_WINDOW: usize = 1_000;
+
+/// Whether a paged catalog request is served by paging through the servers one after
+/// another rather than sorting a window fetched from each of them. That is the case when the
+/// window is too deep to over-fetch, or when a catalog request asks for no sort order.
+fn uses_per_server_paging(url: &url::Url, pagination: Pagination) -> bool {
+    let Some(limit) = pagination.limit else {
+        return false;
+    };
+    if pagination.start_index.saturating_add(limit) > MAX_CROSS_SERVER_WINDOW {
+        return true;
+    }
+
+    !has_query_key(url, &["SortBy"]) && url.path().to_ascii_lowercase().ends_with("/items")
+}
+
+/// Combined `TotalRecordCount` of a windowed catalog, or `None` when every server
+/// returned its whole catalog and the merged item count is exact.
+fn windowed_upstream_total(server_items: &[ServerItems], pagination: Pagination) -> Option<usize> {
+    let upstream_limit = pagination
+        .limit
+        .map(|limit| pagination.start_index.saturating_add(limit))?;
+
+    let mut fully_fetched = true;
+    let mut total = 0usize;
+    for items in server_items {
+        let returned = items.response.len();
+        let server_total = match &items.response {
+            ItemsResponseVariants::WithCount(response) => {
+                (response.total_record_count.max(0) as usize).max(returned)
+            }
+            ItemsResponseVariants::Bare(_) => returned,
+        };
+        fully_fetched &= returned < upstream_limit || server_total <= returned;
+        total = total.saturating_add(server_total);
+    }
+
+    (!fully_fetched).then_some(total)
+}
+
+/// Serve `pagination` as if the servers' catalogs were laid end to end in session
+/// order: servers wholly before the window only report their totals, and the page
+/// is filled from the servers it overlaps using their own paging.
+async fn fetch_per_server_page(
+    state: &AppState,
+    original_request: &reqwest::Request,
+    sessions: Vec<(AuthorizationSession, Server)>,
+    pagination: Pagination,
+) -> Result<RawFederatedCatalog, StatusCode> {
+    let mut skip = pagination.start_index;
+    let mut remaining = pagination.limit.unwrap_or(UPSTREAM_PAGE_SIZE);
+    let mut total = 0usize;
+    let mut server_items = Vec::new();
+    let mut failures = 0;
+
+    for (index, (session, server)) in sessions.into_iter().enumerate() {
+        let Some(mut request) = original_request.try_clone() else {
+            error!("Failed to clone request for server: {}", server.name);
+            failures += 1;
+            continue;
+        };
+        set_upstream_page(request.url_mut(), skip, remaining);
+
+        let mut items =
+            match execute_raw_items_request(index, state.clone(), request, session, server).await {
+                Ok(items) => items,
+                Err(e) => {
+                    failures += 1;
+                    error!("Federated server request failed: {:?}", e);
+                    continue;
+                }
+            };
+
+        items.response.items_mut().truncate(remaining);
+        let returned = items.response.len();
+        let server_total = match &items.response {
+            ItemsResponseVariants::WithCount(response) => {
+                (response.total_record_count.max(0) as usize).max(skip.saturating_add(returned))
+            }
+            ItemsResponseVariants::Bare(_) => skip.saturating_add(returned),
+        };
+        skip = skip.saturating_sub(server_total);
+        remaining -= returned;
+        total = total.saturating_add(server_total);
+        server_items.push(items);
+    }
+
+    if server_items.is_empty() {
+        error!("All federated server requests failed");
+        return Err(StatusCode::BAD_GATEWAY);
+    }
     if failures > 0 {
         warn!(
-            "Returning partial federated response after {} server failure(s)",
+            "Returning partial federated page after {} server failure(s)",
             failures
         );
     }
 
-    items_response_to_json(items.into_response(original_request.url(), pagination, response_shape))
+    let response_shape =
+        ResponseShape::from_responses(server_items.iter().map(|items| &items.response));
+    Ok(RawFederatedCatalog {
+        server_items,
+        failures,
+        response_shape,
+        paging: CatalogPaging::PerServer { total },
+    })
 }
 
 async fn fetch_raw_federated_catalog(
@@ -378,6 +809,10 @@ async fn fetch_raw_federated_catalog(
     sessions: Vec<(AuthorizationSession, Server)>,
     pagination: Pagination,
 ) -> Result<RawFederatedCatalog, StatusCode> {
+    if uses_per_server_paging(original_request.url(), pagination) {
+        return fetch_per_server_page(state, original_request, sessions, pagination).await;
+    }
+
     let mut join_set = JoinSet::new();
     let mut failures = 0;
 
@@ -409,11 +844,15 @@ async fn fetch_raw_federated_catalog(
         .collect::<Vec<_>>();
     let response_shape =
         ResponseShape::from_responses(server_items.iter().map(|items| &items.response));
+    let paging = CatalogPaging::Window {
+        upstream_total: windowed_upstream_total(&server_items, pagination),
+    };
 
     Ok(RawFederatedCatalog {
         server_items,
         failures,
         response_shape,
+        paging,
     })
 }
 
@@ -421,9 +860,15 @@ async fn process_library_group_individually(
     state: &AppState,
     group: Vec<ServerMediaItem>,
 ) -> Result<Vec<MediaItem>, StatusCode> {
+    let suffixes = ServerNameSuffixes::detect(
+        state.server_name_suffix_mode().await,
+        state.title_normalizer().await,
+        group.iter().map(|source| (&source.item, source.server.id)),
+    );
     let mut items = Vec::with_capacity(group.len());
     for ServerMediaItem { item, server } in group {
-        items.push(process_media_item_for_server(item, state, &server, true).await?);
+        let should_change_name = suffixes.applies_to(&item);
+        items.push(process_media_item_for_server(item, state, &server, should_change_name).await?);
     }
     Ok(items)
 }
@@ -547,14 +992,14 @@ async fn present_automatic_library_group(
 async fn get_automatic_library_root(
     state: &AppState,
     preprocessed: PreprocessedRequest,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<FederatedResponse, StatusCode> {
     let PreprocessedRequest {
         original_request,
         sessions,
         access_scope,
         ..
     } = preprocessed;
-    let sessions = unique_server_sessions(sessions.ok_or(StatusCode::UNAUTHORIZED)?);
+    let sessions = unique_server_sessions(state, sessions.ok_or(StatusCode::UNAUTHORIZED)?).await;
     if sessions.is_empty() {
         return Err(StatusCode::UNAUTHORIZED);
     }
@@ -565,9 +1010,10 @@ async fn get_automatic_library_root(
         server_items,
         failures,
         response_shape,
-    } = fetch_raw_federated_catalog(state, &original_request, sessions, pagination).await?;
+        paging,
+    } = fetch_raw_federated_catalog(state, &original_request, sessions.clone(), pagination).await?;
     let mut library_groups: HashMap<String, Vec<ServerMediaItem>> = HashMap::new();
-    let mut non_lib_per_server = Vec::new();
+    let mut raw_non_lib_per_server = Vec::new();
     let mut live_tv_seen = false;
 
     for ServerItems {
@@ -600,17 +1046,20 @@ async fn get_automatic_library_root(
         }
 
         if !non_library_items.is_empty() {
-            let processed =
-                process_media_items_for_server(non_library_items, state, &server, true).await?;
-            if !processed.is_empty() {
-                non_lib_per_server.push(ServerItems {
-                    response: ItemsResponseVariants::Bare(processed),
-                    server,
-                });
-            }
+            raw_non_lib_per_server.push((server, non_library_items));
         }
     }
 
+    let mut non_lib_per_server = process_non_library_items(state, raw_non_lib_per_server).await?;
+    collapse_box_sets(
+        state,
+        &original_request,
+        &sessions,
+        non_lib_per_server.iter_mut(),
+        Some(&access_scope),
+    )
+    .await?;
+
     let mut library_items = Vec::new();
     let mut active_automatic_keys = Vec::new();
     for (key, group) in library_groups {
@@ -634,15 +1083,22 @@ async fn get_automatic_library_root(
 
     let items = FederatedItems::new(library_items)
         .merge_server_items(non_lib_per_server, MergeStrategy::Interleave);
-    items_response_to_json(items.into_response(original_request.url(), pagination, response_shape))
+    items_response_to_json(
+        items.into_paged_response(original_request.url(), pagination, response_shape, paging),
+        failures,
+    )
 }
 
 async fn get_configured_library_root(
     state: &AppState,
     preprocessed: PreprocessedRequest,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<FederatedResponse, StatusCode> {
     let original_request = preprocessed.original_request;
-    let sessions = unique_server_sessions(preprocessed.sessions.ok_or(StatusCode::UNAUTHORIZED)?);
+    let sessions = unique_server_sessions(
+        state,
+        preprocessed.sessions.ok_or(StatusCode::UNAUTHORIZED)?,
+    )
+    .await;
     if sessions.is_empty() {
         return Err(StatusCode::UNAUTHORIZED);
     }
@@ -650,9 +1106,10 @@ async fn get_configured_library_root(
     let pagination = Pagination::from_url(original_request.url());
     let RawFederatedCatalog {
         server_items,
+        failures,
         response_shape,
-        ..
-    } = fetch_raw_federated_catalog(state, &original_request, sessions, pagination).await?;
+        paging,
+    } = fetch_raw_federated_catalog(state, &original_request, sessions.clone(), pagination).await?;
     let custom_assignments = state
         .virtual_library_service
         .get_assignments()
@@ -660,7 +1117,7 @@ async fn get_configured_library_root(
         .unwrap_or_default();
     let mut custom_library_groups: HashMap<String, NamedMediaItemGroup> = HashMap::new();
     let mut library_groups: HashMap<String, Vec<ServerMediaItem>> = HashMap::new();
-    let mut non_lib_per_server = Vec::new();
+    let mut raw_non_lib_per_server = Vec::new();
     let mut live_tv_seen = false;
 
     for ServerItems {
@@ -711,17 +1168,20 @@ async fn get_configured_library_root(
         }
 
         if !non_library_items.is_empty() {
-            let processed =
-                process_media_items_for_server(non_library_items, state, &server, true).await?;
-            if !processed.is_empty() {
-                non_lib_per_server.push(ServerItems {
-                    response: ItemsResponseVariants::Bare(processed),
-                    server,
-                });
-            }
+            raw_non_lib_per_server.push((server, non_library_items));
         }
     }
 
+    let mut non_lib_per_server = process_non_library_items(state, raw_non_lib_per_server).await?;
+    collapse_box_sets(
+        state,
+        &original_request,
+        &sessions,
+        non_lib_per_server.iter_mut(),
+        preprocessed.access_scope.as_ref(),
+    )
+    .await?;
+
     let group_sort_order: HashMap<String, i32> = state
         .virtual_library_service
         .list_groups()
@@ -759,13 +1219,22 @@ async fn get_configured_library_root(
 
     let mut single_groups = library_groups.into_iter().collect::<Vec<_>>();
     single_groups.sort_by(|left, right| left.0.cmp(&right.0));
+    let library_suffixes = ServerNameSuffixes::detect(
+        state.server_name_suffix_mode().await,
+        state.title_normalizer().await,
+        single_groups
+            .iter()
+            .filter_map(|(_, group)| group.first())
+            .map(|source| (&source.item, source.server.id)),
+    );
     for (_key, group) in single_groups {
         if let Some(ServerMediaItem { item, server }) = group.into_iter().next() {
             let index = task_index;
             task_index += 1;
             let state = state.clone();
+            let should_change_name = library_suffixes.applies_to(&item);
             library_join.spawn(async move {
-                let item = process_library_folder(&state, item, &server, true).await;
+                let item = process_library_folder(&state, item, &server, should_change_name).await;
                 (index, item)
             });
         }
@@ -793,16 +1262,237 @@ async fn get_configured_library_root(
             policy: DuplicatePolicy::ServerPriority,
             preferred_server_id: None,
         };
+        let titles = state.title_normalizer().await;
         FederatedItems::new(library_items).merge_server_items(
             non_lib_per_server,
-            MergeStrategy::DuplicatePolicy(&duplicate_config),
+            MergeStrategy::DuplicatePolicy(&duplicate_config, &titles),
         )
     } else {
         FederatedItems::new(library_items)
             .merge_server_items(non_lib_per_server, MergeStrategy::Interleave)
     };
 
-    items_response_to_json(items.into_response(original_request.url(), pagination, response_shape))
+    items_response_to_json(
+        items.into_paged_response(original_request.url(), pagination, response_shape, paging),
+        failures,
+    )
+}
+
+/// An item of a federated response as (server index, item index).
+type ItemPosition = (usize, usize);
+
+/// Collapse box sets that exist under the same name on several servers into a
+/// single entry. Same-named sets only collapse when their children overlap by
+/// provider id or title, so unrelated collections that happen to share a name stay
+/// apart. The collapsed set gets the id of a merged library whose members are the
+/// per-server box sets, so browsing it fans out and merges the children.
+async fn collapse_box_sets<'a>(
+    state: &AppState,
+    original_request: &reqwest::Request,
+    sessions: &[(AuthorizationSession, Server)],
+    server_items: impl IntoIterator<Item = &'a mut ServerItems>,
+    access_scope: Option<&VirtualLibraryAccessScope>,
+) -> Result<(), StatusCode> {
+    let Some(access_scope) = access_scope else {
+        return Ok(());
+    };
+    if !state.merge_box_sets_enabled().await {
+        return Ok(());
+    }
+
+    let titles = state.title_normalizer().await;
+    let mut server_items = server_items.into_iter().collect::<Vec<_>>();
+    let mut groups: HashMap<String, Vec<ItemPosition>> = HashMap::new();
+    for (server_index, items) in server_items.iter().enumerate() {
+        let items = match &items.response {
+            ItemsResponseVariants::WithCount(response) => &response.items,
+            ItemsResponseVariants::Bare(items) => items,
+        };
+        for (item_index, item) in items.iter().enumerate() {
+            if let Some(key) = box_set_merge_key(item, &titles) {
+                groups
+                    .entry(key)
+                    .or_default()
+                    .push((server_index, item_index));
+            }
+        }
+    }
+    let server_count = |server_items: &[&mut ServerItems], positions: &[ItemPosition]| {
+        positions
+            .iter()
+            .map(|(server_index, _)| server_items[*server_index].server.id)
+            .collect::<HashSet<_>>()
+            .len()
+    };
+    groups.retain(|_, positions| server_count(&server_items, positions) >= 2);
+
+    let candidates = groups.values().flatten().copied().collect::<Vec<_>>();
+    let member_keys =
+        futures_util::future::join_all(candidates.iter().map(|&(server_index, item_index)| {
+            let items = &server_items[server_index];
+            box_set_member_keys_on_server(
+                state,
+                original_request,
+                sessions,
+                items.server.clone(),
+                items.response_item(item_index).id.clone(),
+                &titles,
+            )
+        }))
+        .await;
+    let mut member_keys = candidates
+        .into_iter()
+        .zip(member_keys)
+        .collect::<HashMap<_, _>>();
+
+    let mut removed = HashSet::new();
+    for (key, positions) in groups {
+        let mut clusters: Vec<(Vec<ItemPosition>, HashSet<String>)> = Vec::new();
+        for position in positions {
+            let keys = member_keys.remove(&position).unwrap_or_default();
+            match clusters
+                .iter_mut()
+                .find(|(_, cluster_keys)| !cluster_keys.is_disjoint(&keys))
+            {
+                Some((cluster, cluster_keys)) => {
+                    cluster.push(position);
+                    cluster_keys.extend(keys);
+                }
+                None => clusters.push((vec![position], keys)),
+            }
+        }
+
+        for (positions, _) in clusters {
+            if server_count(&server_items, &positions) < 2 {
+                continue;
+            }
+            collapse_box_set_cluster(state, &mut server_items, &key, &positions, access_scope)
+                .await?;
+            removed.extend(positions.into_iter().skip(1));
+        }
+    }
+
+    for (server_index, items) in server_items.iter_mut().enumerate() {
+        let mut item_index = 0;
+        items.response.items_mut().retain(|_| {
+            let keep = !removed.contains(&(server_index, item_index));
+            item_index += 1;
+            keep
+        });
+    }
+    Ok(())
+}
+
+/// Replace the first box set of `positions` with the merged library standing for
+/// all of them. The merged library is keyed by the name and the lowest member id, so
+/// same-named collections with different children get libraries of their own.
+async fn collapse_box_set_cluster(
+    state: &AppState,
+    server_items: &mut [&mut ServerItems],
+    key: &str,
+    positions: &[ItemPosition],
+    access_scope: &VirtualLibraryAccessScope,
+) -> Result<(), StatusCode> {
+    let members = positions
+        .iter()
+        .map(|&(server_index, item_index)| {
+            let items = &server_items[server_index];
+            (items.server.id, items.response_item(item_index).id.clone())
+        })
+        .collect::<Vec<_>>();
+    let child_count = positions
+        .iter()
+        .map(|&(server_index, item_index)| {
+            server_items[server_index]
+                .response_item(item_index)
+                .child_count
+                .unwrap_or(0)
+        })
+        .sum();
+
+    let (first_server, first_item) = positions[0];
+    let display_name = {
+        let items = &server_items[first_server];
+        let name = items
+            .response_item(first_item)
+            .name
+            .clone()
+            .unwrap_or_default();
+        name.strip_suffix(&format!(" [{}]", items.server.name))
+            .map(str::to_string)
+            .unwrap_or(name)
+    };
+
+    let anchor = members
+        .iter()
+        .map(|(_, id)| id)
+        .min()
+        .cloned()
+        .unwrap_or_default();
+    let key = format!("{key}:{anchor}");
+    let merged = state
+        .virtual_library_service
+        .get_or_create_automatic_library(&key, &display_name)
+        .await
+        .map_err(|error| {
+            error!("Failed to get/create merged box set for '{key}': {error}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    state
+        .virtual_library_service
+        .upsert_automatic_library_members(&merged.virtual_id, access_scope, &members)
+        .await
+        .map_err(|error| {
+            error!("Failed to persist merged box set members: {error}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let item = &mut server_items[first_server].response.items_mut()[first_item];
+    item.id = merged.virtual_id.clone();
+    item.display_preferences_id = Some(merged.virtual_id);
+    item.name = Some(display_name.clone());
+    item.sort_name = Some(display_name.to_lowercase());
+    item.child_count = Some(child_count);
+    Ok(())
+}
+
+/// Fetch the children of a box set from its server and reduce them to the keys
+/// [`box_set_member_keys`] compares. A set whose children can't be fetched has no
+/// keys and is left alone.
+async fn box_set_member_keys_on_server(
+    state: &AppState,
+    original_request: &reqwest::Request,
+    sessions: &[(AuthorizationSession, Server)],
+    server: Server,
+    box_set_id: String,
+    titles: &TitleNormalizer,
+) -> HashSet<String> {
+    let Some(session) = sessions
+        .iter()
+        .find(|(_, session_server)| session_server.id == server.id)
+        .map(|(session, _)| session.clone())
+    else {
+        return HashSet::new();
+    };
+    let Some(mut request) = original_request.try_clone() else {
+        return HashSet::new();
+    };
+    *request.method_mut() = reqwest::Method::GET;
+    let url = request.url_mut();
+    url.set_path("/Items");
+    url.set_query(None);
+    url.query_pairs_mut()
+        .append_pair("ParentId", &box_set_id)
+        .append_pair("Fields", "ProviderIds");
+
+    let server_name = server.name.clone();
+    match execute_raw_items_request(0, state.clone(), request, session, server).await {
+        Ok(children) => box_set_member_keys(children.response.items(), titles),
+        Err(status) => {
+            warn!("Failed to fetch the children of box set {box_set_id} from '{server_name}': {status}");
+            HashSet::new()
+        }
+    }
 }
 
 async fn collect_federated_results<T: Send + 'static>(
@@ -1202,11 +1892,26 @@ async fn execute_raw_items_request(
     )
     .await;
 
-    let response = execute_json_request::<serde_json::Value>(&state.reqwest_client, request)
+    let upstream_slot = state.acquire_upstream_slot(&server).await?;
+    let started = Instant::now();
+    let response = state.reqwest_client.execute(request).await;
+    let status = response
+        .as_ref()
+        .ok()
+        .map(|response| response.status().as_u16());
+    state
+        .metrics
+        .record(&server.name, started.elapsed(), status);
+    let response = response.map_err(|e| {
+        error!("Failed to get items from server '{}': {}", server.name, e);
+        StatusCode::BAD_GATEWAY
+    })?;
+    let response = json_response::<serde_json::Value>(response)
         .await
         .inspect_err(|e| {
             error!("Failed to get items from server '{}': {:?}", server.name, e);
         })?;
+    drop(upstream_slot);
 
     let items_response: ItemsResponseVariants = response_json_to_payload(response)?;
     debug!(
@@ -1257,16 +1962,44 @@ async fn process_media_items_for_server(
     items: Vec<MediaItem>,
     state: &AppState,
     server: &Server,
-    should_change_name: bool,
+    suffixes: &ServerNameSuffixes,
 ) -> Result<Vec<MediaItem>, StatusCode> {
     let mut processed = Vec::with_capacity(items.len());
     for item in items {
+        let should_change_name = suffixes.applies_to(&item);
         processed
             .push(process_media_item_for_server(item, state, server, should_change_name).await?);
     }
     Ok(processed)
 }
 
+/// Process the non-library entries of a federated root, suffixing names that collide
+/// across backends.
+async fn process_non_library_items(
+    state: &AppState,
+    items_per_server: Vec<(Server, Vec<MediaItem>)>,
+) -> Result<Vec<ServerItems>, StatusCode> {
+    let suffixes = ServerNameSuffixes::detect(
+        state.server_name_suffix_mode().await,
+        state.title_normalizer().await,
+        items_per_server
+            .iter()
+            .flat_map(|(server, items)| items.iter().map(|item| (item, server.id))),
+    );
+
+    let mut processed_per_server = Vec::with_capacity(items_per_server.len());
+    for (server, items) in items_per_server {
+        let processed = process_media_items_for_server(items, state, &server, &suffixes).await?;
+        if !processed.is_empty() {
+            processed_per_server.push(ServerItems {
+                response: ItemsResponseVariants::Bare(processed),
+                server,
+            });
+        }
+    }
+    Ok(processed_per_server)
+}
+
 async fn build_virtual_library_item(
     state: &AppState,
     group: Vec<ServerMediaItem>,
@@ -1392,10 +2125,15 @@ async fn process_media_item_for_server(
 
 fn items_response_to_json(
     response: ItemsResponseVariants,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    serde_json::to_value(response).map(Json).map_err(|e| {
+    skipped_servers: usize,
+) -> Result<FederatedResponse, StatusCode> {
+    let body = serde_json::to_value(response).map_err(|e| {
         error!("Failed to serialize federated items response: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(FederatedResponse {
+        body,
+        skipped_servers,
     })
 }
 
@@ -1460,6 +2198,38 @@ mod tests {
         assert!(has_query_key(&url, &["ParentId"]));
     }
 
+    #[test]
+    fn series_and_season_listings_are_never_fanned_out() {
+        let restricted = |query: &str| {
+            let url = url::Url::parse(&format!("http://localhost/Items?{query}")).unwrap();
+            should_restrict_federation(&url, &[])
+        };
+
+        assert!(restricted("SeriesId=abc"));
+        assert!(restricted("seasonid=abc&Recursive=true"));
+        assert!(!restricted("ParentId=abc"));
+        assert!(!restricted("IncludeItemTypes=Movie"));
+        assert!(!restricted(""));
+    }
+
+    #[test]
+    fn configured_params_force_single_server_routing() {
+        let params = vec![
+            "AlbumIds".to_string(),
+            "IncludeItemTypes=Playlist".to_string(),
+        ];
+        let restricted = |query: &str| {
+            let url = url::Url::parse(&format!("http://localhost/Items?{query}")).unwrap();
+            should_restrict_federation(&url, &params)
+        };
+
+        assert!(restricted("albumids=abc"));
+        assert!(restricted("IncludeItemTypes=playlist"));
+        assert!(restricted("IncludeItemTypes=Movie,%20Playlist"));
+        assert!(!restricted("IncludeItemTypes=Movie,Series"));
+        assert!(!restricted("Fields=Playlist"));
+    }
+
     #[test]
     fn has_query_key_decodes_encoded_query_keys() {
         let url = url::Url::parse("http://localhost/Items?Parent%49d=abc").unwrap();
@@ -1481,6 +2251,33 @@ mod tests {
         assert!(!is_upstream_limited_catalog_request(&browse));
     }
 
+    #[test]
+    fn per_server_paging_covers_deep_windows_and_unsorted_catalogs() {
+        let pagination = |start_index, limit| Pagination { start_index, limit };
+        let url = |value: &str| url::Url::parse(value).unwrap();
+
+        assert!(!uses_per_server_paging(
+            &url("http://localhost/Items?SortBy=SortName"),
+            pagination(0, Some(50))
+        ));
+        assert!(uses_per_server_paging(
+            &url("http://localhost/Items?SortBy=SortName"),
+            pagination(MAX_CROSS_SERVER_WINDOW, Some(50))
+        ));
+        assert!(uses_per_server_paging(
+            &url("http://localhost/Users/u/Items?Recursive=true"),
+            pagination(0, Some(50))
+        ));
+        assert!(!uses_per_server_paging(
+            &url("http://localhost/Users/u/Items/Resume"),
+            pagination(0, Some(12))
+        ));
+        assert!(!uses_per_server_paging(
+            &url("http://localhost/Items?Recursive=true"),
+            pagination(0, None)
+        ));
+    }
+
     #[test]
     fn merged_library_max_pages_scales_with_client_window() {
         assert_eq!(
@@ -1649,4 +2446,755 @@ mod tests {
         );
         assert_eq!(item.extra.get("PrimaryImageTag"), Some(&json!("tag-123")));
     }
+
+    mod box_sets {
+        use super::*;
+        use crate::{
+            models::Authorization, request_preprocessing::preprocess_request,
+            user_authorization_service::User,
+        };
+        use axum::body::Body;
+        use wiremock::{
+            matchers::{method, path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        pub(super) use crate::test_support::{add_server_with_session, create_test_app_state};
+
+        pub(super) async fn get_federated(
+            state: &AppState,
+            user: &User,
+            uri: &str,
+        ) -> serde_json::Value {
+            get_federated_response(state, user, uri).await.body
+        }
+
+        pub(super) async fn get_federated_response(
+            state: &AppState,
+            user: &User,
+            uri: &str,
+        ) -> FederatedResponse {
+            let auth_header = Authorization {
+                client: "Jellyfin Web".to_string(),
+                device: "Firefox".to_string(),
+                device_id: "web-device-id".to_string(),
+                version: "10.10.7".to_string(),
+                token: Some(user.virtual_key.clone()),
+            }
+            .to_header_value();
+            let uri: axum::http::Uri = uri.parse().unwrap();
+            let request = axum::http::Request::builder()
+                .uri(uri.clone())
+                .header(axum::http::header::HOST, "localhost")
+                .header(axum::http::header::AUTHORIZATION, auth_header)
+                .extension(axum::extract::OriginalUri(uri))
+                .body(Body::empty())
+                .unwrap();
+
+            let preprocessed = preprocess_request(request, state).await.unwrap();
+            get_items_from_all_servers(State(state.clone()), Preprocessed(preprocessed))
+                .await
+                .unwrap()
+        }
+
+        fn box_set(id: &str) -> serde_json::Value {
+            json!({ "Id": id, "Name": "Middle-earth Collection", "Type": "BoxSet", "ChildCount": 1 })
+        }
+
+        pub(super) fn movie(id: &str, name: &str) -> serde_json::Value {
+            json!({ "Id": id, "Name": name, "Type": "Movie" })
+        }
+
+        /// Mount a server whose only box set is `box_set_id`, holding `children`.
+        async fn mount_box_set(
+            upstream: &MockServer,
+            box_set_id: &str,
+            children: Vec<serde_json::Value>,
+        ) {
+            Mock::given(method("GET"))
+                .and(path("/Items"))
+                .and(query_param("IncludeItemTypes", "BoxSet"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "Items": [box_set(box_set_id)],
+                    "TotalRecordCount": 1,
+                    "StartIndex": 0
+                })))
+                .mount(upstream)
+                .await;
+            Mock::given(method("GET"))
+                .and(path("/Items"))
+                .and(query_param("ParentId", box_set_id))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "TotalRecordCount": children.len(),
+                    "Items": children,
+                    "StartIndex": 0
+                })))
+                .mount(upstream)
+                .await;
+        }
+
+        #[tokio::test]
+        async fn identical_box_sets_collapse_and_merge_children() {
+            let state = create_test_app_state().await;
+            state.config.write().await.merge_box_sets = true;
+            let first_upstream = MockServer::start().await;
+            let second_upstream = MockServer::start().await;
+
+            let fellowship = |id: &str| {
+                json!({
+                    "Id": id,
+                    "Name": "The Fellowship of the Ring",
+                    "Type": "Movie",
+                    "ProviderIds": { "Tmdb": "120" }
+                })
+            };
+            mount_box_set(
+                &first_upstream,
+                "11111111111111111111111111111111",
+                vec![fellowship("33333333333333333333333333333333")],
+            )
+            .await;
+            mount_box_set(
+                &second_upstream,
+                "22222222222222222222222222222222",
+                vec![
+                    fellowship("44444444444444444444444444444444"),
+                    movie("55555555555555555555555555555555", "The Two Towers"),
+                ],
+            )
+            .await;
+
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+            add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+            state.server_storage.check_servers_health().await;
+
+            let collections = get_federated(
+                &state,
+                &user,
+                "/Items?IncludeItemTypes=BoxSet&Recursive=true",
+            )
+            .await;
+            let collections = collections["Items"].as_array().unwrap();
+            assert_eq!(collections.len(), 1);
+            assert_eq!(collections[0]["Name"], "Middle-earth Collection");
+            assert_eq!(collections[0]["ChildCount"], 2);
+            let merged_id = collections[0]["Id"].as_str().unwrap().to_string();
+
+            let children =
+                get_federated(&state, &user, &format!("/Items?ParentId={merged_id}")).await;
+            let mut names = children["Items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["Name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(
+                names,
+                [
+                    "The Fellowship of the Ring [First]",
+                    "The Fellowship of the Ring [Second]",
+                    "The Two Towers"
+                ]
+            );
+        }
+
+        #[tokio::test]
+        async fn same_named_box_sets_without_shared_children_stay_separate() {
+            let state = create_test_app_state().await;
+            state.config.write().await.merge_box_sets = true;
+            let first_upstream = MockServer::start().await;
+            let second_upstream = MockServer::start().await;
+
+            mount_box_set(
+                &first_upstream,
+                "11111111111111111111111111111111",
+                vec![movie(
+                    "33333333333333333333333333333333",
+                    "The Fellowship of the Ring",
+                )],
+            )
+            .await;
+            mount_box_set(
+                &second_upstream,
+                "22222222222222222222222222222222",
+                vec![movie("44444444444444444444444444444444", "The Hobbit")],
+            )
+            .await;
+
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+            add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+            state.server_storage.check_servers_health().await;
+
+            let collections = get_federated(
+                &state,
+                &user,
+                "/Items?IncludeItemTypes=BoxSet&Recursive=true",
+            )
+            .await;
+            assert_eq!(collections["Items"].as_array().unwrap().len(), 2);
+        }
+
+        #[tokio::test]
+        async fn box_sets_stay_separate_when_merging_is_disabled() {
+            let state = create_test_app_state().await;
+            let first_upstream = MockServer::start().await;
+            let second_upstream = MockServer::start().await;
+
+            for (upstream, box_set_id) in [
+                (&first_upstream, "11111111111111111111111111111111"),
+                (&second_upstream, "22222222222222222222222222222222"),
+            ] {
+                Mock::given(method("GET"))
+                    .and(path("/Items"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "Items": [box_set(box_set_id)],
+                        "TotalRecordCount": 1,
+                        "StartIndex": 0
+                    })))
+                    .mount(upstream)
+                    .await;
+            }
+
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+            add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+            state.server_storage.check_servers_health().await;
+
+            let collections = get_federated(
+                &state,
+                &user,
+                "/Items?IncludeItemTypes=BoxSet&Recursive=true",
+            )
+            .await;
+            assert_eq!(collections["Items"].as_array().unwrap().len(), 2);
+        }
+    }
+
+    mod server_name_suffixes {
+        use super::box_sets::{
+            add_server_with_session, create_test_app_state, get_federated, movie,
+        };
+        use super::*;
+        use crate::config::ServerNameSuffixMode;
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        async fn federated_movie_names(mode: ServerNameSuffixMode) -> Vec<String> {
+            let state = create_test_app_state().await;
+            state.config.write().await.include_server_name_in_media = mode;
+            let first_upstream = MockServer::start().await;
+            let second_upstream = MockServer::start().await;
+
+            for (upstream, items) in [
+                (
+                    &first_upstream,
+                    json!([
+                        movie("11111111111111111111111111111111", "Heat"),
+                        movie("22222222222222222222222222222222", "Alien"),
+                    ]),
+                ),
+                (
+                    &second_upstream,
+                    json!([movie("33333333333333333333333333333333", "Heat")]),
+                ),
+            ] {
+                Mock::given(method("GET"))
+                    .and(path("/Items"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "Items": items,
+                        "TotalRecordCount": items.as_array().unwrap().len(),
+                        "StartIndex": 0
+                    })))
+                    .mount(upstream)
+                    .await;
+            }
+
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+            add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+            state.server_storage.check_servers_health().await;
+
+            let response = get_federated(
+                &state,
+                &user,
+                "/Items?IncludeItemTypes=Movie&Recursive=true",
+            )
+            .await;
+            let mut names = response["Items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["Name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        }
+
+        #[tokio::test]
+        async fn on_collision_only_suffixes_titles_found_on_several_servers() {
+            assert_eq!(
+                federated_movie_names(ServerNameSuffixMode::OnCollision).await,
+                ["Alien", "Heat [First]", "Heat [Second]"]
+            );
+        }
+
+        #[tokio::test]
+        async fn always_and_never_ignore_collisions() {
+            assert_eq!(
+                federated_movie_names(ServerNameSuffixMode::Always).await,
+                ["Alien [First]", "Heat [First]", "Heat [Second]"]
+            );
+            assert_eq!(
+                federated_movie_names(ServerNameSuffixMode::Never).await,
+                ["Alien", "Heat", "Heat"]
+            );
+        }
+    }
+
+    mod genres {
+        use super::box_sets::{
+            add_server_with_session, create_test_app_state, get_federated, movie,
+        };
+        use super::*;
+        use crate::{
+            models::Authorization, request_preprocessing::preprocess_request,
+            user_authorization_service::User,
+        };
+        use axum::body::Body;
+        use wiremock::{
+            matchers::{method, path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        async fn get_genres(state: &AppState, user: &User) -> serde_json::Value {
+            let auth_header = Authorization {
+                client: "Jellyfin Web".to_string(),
+                device: "Firefox".to_string(),
+                device_id: "web-device-id".to_string(),
+                version: "10.10.7".to_string(),
+                token: Some(user.virtual_key.clone()),
+            }
+            .to_header_value();
+            let uri: axum::http::Uri = "/Genres?Recursive=true".parse().unwrap();
+            let request = axum::http::Request::builder()
+                .uri(uri.clone())
+                .header(axum::http::header::HOST, "localhost")
+                .header(axum::http::header::AUTHORIZATION, auth_header)
+                .extension(axum::extract::OriginalUri(uri))
+                .body(Body::empty())
+                .unwrap();
+
+            let preprocessed = preprocess_request(request, state).await.unwrap();
+            get_named_items_from_all_servers(State(state.clone()), Preprocessed(preprocessed))
+                .await
+                .unwrap()
+                .body
+        }
+
+        #[tokio::test]
+        async fn browsing_a_merged_genre_returns_items_from_both_backends() {
+            let state = create_test_app_state().await;
+            state.config.write().await.include_server_name_in_media =
+                crate::config::ServerNameSuffixMode::Never;
+            let first_upstream = MockServer::start().await;
+            let second_upstream = MockServer::start().await;
+
+            let first_genre = "11111111111111111111111111111111";
+            let second_genre = "22222222222222222222222222222222";
+            for (upstream, genre_id, movie_id, movie_name) in [
+                (
+                    &first_upstream,
+                    first_genre,
+                    "33333333333333333333333333333333",
+                    "Heat",
+                ),
+                (
+                    &second_upstream,
+                    second_genre,
+                    "44444444444444444444444444444444",
+                    "Alien",
+                ),
+            ] {
+                let genre = json!({ "Id": genre_id, "Name": "Drama", "Type": "Genre" });
+                Mock::given(method("GET"))
+                    .and(path("/Genres"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "Items": [genre.clone()],
+                        "TotalRecordCount": 1,
+                        "StartIndex": 0
+                    })))
+                    .mount(upstream)
+                    .await;
+                Mock::given(method("GET"))
+                    .and(path(format!("/Items/{genre_id}")))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(genre))
+                    .mount(upstream)
+                    .await;
+                Mock::given(method("GET"))
+                    .and(path("/Items"))
+                    .and(query_param("Genres", "Drama"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "Items": [movie(movie_id, movie_name)],
+                        "TotalRecordCount": 1,
+                        "StartIndex": 0
+                    })))
+                    .expect(1)
+                    .mount(upstream)
+                    .await;
+            }
+
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+            add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+            state.server_storage.check_servers_health().await;
+
+            let genres = get_genres(&state, &user).await;
+            let genres = genres["Items"].as_array().unwrap();
+            assert_eq!(genres.len(), 1);
+            assert_eq!(genres[0]["Name"], "Drama");
+            let genre_id = genres[0]["Id"].as_str().unwrap().to_string();
+
+            let items = get_federated(
+                &state,
+                &user,
+                &format!("/Items?GenreIds={genre_id}&Recursive=true"),
+            )
+            .await;
+            let mut names = items["Items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["Name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(names, ["Alien", "Heat"]);
+        }
+    }
+
+    mod custom_library_groups {
+        use super::box_sets::{
+            add_server_with_session, create_test_app_state, get_federated, movie,
+        };
+        use super::*;
+        use crate::config::ServerNameSuffixMode;
+        use wiremock::{
+            matchers::{method, path, path_regex, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        #[tokio::test]
+        async fn library_groups_appear_as_views_and_browse_all_members() {
+            let state = create_test_app_state().await;
+            {
+                let mut config = state.config.write().await;
+                config.merge_libraries = false;
+                config.include_server_name_in_media = ServerNameSuffixMode::Never;
+            }
+            let first_upstream = MockServer::start().await;
+            let second_upstream = MockServer::start().await;
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+            add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+            let group = state
+                .virtual_library_service
+                .create_group(
+                    "All Movies",
+                    crate::config::DeduplicationStrategy::default(),
+                )
+                .await
+                .unwrap();
+
+            for (upstream, name, library_id, movie_id, movie_name) in [
+                (
+                    &first_upstream,
+                    "First",
+                    "11111111111111111111111111111111",
+                    "33333333333333333333333333333333",
+                    "Heat",
+                ),
+                (
+                    &second_upstream,
+                    "Second",
+                    "22222222222222222222222222222222",
+                    "44444444444444444444444444444444",
+                    "Alien",
+                ),
+            ] {
+                Mock::given(method("GET"))
+                    .and(path_regex("/Views$"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "Items": [{
+                            "Id": library_id,
+                            "Name": "Movies",
+                            "Type": "CollectionFolder",
+                            "CollectionType": "movies"
+                        }],
+                        "TotalRecordCount": 1,
+                        "StartIndex": 0
+                    })))
+                    .mount(upstream)
+                    .await;
+                Mock::given(method("GET"))
+                    .and(path("/Items"))
+                    .and(query_param("ParentId", library_id))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "Items": [movie(movie_id, movie_name)],
+                        "TotalRecordCount": 1,
+                        "StartIndex": 0
+                    })))
+                    .mount(upstream)
+                    .await;
+                let server = state
+                    .server_storage
+                    .get_server_by_name(name)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                state
+                    .virtual_library_service
+                    .add_member(&group.virtual_id, server.id, library_id, "Movies")
+                    .await
+                    .unwrap();
+            }
+            state.server_storage.check_servers_health().await;
+
+            let views = get_federated(&state, &user, &format!("/Users/{}/Views", user.id)).await;
+            let views = views["Items"].as_array().unwrap();
+            assert_eq!(views.len(), 1);
+            assert_eq!(views[0]["Id"], group.virtual_id.as_str());
+            assert_eq!(views[0]["Name"], "All Movies");
+            assert_eq!(views[0]["CollectionType"], "movies");
+
+            let items = get_federated(
+                &state,
+                &user,
+                &format!("/Items?ParentId={}", group.virtual_id),
+            )
+            .await;
+            let mut names = items["Items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["Name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(names, ["Alien", "Heat"]);
+        }
+    }
+
+    mod pagination {
+        use super::box_sets::{
+            add_server_with_session, create_test_app_state, get_federated, get_federated_response,
+            movie,
+        };
+        use super::*;
+        use crate::config::ServerNameSuffixMode;
+        use wiremock::{
+            matchers::{method, path, query_param, query_param_is_missing},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        fn item_names(response: &serde_json::Value) -> Vec<&str> {
+            response["Items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["Name"].as_str().unwrap())
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn sorted_page_is_sliced_from_merged_window_with_combined_total() {
+            let state = create_test_app_state().await;
+            state.config.write().await.include_server_name_in_media = ServerNameSuffixMode::Never;
+            let first_upstream = MockServer::start().await;
+            let second_upstream = MockServer::start().await;
+
+            for (upstream, items, total) in [
+                (
+                    &first_upstream,
+                    json!([
+                        movie("11111111111111111111111111111111", "Alpha"),
+                        movie("22222222222222222222222222222222", "Charlie"),
+                        movie("55555555555555555555555555555555", "Echo"),
+                    ]),
+                    10,
+                ),
+                (
+                    &second_upstream,
+                    json!([
+                        movie("33333333333333333333333333333333", "Bravo"),
+                        movie("44444444444444444444444444444444", "Delta"),
+                        movie("66666666666666666666666666666666", "Foxtrot"),
+                    ]),
+                    5,
+                ),
+            ] {
+                Mock::given(method("GET"))
+                    .and(path("/Items"))
+                    .and(query_param("Limit", "3"))
+                    .and(query_param_is_missing("StartIndex"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "Items": items,
+                        "TotalRecordCount": total,
+                        "StartIndex": 0
+                    })))
+                    .expect(1)
+                    .mount(upstream)
+                    .await;
+            }
+
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+            add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+            state.server_storage.check_servers_health().await;
+
+            let response = get_federated(
+                &state,
+                &user,
+                "/Items?SortBy=SortName&StartIndex=1&Limit=2&Recursive=true",
+            )
+            .await;
+            assert_eq!(item_names(&response), ["Bravo", "Charlie"]);
+            assert_eq!(response["TotalRecordCount"], 15);
+            assert_eq!(response["StartIndex"], 1);
+        }
+
+        #[tokio::test]
+        async fn unsorted_page_continues_on_the_next_server() {
+            let state = create_test_app_state().await;
+            state.config.write().await.include_server_name_in_media = ServerNameSuffixMode::Never;
+            let first_upstream = MockServer::start().await;
+            let second_upstream = MockServer::start().await;
+
+            Mock::given(method("GET"))
+                .and(path("/Items"))
+                .and(query_param("StartIndex", "3"))
+                .and(query_param("Limit", "3"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "Items": [movie("11111111111111111111111111111111", "Zulu")],
+                    "TotalRecordCount": 4,
+                    "StartIndex": 3
+                })))
+                .expect(1)
+                .mount(&first_upstream)
+                .await;
+            Mock::given(method("GET"))
+                .and(path("/Items"))
+                .and(query_param("StartIndex", "0"))
+                .and(query_param("Limit", "2"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "Items": [
+                        movie("22222222222222222222222222222222", "Bravo"),
+                        movie("33333333333333333333333333333333", "Alpha"),
+                    ],
+                    "TotalRecordCount": 6,
+                    "StartIndex": 0
+                })))
+                .expect(1)
+                .mount(&second_upstream)
+                .await;
+
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "First", &first_upstream, 100).await;
+            add_server_with_session(&state, &user, "Second", &second_upstream, 100).await;
+            state.server_storage.check_servers_health().await;
+
+            let response =
+                get_federated(&state, &user, "/Items?StartIndex=3&Limit=3&Recursive=true").await;
+            assert_eq!(item_names(&response), ["Zulu", "Bravo", "Alpha"]);
+            assert_eq!(response["TotalRecordCount"], 10);
+            assert_eq!(response["StartIndex"], 3);
+        }
+
+        #[tokio::test]
+        async fn failing_server_is_skipped_from_the_merged_page() {
+            let state = create_test_app_state().await;
+            state.config.write().await.include_server_name_in_media = ServerNameSuffixMode::Never;
+            let healthy_upstream = MockServer::start().await;
+            let failing_upstream = MockServer::start().await;
+
+            Mock::given(method("GET"))
+                .and(path("/Items"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "Items": [
+                        movie("11111111111111111111111111111111", "Alpha"),
+                        movie("22222222222222222222222222222222", "Bravo"),
+                    ],
+                    "TotalRecordCount": 2,
+                    "StartIndex": 0
+                })))
+                .mount(&healthy_upstream)
+                .await;
+            Mock::given(method("GET"))
+                .and(path("/Items"))
+                .respond_with(ResponseTemplate::new(500))
+                .mount(&failing_upstream)
+                .await;
+
+            let user = state
+                .user_authorization
+                .get_or_create_user("viewer", &"password".into())
+                .await
+                .unwrap();
+            add_server_with_session(&state, &user, "Failing", &failing_upstream, 100).await;
+            add_server_with_session(&state, &user, "Healthy", &healthy_upstream, 100).await;
+            state.server_storage.check_servers_health().await;
+
+            for uri in [
+                "/Items?SortBy=SortName&Limit=10&Recursive=true",
+                "/Items?StartIndex=0&Limit=10&Recursive=true",
+            ] {
+                let response = get_federated_response(&state, &user, uri)
+                    .await
+                    .into_response();
+                assert_eq!(response.status(), StatusCode::OK, "{uri}");
+                assert_eq!(response.headers()[SKIPPED_SERVERS_HEADER], "1", "{uri}");
+
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(item_names(&body), ["Alpha", "Bravo"], "{uri}");
+                assert_eq!(body["TotalRecordCount"], 2, "{uri}");
+            }
+        }
+    }
 }
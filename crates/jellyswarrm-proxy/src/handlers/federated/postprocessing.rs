@@ -1,12 +1,16 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::str::FromStr;
 
 use crate::{
-    duplicate_policy::{apply_duplicate_policy, DuplicatePolicyConfig, TaggedMediaItem},
+    config::ServerNameSuffixMode,
+    duplicate_policy::{
+        apply_duplicate_policy, DuplicatePolicyConfig, TaggedMediaItem, TitleNormalizer,
+    },
     models::{
         enums::{BaseItemKind, CollectionType, ItemSortBy, SortOrder},
         ItemsResponseVariants, ItemsResponseWithCount, MediaItem,
     },
+    server_id::ServerId,
     server_storage::Server,
 };
 
@@ -91,7 +95,10 @@ impl ResponseShape {
 
 pub(super) enum MergeStrategy<'a> {
     Interleave,
-    DuplicatePolicy(&'a DuplicatePolicyConfig),
+    DuplicatePolicy(&'a DuplicatePolicyConfig, &'a TitleNormalizer),
+    /// Items with the same name are one entry, as for the genres or studios that
+    /// several servers have; the copy of the highest priority server is kept.
+    ByName(&'a TitleNormalizer),
 }
 
 pub(super) struct ServerItems {
@@ -99,6 +106,77 @@ pub(super) struct ServerItems {
     pub(super) server: Server,
 }
 
+impl ServerItems {
+    pub(super) fn response_item(&self, index: usize) -> &MediaItem {
+        match &self.response {
+            ItemsResponseVariants::WithCount(response) => &response.items[index],
+            ItemsResponseVariants::Bare(items) => &items[index],
+        }
+    }
+}
+
+/// Decides which items of a federated response get the ` [ServerName]` suffix.
+pub(super) struct ServerNameSuffixes {
+    mode: ServerNameSuffixMode,
+    titles: TitleNormalizer,
+    colliding_names: HashSet<String>,
+}
+
+impl ServerNameSuffixes {
+    /// Collect the names that more than one backend contributes to the response.
+    pub(super) fn detect<'a>(
+        mode: ServerNameSuffixMode,
+        titles: TitleNormalizer,
+        items: impl IntoIterator<Item = (&'a MediaItem, ServerId)>,
+    ) -> Self {
+        let mut servers_by_name: HashMap<String, HashSet<ServerId>> = HashMap::new();
+        if mode == ServerNameSuffixMode::OnCollision {
+            for (item, server_id) in items {
+                if let Some(name) = name_key(item, &titles) {
+                    servers_by_name.entry(name).or_default().insert(server_id);
+                }
+            }
+        }
+
+        Self {
+            mode,
+            titles,
+            colliding_names: servers_by_name
+                .into_iter()
+                .filter(|(_, servers)| servers.len() > 1)
+                .map(|(name, _)| name)
+                .collect(),
+        }
+    }
+
+    pub(super) fn applies_to(&self, item: &MediaItem) -> bool {
+        match self.mode {
+            ServerNameSuffixMode::Always => true,
+            ServerNameSuffixMode::OnCollision => name_key(item, &self.titles)
+                .is_some_and(|name| self.colliding_names.contains(&name)),
+            ServerNameSuffixMode::Never => false,
+        }
+    }
+}
+
+fn name_key(item: &MediaItem, titles: &TitleNormalizer) -> Option<String> {
+    item.name
+        .as_deref()
+        .map(|name| titles.normalize(name))
+        .filter(|name| !name.is_empty())
+}
+
+/// How a federated catalog response is cut from what the servers returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum CatalogPaging {
+    /// Every server returned its items up to the end of the requested window; the
+    /// merged items are sorted and sliced. `upstream_total` is the servers' combined
+    /// count when some of them have more items than they returned.
+    Window { upstream_total: Option<usize> },
+    /// The servers were paged one after another and their items make up the page.
+    PerServer { total: usize },
+}
+
 #[derive(Default)]
 pub(super) struct FederatedItems {
     items: Vec<MediaItem>,
@@ -120,8 +198,9 @@ impl FederatedItems {
     pub(super) fn from_tagged_items(
         items: Vec<TaggedMediaItem>,
         config: &DuplicatePolicyConfig,
+        titles: &TitleNormalizer,
     ) -> Self {
-        Self::new(apply_duplicate_policy(items, config))
+        Self::new(apply_duplicate_policy(items, config, titles))
     }
 
     pub(super) fn merge_server_items(
@@ -136,9 +215,10 @@ impl FederatedItems {
                     .map(|items| items.response)
                     .collect(),
             ),
-            MergeStrategy::DuplicatePolicy(config) => {
-                apply_duplicate_policy(tag_server_items(server_items), config)
+            MergeStrategy::DuplicatePolicy(config, titles) => {
+                apply_duplicate_policy(tag_server_items(server_items), config, titles)
             }
+            MergeStrategy::ByName(titles) => merge_by_name(server_items, titles),
         };
         self.items.extend(items);
         self
@@ -164,6 +244,29 @@ impl FederatedItems {
         let items = pagination.apply(self.items);
         shape.wrap(items, total_count, pagination)
     }
+
+    pub(super) fn into_paged_response(
+        self,
+        url: &url::Url,
+        pagination: Pagination,
+        shape: ResponseShape,
+        paging: CatalogPaging,
+    ) -> ItemsResponseVariants {
+        match paging {
+            CatalogPaging::Window { upstream_total } => {
+                let items = match upstream_total {
+                    Some(total) => {
+                        let total = total.max(self.items.len());
+                        self.with_reported_total(total)
+                    }
+                    None => self,
+                };
+                items.into_response(url, pagination, shape)
+            }
+            // The servers' pages already are the requested page, in server order.
+            CatalogPaging::PerServer { total } => shape.wrap(self.items, total, pagination),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -258,6 +361,23 @@ fn interleave(responses: Vec<ItemsResponseVariants>) -> Vec<MediaItem> {
     items
 }
 
+fn merge_by_name(mut server_items: Vec<ServerItems>, titles: &TitleNormalizer) -> Vec<MediaItem> {
+    server_items.sort_by(|left, right| {
+        right
+            .server
+            .priority
+            .cmp(&left.server.priority)
+            .then_with(|| left.server.id.as_i64().cmp(&right.server.id.as_i64()))
+    });
+
+    let mut seen_names = HashSet::new();
+    server_items
+        .into_iter()
+        .flat_map(|items| items.response.into_items())
+        .filter(|item| name_key(item, titles).is_none_or(|name| seen_names.insert(name)))
+        .collect()
+}
+
 fn tag_server_items(server_items: Vec<ServerItems>) -> Vec<TaggedMediaItem> {
     server_items
         .into_iter()
@@ -286,7 +406,9 @@ fn to_i32(value: usize) -> i32 {
 mod tests {
     use super::*;
     use crate::{
-        config::MediaStreamingMode, duplicate_policy::DuplicatePolicy, server_id::ServerId,
+        config::{AuthorizationHeaderMode, MediaStreamingMode},
+        duplicate_policy::DuplicatePolicy,
+        server_id::ServerId,
         server_url::ServerUrl,
     };
     use serde_json::json;
@@ -580,7 +702,7 @@ mod tests {
                     server: server(2, 20),
                 },
             ],
-            MergeStrategy::DuplicatePolicy(&policy),
+            MergeStrategy::DuplicatePolicy(&policy, &TitleNormalizer::default()),
         );
 
         assert_eq!(item_ids(&items.items), vec!["high"]);
@@ -633,8 +755,38 @@ mod tests {
             url: ServerUrl::parse("http://example:8096").unwrap(),
             priority,
             media_streaming_mode: MediaStreamingMode::Redirect,
+            authorization_header_mode: AuthorizationHeaderMode::Normalize,
+            max_connections: None,
+            backend_id: None,
             created_at: now,
             updated_at: now,
         }
     }
+
+    #[test]
+    fn server_name_suffixes_detect_names_shared_by_several_servers() {
+        let first = ServerId::new(1);
+        let second = ServerId::new(2);
+        let heat = named_media_item("a", "Heat");
+        let heat_upper = named_media_item("b", " HEAT ");
+        let alien = named_media_item("c", "Alien");
+        let alien_again = named_media_item("d", "Alien");
+
+        let suffixes = ServerNameSuffixes::detect(
+            ServerNameSuffixMode::OnCollision,
+            TitleNormalizer::default(),
+            [
+                (&heat, first),
+                (&heat_upper, second),
+                (&alien, first),
+                (&alien_again, first),
+            ],
+        );
+
+        assert!(suffixes.applies_to(&heat));
+        assert!(suffixes.applies_to(&heat_upper));
+        // Duplicates on a single server are not a cross-server collision.
+        assert!(!suffixes.applies_to(&alien));
+        assert!(!suffixes.applies_to(&named_media_item("e", "Unrelated")));
+    }
 }
@@ -0,0 +1,187 @@
+use axum::{
+    extract::{Path, State},
+    response::Response,
+};
+use hyper::StatusCode;
+use tracing::{debug, error};
+
+use crate::{
+    extractors::Preprocessed,
+    handlers::videos::{proxy_request, session_for_server},
+    request_preprocessing::{apply_to_request, remap_authorization},
+    AppState,
+};
+
+// Item images are routed by the media mapping of the item they belong to, so a
+// poster always comes from the server that produced its id and image tag. The
+// upstream body is streamed back with its caching headers untouched.
+//http://localhost:3000/Items/430c368c5eb34534bf98363d5adbb92f/Images/Primary?fillHeight=396&fillWidth=264&quality=96&tag=2f6b3a5c0e5e4ab2a3f2d0f6d3d4b6a1
+pub async fn get_item_image(
+    State(state): State<AppState>,
+    Path((item_id, _image)): Path<(String, String)>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<Response, StatusCode> {
+    let owner = state
+        .media_storage
+        .get_media_mapping_with_server(&item_id)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to resolve media mapping for image of {}: {}",
+                item_id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(|(_, server)| server);
+
+    let request = match owner {
+        Some(server) if server.id != preprocessed.server.id => {
+            if preprocessed
+                .access_scope
+                .as_ref()
+                .is_some_and(|scope| !scope.allows(server.id))
+            {
+                return Err(StatusCode::NOT_FOUND);
+            }
+
+            debug!(
+                "Routing image of {} to owning server {} instead of {}",
+                item_id, server.name, preprocessed.server.name
+            );
+            let session = session_for_server(&preprocessed.sessions, &server);
+            let new_auth = remap_authorization(&preprocessed.auth, &session, &server)
+                .await
+                .map_err(|e| {
+                    error!("Failed to remap authorization for image request: {}", e);
+                    StatusCode::BAD_REQUEST
+                })?;
+
+            let mut request = preprocessed.original_request;
+            apply_to_request(
+                &mut request,
+                &server,
+                &session,
+                &new_auth,
+                &state,
+                preprocessed.access_scope.as_ref(),
+            )
+            .await;
+            request
+        }
+        _ => preprocessed.request,
+    };
+
+    proxy_request(&state.reqwest_client, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MediaStreamingMode;
+    use crate::test_support::create_test_app_state;
+    use axum::{body::Body, extract::Request, routing::get};
+    use tower::ServiceExt;
+    use wiremock::{
+        matchers::{method, path, path_regex, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn start_server(name: &str) -> MockServer {
+        let upstream = MockServer::start().await;
+        Mock::given(path("/System/Info/Public"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "ServerName": name, "Version": "10.10.0" })),
+            )
+            .mount(&upstream)
+            .await;
+        upstream
+    }
+
+    #[tokio::test]
+    async fn federated_item_images_come_from_the_owning_server() {
+        let state = create_test_app_state().await;
+        let first = start_server("First").await;
+        let second = start_server("Second").await;
+        Mock::given(path_regex("/Images/"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&first)
+            .await;
+
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let image_tag = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        Mock::given(method("GET"))
+            .and(path(format!("/Items/{item_id}/Images/Primary")))
+            .and(query_param("tag", image_tag))
+            .and(query_param("maxWidth", "300"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Cache-Control", "public, max-age=31536000")
+                    .insert_header("ETag", "\"poster\"")
+                    .set_body_raw(b"poster".to_vec(), "image/jpeg"),
+            )
+            .expect(1)
+            .mount(&second)
+            .await;
+
+        for (name, upstream, priority) in [("First", &first, 200), ("Second", &second, 100)] {
+            state
+                .server_storage
+                .add_server(name, &upstream.uri(), priority, MediaStreamingMode::Proxy)
+                .await
+                .unwrap();
+        }
+        state.server_storage.check_servers_health().await;
+        let second_server = state
+            .server_storage
+            .list_servers()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|server| server.name == "Second")
+            .unwrap();
+        let virtual_id = |id: &'static str| {
+            let state = state.clone();
+            let server = second_server.clone();
+            async move {
+                state
+                    .media_storage
+                    .get_or_create_media_mapping(id, &server)
+                    .await
+                    .unwrap()
+                    .virtual_media_id
+            }
+        };
+        let virtual_item_id = virtual_id(item_id).await;
+        let virtual_image_tag = virtual_id(image_tag).await;
+
+        let router = axum::Router::new()
+            .route("/Items/{item_id}/Images/{*image}", get(get_item_image))
+            .with_state(state);
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri(format!(
+                        "/Items/{virtual_item_id}/Images/Primary?tag={virtual_image_tag}&maxWidth=300"
+                    ))
+                    .header(hyper::header::HOST, "localhost")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["cache-control"],
+            "public, max-age=31536000"
+        );
+        assert_eq!(response.headers()["etag"], "\"poster\"");
+        assert_eq!(response.headers()["content-type"], "image/jpeg");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"poster");
+    }
+}
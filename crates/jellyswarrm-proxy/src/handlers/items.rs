@@ -3,48 +3,68 @@ use hyper::StatusCode;
 use tracing::{debug, error, warn};
 
 use crate::{
+    config::ShowChildOrder,
+    duplicate_policy::is_same_version,
     extractors::{Preprocessed, RequireSession},
     handlers::common::{
-        execute_json_request, execute_processed_json_request, payload_from_request,
-        process_playback_response, remap_playback_request, set_json_body,
+        apply_user_bitrate_cap, execute_json_request, execute_processed_json_request,
+        payload_from_request, playback_request_from_query, process_playback_response,
+        remap_playback_request, set_json_body,
     },
-    models::{PlaybackRequest, PlaybackResponse},
+    handlers::watch_state::merge_mirror_user_data,
+    models::{ItemsResponseVariants, MediaItem, PlaybackRequest, PlaybackResponse},
     processors::response_processor::ResponseProcessingProfile,
-    request_preprocessing::PreprocessedRequest,
+    request_preprocessing::{
+        apply_authorization_header, apply_to_request, remap_authorization, JellyfinAuthorization,
+        PreprocessedRequest,
+    },
+    server_storage::Server,
+    url_helper::join_server_url,
+    user_authorization_service::AuthorizationSession,
     virtual_library_service::VirtualLibraryResolution,
     AppState,
 };
 
-async fn get_processed_item_json(
+/// Forward a request to the backend that owns its media id and remap every item id
+/// in the response to a virtual one.
+pub(crate) async fn execute_media_request(
     state: &AppState,
     preprocessed: PreprocessedRequest,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    let virtual_library = preprocessed
-        .original_request
-        .url()
-        .path_segments()
-        .and_then(Iterator::last)
-        .map(str::to_string);
-    let server = preprocessed.server;
+) -> Result<serde_json::Value, StatusCode> {
     let proxy_api_key = preprocessed
         .user
         .as_ref()
         .map(|user| user.virtual_key.clone());
 
-    let mut response = execute_processed_json_request(
+    execute_processed_json_request(
         state,
         preprocessed.request,
-        &server,
+        &preprocessed.server,
         ResponseProcessingProfile::Media,
         false,
         proxy_api_key.as_deref(),
     )
-    .await?;
+    .await
+}
+
+async fn get_processed_item_json(
+    state: &AppState,
+    preprocessed: PreprocessedRequest,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let virtual_library = preprocessed
+        .original_request
+        .url()
+        .path_segments()
+        .and_then(Iterator::last)
+        .map(str::to_string);
+    let access_scope = preprocessed.access_scope.clone();
+
+    let mut response = execute_media_request(state, preprocessed).await?;
 
     if let Some(virtual_id) = virtual_library {
         let resolution = state
             .virtual_library_service
-            .resolve(&virtual_id, preprocessed.access_scope.as_ref())
+            .resolve(&virtual_id, access_scope.as_ref())
             .await
             .map_err(|error| {
                 error!("Failed to resolve virtual library item: {error}");
@@ -64,11 +84,32 @@ async fn get_processed_item_json(
 
 //http://localhost:3000/Users/7bc57a386ab84999ad7262210a9cd253/Items/5f7e146c44d84b479cafecd3280be4ea
 //http://localhost:3000/Items/430c368c5eb34534bf98363d5adbb92f?userId=520ea298ed8044338a28d912523d715f
+/// The query, `Fields` included, is forwarded as the client sent it, so the backend
+/// only returns the fields asked for and none are added to the item here.
 pub async fn get_item(
     State(state): State<AppState>,
     Preprocessed(preprocessed): Preprocessed,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    get_processed_item_json(&state, preprocessed).await
+    let mirror_source = match (
+        &preprocessed.session,
+        item_id_segment(preprocessed.request.url()),
+    ) {
+        (Some(session), Some(item_id)) => Some((
+            preprocessed.server.clone(),
+            session.clone(),
+            preprocessed.sessions.clone(),
+            item_id,
+        )),
+        _ => None,
+    };
+
+    let Json(mut item) = get_processed_item_json(&state, preprocessed).await?;
+
+    if let Some((server, session, sessions, item_id)) = mirror_source {
+        merge_mirror_user_data(&state, &server, &session, &sessions, &item_id, &mut item).await;
+    }
+
+    Ok(Json(item))
 }
 
 //http://localhost:3000/Users/7bc57a386ab84999ad7262210a9cd253/Items?SortBy=SortName%2CProductionYear&SortOrder=Ascending&IncludeItemTypes=Movie&Recursive=true&Fields=PrimaryImageAspectRatio%2CMediaSourceCount&ImageTypeLimit=1&EnableImageTypes=Primary%2CBackdrop%2CBanner%2CThumb&StartIndex=0&ParentId=5f7e146c44d84b479cafecd3280be4ea&Limit=100
@@ -80,16 +121,50 @@ pub async fn get_items(
     get_processed_item_json(&state, preprocessed).await
 }
 
-// can be used for special features etc.
+// Item-scoped child lists such as special features and local trailers. These are
+// never federated: the parent item id pins the request to its backend, and the
+// returned children only need their ids remapped.
+// Seasons and episodes of a show. The show id pins the request to the backend
+// that owns it, so the children keep that server's order and image tags and only
+// have their ids remapped, unless `show_child_order` asks for them to be sorted.
+//http://localhost:3000/Shows/430c368c5eb34534bf98363d5adbb92f/Episodes?seasonId=5f7e146c44d84b479cafecd3280be4ea&userId=520ea298ed8044338a28d912523d715f&Fields=Overview
+pub async fn get_show_children(
+    State(state): State<AppState>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut response = execute_media_request(&state, preprocessed).await?;
+
+    if state.show_child_order().await == ShowChildOrder::Index {
+        if let Some(items) = response
+            .get_mut("Items")
+            .and_then(serde_json::Value::as_array_mut)
+        {
+            items.sort_by_key(|item| {
+                let index = |field: &str| item.get(field).and_then(serde_json::Value::as_i64);
+                (
+                    index("ParentIndexNumber").unwrap_or(i64::MAX),
+                    index("IndexNumber").unwrap_or(i64::MAX),
+                )
+            });
+        }
+    }
+
+    Ok(Json(response))
+}
+
+//http://localhost:3000/Items/430c368c5eb34534bf98363d5adbb92f/SpecialFeatures?userId=520ea298ed8044338a28d912523d715f
 pub async fn get_items_list(
     State(state): State<AppState>,
     Preprocessed(preprocessed): Preprocessed,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    get_processed_item_json(&state, preprocessed).await
+    execute_media_request(&state, preprocessed).await.map(Json)
 }
 
 //http://192.168.188.142:30013/Items/165a66aa5bd2e62c0df0f8da332ae47d/PlaybackInfo
 #[axum::debug_handler]
+/// Resolve playback info on the server owning the item. Older clients send the
+/// request as a `GET` with its fields in the query; it is forwarded as the `POST`
+/// with a JSON body that every backend understands.
 pub async fn post_playback_info(
     State(state): State<AppState>,
     RequireSession {
@@ -97,26 +172,49 @@ pub async fn post_playback_info(
         session,
     }: RequireSession,
 ) -> Result<Json<PlaybackResponse>, StatusCode> {
-    let original_request = preprocessed.original_request;
-    let payload: PlaybackRequest = payload_from_request(&original_request)?;
+    let is_get = preprocessed.original_request.method() == reqwest::Method::GET;
+    let payload: PlaybackRequest = if is_get {
+        playback_request_from_query(&preprocessed.original_request)?
+    } else {
+        payload_from_request(&preprocessed.original_request)?
+    };
 
     if payload.device_profile.is_none() {
         warn!("Got playback request from client without device profile. Transcoding will be enforced!")
     }
 
-    let server = preprocessed.server;
+    let retargeted = match payload.media_source_id.as_deref() {
+        Some(media_source_id) => {
+            retarget_to_media_source_server(&state, &preprocessed, media_source_id).await?
+        }
+        None => None,
+    };
+    let (mut request, server, session) = match retargeted {
+        Some(target) => target,
+        None => (preprocessed.request, preprocessed.server, session),
+    };
 
     let mut payload = payload;
     remap_playback_request(&mut payload, &state, &session).await?;
 
+    let stream_bitrate =
+        apply_user_bitrate_cap(&mut payload, &mut request, &state, &session).await?;
+
     debug!("Forwarding PlaybackRequest JSON: {:?}", &payload);
 
-    let mut request = preprocessed.request;
+    if is_get {
+        *request.method_mut() = reqwest::Method::POST;
+        request.headers_mut().insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+    }
     set_json_body(&mut request, &payload)?;
 
     match execute_json_request::<PlaybackResponse>(&state.reqwest_client, request).await {
         Ok(mut response) => {
-            process_playback_response(&mut response, &state, &server, &session).await?;
+            process_playback_response(&mut response, &state, &server, &session, stream_bitrate)
+                .await?;
 
             debug!("Requested Playback: {:?}", response);
 
@@ -128,3 +226,1269 @@ pub async fn post_playback_info(
         }
     }
 }
+
+//http://localhost:3000/Items/430c368c5eb34534bf98363d5adbb92f/Refresh?Recursive=true&MetadataRefreshMode=Default&ImageRefreshMode=Default
+/// Refresh an item's metadata on the backend that owns it. Whether the mapped user
+/// may refresh metadata is up to that backend, so its status is returned as-is.
+///
+/// With `refresh_all_copies`, copies of the item on the user's other servers are
+/// refreshed too; failures there are only logged.
+pub async fn refresh_item(
+    State(state): State<AppState>,
+    RequireSession {
+        preprocessed,
+        session,
+    }: RequireSession,
+) -> Result<StatusCode, StatusCode> {
+    let copies = if state.refresh_all_copies_enabled().await {
+        find_item_copies(&state, &preprocessed, &session).await
+    } else {
+        Vec::new()
+    };
+
+    let query = preprocessed.request.url().query().map(str::to_string);
+    let response = state
+        .reqwest_client
+        .execute(preprocessed.request)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to forward refresh request to {}: {}",
+                preprocessed.server.name, e
+            );
+            StatusCode::BAD_GATEWAY
+        })?;
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).map_err(|_| StatusCode::BAD_GATEWAY)?;
+    if !status.is_success() {
+        return Ok(status);
+    }
+
+    forward_to_copies(
+        &state,
+        copies,
+        reqwest::Method::POST,
+        query.as_deref(),
+        |_, copy_id| format!("/Items/{copy_id}/Refresh"),
+    )
+    .await;
+
+    Ok(status)
+}
+
+//http://localhost:3000/Users/7bc57a386ab84999ad7262210a9cd253/Items/430c368c5eb34534bf98363d5adbb92f/Rating?likes=true
+/// Like, dislike (`POST`) or clear the rating of (`DELETE`) an item on the backend
+/// that owns it, returning its remapped user data.
+///
+/// With `rate_all_copies`, copies of the item on the user's other servers get the
+/// same rating; failures there are only logged.
+pub async fn update_item_rating(
+    State(state): State<AppState>,
+    RequireSession {
+        preprocessed,
+        session,
+    }: RequireSession,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let copies = if state.rate_all_copies_enabled().await {
+        find_item_copies(&state, &preprocessed, &session).await
+    } else {
+        Vec::new()
+    };
+
+    let method = preprocessed.request.method().clone();
+    let query = preprocessed.request.url().query().map(str::to_string);
+    let user_data = execute_media_request(&state, preprocessed).await?;
+
+    forward_to_copies(
+        &state,
+        copies,
+        method,
+        query.as_deref(),
+        |session, copy_id| format!("/Users/{}/Items/{copy_id}/Rating", session.original_user_id),
+    )
+    .await;
+
+    Ok(Json(user_data))
+}
+
+/// Send the same request to every copy found by [`find_item_copies`], at the path
+/// `path` builds from the copy's session and id. Failures are only logged.
+pub(crate) async fn forward_to_copies(
+    state: &AppState,
+    copies: Vec<(Server, AuthorizationSession, String)>,
+    method: reqwest::Method,
+    query: Option<&str>,
+    path: impl Fn(&AuthorizationSession, &str) -> String,
+) {
+    for (server, session, copy_id) in copies {
+        let mut url = join_server_url(&server.url, &path(&session, &copy_id));
+        url.set_query(query);
+        let request = session_request(state, method.clone(), url, &session).await;
+        match state.reqwest_client.execute(request).await {
+            Ok(response) if response.status().is_success() => {
+                debug!(
+                    "Forwarded {} to copy {} on server {}",
+                    method, copy_id, server.name
+                )
+            }
+            Ok(response) => warn!(
+                "Forwarding {} to copy {} on server {} returned {}",
+                method,
+                copy_id,
+                server.name,
+                response.status()
+            ),
+            Err(e) => warn!(
+                "Failed to forward {} to copy {} on server {}: {}",
+                method, copy_id, server.name, e
+            ),
+        }
+    }
+}
+
+/// Look up the requested item on its backend and find the items on the user's other
+/// servers that the library deduplication strategy considers the same title.
+pub(crate) async fn find_item_copies(
+    state: &AppState,
+    preprocessed: &PreprocessedRequest,
+    session: &AuthorizationSession,
+) -> Vec<(Server, AuthorizationSession, String)> {
+    let Some(item_id) = item_id_segment(preprocessed.request.url()) else {
+        return Vec::new();
+    };
+    let others = preprocessed
+        .sessions
+        .iter()
+        .flatten()
+        .filter(|(_, server)| server.id != preprocessed.server.id)
+        .collect::<Vec<_>>();
+    find_copies_on(state, &preprocessed.server, session, &item_id, others).await
+}
+
+/// Look up `item_id` on `server` and find the items on the servers of `others`
+/// that the library deduplication strategy considers the same title.
+pub(crate) async fn find_copies_on<'a>(
+    state: &AppState,
+    server: &Server,
+    session: &AuthorizationSession,
+    item_id: &str,
+    others: impl IntoIterator<Item = &'a (AuthorizationSession, Server)>,
+) -> Vec<(Server, AuthorizationSession, String)> {
+    let url = join_server_url(
+        &server.url,
+        &format!("/Users/{}/Items/{item_id}", session.original_user_id),
+    );
+    let item: MediaItem = match execute_json_request(
+        &state.reqwest_client,
+        session_request(state, reqwest::Method::GET, url, session).await,
+    )
+    .await
+    {
+        Ok(item) => item,
+        Err(status) => {
+            warn!(
+                "Failed to look up item {} to find its copies: {}",
+                item_id, status
+            );
+            return Vec::new();
+        }
+    };
+    let (Some(name), Some(item_type)) = (
+        item.name.as_deref(),
+        serde_json::to_value(&item.item_type)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string)),
+    ) else {
+        return Vec::new();
+    };
+
+    let strategy = state.library_deduplication_strategy().await;
+    let titles = state.title_normalizer().await;
+    let mut copies = Vec::new();
+    for (other_session, server) in others {
+        let mut url = join_server_url(
+            &server.url,
+            &format!("/Users/{}/Items", other_session.original_user_id),
+        );
+        url.query_pairs_mut()
+            .append_pair("Recursive", "true")
+            .append_pair("IncludeItemTypes", &item_type)
+            .append_pair("SearchTerm", name)
+            .append_pair(
+                "Fields",
+                "ProviderIds,ProductionYear,SortName,OriginalTitle",
+            );
+        let candidates: ItemsResponseVariants = match execute_json_request(
+            &state.reqwest_client,
+            session_request(state, reqwest::Method::GET, url, other_session).await,
+        )
+        .await
+        {
+            Ok(candidates) => candidates,
+            Err(status) => {
+                warn!(
+                    "Failed to search server {} for copies of {}: {}",
+                    server.name, item_id, status
+                );
+                continue;
+            }
+        };
+
+        copies.extend(
+            candidates
+                .into_items()
+                .into_iter()
+                .filter(|candidate| is_same_version(&item, candidate, strategy, &titles))
+                .map(|candidate| (server.clone(), other_session.clone(), candidate.id)),
+        );
+    }
+    copies
+}
+
+pub(crate) async fn session_request(
+    state: &AppState,
+    method: reqwest::Method,
+    url: url::Url,
+    session: &AuthorizationSession,
+) -> reqwest::Request {
+    let mut request = reqwest::Request::new(method, url);
+    *request.timeout_mut() = Some(state.upstream_timeout().await);
+    apply_authorization_header(
+        &mut request,
+        &Some(JellyfinAuthorization::Authorization(
+            session.to_authorization(),
+        )),
+    );
+    request
+}
+
+/// The id following an `Items` segment, or one ending in it such as `PlayedItems`.
+pub(crate) fn item_id_segment(url: &url::Url) -> Option<String> {
+    let mut segments = url.path_segments()?;
+    segments.find(|segment| segment.to_ascii_lowercase().ends_with("items"))?;
+    segments
+        .next()
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+}
+
+/// Merged libraries can list another backend's version of an item among its media
+/// sources. When the client picks one of those, send the playback request to the
+/// server that owns the media source instead of the one that owns the item.
+async fn retarget_to_media_source_server(
+    state: &AppState,
+    preprocessed: &PreprocessedRequest,
+    media_source_id: &str,
+) -> Result<Option<(reqwest::Request, Server, AuthorizationSession)>, StatusCode> {
+    let Some((_, server)) = state
+        .media_storage
+        .get_media_mapping_with_server(media_source_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to resolve media source server: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+    else {
+        return Ok(None);
+    };
+
+    if server.id == preprocessed.server.id {
+        return Ok(None);
+    }
+
+    let Some(session) = preprocessed.sessions.as_ref().and_then(|sessions| {
+        sessions
+            .iter()
+            .find(|(_, session_server)| session_server.id == server.id)
+            .map(|(session, _)| session.clone())
+    }) else {
+        warn!(
+            "Media source {} belongs to server {} but the user has no session there",
+            media_source_id, server.name
+        );
+        return Ok(None);
+    };
+
+    let mut request = preprocessed.original_request.try_clone().ok_or_else(|| {
+        error!("Failed to clone playback request for retargeting");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    replace_item_id_segment(request.url_mut(), media_source_id);
+
+    let session = Some(session);
+    let new_auth = remap_authorization(&preprocessed.auth, &session, &server)
+        .await
+        .map_err(|e| {
+            error!("Failed to remap authorization for playback request: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+    apply_to_request(
+        &mut request,
+        &server,
+        &session,
+        &new_auth,
+        state,
+        preprocessed.access_scope.as_ref(),
+    )
+    .await;
+
+    debug!(
+        "Retargeted playback request for media source {} to server {}",
+        media_source_id, server.name
+    );
+    Ok(session.map(|session| (request, server, session)))
+}
+
+fn replace_item_id_segment(url: &mut url::Url, item_id: &str) {
+    let mut segments: Vec<String> = match url.path_segments() {
+        Some(segments) => segments.map(str::to_string).collect(),
+        None => return,
+    };
+    let Some(index) = segments
+        .iter()
+        .position(|segment| segment.eq_ignore_ascii_case("Items"))
+    else {
+        return;
+    };
+    let Some(segment) = segments.get_mut(index + 1) else {
+        return;
+    };
+    *segment = item_id.to_string();
+
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.clear().extend(segments);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{add_server_with_session, create_test_app_state};
+    use crate::{
+        models::Authorization, request_preprocessing::preprocess_request,
+        user_authorization_service::User,
+    };
+    use axum::body::Body;
+    use wiremock::{
+        matchers::{body_partial_json, header_regex, method, path, path_regex, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn connect_servers<const N: usize>(
+        state: &AppState,
+        upstreams: [(&str, &MockServer, i32); N],
+    ) -> (User, Vec<Server>) {
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let mut servers = Vec::new();
+        for (name, upstream, priority) in upstreams {
+            servers.push(add_server_with_session(state, &user, name, upstream, priority).await);
+        }
+
+        state.server_storage.check_servers_health().await;
+        (user, servers)
+    }
+
+    fn auth_header(user: &User) -> String {
+        Authorization {
+            client: "Jellyfin Web".to_string(),
+            device: "Firefox".to_string(),
+            device_id: "web-device-id".to_string(),
+            version: "10.10.7".to_string(),
+            token: Some(user.virtual_key.clone()),
+        }
+        .to_header_value()
+    }
+
+    #[tokio::test]
+    async fn special_features_resolve_to_item_backend_and_remap_children() {
+        let state = create_test_app_state().await;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+
+        let parent_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let child_id = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+        Mock::given(method("GET"))
+            .and(path(format!("/Items/{parent_id}/SpecialFeatures")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .expect(0)
+            .mount(&first_upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/Items/{parent_id}/SpecialFeatures")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "Id": child_id, "Name": "Behind the Scenes", "ParentId": parent_id }
+            ])))
+            .expect(1)
+            .mount(&second_upstream)
+            .await;
+
+        let (user, servers) = connect_servers(
+            &state,
+            [
+                ("First", &first_upstream, 200),
+                ("Second", &second_upstream, 100),
+            ],
+        )
+        .await;
+
+        let parent_mapping = state
+            .media_storage
+            .get_or_create_media_mapping(parent_id, &servers[1])
+            .await
+            .unwrap();
+
+        let auth_header = auth_header(&user);
+        let uri: axum::http::Uri = format!(
+            "/Items/{}/SpecialFeatures?userId={}",
+            parent_mapping.virtual_media_id, user.id
+        )
+        .parse()
+        .unwrap();
+        let request = axum::http::Request::builder()
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header)
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+
+        let preprocessed = preprocess_request(request, &state).await.unwrap();
+        assert_eq!(preprocessed.server.id, servers[1].id);
+
+        let Json(response) = get_items_list(State(state.clone()), Preprocessed(preprocessed))
+            .await
+            .unwrap();
+
+        let items = response.as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        let returned_id = items[0]["Id"].as_str().unwrap();
+        assert_ne!(returned_id, child_id);
+
+        let child_mapping = state
+            .media_storage
+            .get_media_mapping_by_virtual(returned_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(child_mapping.original_media_id, child_id);
+        assert_eq!(child_mapping.server_id, servers[1].id);
+    }
+
+    #[tokio::test]
+    async fn playback_info_follows_media_source_to_its_backend() {
+        let state = create_test_app_state().await;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let source_id = "cccccccccccccccccccccccccccccccc";
+
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{item_id}/PlaybackInfo")))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&first_upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{source_id}/PlaybackInfo")))
+            .and(body_partial_json(
+                serde_json::json!({ "MediaSourceId": source_id }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "MediaSources": [],
+                "PlaySessionId": "play-session"
+            })))
+            .expect(1)
+            .mount(&second_upstream)
+            .await;
+
+        let (user, servers) = connect_servers(
+            &state,
+            [
+                ("First", &first_upstream, 200),
+                ("Second", &second_upstream, 100),
+            ],
+        )
+        .await;
+
+        let item_mapping = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[0])
+            .await
+            .unwrap();
+        let source_mapping = state
+            .media_storage
+            .get_or_create_media_mapping(source_id, &servers[1])
+            .await
+            .unwrap();
+
+        let uri: axum::http::Uri = format!(
+            "/Items/{}/PlaybackInfo?userId={}",
+            item_mapping.virtual_media_id, user.id
+        )
+        .parse()
+        .unwrap();
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header(&user))
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::from(
+                serde_json::json!({ "MediaSourceId": source_mapping.virtual_media_id }).to_string(),
+            ))
+            .unwrap();
+
+        let preprocessed = preprocess_request(request, &state).await.unwrap();
+        let session = preprocessed.session.clone().unwrap();
+        let Json(response) = post_playback_info(
+            State(state.clone()),
+            RequireSession {
+                preprocessed,
+                session,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.play_session_id, "play-session");
+    }
+
+    #[tokio::test]
+    async fn item_fields_are_forwarded_unchanged_and_not_populated() {
+        let state = create_test_app_state().await;
+        let upstream = MockServer::start().await;
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+        Mock::given(method("GET"))
+            .and(path(format!("/Items/{item_id}")))
+            .and(query_param("Fields", "Overview,Genres"))
+            .and(query_param("userId", "Main-user-id"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Id": item_id,
+                "Name": "Movie",
+                "Type": "Movie",
+                "Overview": "A movie",
+                "Genres": ["Drama"]
+            })))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let (user, servers) = connect_servers(&state, [("Main", &upstream, 100)]).await;
+        let mapping = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[0])
+            .await
+            .unwrap();
+
+        let preprocessed = preprocessed_get(
+            &state,
+            &user,
+            &format!(
+                "/Items/{}?userId={}&Fields=Overview%2CGenres",
+                mapping.virtual_media_id, user.id
+            ),
+        )
+        .await;
+        let Json(item) = get_item(State(state.clone()), Preprocessed(preprocessed))
+            .await
+            .unwrap();
+
+        assert_eq!(item["Id"], mapping.virtual_media_id);
+        assert_eq!(item["Overview"], "A movie");
+        for heavy in ["MediaSources", "MediaStreams", "Chapters", "People"] {
+            assert!(item.get(heavy).is_none(), "{heavy} was not requested");
+        }
+    }
+
+    #[tokio::test]
+    async fn playback_info_get_is_forwarded_as_post_with_the_query_fields() {
+        let state = create_test_app_state().await;
+        let upstream = MockServer::start().await;
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{item_id}/PlaybackInfo")))
+            .and(body_partial_json(serde_json::json!({
+                "MaxStreamingBitrate": 8000000,
+                "AudioStreamIndex": 1,
+                "IsPlayback": true,
+                "EnableDirectPlay": true,
+                "MediaSourceId": item_id
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "MediaSources": [],
+                "PlaySessionId": "play-session"
+            })))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let (user, servers) = connect_servers(&state, [("Main", &upstream, 100)]).await;
+        let mapping = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[0])
+            .await
+            .unwrap();
+
+        let preprocessed = preprocessed_get(
+            &state,
+            &user,
+            &format!(
+                "/Items/{id}/PlaybackInfo?userId={}&MaxStreamingBitrate=8000000\
+                 &AudioStreamIndex=1&IsPlayback=true&EnableDirectPlay=true&MediaSourceId={id}",
+                user.id,
+                id = mapping.virtual_media_id
+            ),
+        )
+        .await;
+        let session = preprocessed.session.clone().unwrap();
+        let Json(response) = post_playback_info(
+            State(state.clone()),
+            RequireSession {
+                preprocessed,
+                session,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.play_session_id, "play-session");
+    }
+
+    #[tokio::test]
+    async fn playback_info_without_playback_start_reserves_no_bitrate() {
+        let state = create_test_app_state().await;
+        state.config.write().await.per_user_max_bitrate = 10_000_000;
+        let upstream = MockServer::start().await;
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{item_id}/PlaybackInfo")))
+            .and(body_partial_json(serde_json::json!({
+                "MaxStreamingBitrate": 10000000
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "MediaSources": [],
+                "PlaySessionId": "play-session"
+            })))
+            .expect(2)
+            .mount(&upstream)
+            .await;
+
+        let (user, servers) = connect_servers(&state, [("Main", &upstream, 100)]).await;
+        let mapping = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[0])
+            .await
+            .unwrap();
+
+        // Neither request asks for a bitrate and neither starts playing.
+        for _ in 0..2 {
+            let preprocessed = preprocessed_get(
+                &state,
+                &user,
+                &format!(
+                    "/Items/{}/PlaybackInfo?userId={}",
+                    mapping.virtual_media_id, user.id
+                ),
+            )
+            .await;
+            let session = preprocessed.session.clone().unwrap();
+            let result = post_playback_info(
+                State(state.clone()),
+                RequireSession {
+                    preprocessed,
+                    session,
+                },
+            )
+            .await;
+            assert!(result.is_ok());
+        }
+
+        assert_eq!(
+            state
+                .play_sessions
+                .active_bitrate_for_user(&user.id, None)
+                .await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn direct_play_stream_urls_resolve_to_the_source_of_the_playback_info() {
+        let state = create_test_app_state().await;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let version_id = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+        let media_source = |id: &str, name: &str| {
+            serde_json::json!({
+                "Protocol": "File",
+                "Id": id,
+                "Path": format!("/media/Movie/{name}.mkv"),
+                "Type": "Default",
+                "Container": "mkv",
+                "Name": name,
+                "IsRemote": false,
+                "ETag": "cccccccccccccccccccccccccccccccc",
+                "SupportsDirectPlay": true,
+                "SupportsDirectStream": true,
+                "SupportsTranscoding": true,
+                "DirectStreamUrl": format!(
+                    "/videos/{item_id}/stream.mkv?Static=true&mediaSourceId={id}\
+                     &PlaySessionId=play-session&api_key=Second-token"
+                ),
+                "MediaStreams": []
+            })
+        };
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{item_id}/PlaybackInfo")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "MediaSources": [media_source(item_id, "1080p"), media_source(version_id, "4K")],
+                "PlaySessionId": "play-session"
+            })))
+            .expect(1)
+            .mount(&second_upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(format!("(?i)^/videos/{item_id}/stream\\.mkv$")))
+            .and(query_param("Static", "true"))
+            .and(query_param("mediaSourceId", version_id))
+            .and(query_param("PlaySessionId", "play-session"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"video".to_vec()))
+            .expect(2)
+            .mount(&second_upstream)
+            .await;
+
+        let (user, servers) = connect_servers(
+            &state,
+            [
+                ("First", &first_upstream, 200),
+                ("Second", &second_upstream, 100),
+            ],
+        )
+        .await;
+        let item = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[1])
+            .await
+            .unwrap();
+
+        let uri: axum::http::Uri = format!(
+            "/Items/{}/PlaybackInfo?userId={}",
+            item.virtual_media_id, user.id
+        )
+        .parse()
+        .unwrap();
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header(&user))
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::from(
+                serde_json::json!({ "IsPlayback": true, "AutoOpenLiveStream": true }).to_string(),
+            ))
+            .unwrap();
+        let preprocessed = preprocess_request(request, &state).await.unwrap();
+        let session = preprocessed.session.clone().unwrap();
+        let Json(playback) = post_playback_info(
+            State(state.clone()),
+            RequireSession {
+                preprocessed,
+                session,
+            },
+        )
+        .await
+        .unwrap();
+
+        let sources = &playback.media_sources;
+        assert_eq!(sources[0].id, item.virtual_media_id);
+        assert_eq!(sources[0].path.as_deref(), Some("/media/Movie/1080p.mkv"));
+        let version = state
+            .media_storage
+            .get_media_mapping_by_virtual(&sources[1].id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(version.original_media_id, version_id);
+        assert_eq!(version.server_id, servers[1].id);
+
+        // Clients either build the stream URL from the source id or use the
+        // `DirectStreamUrl` the backend suggested.
+        let direct_stream_url = sources[1].direct_stream_url.clone().unwrap();
+        assert!(!direct_stream_url.contains(item_id));
+        assert!(!direct_stream_url.contains("Second-token"));
+        for stream_url in [
+            format!(
+                "/Videos/{}/stream.mkv?Static=true&mediaSourceId={}&PlaySessionId={}&api_key={}",
+                item.virtual_media_id, sources[1].id, playback.play_session_id, user.virtual_key
+            ),
+            direct_stream_url,
+        ] {
+            let preprocessed = preprocessed_get(&state, &user, &stream_url).await;
+            assert_eq!(preprocessed.server.name, "Second", "{stream_url}");
+            let response = crate::handlers::videos::get_stream(
+                State(state.clone()),
+                Preprocessed(preprocessed),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    async fn refresh(state: &AppState, user: &User, virtual_id: &str) -> StatusCode {
+        let uri: axum::http::Uri =
+            format!("/Items/{virtual_id}/Refresh?MetadataRefreshMode=FullRefresh")
+                .parse()
+                .unwrap();
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header(user))
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+
+        let preprocessed = preprocess_request(request, state).await.unwrap();
+        let session = preprocessed.session.clone().unwrap();
+        refresh_item(
+            State(state.clone()),
+            RequireSession {
+                preprocessed,
+                session,
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn refresh_resolves_to_item_backend() {
+        let state = create_test_app_state().await;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{item_id}/Refresh")))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(0)
+            .mount(&first_upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{item_id}/Refresh")))
+            .and(query_param("MetadataRefreshMode", "FullRefresh"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&second_upstream)
+            .await;
+
+        let (user, servers) = connect_servers(
+            &state,
+            [
+                ("First", &first_upstream, 200),
+                ("Second", &second_upstream, 100),
+            ],
+        )
+        .await;
+        let mapping = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[1])
+            .await
+            .unwrap();
+
+        let status = refresh(&state, &user, &mapping.virtual_media_id).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn refresh_all_copies_refreshes_matching_items_on_other_servers() {
+        let state = create_test_app_state().await;
+        state.config.write().await.refresh_all_copies = true;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let copy_id = "cccccccccccccccccccccccccccccccc";
+        let other_id = "dddddddddddddddddddddddddddddddd";
+
+        Mock::given(method("GET"))
+            .and(path(format!("/Users/Second-user-id/Items/{item_id}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Id": item_id,
+                "Name": "Heat",
+                "Type": "Movie",
+                "ProviderIds": { "Imdb": "tt0113277" }
+            })))
+            .mount(&second_upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{item_id}/Refresh")))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&second_upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Users/First-user-id/Items"))
+            .and(query_param("SearchTerm", "Heat"))
+            .and(query_param("IncludeItemTypes", "Movie"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Items": [
+                    { "Id": copy_id, "Name": "Heat", "Type": "Movie", "ProviderIds": { "Imdb": "tt0113277" } },
+                    { "Id": other_id, "Name": "Heat", "Type": "Movie", "ProviderIds": { "Imdb": "tt0000001" } }
+                ],
+                "TotalRecordCount": 2,
+                "StartIndex": 0
+            })))
+            .mount(&first_upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{copy_id}/Refresh")))
+            .and(query_param("MetadataRefreshMode", "FullRefresh"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&first_upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/Items/{other_id}/Refresh")))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(0)
+            .mount(&first_upstream)
+            .await;
+
+        let (user, servers) = connect_servers(
+            &state,
+            [
+                ("First", &first_upstream, 200),
+                ("Second", &second_upstream, 100),
+            ],
+        )
+        .await;
+        let mapping = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[1])
+            .await
+            .unwrap();
+
+        let status = refresh(&state, &user, &mapping.virtual_media_id).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    async fn rate(
+        state: &AppState,
+        user: &User,
+        method: &str,
+        virtual_id: &str,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        let uri: axum::http::Uri =
+            format!("/Users/{}/Items/{virtual_id}/Rating?likes=true", user.id)
+                .parse()
+                .unwrap();
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header(user))
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+
+        let preprocessed = preprocess_request(request, state).await.unwrap();
+        let session = preprocessed.session.clone().unwrap();
+        update_item_rating(
+            State(state.clone()),
+            RequireSession {
+                preprocessed,
+                session,
+            },
+        )
+        .await
+    }
+
+    async fn preprocessed_get(state: &AppState, user: &User, uri: &str) -> PreprocessedRequest {
+        let uri: axum::http::Uri = uri.parse().unwrap();
+        let request = axum::http::Request::builder()
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header(user))
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+        preprocess_request(request, state).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn show_episodes_keep_their_order_and_stream_from_the_owning_server() {
+        let state = create_test_app_state().await;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+
+        let show_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let season_id = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        let episode = |id: &str, season: i64, number: i64| {
+            serde_json::json!({
+                "Id": id,
+                "Name": format!("S{season}E{number}"),
+                "Type": "Episode",
+                "SeriesId": show_id,
+                "SeasonId": season_id,
+                "ParentIndexNumber": season,
+                "IndexNumber": number,
+                "ImageTags": { "Primary": "dddddddddddddddddddddddddddddddd" }
+            })
+        };
+        let episode_ids = [
+            "e0000000000000000000000000000002",
+            "e0000000000000000000000000000001",
+            "e0000000000000000000000000000003",
+        ];
+        Mock::given(method("GET"))
+            .and(path(format!("/Shows/{show_id}/Episodes")))
+            .and(query_param("seasonId", season_id))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Items": [
+                    episode(episode_ids[0], 1, 2),
+                    episode(episode_ids[1], 1, 1),
+                    episode(episode_ids[2], 2, 1),
+                ],
+                "TotalRecordCount": 3
+            })))
+            .expect(2)
+            .mount(&second_upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/Videos/{}/stream", episode_ids[1])))
+            .and(query_param("static", "true"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(b"episode".to_vec(), "video/mp4"))
+            .expect(1)
+            .mount(&second_upstream)
+            .await;
+        Mock::given(path_regex("^/(Shows|Videos)/"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&first_upstream)
+            .await;
+
+        let (user, servers) = connect_servers(
+            &state,
+            [
+                ("First", &first_upstream, 200),
+                ("Second", &second_upstream, 100),
+            ],
+        )
+        .await;
+        let mut virtual_ids = Vec::new();
+        for id in [show_id, season_id] {
+            virtual_ids.push(
+                state
+                    .media_storage
+                    .get_or_create_media_mapping(id, &servers[1])
+                    .await
+                    .unwrap()
+                    .virtual_media_id,
+            );
+        }
+        let episodes_uri = format!(
+            "/Shows/{}/Episodes?seasonId={}&userId={}",
+            virtual_ids[0], virtual_ids[1], user.id
+        );
+        let episodes = |state: AppState| {
+            let user = user.clone();
+            let uri = episodes_uri.clone();
+            let virtual_ids = virtual_ids.clone();
+            async move {
+                let preprocessed = preprocessed_get(&state, &user, &uri).await;
+                let Json(response) =
+                    get_show_children(State(state.clone()), Preprocessed(preprocessed))
+                        .await
+                        .unwrap();
+                let mut items = Vec::new();
+                for item in response["Items"].as_array().unwrap() {
+                    let virtual_id = item["Id"].as_str().unwrap().to_string();
+                    let original = state
+                        .media_storage
+                        .get_media_mapping_by_virtual(&virtual_id)
+                        .await
+                        .unwrap()
+                        .unwrap()
+                        .original_media_id;
+                    assert_eq!(item["SeriesId"], virtual_ids[0]);
+                    assert_eq!(item["SeasonId"], virtual_ids[1]);
+                    assert_ne!(
+                        item["ImageTags"]["Primary"],
+                        "dddddddddddddddddddddddddddddddd"
+                    );
+                    items.push((virtual_id, original));
+                }
+                items
+            }
+        };
+
+        let in_server_order = episodes(state.clone()).await;
+        let originals = in_server_order
+            .iter()
+            .map(|(_, original)| original.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(originals, episode_ids);
+
+        state.config.write().await.show_child_order = ShowChildOrder::Index;
+        let in_index_order = episodes(state.clone()).await;
+        let originals = in_index_order
+            .iter()
+            .map(|(_, original)| original.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(originals, [episode_ids[1], episode_ids[0], episode_ids[2]]);
+
+        let stream = preprocessed_get(
+            &state,
+            &user,
+            &format!("/Videos/{}/stream?static=true", in_index_order[0].0),
+        )
+        .await;
+        let response = crate::handlers::videos::get_stream(State(state), Preprocessed(stream))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"episode");
+    }
+
+    #[tokio::test]
+    async fn rating_resolves_to_item_backend_with_remapped_ids() {
+        let state = create_test_app_state().await;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+        Mock::given(method("POST"))
+            .and(path_regex("/Rating$"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&first_upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!(
+                "/Users/Second-user-id/Items/{item_id}/Rating"
+            )))
+            .and(query_param("likes", "true"))
+            .and(header_regex("authorization", "Token=\"Second-token\""))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ItemId": item_id,
+                "Key": "tt0113277",
+                "Likes": true,
+                "IsFavorite": false,
+                "Played": false
+            })))
+            .expect(1)
+            .mount(&second_upstream)
+            .await;
+
+        let (user, servers) = connect_servers(
+            &state,
+            [
+                ("First", &first_upstream, 200),
+                ("Second", &second_upstream, 100),
+            ],
+        )
+        .await;
+        let mapping = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[1])
+            .await
+            .unwrap();
+
+        let Json(user_data) = rate(&state, &user, "POST", &mapping.virtual_media_id)
+            .await
+            .unwrap();
+        assert_eq!(user_data["ItemId"], mapping.virtual_media_id);
+        assert_eq!(user_data["Likes"], true);
+    }
+
+    #[tokio::test]
+    async fn rate_all_copies_clears_the_rating_of_matching_items() {
+        let state = create_test_app_state().await;
+        state.config.write().await.rate_all_copies = true;
+        let first_upstream = MockServer::start().await;
+        let second_upstream = MockServer::start().await;
+
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let copy_id = "cccccccccccccccccccccccccccccccc";
+
+        Mock::given(method("GET"))
+            .and(path(format!("/Users/Second-user-id/Items/{item_id}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Id": item_id,
+                "Name": "Heat",
+                "Type": "Movie",
+                "ProviderIds": { "Imdb": "tt0113277" }
+            })))
+            .mount(&second_upstream)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path(format!(
+                "/Users/Second-user-id/Items/{item_id}/Rating"
+            )))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "ItemId": item_id, "Key": "tt0113277" })),
+            )
+            .expect(1)
+            .mount(&second_upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Users/First-user-id/Items"))
+            .and(query_param("SearchTerm", "Heat"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Items": [
+                    { "Id": copy_id, "Name": "Heat", "Type": "Movie", "ProviderIds": { "Imdb": "tt0113277" } }
+                ],
+                "TotalRecordCount": 1,
+                "StartIndex": 0
+            })))
+            .mount(&first_upstream)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path(format!("/Users/First-user-id/Items/{copy_id}/Rating")))
+            .and(query_param("likes", "true"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&first_upstream)
+            .await;
+
+        let (user, servers) = connect_servers(
+            &state,
+            [
+                ("First", &first_upstream, 200),
+                ("Second", &second_upstream, 100),
+            ],
+        )
+        .await;
+        let mapping = state
+            .media_storage
+            .get_or_create_media_mapping(item_id, &servers[1])
+            .await
+            .unwrap();
+
+        let Json(user_data) = rate(&state, &user, "DELETE", &mapping.virtual_media_id)
+            .await
+            .unwrap();
+        assert_eq!(user_data["ItemId"], mapping.virtual_media_id);
+    }
+}
@@ -5,8 +5,8 @@ use tracing::{debug, error};
 use crate::{
     extractors::RequireSession,
     handlers::common::{
-        execute_json_request, payload_from_request, process_playback_response,
-        remap_playback_request, set_json_body,
+        apply_user_bitrate_cap, execute_json_request, payload_from_request,
+        process_playback_response, remap_playback_request, set_json_body,
     },
     models::{PlaybackRequest, PlaybackResponse},
     AppState,
@@ -31,11 +31,15 @@ pub async fn post_livestream_open(
     remap_playback_request(&mut payload, &state, &session).await?;
 
     let mut request = preprocessed.request;
+    let stream_bitrate =
+        apply_user_bitrate_cap(&mut payload, &mut request, &state, &session).await?;
+
     set_json_body(&mut request, &payload)?;
 
     match execute_json_request::<PlaybackResponse>(&state.reqwest_client, request).await {
         Ok(mut response) => {
-            process_playback_response(&mut response, &state, &server, &session).await?;
+            process_playback_response(&mut response, &state, &server, &session, stream_bitrate)
+                .await?;
 
             debug!("Requested Playback: {:?}", response);
 
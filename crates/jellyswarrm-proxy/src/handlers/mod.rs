@@ -1,10 +1,14 @@
 pub(crate) mod branding;
+pub(crate) mod collections;
 pub(crate) mod common;
 pub(crate) mod federated;
+pub(crate) mod images;
 pub(crate) mod items;
 pub(crate) mod livestreams;
 pub(crate) mod quick_connect;
+pub(crate) mod sessions;
 pub(crate) mod syncplay;
 pub(crate) mod system;
 pub(crate) mod users;
 pub(crate) mod videos;
+pub(crate) mod watch_state;
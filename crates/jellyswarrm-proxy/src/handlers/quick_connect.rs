@@ -1,6 +1,9 @@
 use crate::{
+    config::QuickConnectMode,
     encryption::HashedPassword,
     models::{AuthenticateResponse, Authorization, SyncPlayUserAccessType},
+    server_id::ServerId,
+    server_storage::Server,
     AppState,
 };
 use axum::{
@@ -10,7 +13,7 @@ use axum::{
 };
 use chrono::{DateTime, Duration, Utc};
 use hyper::StatusCode;
-use jellyfin_api::{error::Error as JellyfinApiError, ClientInfo, JellyfinClient};
+use jellyfin_api::{error::JellyfinApiError, ClientInfo, JellyfinClient};
 use jellyswarrm_macros::multi_case_struct;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -37,6 +40,9 @@ pub struct QuickConnectSession {
     pub user_id: Option<String>,
     #[serde(skip)]
     pub expires_at: DateTime<Utc>,
+    /// Backend that issued this code when running in `Passthrough` mode.
+    #[serde(skip)]
+    pub server_id: Option<ServerId>,
 }
 
 impl QuickConnectSession {
@@ -60,9 +66,17 @@ impl QuickConnectSession {
             date_added: now,
             user_id: None,
             expires_at: now + Duration::minutes(10),
+            server_id: None,
         }
     }
 
+    /// Bind a session issued by a backend server to that server.
+    pub fn bound_to_server(mut self, server_id: ServerId) -> Self {
+        self.server_id = Some(server_id);
+        self.expires_at = Utc::now() + Duration::minutes(10);
+        self
+    }
+
     pub fn is_expired(&self) -> bool {
         Utc::now() > self.expires_at
     }
@@ -312,14 +326,96 @@ fn effective_quick_connect_authorization(
     authorization
 }
 
-pub async fn handle_quick_connect_enabled() -> Result<Json<bool>, StatusCode> {
-    Ok(Json(true))
+fn client_info_from_session(session: &QuickConnectSession) -> ClientInfo {
+    ClientInfo {
+        client: session.app_name.clone(),
+        device: session.device_name.clone(),
+        device_id: session.device_id.clone(),
+        version: session.app_version.clone(),
+    }
+}
+
+fn passthrough_client(
+    state: &AppState,
+    server: &Server,
+    client_info: ClientInfo,
+) -> Result<JellyfinClient, StatusCode> {
+    JellyfinClient::new_with_client(
+        server.url.as_str(),
+        client_info,
+        state.reqwest_client.clone(),
+    )
+    .map_err(|e| {
+        warn!(
+            "Failed to create Quick Connect client for {}: {}",
+            server.name, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn bound_server(state: &AppState, server_id: ServerId) -> Result<Server, StatusCode> {
+    state
+        .server_storage
+        .get_server_by_id(server_id)
+        .await
+        .map_err(|e| {
+            warn!("Failed to load Quick Connect server {}: {}", server_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn passthrough_status(err: JellyfinApiError) -> StatusCode {
+    match err {
+        JellyfinApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+        JellyfinApiError::Forbidden => StatusCode::FORBIDDEN,
+        JellyfinApiError::NotFound => StatusCode::NOT_FOUND,
+        e => {
+            warn!("Quick Connect passthrough request failed: {}", e);
+            StatusCode::BAD_GATEWAY
+        }
+    }
+}
+
+pub async fn handle_quick_connect_enabled(
+    State(state): State<AppState>,
+) -> Result<Json<bool>, StatusCode> {
+    match state.quick_connect_mode().await {
+        QuickConnectMode::Local => Ok(Json(true)),
+        QuickConnectMode::Disabled => Ok(Json(false)),
+        QuickConnectMode::Passthrough => {
+            let Some(server) = state.server_storage.get_best_server().await.map_err(|e| {
+                warn!("Failed to select a Quick Connect server: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            else {
+                return Ok(Json(false));
+            };
+
+            let client = passthrough_client(&state, &server, ClientInfo::default())?;
+            let enabled = client.quick_connect_enabled().await.unwrap_or_else(|e| {
+                warn!(
+                    "Failed to query Quick Connect state on {}: {}",
+                    server.name, e
+                );
+                false
+            });
+            Ok(Json(enabled))
+        }
+    }
 }
 
 pub async fn handle_quick_connect_initiate(
     State(state): State<AppState>,
     headers: HeaderMap,
 ) -> Result<Json<QuickConnectSession>, StatusCode> {
+    match state.quick_connect_mode().await {
+        QuickConnectMode::Disabled => return Err(StatusCode::UNAUTHORIZED),
+        QuickConnectMode::Passthrough => return initiate_on_backend(&state, &headers).await,
+        QuickConnectMode::Local => {}
+    }
+
     let secret = Uuid::new_v4().to_string();
     let code = generate_code();
     let (device_id, device_name, app_name, app_version) = parse_client_info(&headers);
@@ -344,13 +440,64 @@ pub async fn handle_quick_connect_initiate(
     Ok(Json(session))
 }
 
+/// Quick Connect has no user context yet, so the code is requested from the best server
+/// and every follow-up request for it is sent to that same server.
+async fn initiate_on_backend(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<Json<QuickConnectSession>, StatusCode> {
+    let server = state
+        .server_storage
+        .get_best_server()
+        .await
+        .map_err(|e| {
+            warn!("Failed to select a Quick Connect server: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let (device_id, device, client, version) = parse_client_info(headers);
+    let client_info = ClientInfo {
+        client,
+        device,
+        device_id,
+        version,
+    };
+
+    let session: QuickConnectSession = passthrough_client(state, &server, client_info)?
+        .quick_connect_initiate()
+        .await
+        .map_err(passthrough_status)?;
+    let session = session.bound_to_server(server.id);
+
+    info!(
+        "Initiated Quick Connect session for {} / {} on server {}",
+        session.app_name, session.device_name, server.name
+    );
+
+    state.quick_connect.store_session(session.clone());
+    state.quick_connect.cleanup_expired();
+
+    Ok(Json(session))
+}
+
 pub async fn handle_quick_connect_authorize(
     Query(params): Query<AuthorizeQuery>,
     State(state): State<AppState>,
     headers: HeaderMap,
 ) -> Result<Json<bool>, StatusCode> {
+    if state.quick_connect_mode().await == QuickConnectMode::Disabled {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
     let user_id = resolve_authorize_user_id(&state, &headers, params.user_id).await?;
 
+    if let Some(session) = state.quick_connect.get_session(&params.code) {
+        if let Some(server_id) = session.server_id {
+            return authorize_on_backend(&state, &params.code, server_id, &user_id).await;
+        }
+    }
+
     let success = state
         .quick_connect
         .update_session_by_code(&params.code, |session| {
@@ -369,6 +516,59 @@ pub async fn handle_quick_connect_authorize(
     }
 }
 
+async fn authorize_on_backend(
+    state: &AppState,
+    code: &str,
+    server_id: ServerId,
+    user_id: &str,
+) -> Result<Json<bool>, StatusCode> {
+    let (auth_session, server) = state
+        .user_authorization
+        .get_user_sessions(user_id, None)
+        .await
+        .map_err(|e| {
+            warn!("Failed to load sessions for user {}: {}", user_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .into_iter()
+        .find(|(_, server)| server.id == server_id)
+        .ok_or_else(|| {
+            warn!(
+                "User {} has no session on Quick Connect server {}",
+                user_id, server_id
+            );
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let authorization = auth_session.to_authorization();
+    let client_info = ClientInfo {
+        client: authorization.client,
+        device: authorization.device,
+        device_id: authorization.device_id,
+        version: authorization.version,
+    };
+    let client = passthrough_client(state, &server, client_info)?;
+    client.with_token(auth_session.jellyfin_token.clone()).await;
+
+    let authorized = client
+        .quick_connect_authorize(code, &auth_session.original_user_id)
+        .await
+        .map_err(passthrough_status)?;
+
+    if authorized {
+        state.quick_connect.update_session_by_code(code, |session| {
+            session.authenticated = true;
+            session.user_id = Some(user_id.to_string());
+        });
+        info!(
+            "Authorized Quick Connect code {} for user {} on server {}",
+            code, user_id, server.name
+        );
+    }
+
+    Ok(Json(authorized))
+}
+
 async fn resolve_authorize_user_id(
     state: &AppState,
     headers: &HeaderMap,
@@ -440,11 +640,36 @@ pub async fn handle_quick_connect_connect(
     Query(params): Query<ConnectQuery>,
     State(state): State<AppState>,
 ) -> Result<Json<QuickConnectSession>, StatusCode> {
-    if let Some(session) = state.quick_connect.get_session(&params.secret) {
-        Ok(Json(session))
-    } else {
-        Err(StatusCode::NOT_FOUND)
+    if state.quick_connect_mode().await == QuickConnectMode::Disabled {
+        return Err(StatusCode::UNAUTHORIZED);
     }
+
+    let session = state
+        .quick_connect
+        .get_session(&params.secret)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let Some(server_id) = session.server_id else {
+        return Ok(Json(session));
+    };
+
+    let server = bound_server(&state, server_id).await?;
+    let upstream: QuickConnectSession =
+        passthrough_client(&state, &server, client_info_from_session(&session))?
+            .quick_connect_connect(&params.secret)
+            .await
+            .map_err(passthrough_status)?;
+
+    state
+        .quick_connect
+        .update_session_by_code(&session.code, |session| {
+            session.authenticated = upstream.authenticated;
+        });
+
+    Ok(Json(QuickConnectSession {
+        authenticated: upstream.authenticated,
+        ..session
+    }))
 }
 
 pub async fn handle_authenticate_with_quick_connect(
@@ -452,11 +677,19 @@ pub async fn handle_authenticate_with_quick_connect(
     headers: HeaderMap,
     Json(request): Json<QuickConnectAuthenticateRequest>,
 ) -> Result<Json<AuthenticateResponse>, StatusCode> {
+    if state.quick_connect_mode().await == QuickConnectMode::Disabled {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
     let session = state
         .quick_connect
         .get_session(&request.secret)
         .ok_or(StatusCode::NOT_FOUND)?;
 
+    if let Some(server_id) = session.server_id {
+        return authenticate_on_backend(&state, &headers, &session, server_id).await;
+    }
+
     let Some(user_id) = session.user_id.clone() else {
         return Err(StatusCode::UNAUTHORIZED);
     };
@@ -561,6 +794,100 @@ pub async fn handle_authenticate_with_quick_connect(
     }
 }
 
+async fn authenticate_on_backend(
+    state: &AppState,
+    headers: &HeaderMap,
+    session: &QuickConnectSession,
+    server_id: ServerId,
+) -> Result<Json<AuthenticateResponse>, StatusCode> {
+    let server = bound_server(state, server_id).await?;
+    let client_info = parse_authorization_from_headers(headers)
+        .map(|auth| ClientInfo {
+            client: auth.client,
+            device: auth.device,
+            device_id: auth.device_id,
+            version: auth.version,
+        })
+        .unwrap_or_else(|| client_info_from_session(session));
+
+    let mut auth_response: AuthenticateResponse = passthrough_client(state, &server, client_info)?
+        .authenticate_with_quick_connect_typed(&session.secret)
+        .await
+        .map_err(passthrough_status)?;
+
+    let user = match &session.user_id {
+        Some(user_id) => state.user_authorization.get_user_by_id(user_id).await,
+        None => {
+            state
+                .user_authorization
+                .get_user_by_mapped_username(server.id, &auth_response.user.name)
+                .await
+        }
+    }
+    .map_err(|e| {
+        warn!("Database error while resolving Quick Connect user: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or_else(|| {
+        warn!(
+            "No proxy user is mapped to '{}' on server '{}'",
+            auth_response.user.name, server.name
+        );
+        StatusCode::UNAUTHORIZED
+    })?;
+
+    let auth_token = auth_response.access_token.clone();
+    let original_user_id = auth_response.user.id.clone();
+    let mut auth_to_store = effective_quick_connect_authorization(headers, session, &user.id);
+    auth_to_store.token = Some(auth_token.clone());
+
+    state
+        .user_authorization
+        .store_authorization_session(
+            &user.id,
+            &server,
+            &auth_to_store,
+            auth_token,
+            original_user_id,
+            None,
+        )
+        .await
+        .map_err(|e| {
+            warn!("Failed to store Quick Connect session: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    rewrite_authenticate_response(state, &mut auth_response, &user).await;
+    state.quick_connect.remove_session(&session.secret);
+
+    info!(
+        "Quick Connect authenticated '{}' on server '{}'",
+        user.original_username, server.name
+    );
+
+    Ok(Json(auth_response))
+}
+
+/// Replace the backend identity in an authentication response with the proxy user.
+async fn rewrite_authenticate_response(
+    state: &AppState,
+    auth_response: &mut AuthenticateResponse,
+    user: &crate::user_authorization_service::User,
+) {
+    let server_id = state.config.read().await.server_id.clone();
+    auth_response.server_id = server_id.clone();
+    auth_response.user.server_id = server_id.clone();
+    auth_response.session_info.server_id = server_id;
+
+    auth_response.session_info.user_id = user.id.clone();
+    auth_response.user.name = user.original_username.clone();
+    auth_response.session_info.user_name = user.original_username.clone();
+    auth_response.user.policy.is_administrator = false;
+    auth_response.user.policy.sync_play_access = SyncPlayUserAccessType::CreateAndJoinGroups;
+    auth_response.access_token = user.virtual_key.clone();
+    auth_response.user.id = user.id.clone();
+}
+
 #[derive(Debug)]
 enum QuickConnectAuthError {
     Network(String),
@@ -624,18 +951,7 @@ async fn authenticate_with_mapping_on_server(
     let auth_token = auth_response.access_token.clone();
     let original_user_id = auth_response.user.id.clone();
 
-    let server_id = state.config.read().await.server_id.clone();
-    auth_response.server_id = server_id.clone();
-    auth_response.user.server_id = server_id.clone();
-    auth_response.session_info.server_id = server_id;
-
-    auth_response.session_info.user_id = user.id.clone();
-    auth_response.user.name = user.original_username.clone();
-    auth_response.session_info.user_name = user.original_username.clone();
-    auth_response.user.policy.is_administrator = false;
-    auth_response.user.policy.sync_play_access = SyncPlayUserAccessType::CreateAndJoinGroups;
-    auth_response.access_token = user.virtual_key.clone();
-    auth_response.user.id = user.id.clone();
+    rewrite_authenticate_response(&state, &mut auth_response, &user).await;
 
     let mut auth_to_store = authorization;
     auth_to_store.token = Some(auth_token.clone());
@@ -663,14 +979,17 @@ async fn authenticate_with_mapping_on_server(
 
 fn map_jellyfin_auth_error(err: JellyfinApiError) -> QuickConnectAuthError {
     match err {
-        JellyfinApiError::AuthenticationFailed(_) => QuickConnectAuthError::InvalidCredentials,
         JellyfinApiError::Unauthorized | JellyfinApiError::Forbidden => {
             QuickConnectAuthError::InvalidCredentials
         }
-        JellyfinApiError::Serialization(e) => QuickConnectAuthError::Parse(e.to_string()),
-        JellyfinApiError::InvalidResponse(e) => QuickConnectAuthError::Parse(e),
+        JellyfinApiError::Deserialize(e) => QuickConnectAuthError::Parse(e.to_string()),
+        JellyfinApiError::InvalidResponse(e) | JellyfinApiError::NotJellyfin(e) => {
+            QuickConnectAuthError::Parse(e)
+        }
         JellyfinApiError::Network(e) => QuickConnectAuthError::Network(e.to_string()),
-        JellyfinApiError::ServerError(e) => QuickConnectAuthError::Network(e),
+        JellyfinApiError::Server(status, body) => {
+            QuickConnectAuthError::Network(format!("{status} - {body}"))
+        }
         JellyfinApiError::UrlParse(e) => QuickConnectAuthError::Internal(e.to_string()),
         JellyfinApiError::NotFound => QuickConnectAuthError::Internal(
             "Users/AuthenticateByName endpoint was not found on target server".to_string(),
@@ -681,56 +1000,20 @@ fn map_jellyfin_auth_error(err: JellyfinApiError) -> QuickConnectAuthError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{add_server_with_session, create_test_app_state};
     use crate::{
-        config::{AppConfig, MediaStreamingMode, MIGRATOR},
-        media_storage_service::MediaStorageService,
+        config::MediaStreamingMode,
         models::{AuthenticateResponse, SessionInfo, SyncPlayUserAccessType, User, UserPolicy},
-        server_storage::ServerStorageService,
-        session_storage::SessionStorage,
-        user_authorization_service::{Device, UserAuthorizationService},
-        AppState, DataContext, ProxyProcessors,
+        user_authorization_service::Device,
     };
     use axum::{extract::Query, Json};
     use hyper::http::HeaderValue;
-    use sqlx::SqlitePool;
-    use std::{collections::HashMap, sync::Arc};
+    use std::collections::HashMap;
     use wiremock::{
         matchers::{method, path},
         Mock, MockServer, ResponseTemplate,
     };
 
-    async fn create_test_app_state() -> AppState {
-        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&pool).await.unwrap();
-        let server_storage = ServerStorageService::new(pool.clone());
-        let media_storage = MediaStorageService::new(pool.clone());
-
-        let data_context = DataContext {
-            user_authorization: Arc::new(UserAuthorizationService::new(pool.clone())),
-            server_storage: Arc::new(server_storage.clone()),
-            media_storage: Arc::new(media_storage.clone()),
-            virtual_library_service: Arc::new(
-                crate::virtual_library_service::VirtualLibraryService::new(
-                    pool,
-                    server_storage,
-                    media_storage,
-                ),
-            ),
-            play_sessions: Arc::new(SessionStorage::new()),
-            config: Arc::new(tokio::sync::RwLock::new(AppConfig::default())),
-        };
-
-        let processors = ProxyProcessors::new(data_context.clone());
-
-        AppState::new(
-            reqwest::Client::new(),
-            reqwest::Client::new(),
-            data_context,
-            processors,
-            QuickConnectStorage::new(),
-        )
-    }
-
     #[test]
     fn quick_connect_session_serializes_to_jellyfin_shape() {
         let session = QuickConnectSession::new(
@@ -1041,4 +1324,145 @@ mod tests {
         );
         assert_eq!(web_sessions[0].0.jellyfin_token, "web-upstream-token");
     }
+
+    fn upstream_auth_response() -> AuthenticateResponse {
+        AuthenticateResponse {
+            user: User {
+                name: "mappeduser".to_string(),
+                server_id: "upstream-server".to_string(),
+                id: "upstream-user-id".to_string(),
+                policy: UserPolicy {
+                    is_administrator: false,
+                    sync_play_access: SyncPlayUserAccessType::None,
+                    extra: HashMap::new(),
+                },
+                extra: HashMap::new(),
+            },
+            session_info: SessionInfo {
+                user_id: "upstream-user-id".to_string(),
+                user_name: "mappeduser".to_string(),
+                server_id: "upstream-server".to_string(),
+                extra: HashMap::new(),
+            },
+            access_token: "qc-upstream-token".to_string(),
+            server_id: "upstream-server".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn passthrough_quick_connect_is_bound_to_the_initiating_server() {
+        let state = create_test_app_state().await;
+        state.config.write().await.quick_connect_mode = QuickConnectMode::Passthrough;
+        let upstream = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/QuickConnect/Initiate"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Authenticated": false,
+                "Secret": "upstream-secret",
+                "Code": "654321",
+                "DeviceId": "tv-device",
+                "DeviceName": "Chromecast",
+                "AppName": "Jellyfin Android TV",
+                "AppVersion": "0.19.7",
+                "DateAdded": "2024-01-01T00:00:00Z"
+            })))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/QuickConnect/Authorize"))
+            .and(wiremock::matchers::query_param("Code", "654321"))
+            .and(wiremock::matchers::query_param(
+                "UserId",
+                "Upstream-user-id",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(true))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/Users/AuthenticateWithQuickConnect"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(upstream_auth_response()))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let user = state
+            .user_authorization
+            .get_or_create_user("MyUser", &"local-pass".into())
+            .await
+            .unwrap();
+        let server = add_server_with_session(&state, &user, "Upstream", &upstream, 100).await;
+
+        let mut tv_headers = HeaderMap::new();
+        tv_headers.insert(
+            "authorization",
+            HeaderValue::from_static(
+                "MediaBrowser Client=\"Jellyfin Android TV\", Device=\"Chromecast\", DeviceId=\"tv-device\", Version=\"0.19.7\"",
+            ),
+        );
+
+        let Json(initiated) =
+            handle_quick_connect_initiate(axum::extract::State(state.clone()), tv_headers.clone())
+                .await
+                .unwrap();
+        assert_eq!(initiated.code, "654321");
+        assert_eq!(
+            state
+                .quick_connect
+                .get_session("upstream-secret")
+                .and_then(|session| session.server_id),
+            Some(server.id)
+        );
+
+        let Json(authorized) = handle_quick_connect_authorize(
+            Query(AuthorizeQuery {
+                code: "654321".to_string(),
+                user_id: Some(user.id.clone()),
+            }),
+            axum::extract::State(state.clone()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert!(authorized);
+
+        let Json(response) = handle_authenticate_with_quick_connect(
+            axum::extract::State(state.clone()),
+            tv_headers,
+            Json(QuickConnectAuthenticateRequest {
+                secret: "upstream-secret".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.access_token, user.virtual_key);
+        assert_eq!(response.user.id, user.id);
+        let sessions = state
+            .user_authorization
+            .get_user_sessions(&user.id, None)
+            .await
+            .unwrap();
+        assert!(sessions
+            .iter()
+            .any(|(session, _)| session.jellyfin_token == "qc-upstream-token"));
+        assert!(state.quick_connect.get_session("upstream-secret").is_none());
+    }
+
+    #[tokio::test]
+    async fn disabled_quick_connect_rejects_requests() {
+        let state = create_test_app_state().await;
+        state.config.write().await.quick_connect_mode = QuickConnectMode::Disabled;
+
+        let Json(enabled) = handle_quick_connect_enabled(axum::extract::State(state.clone()))
+            .await
+            .unwrap();
+        assert!(!enabled);
+
+        let initiate =
+            handle_quick_connect_initiate(axum::extract::State(state), HeaderMap::new()).await;
+        assert_eq!(initiate.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
 }
@@ -0,0 +1,414 @@
+use axum::extract::State;
+use hyper::StatusCode;
+use serde_json::Value;
+use tracing::{debug, error};
+
+use crate::{
+    extractors::Preprocessed,
+    handlers::{
+        common::{set_json_body, update_stream_activity},
+        watch_state::sync_stopped_position,
+    },
+    http_util::execute_with_retry,
+    models::ProgressRequest,
+    processors::request_processor::RequestProcessingContext,
+    AppState,
+};
+
+//http://localhost:3000/Sessions/Playing/Progress
+/// Forward a playback start, progress or stop report to the backend playing the
+/// item. The position a playback stopped at is also copied to the item's watch
+/// state mirrors.
+///
+/// The remapped report is sent as a `ProgressRequest` rather than as raw JSON, so
+/// whole playback rates reach Jellyfin as integers (`1`, not `1.0`).
+pub async fn post_playback_report(
+    State(state): State<AppState>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<StatusCode, StatusCode> {
+    update_stream_activity(&state, &preprocessed.original_request).await;
+
+    let request_url = preprocessed.request.url().clone();
+    let context = RequestProcessingContext::new(&preprocessed);
+    let server = preprocessed.server;
+    let mut request = preprocessed.request;
+    let is_stop_report = request_url
+        .path()
+        .to_ascii_lowercase()
+        .ends_with("/playing/stopped");
+    state
+        .processors
+        .process_request_body(&mut request, &context, &request_url)
+        .await?;
+
+    let sanitize_buffered_ranges_enabled = state.sanitize_buffered_ranges_enabled().await;
+    let report = request
+        .body()
+        .and_then(reqwest::Body::as_bytes)
+        .and_then(|body| serde_json::from_slice::<ProgressRequest>(body).ok());
+    let stopped_at = report
+        .as_ref()
+        .filter(|_| is_stop_report)
+        .and_then(|report| Some((report.item_id.clone(), report.position_ticks?)));
+    match report {
+        Some(mut report) => {
+            if sanitize_buffered_ranges_enabled {
+                report.buffered_ranges = report
+                    .buffered_ranges
+                    .take()
+                    .and_then(sanitize_buffered_ranges);
+            }
+            set_json_body(&mut request, &report)?
+        }
+        None => debug!(
+            "Forwarding playback report to {} without normalizing it",
+            request_url
+        ),
+    }
+
+    let upstream_retries = state.upstream_retries().await;
+    let response = execute_with_retry(&state.reqwest_client, request, upstream_retries)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to forward playback report to {}: {}",
+                server.name, e
+            );
+            StatusCode::BAD_GATEWAY
+        })?;
+
+    if let (true, Some(session), Some((item_id, position_ticks))) = (
+        response.status().is_success(),
+        &preprocessed.session,
+        stopped_at,
+    ) {
+        sync_stopped_position(
+            &state,
+            &server,
+            session,
+            &preprocessed.sessions,
+            &item_id,
+            position_ticks,
+        )
+        .await;
+    }
+
+    StatusCode::from_u16(response.status().as_u16()).map_err(|_| StatusCode::BAD_GATEWAY)
+}
+
+/// Keep only the buffered ranges Jellyfin can read: objects with integer `start`
+/// and `end` ticks in order. A value that isn't a list is dropped entirely.
+fn sanitize_buffered_ranges(ranges: Value) -> Option<Value> {
+    let Value::Array(ranges) = ranges else {
+        debug!("Dropping buffered ranges that are not a list: {}", ranges);
+        return None;
+    };
+
+    let total = ranges.len();
+    let ranges = ranges
+        .into_iter()
+        .filter(is_valid_buffered_range)
+        .collect::<Vec<_>>();
+    if ranges.len() < total {
+        debug!("Dropped {} malformed buffered ranges", total - ranges.len());
+    }
+    Some(Value::Array(ranges))
+}
+
+fn is_valid_buffered_range(range: &Value) -> bool {
+    let ticks = |name: &str, pascal_name: &str| {
+        range
+            .get(name)
+            .or_else(|| range.get(pascal_name))
+            .and_then(Value::as_i64)
+    };
+    matches!(
+        (ticks("start", "Start"), ticks("end", "End")),
+        (Some(start), Some(end)) if start <= end
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{add_server_with_session, create_test_app_state};
+    use crate::{
+        models::Authorization, request_preprocessing::preprocess_request,
+        session_storage::PlaybackSession,
+    };
+    use axum::body::Body;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    /// Connect a user to a single backend and return the header of their proxy
+    /// session together with the virtual id of an item on that backend.
+    async fn connect_server(state: &AppState, upstream: &MockServer) -> (String, String) {
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let server = add_server_with_session(state, &user, "Upstream", upstream, 100).await;
+        state.server_storage.check_servers_health().await;
+
+        let mapping = state
+            .media_storage
+            .get_or_create_media_mapping("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", &server)
+            .await
+            .unwrap();
+        let auth_header = Authorization {
+            client: "Jellyfin Web".to_string(),
+            device: "Firefox".to_string(),
+            device_id: "web-device-id".to_string(),
+            version: "10.10.7".to_string(),
+            token: Some(user.virtual_key.clone()),
+        }
+        .to_header_value();
+        (auth_header, mapping.virtual_media_id)
+    }
+
+    async fn post_progress(state: &AppState, auth_header: &str, body: String) -> StatusCode {
+        let uri: axum::http::Uri = "/Sessions/Playing/Progress".parse().unwrap();
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::from(body))
+            .unwrap();
+
+        let preprocessed = preprocess_request(request, state).await.unwrap();
+        post_playback_report(State(state.clone()), Preprocessed(preprocessed))
+            .await
+            .unwrap()
+    }
+
+    async fn forwarded_reports(upstream: &MockServer) -> Vec<Value> {
+        upstream
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.url.path() == "/Sessions/Playing/Progress")
+            .map(|request| serde_json::from_slice(&request.body).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn stop_reports_with_only_a_play_session_reach_the_playing_server() {
+        let state = create_test_app_state().await;
+        let preferred = MockServer::start().await;
+        let playing = MockServer::start().await;
+        let (auth_header, preferred_item_id) = connect_server(&state, &preferred).await;
+        Mock::given(method("POST"))
+            .and(path("/Sessions/Playing/Stopped"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&playing)
+            .await;
+
+        let user = state
+            .user_authorization
+            .get_user_by_username("viewer")
+            .await
+            .unwrap()
+            .unwrap();
+        let server = add_server_with_session(&state, &user, "Playing", &playing, 50).await;
+        state.server_storage.check_servers_health().await;
+        state
+            .play_sessions
+            .add_session(PlaybackSession {
+                session_id: "play-session".to_string(),
+                item_id: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+                user_id: user.id.clone(),
+                server_id: server.id,
+                original_user_id: "Playing-user-id".to_string(),
+                original_item_id: "cccccccccccccccccccccccccccccccc".to_string(),
+            })
+            .await;
+
+        let uri: axum::http::Uri = "/Sessions/Playing/Stopped".parse().unwrap();
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, &auth_header)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::from(
+                serde_json::json!({ "PlaySessionId": "play-session", "PositionTicks": 42 })
+                    .to_string(),
+            ))
+            .unwrap();
+        let preprocessed = preprocess_request(request, &state).await.unwrap();
+        assert_eq!(preprocessed.server.name, "Playing");
+
+        let status = post_playback_report(State(state.clone()), Preprocessed(preprocessed))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(preferred
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .all(|request| request.url.path() == "/System/Info/Public"));
+
+        // The tracked play session outweighs an item id of another server.
+        let uri: axum::http::Uri = "/Sessions/Playing/Progress".parse().unwrap();
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, &auth_header)
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::from(
+                serde_json::json!({
+                    "ItemId": preferred_item_id,
+                    "PlaySessionId": "play-session",
+                    "PositionTicks": 84
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        let preprocessed = preprocess_request(request, &state).await.unwrap();
+        assert_eq!(preprocessed.server.name, "Playing");
+    }
+
+    #[tokio::test]
+    async fn progress_reports_translate_ids_and_user_for_the_backend() {
+        let state = create_test_app_state().await;
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/Sessions/Playing/Progress"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let (auth_header, item_id) = connect_server(&state, &upstream).await;
+        let server = state
+            .server_storage
+            .get_server_by_name("Upstream")
+            .await
+            .unwrap()
+            .unwrap();
+        let source_id = state
+            .media_storage
+            .get_or_create_media_mapping("dddddddddddddddddddddddddddddddd", &server)
+            .await
+            .unwrap()
+            .virtual_media_id;
+        let user = state
+            .user_authorization
+            .get_user_by_username("viewer")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let body = serde_json::json!({
+            "ItemId": item_id,
+            "MediaSourceId": source_id,
+            "PlaySessionId": "backend-play-session",
+            "UserId": user.id,
+            "NowPlayingQueue": [{ "Id": item_id, "PlaylistItemId": "playlistItem0" }],
+            "PositionTicks": 100,
+            "PlaybackRate": 1.0
+        });
+        let status = post_progress(&state, &auth_header, body.to_string()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let bodies = forwarded_reports(&upstream).await;
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0]["ItemId"], "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        assert_eq!(
+            bodies[0]["MediaSourceId"],
+            "dddddddddddddddddddddddddddddddd"
+        );
+        assert_eq!(bodies[0]["PlaySessionId"], "backend-play-session");
+        assert_eq!(bodies[0]["UserId"], "Upstream-user-id");
+        assert_eq!(
+            bodies[0]["NowPlayingQueue"][0]["Id"],
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+        );
+        assert_eq!(bodies[0]["PlaybackRate"], 1);
+    }
+
+    #[tokio::test]
+    async fn progress_reports_forward_whole_playback_rates_as_integers() {
+        let state = create_test_app_state().await;
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/Sessions/Playing/Progress"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(2)
+            .mount(&upstream)
+            .await;
+        let (auth_header, item_id) = connect_server(&state, &upstream).await;
+
+        for rate in ["1.0", "1.5"] {
+            let body =
+                format!(r#"{{"ItemId":"{item_id}","PositionTicks":100,"PlaybackRate":{rate}}}"#);
+            let status = post_progress(&state, &auth_header, body).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+
+        let bodies = forwarded_reports(&upstream).await;
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0]["ItemId"], "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        assert!(bodies[0]["PlaybackRate"].is_u64());
+        assert_eq!(bodies[0]["PlaybackRate"], 1);
+        assert!(!bodies[1]["PlaybackRate"].is_u64());
+        assert_eq!(bodies[1]["PlaybackRate"].as_f64(), Some(1.5));
+        assert_eq!(bodies[1]["PositionTicks"], 100);
+    }
+
+    #[tokio::test]
+    async fn malformed_buffered_ranges_are_dropped_when_sanitizing() {
+        let state = create_test_app_state().await;
+        state.config.write().await.sanitize_buffered_ranges = true;
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/Sessions/Playing/Progress"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&upstream)
+            .await;
+        let (auth_header, item_id) = connect_server(&state, &upstream).await;
+
+        let body = serde_json::json!({
+            "ItemId": item_id,
+            "BufferedRanges": [
+                { "start": 0, "end": 500 },
+                { "start": 900, "end": 100 },
+                { "start": "0", "end": 100 },
+                { "end": 100 },
+                "0-100",
+                { "Start": 600, "End": 800 }
+            ]
+        });
+        let status = post_progress(&state, &auth_header, body.to_string()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let bodies = forwarded_reports(&upstream).await;
+        assert_eq!(
+            bodies[0]["BufferedRanges"],
+            serde_json::json!([
+                { "start": 0, "end": 500 },
+                { "Start": 600, "End": 800 }
+            ])
+        );
+    }
+
+    #[test]
+    fn buffered_ranges_that_are_not_a_list_are_dropped() {
+        assert_eq!(sanitize_buffered_ranges(serde_json::json!("0-100")), None);
+        assert_eq!(
+            sanitize_buffered_ranges(serde_json::json!([])),
+            Some(serde_json::json!([]))
+        );
+    }
+}
@@ -1,12 +1,86 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    Json,
+};
+use base64::Engine;
 use hyper::StatusCode;
+use serde::Serialize;
 use tracing::error;
 
 use crate::{
-    extractors::RequireUser, handlers::common::execute_json_request, ui::JELLYFIN_UI_VERSION,
+    encryption::Password,
+    extractors::RequireUser,
+    server_storage::{version_parts, Server, ServerHealthStatus},
+    ui::{
+        auth::{AuthenticatedUser, UserRole},
+        JELLYFIN_UI_VERSION,
+    },
     AppState,
 };
 
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FederationStatus {
+    pub version: String,
+    pub server_id: String,
+    pub servers: Vec<FederatedServerStatus>,
+    pub user_count: i64,
+    pub mapping_count: i64,
+    pub session_count: i64,
+    pub active_streams: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FederatedServerStatus {
+    pub id: i64,
+    pub name: String,
+    pub url: String,
+    pub priority: i32,
+    /// `Healthy`, `Unhealthy` or `NotJellyfin`.
+    pub status: &'static str,
+    pub outdated: bool,
+    pub version: Option<String>,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HealthStatus {
+    pub status: &'static str,
+    pub servers_online: usize,
+    pub db_ok: bool,
+}
+
+/// `/health`: a liveness and readiness probe for container orchestration. It never
+/// contacts a backend, the server count comes from the background health checks, so
+/// it is cheap to poll. Answers `503` when the database can't be queried.
+pub async fn health(State(state): State<AppState>) -> (StatusCode, Json<HealthStatus>) {
+    let db_ok = state.server_storage.database_reachable().await;
+    let servers_online = state.server_storage.healthy_server_count().await;
+    let (status_code, status) = if db_ok {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+
+    (
+        status_code,
+        Json(HealthStatus {
+            status,
+            servers_online,
+            db_ok,
+        }),
+    )
+}
+
+/// `/System/Ping`: answered by the proxy itself, like Jellyfin does, with the
+/// product name.
+pub async fn ping() -> Json<&'static str> {
+    Json("Jellyfin Server")
+}
+
 pub async fn info_public(
     State(state): State<AppState>,
 ) -> Result<Json<crate::models::PublicServerInfo>, StatusCode> {
@@ -23,29 +97,395 @@ pub async fn info_public(
     }))
 }
 
+// The server info describes the proxy itself rather than one of its backends:
+// clients key their sessions off `Id`, so it has to stay the same whichever server
+// would have answered. Only `Version` is taken from the backends, as clients use
+// it to decide which features they can rely on.
 pub async fn info(
     State(state): State<AppState>,
     RequireUser { preprocessed, .. }: RequireUser,
 ) -> Result<Json<crate::models::ServerInfo>, StatusCode> {
-    // return Err(StatusCode::UNAUTHORIZED);
+    let backend_version = if state.report_backend_version_enabled().await {
+        let servers = match &preprocessed.sessions {
+            Some(sessions) => sessions.iter().map(|(_, server)| server).collect(),
+            None => vec![&preprocessed.server],
+        };
+        lowest_backend_version(&state, servers).await
+    } else {
+        None
+    };
 
-    match execute_json_request::<crate::models::ServerInfo>(
-        &state.reqwest_client,
-        preprocessed.request,
-    )
-    .await
-    {
-        Ok(mut server_info) => {
-            let cfg = state.config.read().await;
-            server_info.id = cfg.server_id.clone();
-            server_info.server_name = "Jellyswarrm Proxy".to_string();
-            server_info.local_address = cfg.public_address.clone();
-
-            Ok(Json(server_info))
+    let cfg = state.config.read().await;
+    Ok(Json(crate::models::ServerInfo {
+        operating_system_display_name: Some(std::env::consts::OS.to_string()),
+        has_pending_restart: Some(false),
+        is_shutting_down: Some(false),
+        supports_library_monitor: Some(false),
+        web_socket_port_number: None,
+        completed_installations: None,
+        can_self_restart: Some(false),
+        can_launch_web_browser: Some(false),
+        program_data_path: None,
+        web_path: None,
+        items_by_name_path: None,
+        cache_path: None,
+        log_path: None,
+        internal_metadata_path: None,
+        transcoding_temp_path: None,
+        cast_receiver_applications: None,
+        has_update_available: Some(false),
+        encoder_location: None,
+        system_architecture: Some(std::env::consts::ARCH.to_string()),
+        local_address: cfg.public_address.clone(),
+        server_name: cfg.server_name.clone(),
+        version: backend_version.or_else(|| JELLYFIN_UI_VERSION.clone().map(|ui| ui.version)),
+        operating_system: Some(std::env::consts::OS.to_string()),
+        id: cfg.server_id.clone(),
+        startup_wizard_completed: Some(true),
+    }))
+}
+
+/// `/System/Jellyswarrm`: the servers behind the proxy and how they are doing, for
+/// dashboards. As it exposes the topology, only the admin can read it, through a UI
+/// session or HTTP Basic credentials.
+pub async fn federation_status(
+    State(state): State<AppState>,
+    session_user: Result<AuthenticatedUser, StatusCode>,
+    headers: HeaderMap,
+) -> Result<Json<FederationStatus>, StatusCode> {
+    if !state.federation_status_exposed().await {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    match session_user {
+        Ok(AuthenticatedUser(user)) if user.role == UserRole::Admin => {}
+        Ok(_) => return Err(StatusCode::FORBIDDEN),
+        Err(_) if has_admin_basic_credentials(&state, &headers).await => {}
+        Err(_) => return Err(StatusCode::UNAUTHORIZED),
+    }
+
+    let servers = state.server_storage.list_servers().await.map_err(|e| {
+        error!("Failed to list servers for federation status: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut server_statuses = Vec::new();
+    for server in servers {
+        let health = state.server_storage.server_status(server.id).await;
+        let latency = state.server_storage.server_latency(server.id).await;
+        let (status, version, error) = match &health {
+            ServerHealthStatus::Healthy(info) => ("Healthy", info.version.clone(), None),
+            ServerHealthStatus::Unhealthy(reason) => ("Unhealthy", None, Some(reason.clone())),
+            ServerHealthStatus::NotJellyfin(reason) => ("NotJellyfin", None, Some(reason.clone())),
+        };
+        server_statuses.push(FederatedServerStatus {
+            id: server.id.as_i64(),
+            name: server.name,
+            url: server.url.to_string(),
+            priority: server.priority,
+            status,
+            outdated: health.is_outdated(),
+            version,
+            latency_ms: latency.map(|latency| latency.as_millis() as u64),
+            error,
+        });
+    }
+
+    let (user_count, mapping_count, session_count) = state
+        .user_authorization
+        .record_counts()
+        .await
+        .map_err(|e| {
+            error!("Failed to count users for federation status: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(FederationStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        server_id: state.config.read().await.server_id.clone(),
+        servers: server_statuses,
+        user_count,
+        mapping_count,
+        session_count,
+        active_streams: state.play_sessions.active_stream_count().await,
+    }))
+}
+
+async fn has_admin_basic_credentials(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(encoded) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+    else {
+        return false;
+    };
+    let Some(decoded) = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+    else {
+        return false;
+    };
+    let Some((username, password)) = decoded.split_once(':') else {
+        return false;
+    };
+
+    let config = state.config.read().await;
+    username == config.username && Password::from(password) == config.password
+}
+
+/// The lowest version reported by the healthy servers among `servers`.
+async fn lowest_backend_version(state: &AppState, servers: Vec<&Server>) -> Option<String> {
+    let mut lowest: Option<String> = None;
+    for server in servers {
+        let ServerHealthStatus::Healthy(info) = state.server_storage.server_status(server.id).await
+        else {
+            continue;
+        };
+        let Some(version) = info.version else {
+            continue;
+        };
+        if lowest
+            .as_deref()
+            .is_none_or(|current| version_parts(&version) < version_parts(current))
+        {
+            lowest = Some(version);
         }
-        Err(e) => {
-            error!("Failed to get server info: {:?}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+    lowest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MediaStreamingMode;
+    use crate::test_support::{add_server_with_session, create_test_app_state, web_authorization};
+    use axum::{body::Body, extract::Request, routing::get};
+    use tower::ServiceExt;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn server_info_keeps_the_proxy_id_and_reports_the_lowest_backend_version() {
+        let state = create_test_app_state().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+
+        let mut upstreams = Vec::new();
+        for (name, version, priority) in [("New", "10.10.7", 100), ("Old", "10.9.11", 50)] {
+            let upstream = MockServer::start().await;
+            // Outranks the `/System/Info/Public` mock the shared fixture mounts.
+            Mock::given(method("GET"))
+                .and(path("/System/Info/Public"))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(
+                        serde_json::json!({ "ServerName": name, "Version": version }),
+                    ),
+                )
+                .with_priority(1)
+                .mount(&upstream)
+                .await;
+            Mock::given(path("/System/Info"))
+                .respond_with(ResponseTemplate::new(500))
+                .expect(0)
+                .mount(&upstream)
+                .await;
+            add_server_with_session(&state, &user, name, &upstream, priority).await;
+            upstreams.push(upstream);
+        }
+        state.server_storage.check_servers_health().await;
+        let expected_id = state.config.read().await.server_id.clone();
+
+        let router = axum::Router::new()
+            .route("/System/Info", get(info))
+            .with_state(state);
+        let mut reported = Vec::new();
+        for _ in 0..2 {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/System/Info")
+                        .header(hyper::header::HOST, "localhost")
+                        .header(
+                            "Authorization",
+                            web_authorization(Some(user.virtual_key.clone())).to_header_value(),
+                        )
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            reported.push(serde_json::from_slice::<serde_json::Value>(&body).unwrap());
         }
+
+        assert_eq!(reported[0]["Id"], expected_id);
+        assert_eq!(reported[0]["Id"], reported[1]["Id"]);
+        assert_eq!(reported[0]["ServerName"], "Jellyswarrm Proxy");
+        assert_eq!(reported[0]["Version"], "10.9.11");
+    }
+
+    #[tokio::test]
+    async fn federation_status_is_admin_only_and_describes_the_servers() {
+        let state = create_test_app_state().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/System/Info/Public"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({ "ServerName": "Home", "Version": "10.10.7" }),
+                ),
+            )
+            .mount(&upstream)
+            .await;
+        let server_id = state
+            .server_storage
+            .add_server("Home", &upstream.uri(), 100, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+        let server = state
+            .server_storage
+            .get_server_by_id(server_id)
+            .await
+            .unwrap()
+            .unwrap();
+        state
+            .user_authorization
+            .add_server_mapping(&user.id, &server, "viewer", &"password".into(), None)
+            .await
+            .unwrap();
+        state.server_storage.check_servers_health().await;
+
+        let router = axum::Router::new()
+            .route("/System/Jellyswarrm", get(federation_status))
+            .with_state(state.clone());
+        let request = |authorization: Option<String>| {
+            let mut builder = Request::builder().uri("/System/Jellyswarrm");
+            if let Some(authorization) = authorization {
+                builder = builder.header("Authorization", authorization);
+            }
+            router.clone().oneshot(builder.body(Body::empty()).unwrap())
+        };
+        let basic = |credentials: &str| {
+            Some(format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            ))
+        };
+
+        assert_eq!(
+            request(basic("admin:jellyswarrm")).await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+        state.config.write().await.expose_federation_status = true;
+
+        assert_eq!(
+            request(None).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            request(basic("admin:wrong")).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            request(Some(
+                web_authorization(Some(user.virtual_key.clone())).to_header_value()
+            ))
+            .await
+            .unwrap()
+            .status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let response = request(basic("admin:jellyswarrm")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["Version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(status["ServerId"], state.config.read().await.server_id);
+        assert_eq!(status["UserCount"], 1);
+        assert_eq!(status["MappingCount"], 1);
+        assert_eq!(status["SessionCount"], 0);
+        assert_eq!(status["ActiveStreams"], 0);
+        let servers = status["Servers"].as_array().unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0]["Name"], "Home");
+        assert_eq!(servers[0]["Status"], "Healthy");
+        assert_eq!(servers[0]["Version"], "10.10.7");
+        assert_eq!(servers[0]["Outdated"], false);
+        assert!(servers[0]["LatencyMs"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn health_reports_online_servers_without_authentication() {
+        let state = create_test_app_state().await;
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/System/Info/Public"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({ "ServerName": "Home", "Version": "10.10.7" }),
+                ),
+            )
+            .mount(&upstream)
+            .await;
+        state
+            .server_storage
+            .add_server("Home", &upstream.uri(), 100, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+        state
+            .server_storage
+            .add_server(
+                "Offline",
+                "http://127.0.0.1:9",
+                50,
+                MediaStreamingMode::Proxy,
+            )
+            .await
+            .unwrap();
+        state.server_storage.check_servers_health().await;
+
+        let router = axum::Router::new()
+            .route("/health", get(health))
+            .with_state(state);
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["servers_online"], 1);
+        assert_eq!(health["db_ok"], true);
+    }
+
+    #[test]
+    fn versions_are_compared_numerically() {
+        assert!(version_parts("10.9.11") < version_parts("10.10.0"));
+        assert!(version_parts("10.10") < version_parts("10.10.1"));
     }
 }
@@ -1,14 +1,20 @@
 use axum::{
     extract::{Path, State},
+    response::{IntoResponse, Response},
     Json,
 };
 use hyper::{HeaderMap, StatusCode};
+use serde::Deserialize;
 use tracing::{debug, error, info, warn};
 
 use crate::{
     encryption::Password,
+    error_response::problem_response_with_detail,
     extractors::{RequireUser, RequireUserSession},
-    handlers::common::execute_json_request,
+    handlers::{
+        common::execute_json_request,
+        quick_connect::{handle_authenticate_with_quick_connect, QuickConnectAuthenticateRequest},
+    },
     models::{AuthenticateRequest, AuthenticateResponse, Authorization, SyncPlayUserAccessType},
     url_helper::join_server_url,
     AppState,
@@ -32,6 +38,87 @@ async fn process_user(
     Ok(server_user)
 }
 
+/// Fields backends use to tell that a user has to change their password before they
+/// can keep using their account. Matched case-insensitively on the user and its policy.
+const PASSWORD_RESET_FIELDS: &[&str] = &[
+    "PasswordResetRequired",
+    "MustChangePassword",
+    "RequirePasswordChange",
+    "EnforcePasswordChange",
+];
+
+/// Phrases of backend error messages that ask for a password change.
+const PASSWORD_RESET_MESSAGES: &[&str] = &[
+    "password reset required",
+    "password must be changed",
+    "must change the password",
+    "must change their password",
+    "password has expired",
+];
+
+fn is_password_reset_field(key: &str) -> bool {
+    PASSWORD_RESET_FIELDS
+        .iter()
+        .any(|field| field.eq_ignore_ascii_case(key))
+}
+
+fn flags_password_reset(fields: &std::collections::HashMap<String, serde_json::Value>) -> bool {
+    fields
+        .iter()
+        .any(|(key, value)| is_password_reset_field(key) && value.as_bool() == Some(true))
+}
+
+fn user_requires_password_reset(user: &crate::models::User) -> bool {
+    flags_password_reset(&user.extra) || flags_password_reset(&user.policy.extra)
+}
+
+/// Whether a failed authentication response tells the user to change their password,
+/// either through one of `PASSWORD_RESET_FIELDS` or in its message.
+fn is_password_reset_error(body: &str) -> bool {
+    if let Ok(serde_json::Value::Object(fields)) = serde_json::from_str(body) {
+        if fields
+            .iter()
+            .any(|(key, value)| is_password_reset_field(key) && value.as_bool() == Some(true))
+        {
+            return true;
+        }
+    }
+
+    let body = body.to_ascii_lowercase();
+    PASSWORD_RESET_MESSAGES
+        .iter()
+        .any(|message| body.contains(message))
+}
+
+/// Key of the federation status object added to `/Users/Me` when `enrich_user_me`
+/// is enabled. It lands in the flattened `extra` fields, which standard clients ignore.
+const FEDERATION_STATUS_KEY: &str = "JellyswarrmFederation";
+
+/// Add the number of servers the user is mapped to, and how many of those they have
+/// a live session on, to their `/Users/Me` response.
+async fn enrich_user_me(
+    server_user: &mut crate::models::User,
+    user: &crate::user_authorization_service::User,
+    active_servers: usize,
+    state: &AppState,
+) -> Result<()> {
+    let mapped_servers = state
+        .user_authorization
+        .list_server_mappings(&user.id)
+        .await?
+        .len();
+
+    server_user.extra.insert(
+        FEDERATION_STATUS_KEY.to_string(),
+        serde_json::json!({
+            "MappedServers": mapped_servers,
+            "ActiveServers": active_servers,
+        }),
+    );
+
+    Ok(())
+}
+
 // http://foo:3000/users/public?)
 pub async fn handle_public(
     _state: State<AppState>,
@@ -44,17 +131,28 @@ pub async fn handle_get_me(
     State(state): State<AppState>,
     RequireUser { preprocessed, user }: RequireUser,
 ) -> Result<Json<crate::models::User>, StatusCode> {
+    let active_servers = preprocessed.sessions.as_ref().map_or(0, Vec::len);
+
     // Execute request and parse JSON response
     let server_user: crate::models::User =
         execute_json_request(&state.reqwest_client, preprocessed.request).await?;
 
-    let server_user = process_user(server_user, &user, &state)
+    let mut server_user = process_user(server_user, &user, &state)
         .await
         .map_err(|e| {
             error!("Failed to process user: {}", e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
+    if state.enrich_user_me_enabled().await {
+        enrich_user_me(&mut server_user, &user, active_servers, &state)
+            .await
+            .map_err(|e| {
+                error!("Failed to add federation status to user: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    }
+
     Ok(Json(server_user))
 }
 
@@ -88,12 +186,38 @@ pub async fn handle_get_user_by_id(
     Ok(Json(server_user))
 }
 
-// Authenticates a user by trying all configured servers in parallel
+/// Body of `/Users/AuthenticateByName`. Some clients complete Quick Connect by posting
+/// the secret here rather than to `/Users/AuthenticateWithQuickConnect`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum AuthenticateBody {
+    ByName(AuthenticateRequest),
+    WithQuickConnect(QuickConnectAuthenticateRequest),
+}
+
 pub async fn handle_authenticate_by_name(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<AuthenticateRequest>,
-) -> Result<Json<AuthenticateResponse>, StatusCode> {
+    Json(body): Json<AuthenticateBody>,
+) -> Result<Response, StatusCode> {
+    match body {
+        AuthenticateBody::ByName(payload) => authenticate_by_name(state, headers, payload).await,
+        AuthenticateBody::WithQuickConnect(request) => {
+            handle_authenticate_with_quick_connect(State(state), headers, Json(request))
+                .await
+                .map(IntoResponse::into_response)
+        }
+    }
+}
+
+// Authenticates a user by trying all configured servers in parallel. An existing user
+// is logged in on each of their mapped servers, and gets a session on every one that
+// accepts the mapping credentials.
+async fn authenticate_by_name(
+    state: AppState,
+    headers: HeaderMap,
+    payload: AuthenticateRequest,
+) -> Result<Response, StatusCode> {
     let mut servers = state
         .server_storage
         .list_servers()
@@ -102,7 +226,7 @@ pub async fn handle_authenticate_by_name(
 
     if servers.is_empty() {
         tracing::warn!("No servers configured for authentication");
-        return Err(StatusCode::NOT_FOUND);
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
     }
 
     let authentication = extract_auth_header(&headers).map_err(|_| {
@@ -161,16 +285,20 @@ pub async fn handle_authenticate_by_name(
                         let state = state.clone();
                         let authentication = authentication.clone();
                         let payload = payload.clone();
-                        auth_tasks.push(tokio::spawn(async move {
-                            authenticate_on_server(
-                                state.clone(),
-                                authentication.clone(),
-                                payload.clone(),
-                                server,
-                                Some(server_mapping),
-                            )
-                            .await
-                        }));
+                        let server_name = server.name.clone();
+                        auth_tasks.push((
+                            server_name,
+                            tokio::spawn(async move {
+                                authenticate_on_server(
+                                    state.clone(),
+                                    authentication.clone(),
+                                    payload.clone(),
+                                    server,
+                                    Some(server_mapping),
+                                )
+                                .await
+                            }),
+                        ));
                     }
                 }
             }
@@ -198,9 +326,12 @@ pub async fn handle_authenticate_by_name(
                     payload.username, server.name
                 );
 
-                tokio::spawn(async move {
-                    authenticate_on_server(state, authentication, payload, server, None).await
-                })
+                (
+                    server.name.clone(),
+                    tokio::spawn(async move {
+                        authenticate_on_server(state, authentication, payload, server, None).await
+                    }),
+                )
             })
             .collect();
 
@@ -209,24 +340,65 @@ pub async fn handle_authenticate_by_name(
 
     // Wait for all authentication attempts to complete
     let mut successful_auths: Vec<SuccessfulServerAuth> = Vec::new();
+    let mut reset_required_on = Vec::new();
     let total_servers = auth_tasks.len();
 
-    for task in auth_tasks {
+    for (server_name, task) in auth_tasks {
         match task.await {
             Ok(Ok(auth_response)) => {
-                info!("Successfully authenticated user: {}", payload.username);
+                info!(
+                    "Successfully authenticated user '{}' on server '{}'",
+                    payload.username, server_name
+                );
                 successful_auths.push(auth_response);
             }
+            Ok(Err(AuthError::PasswordResetRequired(server_name))) => {
+                reset_required_on.push(server_name);
+            }
+            Ok(Err(e)) if is_existing_user => {
+                // A mapped server that is down or rejects the login doesn't fail the
+                // login, the user just can't browse it until the next one.
+                warn!(
+                    "Authentication of user '{}' on mapped server '{}' failed: {:?}",
+                    payload.username, server_name, e
+                );
+            }
             Ok(Err(e)) => {
-                tracing::debug!("Authentication attempt failed: {:?}", e);
+                tracing::debug!(
+                    "Authentication attempt on server '{}' failed: {:?}",
+                    server_name,
+                    e
+                );
             }
             Err(join_err) => {
-                tracing::error!("Authentication task failed: {}", join_err);
+                tracing::error!(
+                    "Authentication task for server '{}' failed: {}",
+                    server_name,
+                    join_err
+                );
             }
         }
     }
 
-    if successful_auths.is_empty() {
+    if successful_auths.is_empty() && !reset_required_on.is_empty() {
+        warn!(
+            "User '{}' has to change their password on {} before logging in",
+            payload.username,
+            reset_required_on.join(", ")
+        );
+        let detail = format!(
+            "The password of '{}' has to be changed on {} before it can be used. \
+             Sign in to that server directly to change it, then log in again.",
+            payload.username,
+            reset_required_on.join(", ")
+        );
+        let request_id = uuid::Uuid::new_v4().simple().to_string();
+        Ok(problem_response_with_detail(
+            StatusCode::FORBIDDEN,
+            &request_id,
+            &detail,
+        ))
+    } else if successful_auths.is_empty() {
         tracing::warn!(
             "All authentication attempts failed for user: {}",
             payload.username
@@ -245,8 +417,23 @@ pub async fn handle_authenticate_by_name(
         )
         .await?;
 
-        let auth_response =
+        let mut auth_response =
             decorate_auth_response(&state, &user, &payload.username, &successful_auths[0]).await;
+        if !user_requires_password_reset(&auth_response.user) {
+            if let Some(flagged) = successful_auths
+                .iter()
+                .find(|auth| user_requires_password_reset(&auth.auth_response.user))
+            {
+                warn!(
+                    "Server '{}' asks user '{}' to change their password",
+                    flagged.server.name, payload.username
+                );
+                auth_response
+                    .user
+                    .extra
+                    .insert(PASSWORD_RESET_FIELDS[0].to_string(), true.into());
+            }
+        }
 
         info!(
             "User '{}' successfully authenticated on {} out of {} servers and stored in authorization storage",
@@ -254,7 +441,7 @@ pub async fn handle_authenticate_by_name(
             successful_auths.len(),
             total_servers
         );
-        Ok(Json(auth_response))
+        Ok(Json(auth_response).into_response())
     }
 }
 
@@ -386,6 +573,7 @@ async fn authenticate_on_server(
     let response = state
         .reqwest_client
         .post(auth_url.as_str())
+        .timeout(state.upstream_timeout().await)
         .header("Authorization", authorization.to_header_value())
         .header("Accept", "application/json")
         .header("Content-Type", "application/json")
@@ -403,11 +591,16 @@ async fn authenticate_on_server(
 
     // Check response status
     if !response.status().is_success() {
+        let status = response.status();
         tracing::warn!(
             "Authentication failed for server '{}' with status: {}",
             server.name,
-            response.status()
+            status
         );
+        let body = response.text().await.unwrap_or_default();
+        if is_password_reset_error(&body) {
+            return Err(AuthError::PasswordResetRequired(server.name));
+        }
         return Err(AuthError::InvalidCredentials);
     }
 
@@ -493,6 +686,8 @@ fn extract_auth_header(headers: &HeaderMap) -> Result<Authorization, AuthError>
 enum AuthError {
     NetworkError(String),
     InvalidCredentials,
+    /// The server holds the right credentials but wants the password changed first.
+    PasswordResetRequired(String),
     ParseError(String),
     InternalError,
 }
@@ -504,3 +699,295 @@ struct SuccessfulServerAuth {
     final_username: String,
     final_password: crate::encryption::Password,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{add_server_with_session, create_test_app_state};
+    use crate::{config::MediaStreamingMode, request_preprocessing::preprocess_request};
+    use axum::body::Body;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn get_me(
+        state: &AppState,
+        user: &crate::user_authorization_service::User,
+    ) -> serde_json::Value {
+        let auth_header = Authorization {
+            client: "Jellyfin Web".to_string(),
+            device: "Firefox".to_string(),
+            device_id: "web-device-id".to_string(),
+            version: "10.10.7".to_string(),
+            token: Some(user.virtual_key.clone()),
+        }
+        .to_header_value();
+        let uri: axum::http::Uri = "/Users/Me".parse().unwrap();
+        let request = axum::http::Request::builder()
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(axum::http::header::AUTHORIZATION, auth_header)
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+
+        let preprocessed = preprocess_request(request, state).await.unwrap();
+        let Json(response) = handle_get_me(
+            State(state.clone()),
+            RequireUser {
+                preprocessed,
+                user: user.clone(),
+            },
+        )
+        .await
+        .unwrap();
+
+        serde_json::to_value(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn federation_status_is_only_added_when_enabled() {
+        let state = create_test_app_state().await;
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Users/Me"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Name": "upstream-viewer",
+                "ServerId": "upstream-server",
+                "Id": "upstream-user-id",
+                "Policy": { "IsAdministrator": true, "SyncPlayAccess": "CreateAndJoinGroups" }
+            })))
+            .mount(&upstream)
+            .await;
+
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        add_server_with_session(&state, &user, "Upstream", &upstream, 100).await;
+        state.server_storage.check_servers_health().await;
+
+        let plain = get_me(&state, &user).await;
+        assert_eq!(plain["Id"], user.id);
+        assert!(plain.get(FEDERATION_STATUS_KEY).is_none());
+
+        state.config.write().await.enrich_user_me = true;
+        let enriched = get_me(&state, &user).await;
+        assert_eq!(
+            enriched[FEDERATION_STATUS_KEY],
+            serde_json::json!({ "MappedServers": 1, "ActiveServers": 1 })
+        );
+    }
+
+    #[test]
+    fn authenticate_body_accepts_credentials_and_quick_connect_secrets() {
+        let body: AuthenticateBody =
+            serde_json::from_str(r#"{"Username":"viewer","Pw":"password"}"#).unwrap();
+        let AuthenticateBody::ByName(request) = body else {
+            panic!("expected a username/password body");
+        };
+        assert_eq!(request.username, "viewer");
+        assert_eq!(request.password.as_str(), "password");
+
+        let body: AuthenticateBody =
+            serde_json::from_str(r#"{"username":"viewer","pw":"password"}"#).unwrap();
+        assert!(matches!(body, AuthenticateBody::ByName(_)));
+
+        for json in [r#"{"Secret":"abc"}"#, r#"{"secret":"abc"}"#] {
+            let body: AuthenticateBody = serde_json::from_str(json).unwrap();
+            let AuthenticateBody::WithQuickConnect(request) = body else {
+                panic!("expected a Quick Connect body for {json}");
+            };
+            assert_eq!(request.secret, "abc");
+        }
+
+        assert!(serde_json::from_str::<AuthenticateBody>(r#"{"Username":"viewer"}"#).is_err());
+    }
+
+    #[test]
+    fn password_reset_errors_are_recognized() {
+        assert!(is_password_reset_error(
+            r#"{"passwordResetRequired": true}"#
+        ));
+        assert!(is_password_reset_error(
+            "Password reset required. Change your password to continue."
+        ));
+        assert!(!is_password_reset_error(
+            r#"{"PasswordResetRequired": false}"#
+        ));
+        assert!(!is_password_reset_error(
+            "Invalid username or password entered."
+        ));
+    }
+
+    #[tokio::test]
+    async fn backend_password_reset_is_reported_to_the_client() {
+        let state = create_test_app_state().await;
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/Users/AuthenticateByName"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_string("Password reset required. Change your password to continue."),
+            )
+            .mount(&upstream)
+            .await;
+        state
+            .server_storage
+            .add_server("Upstream", &upstream.uri(), 100, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            axum::http::header::AUTHORIZATION,
+            Authorization {
+                client: "Jellyfin Web".to_string(),
+                device: "Firefox".to_string(),
+                device_id: "web-device-id".to_string(),
+                version: "10.10.7".to_string(),
+                token: None,
+            }
+            .to_header_value()
+            .parse()
+            .unwrap(),
+        );
+        let response = handle_authenticate_by_name(
+            State(state),
+            headers,
+            Json(AuthenticateBody::ByName(AuthenticateRequest {
+                username: "viewer".to_string(),
+                password: "password".into(),
+            })),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            problem["detail"],
+            "The password of 'viewer' has to be changed on Upstream before it can be used. \
+             Sign in to that server directly to change it, then log in again."
+        );
+    }
+
+    fn upstream_auth_response(user_id: &str, token: &str) -> serde_json::Value {
+        serde_json::json!({
+            "User": {
+                "Name": "viewer",
+                "ServerId": "upstream-server",
+                "Id": user_id,
+                "Policy": { "IsAdministrator": false, "SyncPlayAccess": "None" }
+            },
+            "SessionInfo": {
+                "UserId": user_id,
+                "UserName": "viewer",
+                "ServerId": "upstream-server"
+            },
+            "AccessToken": token,
+            "ServerId": "upstream-server"
+        })
+    }
+
+    #[tokio::test]
+    async fn login_creates_a_session_on_every_mapped_server() {
+        let state = create_test_app_state().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+
+        let mut upstreams = Vec::new();
+        for (name, status) in [("Movies", 200), ("Shows", 200), ("Offline", 500)] {
+            let upstream = MockServer::start().await;
+            let response = if status == 200 {
+                ResponseTemplate::new(200).set_body_json(upstream_auth_response(
+                    &format!("{}-user-id", name.to_lowercase()),
+                    &format!("{}-token", name.to_lowercase()),
+                ))
+            } else {
+                ResponseTemplate::new(status)
+            };
+            Mock::given(method("POST"))
+                .and(path("/Users/AuthenticateByName"))
+                .respond_with(response)
+                .mount(&upstream)
+                .await;
+            let server_id = state
+                .server_storage
+                .add_server(name, &upstream.uri(), 100, MediaStreamingMode::Proxy)
+                .await
+                .unwrap();
+            let server = state
+                .server_storage
+                .get_server_by_id(server_id)
+                .await
+                .unwrap()
+                .unwrap();
+            state
+                .user_authorization
+                .add_server_mapping(
+                    &user.id,
+                    &server,
+                    "viewer",
+                    &"password".into(),
+                    Some(&user.original_password_hash),
+                )
+                .await
+                .unwrap();
+            upstreams.push(upstream);
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            axum::http::header::AUTHORIZATION,
+            Authorization {
+                client: "Jellyfin Web".to_string(),
+                device: "Firefox".to_string(),
+                device_id: "web-device-id".to_string(),
+                version: "10.10.7".to_string(),
+                token: None,
+            }
+            .to_header_value()
+            .parse()
+            .unwrap(),
+        );
+        let response = handle_authenticate_by_name(
+            State(state.clone()),
+            headers,
+            Json(AuthenticateBody::ByName(AuthenticateRequest {
+                username: "viewer".to_string(),
+                password: "password".into(),
+            })),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let auth_response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(auth_response["AccessToken"], user.virtual_key);
+        assert_eq!(auth_response["User"]["Id"], user.id);
+
+        let sessions = state
+            .user_authorization
+            .get_user_sessions(&user.id, None)
+            .await
+            .unwrap();
+        let mut tokens: Vec<_> = sessions
+            .iter()
+            .map(|(session, _)| session.jellyfin_token.as_str())
+            .collect();
+        tokens.sort();
+        assert_eq!(tokens, ["movies-token", "shows-token"]);
+    }
+}
@@ -1,5 +1,5 @@
 use axum::body::Body;
-use axum::extract::State;
+use axum::extract::{Path, State};
 use axum::response::{IntoResponse, Response};
 use futures_util::StreamExt;
 use hyper::StatusCode;
@@ -7,7 +7,9 @@ use tracing::{error, info, warn};
 
 use crate::{
     config::MediaStreamingMode,
+    error_response::UpstreamResponse,
     extractors::Preprocessed,
+    processors::url_processor::{matches_case_insensitive, PLAY_SESSION_ID_QUERY_TAGS},
     proxy_headers::remove_hop_by_hop_headers,
     request_preprocessing::{apply_to_request, remap_authorization},
     server_storage::Server,
@@ -30,9 +32,8 @@ fn extract_video_id(path: &str) -> Option<&str> {
 
 fn extract_play_session_id(url: &url::Url) -> Option<String> {
     url.query_pairs().find_map(|(key, value)| {
-        (key.eq_ignore_ascii_case("PlaySessionId") || key.eq_ignore_ascii_case("SessionId"))
-            .then(|| value.to_string())
-            .filter(|value| !value.is_empty())
+        (matches_case_insensitive(&key, PLAY_SESSION_ID_QUERY_TAGS) && !value.is_empty())
+            .then(|| value.into_owned())
     })
 }
 
@@ -50,7 +51,7 @@ fn single_matching_play_session(
     }
 }
 
-async fn proxy_request(
+pub(crate) async fn proxy_request(
     client: &reqwest::Client,
     request: reqwest::Request,
 ) -> Result<Response, StatusCode> {
@@ -74,6 +75,7 @@ async fn proxy_request(
 
     let mut response = Response::builder()
         .status(status)
+        .extension(UpstreamResponse)
         .body(body)
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
@@ -85,7 +87,7 @@ async fn proxy_request(
 async fn forward_video_request(
     state: &AppState,
     server: &Server,
-    request: reqwest::Request,
+    mut request: reqwest::Request,
     log_label: &str,
 ) -> Result<Response, StatusCode> {
     let url = request.url().clone();
@@ -97,12 +99,14 @@ async fn forward_video_request(
         }
         MediaStreamingMode::Proxy => {
             info!("Proxying {} from: {}", log_label, url);
+            // Streams outlive the API `timeout`; the streaming client only bounds connecting.
+            *request.timeout_mut() = None;
             proxy_request(&state.streaming_reqwest_client, request).await
         }
     }
 }
 
-fn session_for_server(
+pub(crate) fn session_for_server(
     sessions: &Option<Vec<(AuthorizationSession, Server)>>,
     server: &Server,
 ) -> Option<AuthorizationSession> {
@@ -267,7 +271,7 @@ pub async fn get_video_resource(
             .then(|| preprocessed.session.clone())
             .flatten()
     });
-    let new_auth = remap_authorization(&preprocessed.auth, &session)
+    let new_auth = remap_authorization(&preprocessed.auth, &session, &server)
         .await
         .map_err(|e| {
             error!("Failed to remap authorization for resource request: {}", e);
@@ -287,6 +291,84 @@ pub async fn get_video_resource(
     forward_video_request(&state, &server, upstream_request, "HLS stream").await
 }
 
+/// Subtitle streams (`/Videos/{id}/{mediaSourceId}/Subtitles/{index}/Stream.{format}` and
+/// the form with a start position). The ids were already translated for the owning
+/// server during preprocessing. Subtitles are always proxied, whatever the streaming
+/// mode, because players fetch them separately and often can't reach the backend.
+pub async fn get_subtitle_stream(
+    State(state): State<AppState>,
+    Path(params): Path<SubtitleStreamPath>,
+    Preprocessed(preprocessed): Preprocessed,
+) -> Result<Response, StatusCode> {
+    let server = preprocessed.server;
+    let mut request = preprocessed.request;
+    request.headers_mut().remove(hyper::header::RANGE);
+
+    // Only the item id follows a known path tag, so the media source id is
+    // translated here.
+    let source_mapping = state
+        .media_storage
+        .get_media_mapping_by_virtual(&params.media_source_id)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to resolve media source {} for subtitle stream: {}",
+                params.media_source_id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if let Some(mapping) = source_mapping.filter(|mapping| mapping.server_id == server.id) {
+        let path = request
+            .url()
+            .path()
+            .split('/')
+            .map(|segment| {
+                if segment == params.media_source_id {
+                    mapping.original_media_id.as_str()
+                } else {
+                    segment
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        request.url_mut().set_path(&path);
+    }
+    info!(
+        "Proxying subtitle stream from {}: {}",
+        server.name,
+        request.url()
+    );
+
+    *request.timeout_mut() = None;
+    let mut response = proxy_request(&state.streaming_reqwest_client, request).await?;
+    if !response.headers().contains_key(hyper::header::CONTENT_TYPE) {
+        if let Some(content_type) = subtitle_content_type(&params.format) {
+            response.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static(content_type),
+            );
+        }
+    }
+    Ok(response)
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubtitleStreamPath {
+    media_source_id: String,
+    format: String,
+}
+
+fn subtitle_content_type(format: &str) -> Option<&'static str> {
+    match format.to_ascii_lowercase().as_str() {
+        "vtt" => Some("text/vtt"),
+        "srt" | "subrip" => Some("application/x-subrip"),
+        "ass" | "ssa" => Some("text/x-ssa"),
+        "ttml" => Some("application/ttml+xml"),
+        "js" | "json" => Some("application/json"),
+        _ => None,
+    }
+}
+
 pub async fn get_stream(
     State(state): State<AppState>,
     Preprocessed(preprocessed): Preprocessed,
@@ -300,6 +382,124 @@ pub async fn get_stream(
 mod tests {
     use super::*;
     use crate::server_id::ServerId;
+    use crate::test_support::create_test_app_state;
+    use axum::{extract::Request, routing::get};
+    use tower::ServiceExt;
+    use wiremock::{
+        matchers::{method, path, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn start_server(name: &str) -> MockServer {
+        let upstream = MockServer::start().await;
+        Mock::given(path("/System/Info/Public"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "ServerName": name, "Version": "10.10.0" })),
+            )
+            .mount(&upstream)
+            .await;
+        upstream
+    }
+
+    #[tokio::test]
+    async fn subtitle_conversion_reaches_the_owning_server() {
+        let state = create_test_app_state().await;
+        let first = start_server("First").await;
+        let second = start_server("Second").await;
+        Mock::given(path_regex("/Subtitles/"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&first)
+            .await;
+
+        let item_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let source_id = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        let vtt = "WEBVTT\n\n00:00.000 --> 00:01.000\nHello\n";
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/Videos/{item_id}/{source_id}/Subtitles/2/0/Stream.vtt"
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vtt.as_bytes(), "text/vtt"))
+            .expect(1)
+            .mount(&second)
+            .await;
+
+        // Redirect mode would send the player straight to the backend; subtitles are
+        // proxied regardless.
+        for (name, upstream, priority) in [("First", &first, 200), ("Second", &second, 100)] {
+            state
+                .server_storage
+                .add_server(
+                    name,
+                    &upstream.uri(),
+                    priority,
+                    MediaStreamingMode::Redirect,
+                )
+                .await
+                .unwrap();
+        }
+        state.server_storage.check_servers_health().await;
+        let second_server = state
+            .server_storage
+            .list_servers()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|server| server.name == "Second")
+            .unwrap();
+        let mut virtual_ids = Vec::new();
+        for id in [item_id, source_id] {
+            virtual_ids.push(
+                state
+                    .media_storage
+                    .get_or_create_media_mapping(id, &second_server)
+                    .await
+                    .unwrap()
+                    .virtual_media_id,
+            );
+        }
+
+        let router = axum::Router::new()
+            .route(
+                "/Videos/{item_id}/{media_source_id}/Subtitles/{index}/{start_position_ticks}/Stream.{format}",
+                get(get_subtitle_stream),
+            )
+            .with_state(state);
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri(format!(
+                        "/Videos/{}/{}/Subtitles/2/0/Stream.vtt",
+                        virtual_ids[0], virtual_ids[1]
+                    ))
+                    .header(hyper::header::HOST, "localhost")
+                    .header(hyper::header::RANGE, "bytes=0-")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/vtt");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], vtt.as_bytes());
+        let received = second.received_requests().await.unwrap();
+        assert!(received
+            .iter()
+            .filter(|request| request.url.path().contains("/Subtitles/"))
+            .all(|request| !request.headers.contains_key("range")));
+    }
+
+    #[test]
+    fn subtitle_content_types_follow_the_format() {
+        assert_eq!(subtitle_content_type("vtt"), Some("text/vtt"));
+        assert_eq!(subtitle_content_type("SRT"), Some("application/x-subrip"));
+        assert_eq!(subtitle_content_type("mkv"), None);
+    }
 
     fn playback_session(session_id: &str, user_id: &str, server_id: i64) -> PlaybackSession {
         PlaybackSession {
@@ -307,6 +507,8 @@ mod tests {
             item_id: "item-1".to_string(),
             user_id: user_id.to_string(),
             server_id: ServerId::new(server_id),
+            original_user_id: format!("{user_id}-original"),
+            original_item_id: "original-item-1".to_string(),
         }
     }
 
@@ -0,0 +1,608 @@
+use axum::{extract::State, Json};
+use hyper::StatusCode;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::{
+    config::WatchStateMirror,
+    extractors::RequireSession,
+    handlers::{
+        common::execute_json_request,
+        items::{
+            execute_media_request, find_copies_on, find_item_copies, forward_to_copies,
+            item_id_segment, session_request,
+        },
+    },
+    server_storage::Server,
+    url_helper::join_server_url,
+    user_authorization_service::AuthorizationSession,
+    AppState,
+};
+
+// Watch state mirrors are servers holding the same library. Libraries are only
+// browsed on the display server; the sync servers get the same played, favorite
+// and resume changes, so the user can switch to any of them without losing track.
+
+/// Drop the servers that only mirror the watch state of another server in `sessions`.
+pub(crate) fn without_sync_mirrors(
+    sessions: Vec<(AuthorizationSession, Server)>,
+    mirrors: &[WatchStateMirror],
+) -> Vec<(AuthorizationSession, Server)> {
+    let hidden = mirrors
+        .iter()
+        .filter(|mirror| {
+            sessions
+                .iter()
+                .any(|(_, server)| server.name == mirror.display)
+        })
+        .flat_map(|mirror| mirror.sync.iter())
+        .collect::<Vec<_>>();
+    sessions
+        .into_iter()
+        .filter(|(_, server)| !hidden.contains(&&server.name))
+        .collect()
+}
+
+/// The sessions of the servers that keep the watch state of `server` in sync.
+pub(crate) async fn sync_mirror_sessions<'a>(
+    state: &AppState,
+    server: &Server,
+    sessions: &'a Option<Vec<(AuthorizationSession, Server)>>,
+) -> Vec<&'a (AuthorizationSession, Server)> {
+    let mirrors = state.watch_state_mirrors().await;
+    let Some(mirror) = mirrors.iter().find(|mirror| mirror.display == server.name) else {
+        return Vec::new();
+    };
+    let mut synced = Vec::new();
+    for entry in sessions.iter().flatten() {
+        let (_, other) = entry;
+        if mirror.sync.contains(&other.name)
+            && !synced
+                .iter()
+                .any(|(_, seen): &&(AuthorizationSession, Server)| seen.id == other.id)
+        {
+            synced.push(entry);
+        }
+    }
+    synced
+}
+
+/// Find the copies of `item_id` on the sync mirrors of `server`.
+async fn mirror_copies(
+    state: &AppState,
+    server: &Server,
+    session: &AuthorizationSession,
+    sessions: &Option<Vec<(AuthorizationSession, Server)>>,
+    item_id: &str,
+) -> Vec<(Server, AuthorizationSession, String)> {
+    let mirrors = sync_mirror_sessions(state, server, sessions).await;
+    if mirrors.is_empty() {
+        return Vec::new();
+    }
+    find_copies_on(state, server, session, item_id, mirrors).await
+}
+
+/// The copies a played or favorite change is forwarded to: the sync mirrors of
+/// the owning server and, with `mark_all_copies`, the item's duplicates on the
+/// user's other servers. Each server is only written to once.
+pub(crate) fn watch_state_targets(
+    mirrors: Vec<(Server, AuthorizationSession, String)>,
+    duplicates: Vec<(Server, AuthorizationSession, String)>,
+) -> Vec<(Server, AuthorizationSession, String)> {
+    let mut targets = mirrors;
+    for duplicate in duplicates {
+        if !targets
+            .iter()
+            .any(|(server, _, _)| server.id == duplicate.0.id)
+        {
+            targets.push(duplicate);
+        }
+    }
+    targets
+}
+
+//http://localhost:3000/Users/7bc57a386ab84999ad7262210a9cd253/PlayedItems/430c368c5eb34534bf98363d5adbb92f
+//http://localhost:3000/UserFavoriteItems/430c368c5eb34534bf98363d5adbb92f?userId=7bc57a386ab84999ad7262210a9cd253
+/// Mark an item played or favorite (`POST`), or clear that (`DELETE`), on its
+/// backend and on the copies of the item on that backend's sync mirrors.
+///
+/// With `mark_all_copies`, the copies of the item on the user's other servers get
+/// the same change; failures there are only logged.
+pub async fn update_played_or_favorite(
+    State(state): State<AppState>,
+    RequireSession {
+        preprocessed,
+        session,
+    }: RequireSession,
+) -> Result<Json<Value>, StatusCode> {
+    let url = preprocessed.request.url().clone();
+    let kind = if url.path().to_ascii_lowercase().contains("favoriteitems") {
+        "FavoriteItems"
+    } else {
+        "PlayedItems"
+    };
+    let mirrors = match item_id_segment(&url) {
+        Some(item_id) => {
+            mirror_copies(
+                &state,
+                &preprocessed.server,
+                &session,
+                &preprocessed.sessions,
+                &item_id,
+            )
+            .await
+        }
+        None => Vec::new(),
+    };
+    let duplicates = if state.mark_all_copies_enabled().await {
+        find_item_copies(&state, &preprocessed, &session).await
+    } else {
+        Vec::new()
+    };
+    let copies = watch_state_targets(mirrors, duplicates);
+    let method = preprocessed.request.method().clone();
+
+    let user_data = execute_media_request(&state, preprocessed).await?;
+
+    forward_to_copies(&state, copies, method, None, |session, copy_id| {
+        format!("/Users/{}/{kind}/{copy_id}", session.original_user_id)
+    })
+    .await;
+
+    Ok(Json(user_data))
+}
+
+/// Copy the resume position of a stopped playback to the item's copies on the
+/// sync mirrors of the server that played it.
+pub(crate) async fn sync_stopped_position(
+    state: &AppState,
+    server: &Server,
+    session: &AuthorizationSession,
+    sessions: &Option<Vec<(AuthorizationSession, Server)>>,
+    item_id: &str,
+    position_ticks: i64,
+) {
+    let copies = mirror_copies(state, server, session, sessions, item_id).await;
+    for (mirror, mirror_session, copy_id) in copies {
+        let mut url = join_server_url(&mirror.url, &format!("/UserItems/{copy_id}/UserData"));
+        url.query_pairs_mut()
+            .append_pair("userId", &mirror_session.original_user_id);
+        let mut request = session_request(state, reqwest::Method::POST, url, &mirror_session).await;
+        let body = serde_json::json!({ "PlaybackPositionTicks": position_ticks });
+        request.headers_mut().insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        *request.body_mut() = Some(body.to_string().into());
+
+        match state.reqwest_client.execute(request).await {
+            Ok(response) if response.status().is_success() => debug!(
+                "Synced resume position of {} to server {}",
+                copy_id, mirror.name
+            ),
+            Ok(response) => warn!(
+                "Syncing resume position of {} to server {} returned {}",
+                copy_id,
+                mirror.name,
+                response.status()
+            ),
+            Err(e) => warn!(
+                "Failed to sync resume position of {} to server {}: {}",
+                copy_id, mirror.name, e
+            ),
+        }
+    }
+}
+
+/// Combine the watch state of the item's copies on the sync mirrors of `server`
+/// into the `UserData` of `item`.
+pub(crate) async fn merge_mirror_user_data(
+    state: &AppState,
+    server: &Server,
+    session: &AuthorizationSession,
+    sessions: &Option<Vec<(AuthorizationSession, Server)>>,
+    item_id: &str,
+    item: &mut Value,
+) {
+    let Some(user_data) = item.get_mut("UserData") else {
+        return;
+    };
+    let copies = mirror_copies(state, server, session, sessions, item_id).await;
+    for (mirror, mirror_session, copy_id) in copies {
+        let url = join_server_url(
+            &mirror.url,
+            &format!("/Users/{}/Items/{copy_id}", mirror_session.original_user_id),
+        );
+        match execute_json_request::<Value>(
+            &state.reqwest_client,
+            session_request(state, reqwest::Method::GET, url, &mirror_session).await,
+        )
+        .await
+        {
+            Ok(copy) => {
+                if let Some(copy_user_data) = copy.get("UserData") {
+                    merge_user_data(user_data, copy_user_data);
+                }
+            }
+            Err(status) => warn!(
+                "Failed to read watch state of {} from server {}: {}",
+                copy_id, mirror.name, status
+            ),
+        }
+    }
+}
+
+/// Merge the watch state of a copy into `target`: an item counts as played or
+/// favorite if it is on either server, and the resume position comes from the
+/// server it was played on last.
+fn merge_user_data(target: &mut Value, copy: &Value) {
+    for flag in ["Played", "IsFavorite"] {
+        if copy.get(flag).and_then(Value::as_bool) == Some(true) {
+            target[flag] = Value::Bool(true);
+        }
+    }
+
+    let play_count = |data: &Value| data.get("PlayCount").and_then(Value::as_i64);
+    if play_count(copy) > play_count(target) {
+        target["PlayCount"] = copy["PlayCount"].clone();
+    }
+
+    let last_played = |data: &Value| {
+        data.get("LastPlayedDate")
+            .and_then(Value::as_str)
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+    };
+    if last_played(copy) > last_played(target) {
+        for field in [
+            "LastPlayedDate",
+            "PlaybackPositionTicks",
+            "PlayedPercentage",
+        ] {
+            match copy.get(field) {
+                Some(value) => target[field] = value.clone(),
+                None => {
+                    if let Some(target) = target.as_object_mut() {
+                        target.remove(field);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        add_server_with_session, create_test_app_state_with_config, web_authorization,
+    };
+    use crate::{
+        config::AppConfig,
+        extractors::Preprocessed,
+        handlers::{federated::get_items_from_all_servers, items::get_item},
+        request_preprocessing::{preprocess_request, PreprocessedRequest},
+        user_authorization_service::User,
+    };
+    use axum::{body::Body, response::IntoResponse};
+    use wiremock::{
+        matchers::{method, path, query_param, query_param_is_missing},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const MAIN_ID: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const BACKUP_ID: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    async fn create_test_app_state() -> AppState {
+        create_test_app_state_with_config(AppConfig {
+            watch_state_mirrors: vec![WatchStateMirror {
+                display: "Main".to_string(),
+                sync: vec!["Backup".to_string()],
+            }],
+            ..AppConfig::default()
+        })
+        .await
+    }
+
+    /// Connect a user to a `Main` server and its `Backup` mirror, both holding the
+    /// same movie.
+    async fn connect_mirrors(
+        state: &AppState,
+        main: &MockServer,
+        backup: &MockServer,
+    ) -> (User, Vec<Server>) {
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let mut servers = Vec::new();
+        for (name, upstream, priority) in [("Main", main, 100), ("Backup", backup, 200)] {
+            servers.push(add_server_with_session(state, &user, name, upstream, priority).await);
+        }
+        state.server_storage.check_servers_health().await;
+
+        Mock::given(method("GET"))
+            .and(path(format!("/Users/Main-user-id/Items/{MAIN_ID}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Id": MAIN_ID,
+                "Name": "Heat",
+                "Type": "Movie",
+                "ProviderIds": { "Imdb": "tt0113277" },
+                "UserData": {
+                    "Key": "tt0113277",
+                    "ItemId": MAIN_ID,
+                    "IsFavorite": true,
+                    "Played": false,
+                    "PlayCount": 0,
+                    "PlaybackPositionTicks": 100,
+                    "LastPlayedDate": "2026-01-01T20:00:00.0000000Z"
+                }
+            })))
+            .mount(main)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Users/Backup-user-id/Items"))
+            .and(query_param("SearchTerm", "Heat"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Items": [{
+                    "Id": BACKUP_ID,
+                    "Name": "Heat",
+                    "Type": "Movie",
+                    "ProviderIds": { "Imdb": "tt0113277" }
+                }],
+                "TotalRecordCount": 1,
+                "StartIndex": 0
+            })))
+            .mount(backup)
+            .await;
+
+        (user, servers)
+    }
+
+    async fn preprocessed(
+        state: &AppState,
+        user: &User,
+        method: &str,
+        uri: &str,
+    ) -> PreprocessedRequest {
+        let uri: axum::http::Uri = uri.parse().unwrap();
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri.clone())
+            .header(axum::http::header::HOST, "localhost")
+            .header(
+                axum::http::header::AUTHORIZATION,
+                web_authorization(Some(user.virtual_key.clone())).to_header_value(),
+            )
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+        preprocess_request(request, state).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn libraries_list_the_display_server_with_the_watch_state_of_both() {
+        let state = create_test_app_state().await;
+        let main = MockServer::start().await;
+        let backup = MockServer::start().await;
+        let (user, servers) = connect_mirrors(&state, &main, &backup).await;
+
+        Mock::given(method("GET"))
+            .and(path("/Users/Main-user-id/Items"))
+            .and(query_param_is_missing("SearchTerm"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Items": [{ "Id": MAIN_ID, "Name": "Heat", "Type": "Movie" }],
+                "TotalRecordCount": 1,
+                "StartIndex": 0
+            })))
+            .expect(1)
+            .mount(&main)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Users/Backup-user-id/Items"))
+            .and(query_param_is_missing("SearchTerm"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&backup)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/Users/Backup-user-id/Items/{BACKUP_ID}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Id": BACKUP_ID,
+                "UserData": {
+                    "Key": "tt0113277",
+                    "ItemId": BACKUP_ID,
+                    "IsFavorite": false,
+                    "Played": true,
+                    "PlayCount": 2,
+                    "PlaybackPositionTicks": 0,
+                    "LastPlayedDate": "2026-02-01T20:00:00.0000000Z"
+                }
+            })))
+            .mount(&backup)
+            .await;
+
+        let listing = preprocessed(
+            &state,
+            &user,
+            "GET",
+            &format!(
+                "/Users/{}/Items?Recursive=true&IncludeItemTypes=Movie",
+                user.id
+            ),
+        )
+        .await;
+        let response = get_items_from_all_servers(State(state.clone()), Preprocessed(listing))
+            .await
+            .unwrap()
+            .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let listing: Value = serde_json::from_slice(&body).unwrap();
+        let items = listing["Items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        let virtual_id = items[0]["Id"].as_str().unwrap();
+        let mapping = state
+            .media_storage
+            .get_media_mapping_by_virtual(virtual_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(mapping.server_id, servers[0].id);
+
+        let detail = preprocessed(
+            &state,
+            &user,
+            "GET",
+            &format!("/Users/{}/Items/{virtual_id}", user.id),
+        )
+        .await;
+        let Json(item) = get_item(State(state), Preprocessed(detail)).await.unwrap();
+        let user_data = &item["UserData"];
+        assert_eq!(user_data["IsFavorite"], true);
+        assert_eq!(user_data["Played"], true);
+        assert_eq!(user_data["PlayCount"], 2);
+        assert_eq!(user_data["PlaybackPositionTicks"], 0);
+        assert_eq!(user_data["LastPlayedDate"], "2026-02-01T20:00:00.0000000Z");
+    }
+
+    #[tokio::test]
+    async fn marking_an_item_played_reaches_its_mirrors() {
+        let state = create_test_app_state().await;
+        let main = MockServer::start().await;
+        let backup = MockServer::start().await;
+        let (user, servers) = connect_mirrors(&state, &main, &backup).await;
+
+        for (upstream, owner, item_id) in [(&main, "Main", MAIN_ID), (&backup, "Backup", BACKUP_ID)]
+        {
+            Mock::given(method("POST"))
+                .and(path(format!(
+                    "/Users/{owner}-user-id/PlayedItems/{item_id}"
+                )))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "ItemId": item_id,
+                    "Played": true
+                })))
+                .expect(1)
+                .mount(upstream)
+                .await;
+        }
+        let virtual_id = state
+            .media_storage
+            .get_or_create_media_mapping(MAIN_ID, &servers[0])
+            .await
+            .unwrap()
+            .virtual_media_id;
+
+        let request = preprocessed(
+            &state,
+            &user,
+            "POST",
+            &format!("/Users/{}/PlayedItems/{virtual_id}", user.id),
+        )
+        .await;
+        let session = request.session.clone().unwrap();
+        let Json(user_data) = update_played_or_favorite(
+            State(state),
+            RequireSession {
+                preprocessed: request,
+                session,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(user_data["ItemId"], virtual_id);
+        assert_eq!(user_data["Played"], true);
+    }
+
+    /// Send a favorite change for the movie on `Main` through the handler and
+    /// expect `backup_calls` requests for its copy on `Backup`.
+    async fn mark_favorite(
+        state: AppState,
+        main: &MockServer,
+        backup: &MockServer,
+        method_name: &str,
+        backup_calls: u64,
+    ) {
+        let (user, servers) = connect_mirrors(&state, main, backup).await;
+        // The owning server gets the request with its own item and user id.
+        Mock::given(method(method_name))
+            .and(path(format!("/UserFavoriteItems/{MAIN_ID}")))
+            .and(query_param("userId", "Main-user-id"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ItemId": MAIN_ID,
+                "IsFavorite": method_name == "POST"
+            })))
+            .expect(1)
+            .mount(main)
+            .await;
+        Mock::given(method(method_name))
+            .and(path(format!(
+                "/Users/Backup-user-id/FavoriteItems/{BACKUP_ID}"
+            )))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(backup_calls)
+            .mount(backup)
+            .await;
+        let virtual_id = state
+            .media_storage
+            .get_or_create_media_mapping(MAIN_ID, &servers[0])
+            .await
+            .unwrap()
+            .virtual_media_id;
+
+        let request = preprocessed(
+            &state,
+            &user,
+            method_name,
+            &format!("/UserFavoriteItems/{virtual_id}?userId={}", user.id),
+        )
+        .await;
+        let session = request.session.clone().unwrap();
+        let Json(user_data) = update_played_or_favorite(
+            State(state),
+            RequireSession {
+                preprocessed: request,
+                session,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(user_data["ItemId"], virtual_id);
+    }
+
+    #[tokio::test]
+    async fn favorites_only_reach_the_owning_server_by_default() {
+        let state = create_test_app_state().await;
+        state.config.write().await.watch_state_mirrors.clear();
+        let main = MockServer::start().await;
+        let backup = MockServer::start().await;
+
+        mark_favorite(state, &main, &backup, "POST", 0).await;
+    }
+
+    #[tokio::test]
+    async fn mark_all_copies_reaches_the_duplicates_on_other_servers() {
+        let state = create_test_app_state().await;
+        {
+            let mut config = state.config.write().await;
+            config.watch_state_mirrors.clear();
+            config.mark_all_copies = true;
+        }
+        let main = MockServer::start().await;
+        let backup = MockServer::start().await;
+
+        mark_favorite(state, &main, &backup, "DELETE", 1).await;
+    }
+
+    #[tokio::test]
+    async fn mirrors_that_are_also_duplicates_are_marked_once() {
+        let state = create_test_app_state().await;
+        state.config.write().await.mark_all_copies = true;
+        let main = MockServer::start().await;
+        let backup = MockServer::start().await;
+
+        mark_favorite(state, &main, &backup, "POST", 1).await;
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use reqwest::{header::RANGE, Method, StatusCode};
+use tracing::debug;
+
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(4);
+
+/// Execute an upstream request, retrying transient failures up to `max_retries` times
+/// with exponential backoff.
+///
+/// Only `GET`/`HEAD` requests without a body (or with an empty buffered one) are retried, and never range requests, since
+/// those belong to a media stream the client is already consuming. Connection errors,
+/// timeouts and `502`/`503`/`504` responses count as transient; once the retries are
+/// used up the last error or response is returned as-is.
+pub async fn execute_with_retry(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    max_retries: u32,
+) -> reqwest::Result<reqwest::Response> {
+    if max_retries == 0 || !is_retryable_request(&request) {
+        return client.execute(request).await;
+    }
+
+    let mut backoff = INITIAL_RETRY_BACKOFF;
+    for attempt in 1..=max_retries {
+        let Some(retry_request) = request.try_clone() else {
+            break;
+        };
+
+        match client.execute(retry_request).await {
+            Ok(response) if !is_transient_status(response.status()) => return Ok(response),
+            Ok(response) => debug!(
+                "Upstream returned {} for {} (attempt {}/{}), retrying in {:?}",
+                response.status(),
+                request.url(),
+                attempt,
+                max_retries + 1,
+                backoff
+            ),
+            Err(e) if is_transient_error(&e) => debug!(
+                "Upstream request to {} failed (attempt {}/{}), retrying in {:?}: {}",
+                request.url(),
+                attempt,
+                max_retries + 1,
+                backoff,
+                e
+            ),
+            Err(e) => return Err(e),
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+    }
+
+    client.execute(request).await
+}
+
+fn is_retryable_request(request: &reqwest::Request) -> bool {
+    matches!(*request.method(), Method::GET | Method::HEAD)
+        && request
+            .body()
+            .is_none_or(|body| body.as_bytes().is_some_and(<[u8]>::is_empty))
+        && !request.headers().contains_key(RANGE)
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn is_transient_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn request(method: Method, url: &str) -> reqwest::Request {
+        reqwest::Request::new(method, url.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn retries_transient_get_failures_until_success() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Items"))
+            .respond_with(ResponseTemplate::new(502))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Items"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let response = execute_with_retry(
+            &reqwest::Client::new(),
+            request(Method::GET, &format!("{}/Items", upstream.uri())),
+            3,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn returns_last_response_once_retries_are_exhausted() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Items"))
+            .respond_with(ResponseTemplate::new(504))
+            .expect(2)
+            .mount(&upstream)
+            .await;
+
+        let response = execute_with_retry(
+            &reqwest::Client::new(),
+            request(Method::GET, &format!("{}/Items", upstream.uri())),
+            1,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_posts_bodies_or_range_requests() {
+        let upstream = MockServer::start().await;
+        Mock::given(path("/Items"))
+            .respond_with(ResponseTemplate::new(502))
+            .expect(3)
+            .mount(&upstream)
+            .await;
+        let url = format!("{}/Items", upstream.uri());
+        let client = reqwest::Client::new();
+
+        let post = request(Method::POST, &url);
+
+        let mut with_body = request(Method::GET, &url);
+        *with_body.body_mut() = Some(reqwest::Body::from("{}"));
+
+        let mut range = request(Method::GET, &url);
+        range
+            .headers_mut()
+            .insert(RANGE, "bytes=0-".parse().unwrap());
+
+        for request in [post, with_body, range] {
+            let response = execute_with_retry(&client, request, 3).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_gets_with_an_empty_buffered_body() {
+        let upstream = MockServer::start().await;
+        Mock::given(path("/Items"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        Mock::given(path("/Items"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let mut get = request(Method::GET, &format!("{}/Items", upstream.uri()));
+        *get.body_mut() = Some(reqwest::Body::from(Vec::new()));
+
+        let response = execute_with_retry(&reqwest::Client::new(), get, 3)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
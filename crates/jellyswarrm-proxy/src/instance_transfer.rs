@@ -0,0 +1,647 @@
+//! Export and import of everything needed to move an instance: servers and their
+//! admin credentials, users, server mappings and media mappings.
+//!
+//! Secrets (password hashes, virtual keys and the stored, encrypted server
+//! passwords) are either left out of the document or sealed with a passphrase chosen
+//! at export time. The sealed values are the stored ones, so server passwords that
+//! were encrypted with the admin password only decrypt on an instance that uses the
+//! same admin password.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::{AuthorizationHeaderMode, MediaStreamingMode},
+    encryption::{decrypt_password, encrypt_password, EncryptedPassword, HashedPassword},
+    media_storage_service::MediaStorageService,
+    server_storage::{Server, ServerStorageService},
+    user_authorization_service::UserAuthorizationService,
+    AppState,
+};
+
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub enum SecretsMode {
+    /// Secrets are encrypted with the export passphrase.
+    Sealed,
+    /// Secrets are left out.
+    Redacted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstanceExport {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub secrets: SecretsMode,
+    pub servers: Vec<ExportedServer>,
+    pub users: Vec<ExportedUser>,
+    pub server_mappings: Vec<ExportedServerMapping>,
+    pub media_mappings: Vec<ExportedMediaMapping>,
+}
+
+/// A server. `id` is the server's id on the exporting instance and only links the
+/// other entries of the document to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExportedServer {
+    pub id: i64,
+    pub name: String,
+    pub url: String,
+    pub priority: i32,
+    pub media_streaming_mode: MediaStreamingMode,
+    pub authorization_header_mode: AuthorizationHeaderMode,
+    pub max_connections: Option<u32>,
+    pub backend_id: Option<String>,
+    pub admin: Option<ExportedServerAdmin>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExportedServerAdmin {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExportedUser {
+    pub id: String,
+    /// Left out with the other secrets; imported users without one get a new key.
+    pub virtual_key: Option<String>,
+    pub username: String,
+    pub password_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExportedServerMapping {
+    pub user_id: String,
+    pub server_id: i64,
+    pub mapped_username: String,
+    pub mapped_password: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExportedMediaMapping {
+    pub virtual_media_id: String,
+    pub original_media_id: String,
+    pub server_id: i64,
+}
+
+/// Rows written by an import. Entries whose secrets were redacted and that had
+/// nothing to update are counted as skipped; skipped users are also listed by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImportSummary {
+    pub servers: usize,
+    pub server_admins: usize,
+    pub users: usize,
+    pub server_mappings: usize,
+    pub media_mappings: usize,
+    pub skipped: usize,
+    pub skipped_users: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransferError {
+    #[error("Unsupported export format version {0}")]
+    UnsupportedVersion(u32),
+    #[error("The export contains sealed secrets, a passphrase is required")]
+    MissingPassphrase,
+    #[error("The passphrase does not match the export")]
+    WrongPassphrase,
+    #[error("The export references unknown server {0}")]
+    UnknownServer(i64),
+    #[error("Failed to seal a secret: {0}")]
+    Encryption(#[from] crate::encryption::EncryptionError),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl TransferError {
+    /// Whether the error is caused by the request rather than by this instance.
+    pub fn is_client_error(&self) -> bool {
+        !matches!(self, Self::Encryption(_) | Self::Database(_))
+    }
+}
+
+/// Seals or opens the secrets of an export.
+struct SecretBox(Option<HashedPassword>);
+
+impl SecretBox {
+    fn new(passphrase: Option<&str>) -> Self {
+        Self(
+            passphrase
+                .filter(|passphrase| !passphrase.is_empty())
+                .map(HashedPassword::from_password),
+        )
+    }
+
+    fn mode(&self) -> SecretsMode {
+        if self.0.is_some() {
+            SecretsMode::Sealed
+        } else {
+            SecretsMode::Redacted
+        }
+    }
+
+    fn seal(&self, secret: &str) -> Result<Option<String>, TransferError> {
+        let Some(key) = &self.0 else {
+            return Ok(None);
+        };
+        Ok(Some(encrypt_password(&secret.into(), key)?.into_inner()))
+    }
+
+    fn open(&self, sealed: Option<&str>) -> Result<Option<String>, TransferError> {
+        let (Some(key), Some(sealed)) = (&self.0, sealed) else {
+            return Ok(None);
+        };
+        decrypt_password(&EncryptedPassword::from_raw(sealed.to_string()), key)
+            .map(|secret| Some(secret.into_inner()))
+            .map_err(|_| TransferError::WrongPassphrase)
+    }
+}
+
+pub struct InstanceTransfer<'a> {
+    pub server_storage: &'a ServerStorageService,
+    pub user_authorization: &'a UserAuthorizationService,
+    pub media_storage: &'a MediaStorageService,
+}
+
+impl<'a> InstanceTransfer<'a> {
+    pub fn from_state(state: &'a AppState) -> Self {
+        Self {
+            server_storage: &state.server_storage,
+            user_authorization: &state.user_authorization,
+            media_storage: &state.media_storage,
+        }
+    }
+
+    /// Export the instance. Secrets are sealed with `passphrase`, or left out
+    /// without one.
+    pub async fn export(&self, passphrase: Option<&str>) -> Result<InstanceExport, TransferError> {
+        let secrets = SecretBox::new(passphrase);
+
+        let admins = self
+            .server_storage
+            .list_server_admins()
+            .await?
+            .into_iter()
+            .map(|admin| (admin.server_id, admin))
+            .collect::<HashMap<_, _>>();
+        let mut servers = Vec::new();
+        for server in self.server_storage.list_servers().await? {
+            let admin = match admins.get(&server.id) {
+                Some(admin) => Some(ExportedServerAdmin {
+                    username: admin.username.clone(),
+                    password: secrets.seal(admin.password.as_str())?,
+                }),
+                None => None,
+            };
+            servers.push(ExportedServer {
+                id: server.id.as_i64(),
+                name: server.name,
+                url: server.url.as_str().to_string(),
+                priority: server.priority,
+                media_streaming_mode: server.media_streaming_mode,
+                authorization_header_mode: server.authorization_header_mode,
+                max_connections: server.max_connections,
+                backend_id: server.backend_id,
+                admin,
+            });
+        }
+
+        let mut users = Vec::new();
+        for user in self.user_authorization.list_users().await? {
+            users.push(ExportedUser {
+                password_hash: secrets.seal(user.original_password_hash.as_str())?,
+                virtual_key: secrets.seal(&user.virtual_key)?,
+                id: user.id,
+                username: user.original_username,
+            });
+        }
+
+        let mut server_mappings = Vec::new();
+        for mapping in self.user_authorization.list_all_server_mappings().await? {
+            server_mappings.push(ExportedServerMapping {
+                mapped_password: secrets.seal(mapping.mapped_password.as_str())?,
+                user_id: mapping.user_id,
+                server_id: mapping.server_id.as_i64(),
+                mapped_username: mapping.mapped_username,
+            });
+        }
+
+        let media_mappings = self
+            .media_storage
+            .list_media_mappings()
+            .await?
+            .into_iter()
+            .map(|mapping| ExportedMediaMapping {
+                virtual_media_id: mapping.virtual_media_id,
+                original_media_id: mapping.original_media_id,
+                server_id: mapping.server_id.as_i64(),
+            })
+            .collect();
+
+        Ok(InstanceExport {
+            version: EXPORT_FORMAT_VERSION,
+            exported_at: Utc::now(),
+            secrets: secrets.mode(),
+            servers,
+            users,
+            server_mappings,
+            media_mappings,
+        })
+    }
+
+    /// Restore an export in a single transaction. Servers are matched by URL and
+    /// users by username; existing entries are updated, missing ones are created.
+    pub async fn import(
+        &self,
+        export: &InstanceExport,
+        passphrase: Option<&str>,
+    ) -> Result<ImportSummary, TransferError> {
+        if export.version != EXPORT_FORMAT_VERSION {
+            return Err(TransferError::UnsupportedVersion(export.version));
+        }
+        let secrets = match export.secrets {
+            SecretsMode::Sealed => {
+                let secrets = SecretBox::new(passphrase);
+                if secrets.0.is_none() {
+                    return Err(TransferError::MissingPassphrase);
+                }
+                secrets
+            }
+            SecretsMode::Redacted => SecretBox::new(None),
+        };
+
+        let mut summary = ImportSummary::default();
+        let mut transaction = self.server_storage.begin().await?;
+
+        let mut servers: HashMap<i64, Server> = HashMap::new();
+        for exported in &export.servers {
+            let server = self
+                .server_storage
+                .import_server(&mut transaction, exported)
+                .await?;
+            summary.servers += 1;
+
+            if let Some(admin) = &exported.admin {
+                match secrets.open(admin.password.as_deref())? {
+                    Some(password) => {
+                        self.server_storage
+                            .import_server_admin(
+                                &mut transaction,
+                                server.id,
+                                &admin.username,
+                                &EncryptedPassword::from_raw(password),
+                            )
+                            .await?;
+                        summary.server_admins += 1;
+                    }
+                    None => summary.skipped += 1,
+                }
+            }
+            servers.insert(exported.id, server);
+        }
+
+        let mut users: HashMap<&str, String> = HashMap::new();
+        for exported in &export.users {
+            let password_hash = secrets
+                .open(exported.password_hash.as_deref())?
+                .map(HashedPassword::from_hashed);
+            let virtual_key = secrets.open(exported.virtual_key.as_deref())?;
+            match self
+                .user_authorization
+                .import_user(
+                    &mut transaction,
+                    &exported.id,
+                    virtual_key.as_deref(),
+                    &exported.username,
+                    password_hash.as_ref(),
+                )
+                .await?
+            {
+                Some(user_id) => {
+                    summary.users += 1;
+                    users.insert(&exported.id, user_id);
+                }
+                None => {
+                    summary.skipped += 1;
+                    summary.skipped_users.push(exported.username.clone());
+                }
+            }
+        }
+
+        for exported in &export.server_mappings {
+            let server = servers
+                .get(&exported.server_id)
+                .ok_or(TransferError::UnknownServer(exported.server_id))?;
+            let Some(user_id) = users.get(exported.user_id.as_str()) else {
+                summary.skipped += 1;
+                continue;
+            };
+            let password = secrets
+                .open(exported.mapped_password.as_deref())?
+                .map(EncryptedPassword::from_raw);
+            if self
+                .user_authorization
+                .import_server_mapping(
+                    &mut transaction,
+                    user_id,
+                    server,
+                    &exported.mapped_username,
+                    password.as_ref(),
+                )
+                .await?
+            {
+                summary.server_mappings += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
+
+        for exported in &export.media_mappings {
+            let server = servers
+                .get(&exported.server_id)
+                .ok_or(TransferError::UnknownServer(exported.server_id))?;
+            if self
+                .media_storage
+                .import_media_mapping(
+                    &mut transaction,
+                    &exported.virtual_media_id,
+                    &exported.original_media_id,
+                    server,
+                )
+                .await?
+            {
+                summary.media_mappings += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
+
+        transaction.commit().await?;
+        info!("Imported instance export: {:?}", summary);
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::MIGRATOR,
+        encryption::{decrypt_password, Password},
+    };
+    use sqlx::SqlitePool;
+
+    struct Services {
+        server_storage: ServerStorageService,
+        user_authorization: UserAuthorizationService,
+        media_storage: MediaStorageService,
+    }
+
+    impl Services {
+        async fn new() -> Self {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            MIGRATOR.run(&pool).await.unwrap();
+            Self {
+                server_storage: ServerStorageService::new(pool.clone()),
+                user_authorization: UserAuthorizationService::new(pool.clone()),
+                media_storage: MediaStorageService::new(pool),
+            }
+        }
+
+        fn transfer(&self) -> InstanceTransfer<'_> {
+            InstanceTransfer {
+                server_storage: &self.server_storage,
+                user_authorization: &self.user_authorization,
+                media_storage: &self.media_storage,
+            }
+        }
+    }
+
+    /// A server with an admin, a user mapped to it with an admin-encrypted password
+    /// and one media mapping.
+    async fn populate(services: &Services, admin_password: &Password) {
+        let admin_hash: HashedPassword = admin_password.into();
+        let server_id = services
+            .server_storage
+            .add_server(
+                "Movies",
+                "http://movies.local:8096",
+                150,
+                MediaStreamingMode::Proxy,
+            )
+            .await
+            .unwrap();
+        let server = services
+            .server_storage
+            .get_server_by_id(server_id)
+            .await
+            .unwrap()
+            .unwrap();
+        services
+            .server_storage
+            .add_server_admin(
+                server_id,
+                "root",
+                &encrypt_password(&"root-secret".into(), &admin_hash).unwrap(),
+            )
+            .await
+            .unwrap();
+        let user = services
+            .user_authorization
+            .create_user("alice", &"alice-password".into())
+            .await
+            .unwrap();
+        services
+            .user_authorization
+            .add_server_mapping(
+                &user.id,
+                &server,
+                "alice-movies",
+                &"mapped-secret".into(),
+                Some(&admin_hash),
+            )
+            .await
+            .unwrap();
+        services
+            .media_storage
+            .get_or_create_media_mapping("11111111111111111111111111111111", &server)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn export_then_import_into_a_fresh_database_yields_the_same_rows() {
+        let admin_password: Password = "admin".into();
+        let admin_hash: HashedPassword = (&admin_password).into();
+        let source = Services::new().await;
+        populate(&source, &admin_password).await;
+
+        let export = source.transfer().export(Some("passphrase")).await.unwrap();
+        assert_eq!(export.secrets, SecretsMode::Sealed);
+        // The document goes through JSON on its way to the other instance.
+        let export: InstanceExport =
+            serde_json::from_str(&serde_json::to_string(&export).unwrap()).unwrap();
+
+        let target = Services::new().await;
+        let summary = target
+            .transfer()
+            .import(&export, Some("passphrase"))
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                servers: 1,
+                server_admins: 1,
+                users: 1,
+                server_mappings: 1,
+                media_mappings: 1,
+                skipped: 0,
+                skipped_users: Vec::new(),
+            }
+        );
+
+        let source_servers = source.server_storage.list_servers().await.unwrap();
+        let target_servers = target.server_storage.list_servers().await.unwrap();
+        assert_eq!(target_servers.len(), 1);
+        let (source_server, target_server) = (&source_servers[0], &target_servers[0]);
+        assert_eq!(target_server.name, source_server.name);
+        assert_eq!(target_server.url, source_server.url);
+        assert_eq!(target_server.priority, source_server.priority);
+        assert_eq!(
+            target_server.media_streaming_mode,
+            source_server.media_streaming_mode
+        );
+        let admin = target
+            .server_storage
+            .get_server_admin(target_server.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(admin.username, "root");
+        assert_eq!(
+            decrypt_password(&admin.password, &admin_hash)
+                .unwrap()
+                .as_str(),
+            "root-secret"
+        );
+
+        let source_user = &source.user_authorization.list_users().await.unwrap()[0];
+        let target_users = target.user_authorization.list_users().await.unwrap();
+        assert_eq!(target_users.len(), 1);
+        let target_user = &target_users[0];
+        assert_eq!(target_user.id, source_user.id);
+        assert_eq!(target_user.virtual_key, source_user.virtual_key);
+        assert_eq!(target_user.original_username, source_user.original_username);
+        assert!(target_user.original_password_hash.verify("alice-password"));
+
+        let mappings = target
+            .user_authorization
+            .list_server_mappings(&target_user.id)
+            .await
+            .unwrap();
+        assert_eq!(mappings.len(), 1);
+        assert_eq!(mappings[0].server_id, target_server.id);
+        assert_eq!(mappings[0].mapped_username, "alice-movies");
+        assert_eq!(
+            target
+                .user_authorization
+                .decrypt_server_mapping_password(
+                    &mappings[0],
+                    &target_user.original_password_hash,
+                    &admin_hash,
+                    None,
+                    None,
+                )
+                .as_str(),
+            "mapped-secret"
+        );
+
+        let source_media = source.media_storage.list_media_mappings().await.unwrap();
+        let target_media = target.media_storage.list_media_mappings().await.unwrap();
+        assert_eq!(target_media.len(), 1);
+        assert_eq!(
+            target_media[0].virtual_media_id,
+            source_media[0].virtual_media_id
+        );
+        assert_eq!(
+            target_media[0].original_media_id,
+            source_media[0].original_media_id
+        );
+        assert_eq!(target_media[0].server_id, target_server.id);
+    }
+
+    #[tokio::test]
+    async fn exports_without_a_passphrase_leave_out_secrets() {
+        let source = Services::new().await;
+        populate(&source, &"admin".into()).await;
+
+        let export = source.transfer().export(None).await.unwrap();
+        assert_eq!(export.secrets, SecretsMode::Redacted);
+        assert!(export.users.iter().all(|user| user.password_hash.is_none()));
+        assert!(export.users.iter().all(|user| user.virtual_key.is_none()));
+        let document = serde_json::to_string(&export).unwrap();
+        let virtual_key = &source.user_authorization.list_users().await.unwrap()[0].virtual_key;
+        assert!(!document.contains(virtual_key.as_str()));
+        assert!(export
+            .server_mappings
+            .iter()
+            .all(|mapping| mapping.mapped_password.is_none()));
+        assert!(export
+            .servers
+            .iter()
+            .filter_map(|server| server.admin.as_ref())
+            .all(|admin| admin.password.is_none()));
+
+        // Without secrets, only entries that don't need one are created.
+        let target = Services::new().await;
+        let summary = target.transfer().import(&export, None).await.unwrap();
+        assert_eq!(summary.servers, 1);
+        assert_eq!(summary.media_mappings, 1);
+        assert_eq!(summary.users, 0);
+        assert_eq!(summary.skipped, 3);
+        assert_eq!(summary.skipped_users, ["alice"]);
+        assert!(target
+            .user_authorization
+            .list_users()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn sealed_exports_need_the_right_passphrase() {
+        let source = Services::new().await;
+        populate(&source, &"admin".into()).await;
+        let export = source.transfer().export(Some("passphrase")).await.unwrap();
+
+        let target = Services::new().await;
+        assert!(matches!(
+            target.transfer().import(&export, None).await,
+            Err(TransferError::MissingPassphrase)
+        ));
+        assert!(matches!(
+            target.transfer().import(&export, Some("wrong")).await,
+            Err(TransferError::WrongPassphrase)
+        ));
+        // A failed import leaves nothing behind.
+        assert!(target
+            .server_storage
+            .list_servers()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -11,14 +11,17 @@ use axum_messages::MessagesManagerLayer;
 use percent_encoding::percent_decode_str;
 use rust_embed::RustEmbed;
 use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
-use std::{net::SocketAddr, str::FromStr};
-use std::{sync::Arc, time::Duration};
+use std::str::FromStr;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::task::AbortHandle;
 use tower::ServiceBuilder;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use tower_http::trace::TraceLayer;
 use tower_sessions::cookie::Key;
 use tower_sessions_sqlx_store::SqliteStore;
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, error, field, info, info_span, trace, warn, Instrument, Span};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 use axum_login::{
@@ -27,31 +30,50 @@ use axum_login::{
 };
 
 mod config;
+mod cors;
+mod database_backup;
 mod duplicate_policy;
 mod encryption;
+mod error_response;
 mod extractors;
 mod federated_users;
 mod handlers;
+mod http_util;
+mod instance_transfer;
 mod legacy_server_identity;
 mod media_storage_service;
+mod metrics;
 mod models;
+mod mutation_dedup;
+mod passthrough;
 mod processors;
 mod proxy_headers;
+mod rate_limit;
 mod request_preprocessing;
+mod self_check;
 mod server_id;
 mod server_storage;
 mod server_url;
+mod session_refresh;
 mod session_storage;
+#[cfg(test)]
+pub(crate) mod test_support;
 mod ui;
+mod upstream_limit;
 mod url_helper;
 mod user_authorization_service;
 mod virtual_library_service;
 
+use duplicate_policy::{DuplicatePolicy, TitleNormalizer};
 use federated_users::FederatedUserService;
 use handlers::syncplay::SyncPlayService;
 use legacy_server_identity::canonicalize_legacy_server_identity;
 use media_storage_service::MediaStorageService;
+use metrics::ProxyMetrics;
+use mutation_dedup::MutationDeduplicator;
+use rate_limit::ClientRateLimiter;
 use server_storage::{Server, ServerStorageService};
+use upstream_limit::UpstreamLimiter;
 use user_authorization_service::UserAuthorizationService;
 use virtual_library_service::VirtualLibraryService;
 
@@ -72,7 +94,11 @@ use crate::{
     ui::Backend,
 };
 use crate::{
-    config::{MediaStreamingMode, DATA_DIR},
+    config::{
+        DeduplicationStrategy, JsonRewriteMode, MediaStreamingMode, PinnedLibrary,
+        QuickConnectMode, ServerNameSuffixMode, ShowChildOrder, UnauthenticatedAuditMode,
+        UnmappedMediaIdMode, WatchStateMirror, DATA_DIR,
+    },
     encryption::Password,
     request_preprocessing::preprocess_request,
     session_storage::SessionStorage,
@@ -94,6 +120,11 @@ pub struct AppState {
     pub quick_connect: QuickConnectStorage,
     pub federated_users: Arc<FederatedUserService>,
     pub syncplay: Arc<SyncPlayService>,
+    pub metrics: Arc<ProxyMetrics>,
+    pub rate_limiter: Arc<ClientRateLimiter>,
+    pub mutation_dedup: Arc<MutationDeduplicator>,
+    pub upstream_limiter: Arc<UpstreamLimiter>,
+    pub cors: Arc<cors::CorsCache>,
 }
 
 impl AppState {
@@ -126,6 +157,11 @@ impl AppState {
             quick_connect,
             federated_users,
             syncplay: Arc::new(SyncPlayService::new()),
+            metrics: Arc::new(ProxyMetrics::new()),
+            rate_limiter: Arc::new(ClientRateLimiter::new()),
+            mutation_dedup: Arc::new(MutationDeduplicator::new()),
+            upstream_limiter: Arc::new(UpstreamLimiter::new()),
+            cors: Arc::new(cors::CorsCache::default()),
         }
     }
 
@@ -148,9 +184,12 @@ impl AppState {
         config.password.clone()
     }
 
+    pub async fn server_name_suffix_mode(&self) -> ServerNameSuffixMode {
+        self.config.read().await.include_server_name_in_media
+    }
+
     pub async fn can_change_item_names(&self) -> bool {
-        let config = self.config.read().await;
-        config.include_server_name_in_media
+        self.server_name_suffix_mode().await != ServerNameSuffixMode::Never
     }
 
     pub async fn remove_prefix_from_path<'a>(&self, path: &'a str) -> &'a str {
@@ -172,10 +211,175 @@ impl AppState {
         config.auto_create_users_on_login
     }
 
+    pub async fn enrich_user_me_enabled(&self) -> bool {
+        self.config.read().await.enrich_user_me
+    }
+
     pub async fn merge_libraries_enabled(&self) -> bool {
         self.config.read().await.merge_libraries
     }
 
+    pub async fn merge_box_sets_enabled(&self) -> bool {
+        self.config.read().await.merge_box_sets
+    }
+
+    pub async fn library_deduplication_strategy(&self) -> DeduplicationStrategy {
+        self.config.read().await.merge_library_versions
+    }
+
+    pub async fn default_dedup_strategy(&self) -> DeduplicationStrategy {
+        self.config.read().await.default_dedup_strategy
+    }
+
+    pub async fn refresh_all_copies_enabled(&self) -> bool {
+        self.config.read().await.refresh_all_copies
+    }
+
+    pub async fn rate_all_copies_enabled(&self) -> bool {
+        self.config.read().await.rate_all_copies
+    }
+
+    pub async fn mark_all_copies_enabled(&self) -> bool {
+        self.config.read().await.mark_all_copies
+    }
+
+    pub async fn report_backend_version_enabled(&self) -> bool {
+        self.config.read().await.report_backend_version
+    }
+
+    pub async fn upstream_error_details_enabled(&self) -> bool {
+        self.config.read().await.upstream_error_details
+    }
+
+    pub async fn sanitize_buffered_ranges_enabled(&self) -> bool {
+        self.config.read().await.sanitize_buffered_ranges
+    }
+
+    pub async fn box_set_duplicate_policy(&self) -> DuplicatePolicy {
+        self.config.read().await.box_set_duplicate_policy
+    }
+
+    pub async fn title_normalizer(&self) -> TitleNormalizer {
+        TitleNormalizer::new(self.config.read().await.title_articles.values().flatten())
+    }
+
+    pub async fn pinned_libraries(&self) -> Vec<PinnedLibrary> {
+        self.config.read().await.pinned_libraries.clone()
+    }
+
+    pub async fn watch_state_mirrors(&self) -> Vec<WatchStateMirror> {
+        self.config.read().await.watch_state_mirrors.clone()
+    }
+
+    pub async fn per_user_max_bitrate(&self) -> Option<i64> {
+        match self.config.read().await.per_user_max_bitrate {
+            0 => None,
+            cap => Some(i64::try_from(cap).unwrap_or(i64::MAX)),
+        }
+    }
+
+    /// Time allowed for an API request to a server. Read for every request, so a
+    /// reloaded `timeout` applies right away.
+    pub async fn upstream_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.read().await.timeout)
+    }
+
+    pub async fn trust_forwarded_headers(&self) -> bool {
+        self.config.read().await.trust_forwarded_headers
+    }
+
+    pub async fn upstream_retries(&self) -> u32 {
+        self.config.read().await.upstream_retries
+    }
+
+    pub async fn show_child_order(&self) -> ShowChildOrder {
+        self.config.read().await.show_child_order
+    }
+
+    pub async fn json_full_parse_limit(&self) -> u64 {
+        self.config.read().await.json_full_parse_limit
+    }
+
+    pub async fn json_rewrite_mode(&self) -> JsonRewriteMode {
+        self.config.read().await.json_rewrite_mode
+    }
+
+    pub async fn tag_source_server(&self) -> bool {
+        self.config.read().await.tag_source_server
+    }
+
+    pub async fn proxy_unknown_paths_enabled(&self) -> bool {
+        self.config.read().await.proxy_unknown_paths
+    }
+
+    pub async fn quick_connect_mode(&self) -> QuickConnectMode {
+        self.config.read().await.quick_connect_mode
+    }
+
+    pub async fn client_rate_limit_per_second(&self) -> f64 {
+        self.config.read().await.client_rate_limit_per_second
+    }
+
+    pub async fn client_rate_limit_burst(&self) -> u32 {
+        self.config.read().await.client_rate_limit_burst
+    }
+
+    pub async fn dedup_mutations_window_ms(&self) -> u64 {
+        self.config.read().await.dedup_mutations_window_ms
+    }
+
+    pub async fn max_connections_per_server(&self) -> u32 {
+        self.config.read().await.max_connections_per_server
+    }
+
+    pub async fn upstream_queue_timeout_ms(&self) -> u64 {
+        self.config.read().await.upstream_queue_timeout_ms
+    }
+
+    /// Wait for a free connection slot on `server`, answering `503` when none became
+    /// free within `upstream_queue_timeout_ms`. Hold the permit until the upstream
+    /// response has been read.
+    pub async fn acquire_upstream_slot(
+        &self,
+        server: &Server,
+    ) -> Result<Option<tokio::sync::OwnedSemaphorePermit>, StatusCode> {
+        let limit = match server.max_connections {
+            Some(limit) => limit,
+            None => self.max_connections_per_server().await,
+        };
+        let timeout = Duration::from_millis(self.upstream_queue_timeout_ms().await);
+        self.upstream_limiter
+            .acquire(server.id, limit, timeout)
+            .await
+            .map_err(|()| {
+                warn!(
+                    "No free connection to server {} within {:?}",
+                    server.name, timeout
+                );
+                StatusCode::SERVICE_UNAVAILABLE
+            })
+    }
+
+    pub async fn id_remapping_disabled(&self) -> bool {
+        self.config.read().await.disable_id_remapping
+    }
+
+    pub async fn federation_status_exposed(&self) -> bool {
+        self.config.read().await.expose_federation_status
+    }
+
+    pub async fn unmapped_media_id_mode(&self) -> UnmappedMediaIdMode {
+        self.config.read().await.unmapped_media_id
+    }
+
+    pub async fn audit_unauthenticated_mode(&self) -> UnauthenticatedAuditMode {
+        self.config.read().await.audit_unauthenticated
+    }
+
+    pub async fn legacy_emby_auth_enabled(&self) -> bool {
+        self.config.read().await.enable_legacy_emby_auth
+    }
+
     pub async fn process_response_json(
         &self,
         payload: &mut serde_json::Value,
@@ -184,18 +388,56 @@ impl AppState {
         should_change_name: bool,
         proxy_api_key: Option<&str>,
     ) -> Result<bool, StatusCode> {
-        let context = ResponseProcessingContext {
+        let context = self
+            .response_context(server, profile, should_change_name, proxy_api_key)
+            .await;
+
+        self.processors
+            .process_response_json(payload, &context)
+            .await
+    }
+
+    /// Lightweight counterpart of [`Self::process_response_json`] for bodies above
+    /// `json_full_parse_limit` or in [`JsonRewriteMode::Streaming`]: only known id
+    /// fields are rewritten, in place.
+    pub async fn rewrite_response_fields(
+        &self,
+        body: &[u8],
+        server: &Server,
+        profile: ResponseProcessingProfile,
+        proxy_api_key: Option<&str>,
+    ) -> Result<Option<Vec<u8>>, StatusCode> {
+        let context = self
+            .response_context(server, profile, false, proxy_api_key)
+            .await;
+
+        self.processors
+            .response_processor
+            .rewrite_known_fields(body, &context)
+            .await
+            .map_err(|e| {
+                error!("Failed to rewrite response fields: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })
+    }
+
+    async fn response_context(
+        &self,
+        server: &Server,
+        profile: ResponseProcessingProfile,
+        should_change_name: bool,
+        proxy_api_key: Option<&str>,
+    ) -> ResponseProcessingContext {
+        ResponseProcessingContext {
             server: server.clone(),
             proxy_server_id: self.config.read().await.server_id.clone(),
             proxy_api_key: proxy_api_key.map(str::to_string),
             profile,
             should_change_name,
             can_change_item_names: self.can_change_item_names().await,
-        };
-
-        self.processors
-            .process_response_json(payload, &context)
-            .await
+            tag_source_server: self.tag_source_server().await,
+            public_base_url: proxy_headers::current_public_base_url(),
+        }
     }
 }
 
@@ -257,6 +499,14 @@ impl ProxyProcessors {
         payload: &mut serde_json::Value,
         context: &ResponseProcessingContext,
     ) -> Result<bool, StatusCode> {
+        if let Err(e) = self
+            .response_processor
+            .prefetch_media_mappings(payload, context)
+            .await
+        {
+            warn!("Failed to create media mappings in bulk: {}", e);
+        }
+
         let processed = processors::process_json(payload, &self.response_processor, context)
             .await
             .map_err(|e| {
@@ -272,6 +522,55 @@ impl ProxyProcessors {
 #[folder = "static/"]
 struct Asset;
 
+/// Connection pool, transport and TLS settings shared by both upstream clients.
+fn upstream_client_builder(
+    config: &AppConfig,
+    ca_certificates: &[reqwest::Certificate],
+) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder()
+        .pool_idle_timeout(non_zero_secs(config.pool_idle_timeout_secs))
+        .tcp_keepalive(non_zero_secs(config.tcp_keepalive_secs));
+    if config.pool_max_idle_per_host > 0 {
+        builder = builder.pool_max_idle_per_host(
+            usize::try_from(config.pool_max_idle_per_host).unwrap_or(usize::MAX),
+        );
+    }
+    if let Some(connect_timeout) = non_zero_secs(config.connect_timeout_secs) {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if config.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    for certificate in ca_certificates {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    if config.allow_invalid_upstream_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder
+}
+
+/// Load the extra CA certificates configured in `upstream_ca_bundle`.
+fn load_upstream_ca_bundle(config: &AppConfig) -> Result<Vec<reqwest::Certificate>, String> {
+    if config.upstream_ca_bundle.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let path = DATA_DIR.join(&config.upstream_ca_bundle);
+    let pem =
+        std::fs::read(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+        .map_err(|e| format!("invalid certificates in {}: {}", path.display(), e))?;
+    if certificates.is_empty() {
+        return Err(format!("no certificates found in {}", path.display()));
+    }
+    Ok(certificates)
+}
+
+fn non_zero_secs(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize file logging
@@ -301,6 +600,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let loaded_config = crate::config::load_config();
     info!("Loaded configuration: {:?}", loaded_config);
+    if let Err(errors) = loaded_config.validate() {
+        for e in errors {
+            error!("Invalid configuration: {}", e);
+        }
+        std::process::exit(1);
+    }
 
     // Resolve database path inside DATA_DIR
     let db_path = DATA_DIR.join("jellyswarrm.db");
@@ -339,8 +644,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         std::process::exit(1);
     });
 
-    // Create reqwest client for regular API traffic.
-    let reqwest_client = reqwest::Client::builder()
+    let ca_certificates = load_upstream_ca_bundle(&loaded_config).unwrap_or_else(|e| {
+        error!("Failed to load upstream CA bundle: {}", e);
+        std::process::exit(1);
+    });
+    if !ca_certificates.is_empty() {
+        info!(
+            "Trusting {} additional CA certificates for upstream servers",
+            ca_certificates.len()
+        );
+    }
+    if loaded_config.disable_id_remapping {
+        warn!("!!! disable_id_remapping is enabled: every request is passed through unchanged to the highest-priority server and ids are NOT remapped. Clients must log in with that server's own accounts. Use this for troubleshooting only. !!!");
+    }
+    if loaded_config.allow_invalid_upstream_certs {
+        warn!("!!! allow_invalid_upstream_certs is enabled: TLS certificates of upstream servers are NOT verified, so connections to them can be intercepted. Prefer upstream_ca_bundle for self-signed certificates. !!!");
+    }
+
+    // Create reqwest client for regular API traffic. Proxied requests carry the live
+    // `timeout` themselves; the client's own only covers requests made without one.
+    let reqwest_client = upstream_client_builder(&loaded_config, &ca_certificates)
         .timeout(Duration::from_secs(loaded_config.timeout))
         .build()
         .unwrap_or_else(|e| {
@@ -351,8 +674,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Create a dedicated client for proxied media streams.
     // Avoid a global request timeout on long-lived responses and disable automatic
     // response decompression so we forward bytes as-is with less CPU overhead.
-    let streaming_reqwest_client = reqwest::Client::builder()
-        .connect_timeout(Duration::from_secs(loaded_config.timeout))
+    let streaming_connect_timeout = non_zero_secs(loaded_config.connect_timeout_secs)
+        .unwrap_or(Duration::from_secs(loaded_config.timeout));
+    let streaming_reqwest_client = upstream_client_builder(&loaded_config, &ca_certificates)
+        .connect_timeout(streaming_connect_timeout)
         .tcp_nodelay(true)
         .no_gzip()
         .no_brotli()
@@ -364,14 +689,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
 
     // Initialize user authorization service
-    let user_authorization = UserAuthorizationService::new(pool.clone());
+    let user_authorization = UserAuthorizationService::new(pool.clone()).with_session_lifetime(
+        (loaded_config.session_lifetime_secs > 0)
+            .then(|| chrono::Duration::seconds(loaded_config.session_lifetime_secs as i64)),
+    );
 
     // Initialize server storage service
     let server_storage = ServerStorageService::new(pool.clone());
     server_storage.start_health_check_loop(loaded_config.server_background_check_interval_secs);
 
     // Initialize media storage service
-    let media_storage = MediaStorageService::new(pool.clone());
+    let media_storage = MediaStorageService::with_cache(
+        pool.clone(),
+        loaded_config.media_mapping_cache_capacity,
+        Duration::from_secs(loaded_config.media_mapping_cache_ttl_secs),
+    );
 
     let virtual_library_service =
         VirtualLibraryService::new(pool.clone(), server_storage.clone(), media_storage.clone());
@@ -409,16 +741,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     match server_storage.list_servers().await {
         Ok(servers) => {
-            if servers.is_empty() {
-                warn!("No servers found, configure them via the UI.");
-            } else {
-                info!("Found {} configured servers", servers.len());
-                for server in &servers {
-                    info!(
-                        "  {} ({}): priority {}",
-                        server.name, server.url, server.priority,
-                    );
-                }
+            info!("Found {} configured servers", servers.len());
+            for server in &servers {
+                info!(
+                    "  {} ({}): priority {}",
+                    server.name, server.url, server.priority,
+                );
             }
         }
         Err(e) => {
@@ -426,14 +754,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    if loaded_config.startup_self_check {
+        self_check::run(&loaded_config, &server_storage).await;
+    }
+
     let data_context = DataContext {
         user_authorization: Arc::new(user_authorization.clone()),
         server_storage: Arc::new(server_storage.clone()),
         media_storage: Arc::new(media_storage.clone()),
         virtual_library_service: Arc::new(virtual_library_service),
-        play_sessions: Arc::new(SessionStorage::new()),
+        play_sessions: Arc::new(SessionStorage::with_limits(
+            Duration::from_secs(loaded_config.play_session_ttl_secs),
+            loaded_config.play_session_capacity as usize,
+        )),
         config: Arc::new(tokio::sync::RwLock::new(loaded_config.clone())),
     };
+    data_context.play_sessions.start_reaper_loop();
 
     let proxy_processors = ProxyProcessors::new(data_context.clone());
 
@@ -446,6 +782,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     quick_connect::QuickConnectStorage::start_cleanup_task(app_state.quick_connect.clone());
+    session_refresh::start_session_refresh_loop(app_state.clone());
 
     let session_store = SqliteStore::new(pool);
     session_store.migrate().await?;
@@ -456,10 +793,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .continuously_delete_expired(tokio::time::Duration::from_secs(60)),
     );
 
+    // The key length was checked when the config was validated.
     let key = Key::from(loaded_config.session_key.as_slice());
 
     let session_layer = SessionManagerLayer::new(session_store)
-        .with_secure(false)
+        .with_secure(loaded_config.secure_cookies)
         .with_same_site(tower_sessions::cookie::SameSite::Lax)
         .with_expiry(Expiry::OnInactivity(time::Duration::days(1))) // 24 hour
         .with_signed(key);
@@ -472,11 +810,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let ui_route = loaded_config.ui_route.to_string();
 
+    let metrics_routes = Router::new();
+    #[cfg(feature = "prometheus")]
+    let metrics_routes = metrics_routes.route("/metrics", get(metrics::prometheus_metrics));
+
     let app = lowercase_routes! {
         Router::new()
             // UI Management routes
             .nest(&format!("/{ui_route}"), ui_routes())
+            .merge(metrics_routes)
             .route("/", get(index_handler))
+            .route("/health", get(handlers::system::health))
             .route(
                 "/QuickConnect/Enabled",
                 get(handlers::quick_connect::handle_quick_connect_enabled),
@@ -564,11 +908,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         get(handlers::federated::get_items_from_all_servers_if_not_restricted),
                     )
                     .route("/{user_id}/Items/{item_id}", get(handlers::items::get_item))
+                    // Legacy playback reports carry their state in query parameters.
+                    .route("/{user_id}/PlayingItems/{item_id}", any(proxy_handler))
+                    .route(
+                        "/{user_id}/PlayingItems/{item_id}/Progress",
+                        any(proxy_handler),
+                    )
+                    .route(
+                        "/{user_id}/PlayedItems/{item_id}",
+                        post(handlers::watch_state::update_played_or_favorite)
+                            .delete(handlers::watch_state::update_played_or_favorite),
+                    )
+                    .route(
+                        "/{user_id}/FavoriteItems/{item_id}",
+                        post(handlers::watch_state::update_played_or_favorite)
+                            .delete(handlers::watch_state::update_played_or_favorite),
+                    )
+                    .route(
+                        "/{user_id}/Items/{item_id}/Rating",
+                        post(handlers::items::update_item_rating)
+                            .delete(handlers::items::update_item_rating),
+                    )
+                    .route(
+                        "/{user_id}/Items/{item_id}/PlaybackInfo",
+                        get(handlers::items::post_playback_info)
+                            .post(handlers::items::post_playback_info),
+                    )
                     .route(
                         "/{user_id}/Items/{item_id}/SpecialFeatures",
                         get(handlers::items::get_items_list),
+                    )
+                    .route(
+                        "/{user_id}/Items/{item_id}/LocalTrailers",
+                        get(handlers::items::get_items_list),
                     ),
             )
+            .route(
+                "/UserPlayedItems/{item_id}",
+                post(handlers::watch_state::update_played_or_favorite)
+                    .delete(handlers::watch_state::update_played_or_favorite),
+            )
+            .route(
+                "/UserFavoriteItems/{item_id}",
+                post(handlers::watch_state::update_played_or_favorite)
+                    .delete(handlers::watch_state::update_played_or_favorite),
+            )
             .route(
                 "/UserViews",
                 get(handlers::federated::get_items_from_all_servers),
@@ -587,7 +971,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "/System",
                 Router::new()
                     .route("/Info", get(handlers::system::info))
-                    .route("/Info/Public", get(handlers::system::info_public)),
+                    .route("/Info/Public", get(handlers::system::info_public))
+                    .route(
+                        "/Ping",
+                        get(handlers::system::ping).post(handlers::system::ping),
+                    )
+                    .route("/Jellyswarrm", get(handlers::system::federation_status)),
             )
             // Item routes (non-user specific)
             .nest(
@@ -607,23 +996,61 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     )
                     .route("/{item_id}", get(handlers::items::get_item))
                     .route("/{item_id}/Similar", get(handlers::items::get_items))
-                    .route("/{item_id}/LocalTrailers", get(handlers::items::get_items))
+                    .route(
+                        "/{item_id}/LocalTrailers",
+                        get(handlers::items::get_items_list),
+                    )
                     .route(
                         "/{item_id}/SpecialFeatures",
-                        get(handlers::items::get_items),
+                        get(handlers::items::get_items_list),
                     )
                     .route(
                         "/{item_id}/PlaybackInfo",
-                        post(handlers::items::post_playback_info),
+                        get(handlers::items::post_playback_info)
+                            .post(handlers::items::post_playback_info),
+                    )
+                    .route("/{item_id}/Refresh", post(handlers::items::refresh_item))
+                    .route(
+                        "/{item_id}/Images/{*image}",
+                        get(handlers::images::get_item_image),
                     ),
             )
             .route("/MediaSegments/{item_id}", get(handlers::items::get_items))
+            .route(
+                "/Sessions/Playing",
+                post(handlers::sessions::post_playback_report),
+            )
+            .route(
+                "/Sessions/Playing/Progress",
+                post(handlers::sessions::post_playback_report),
+            )
+            .route(
+                "/Sessions/Playing/Stopped",
+                post(handlers::sessions::post_playback_report),
+            )
+            // Collections can't span servers; creation and membership changes are
+            // validated to stay on the backend that owns the items.
+            .route(
+                "/Collections",
+                post(handlers::collections::create_collection),
+            )
+            .route(
+                "/Collections/{collection_id}/Items",
+                post(handlers::collections::add_collection_items)
+                    .delete(handlers::collections::remove_collection_items),
+            )
             // Show-specific routes
             .nest(
                 "/Shows",
                 Router::new()
-                    .route("/{item_id}/Seasons", get(handlers::items::get_items))
-                    .route("/{item_id}/Episodes", get(handlers::items::get_items))
+                    .route(
+                        "/{item_id}/Seasons",
+                        get(handlers::items::get_show_children),
+                    )
+                    .route(
+                        "/{item_id}/Episodes",
+                        get(handlers::items::get_show_children),
+                    )
                     .route(
                         "/NextUp",
                         get(handlers::federated::get_items_from_all_servers_if_not_restricted),
@@ -673,6 +1100,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .route("/{item_id}/stream.mkv", get(handlers::videos::get_stream))
                     .route("/{item_id}/stream.mp4", get(handlers::videos::get_stream))
                     .route("/{item_id}/stream.mov", get(handlers::videos::get_stream))
+                    .route(
+                        "/{item_id}/{media_source_id}/Subtitles/{index}/Stream.{format}",
+                        get(handlers::videos::get_subtitle_stream),
+                    )
+                    .route(
+                        "/{item_id}/{media_source_id}/Subtitles/{index}/{start_position_ticks}/Stream.{format}",
+                        get(handlers::videos::get_subtitle_stream),
+                    )
                     .route(
                         "/{stream_id}/{*path}",
                         get(handlers::videos::get_video_resource),
@@ -696,12 +1131,58 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "/Artists",
                 Router::new().route("/", get(handlers::federated::get_items_from_all_servers)),
             )
-            .route("/{*path}", any(proxy_handler))
-            .fallback(proxy_handler)
+            // Genres and studios
+            .nest(
+                "/Genres",
+                Router::new()
+                    .route(
+                        "/",
+                        get(handlers::federated::get_named_items_from_all_servers),
+                    )
+                    .route("/{name}", get(handlers::federated::get_named_item)),
+            )
+            .nest(
+                "/Studios",
+                Router::new()
+                    .route(
+                        "/",
+                        get(handlers::federated::get_named_items_from_all_servers),
+                    )
+                    .route("/{name}", get(handlers::federated::get_named_item)),
+            )
+            .route("/{*path}", any(unknown_path_handler))
+            .fallback(unknown_path_handler)
+            .layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                passthrough::passthrough_without_remapping,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                rate_limit::limit_client_requests,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                mutation_dedup::dedup_mutations,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                request_preprocessing::audit_unauthenticated,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                error_response::jellyfin_error_bodies,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                proxy_headers::resolve_public_base_url,
+            ))
             .layer(
                 ServiceBuilder::new()
                     .layer(TraceLayer::new_for_http())
-                    .layer(CorsLayer::permissive()),
+                    .layer(axum::middleware::from_fn_with_state(
+                        app_state.clone(),
+                        cors::reloadable_cors,
+                    )),
             )
             .layer(MessagesManagerLayer)
             .layer(auth_layer)
@@ -710,14 +1191,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     .route("/GetUTCTime", get(handlers::syncplay::get_utc_time));
 
     // Create socket address
-    let addr = match format!("{}:{}", loaded_config.host, loaded_config.port).parse::<SocketAddr>()
-    {
+    let addr = match loaded_config.bind_address() {
         Ok(addr) => addr,
         Err(e) => {
-            error!(
-                "Invalid address {}:{}: {}",
-                loaded_config.host, loaded_config.port, e
-            );
+            error!("Invalid configuration: {}", e);
             std::process::exit(1);
         }
     };
@@ -745,15 +1222,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     move |req: Request| {
                         let prefix = prefix.clone();
                         async move {
-                            let orig = req.uri().path().trim_end_matches("/");
-                            let prefix_slash = format!("/{}", prefix);
-                            let target = if orig.starts_with(&prefix_slash) {
-                                // already has prefix - avoid double-appending
-                                orig
-                            } else {
-                                &format!("{prefix_slash}{orig}")
-                            };
-                            axum::response::Redirect::temporary(target).into_response()
+                            axum::response::Redirect::temporary(&prefixed_redirect_target(
+                                &prefix,
+                                req.uri(),
+                            ))
+                            .into_response()
                         }
                     }
                 },
@@ -785,6 +1258,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Where a request outside the `url_prefix` subtree is redirected: the same path
+/// and query under the prefix, e.g. `/Items?ParentId=x` -> `/{prefix}/Items?ParentId=x`.
+fn prefixed_redirect_target(prefix: &str, uri: &axum::http::Uri) -> String {
+    let path = uri.path().trim_end_matches('/');
+    let prefix_slash = format!("/{prefix}");
+    let mut target = if path.starts_with(&prefix_slash) {
+        // already has prefix - avoid double-appending
+        path.to_string()
+    } else {
+        format!("{prefix_slash}{path}")
+    };
+    if let Some(query) = uri.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    target
+}
+
 async fn index_handler(
     State(state): State<AppState>,
     _req: Request,
@@ -822,14 +1313,28 @@ async fn index_handler(
     }
 }
 
-#[axum::debug_handler]
-async fn proxy_handler(
+/// Handles every path without a dedicated route. Static assets are always served;
+/// anything else is only forwarded to a backend when `proxy_unknown_paths` is enabled.
+async fn unknown_path_handler(
     State(state): State<AppState>,
     req: Request,
 ) -> Result<Response<Body>, StatusCode> {
-    // check if a resource was requested
-    let path = req.uri().path();
-    debug!("Using generic processing for path: {}", path);
+    if let Some(response) = static_asset_response(req.uri().path()) {
+        return response;
+    }
+
+    if !state.proxy_unknown_paths_enabled().await {
+        debug!(
+            "Rejecting request for unrouted path {} (proxy_unknown_paths disabled)",
+            req.uri().path()
+        );
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(proxy_handler(State(state), req).await)
+}
+
+fn static_asset_response(path: &str) -> Option<Result<Response<Body>, StatusCode>> {
     let path = if let Some(path) = path.strip_prefix('/') {
         path
     } else {
@@ -837,23 +1342,70 @@ async fn proxy_handler(
     };
     let path = if path.is_empty() { "index.html" } else { path };
     let decoded_path = percent_decode_str(path).decode_utf8_lossy().to_string();
-    if let Some(content) = Asset::get(&decoded_path) {
-        let mime = mime_guess::from_path(decoded_path).first_or_octet_stream();
-        return Response::builder()
+    let content = Asset::get(&decoded_path)?;
+    let mime = mime_guess::from_path(decoded_path).first_or_octet_stream();
+    Some(
+        Response::builder()
             .header("Content-Type", mime.as_ref())
             .body(Body::from(content.data.into_owned()))
             .map_err(|e| {
                 error!("Failed to build static asset response: {}", e);
                 StatusCode::INTERNAL_SERVER_ERROR
-            });
+            }),
+    )
+}
+
+/// Response header carrying the id of a proxied request, so log lines can be
+/// matched to a bug report.
+const REQUEST_ID_HEADER: &str = "x-jellyswarrm-request-id";
+
+#[axum::debug_handler]
+async fn proxy_handler(State(state): State<AppState>, req: Request) -> Response<Body> {
+    let request_id = uuid::Uuid::new_v4().simple().to_string();
+    let span = info_span!(
+        "proxy_request",
+        request_id = %request_id,
+        server = field::Empty,
+        client = field::Empty,
+        device = field::Empty,
+        target = field::Empty,
+    );
+
+    let mut response = forward_request(state, req)
+        .instrument(span)
+        .await
+        .into_response();
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+async fn forward_request(state: AppState, req: Request) -> Result<Response<Body>, StatusCode> {
+    // check if a resource was requested
+    debug!("Using generic processing for path: {}", req.uri().path());
+    if let Some(response) = static_asset_response(req.uri().path()) {
+        return response;
     }
 
     let preprocessed = preprocess_request(req, &state).await.map_err(|e| {
         error!("Failed to preprocess request: {}", e);
-        StatusCode::BAD_REQUEST
+        request_preprocessing::preprocess_error_status(&e)
     })?;
+    handlers::common::update_stream_activity(&state, &preprocessed.original_request).await;
 
     let request_url = preprocessed.request.url().clone();
+    let span = Span::current();
+    span.record("server", preprocessed.server.name.as_str());
+    span.record("target", request_url.as_str());
+    if let Some(device) = preprocessed
+        .auth
+        .as_ref()
+        .and_then(|auth| auth.get_device(preprocessed.original_request.headers()))
+    {
+        span.record("client", device.client.as_str());
+        span.record("device", device.device.as_str());
+    }
     let response_server = preprocessed.server.clone();
     let response_proxy_api_key = preprocessed
         .user
@@ -866,13 +1418,33 @@ async fn proxy_handler(
         preprocessed.request
     );
 
+    // A HEAD response carries the headers of the GET response but never a body, so
+    // the upstream body (if a backend sends one anyway) is neither read nor rewritten.
+    let is_head = preprocessed.original_request.method() == reqwest::Method::HEAD;
+    let is_stream = rate_limit::is_streaming_path(preprocessed.original_request.url().path());
     let request_processing_context = RequestProcessingContext::new(&preprocessed);
     let mut request = preprocessed.request;
     state
         .processors
         .process_request_body(&mut request, &request_processing_context, &request_url)
         .await?;
-    let response = state.reqwest_client.execute(request).await.map_err(|e| {
+    let upstream_slot = if is_stream {
+        None
+    } else {
+        state.acquire_upstream_slot(&response_server).await?
+    };
+    let upstream_retries = state.upstream_retries().await;
+    let started = Instant::now();
+    let response =
+        http_util::execute_with_retry(&state.reqwest_client, request, upstream_retries).await;
+    let response_status = response
+        .as_ref()
+        .ok()
+        .map(|response| response.status().as_u16());
+    state
+        .metrics
+        .record(&response_server.name, started.elapsed(), response_status);
+    let response = response.map_err(|e| {
         error!("Failed to execute proxy request: {}", e);
         StatusCode::BAD_GATEWAY
     })?;
@@ -885,12 +1457,35 @@ async fn proxy_handler(
         );
     }
     let mut headers = response.headers().clone();
-    let mut body_bytes = response.bytes().await.map_err(|e| {
-        error!("Failed to read response body: {}", e);
-        StatusCode::BAD_GATEWAY
-    })?;
-
-    if is_json_response(&headers) && !body_bytes.is_empty() {
+    let mut body_bytes = if is_head {
+        axum::body::Bytes::new()
+    } else {
+        response.bytes().await.map_err(|e| {
+            error!("Failed to read response body: {}", e);
+            StatusCode::BAD_GATEWAY
+        })?
+    };
+    drop(upstream_slot);
+
+    let full_parse_limit = state.json_full_parse_limit().await;
+    let streaming = state.json_rewrite_mode().await == JsonRewriteMode::Streaming;
+    let processed_body = if is_head || !is_json_response(&headers) || body_bytes.is_empty() {
+        None
+    } else if streaming || (full_parse_limit > 0 && body_bytes.len() as u64 > full_parse_limit) {
+        debug!(
+            "Rewriting {} byte response from {} without a full parse",
+            body_bytes.len(),
+            request_url
+        );
+        state
+            .rewrite_response_fields(
+                &body_bytes,
+                &response_server,
+                ResponseProcessingProfile::BestEffortMedia,
+                response_proxy_api_key.as_deref(),
+            )
+            .await?
+    } else {
         match serde_json::from_slice::<serde_json::Value>(&body_bytes) {
             Ok(mut json_value) => {
                 let was_modified = state
@@ -904,22 +1499,12 @@ async fn proxy_handler(
                     .await?;
 
                 if was_modified {
-                    let processed_body = serde_json::to_vec(&json_value).map_err(|e| {
+                    Some(serde_json::to_vec(&json_value).map_err(|e| {
                         error!("Failed to serialize processed response JSON: {}", e);
                         StatusCode::INTERNAL_SERVER_ERROR
-                    })?;
-
-                    debug!("Modified JSON response body for request to {}", request_url);
-                    headers.remove(header::CONTENT_LENGTH);
-                    headers.remove(header::TRANSFER_ENCODING);
-                    headers.insert(
-                        header::CONTENT_LENGTH,
-                        HeaderValue::from_str(&processed_body.len().to_string()).map_err(|e| {
-                            error!("Failed to build response Content-Length header: {}", e);
-                            StatusCode::INTERNAL_SERVER_ERROR
-                        })?,
-                    );
-                    body_bytes = processed_body.into();
+                    })?)
+                } else {
+                    None
                 }
             }
             Err(e) => {
@@ -927,8 +1512,33 @@ async fn proxy_handler(
                     "Skipping JSON response processing for {} because body parsing failed: {}",
                     request_url, e
                 );
+                None
             }
         }
+    };
+
+    if let Some(processed_body) = processed_body {
+        debug!("Modified JSON response body for request to {}", request_url);
+        headers.remove(header::CONTENT_LENGTH);
+        headers.remove(header::TRANSFER_ENCODING);
+        headers.insert(
+            header::CONTENT_LENGTH,
+            HeaderValue::from_str(&processed_body.len().to_string()).map_err(|e| {
+                error!("Failed to build response Content-Length header: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+        );
+        body_bytes = processed_body.into();
+    }
+
+    if (status.is_client_error() || status.is_server_error())
+        && state.upstream_error_details_enabled().await
+    {
+        error_response::insert_upstream_headers(
+            &mut headers,
+            status.as_u16(),
+            &response_server.name,
+        );
     }
 
     let mut response_builder = Response::builder().status(status);
@@ -940,10 +1550,13 @@ async fn proxy_handler(
         }
     }
 
-    let response = response_builder.body(Body::from(body_bytes)).map_err(|e| {
-        error!("Failed to build response: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let response = response_builder
+        .extension(error_response::UpstreamResponse)
+        .body(Body::from(body_bytes))
+        .map_err(|e| {
+            error!("Failed to build response: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
     Ok(response)
 }
@@ -983,3 +1596,656 @@ async fn shutdown_signal(deletion_task_abort_handle: AbortHandle) {
         _ = terminate => { deletion_task_abort_handle.abort() },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::test_support::{add_server_with_session, create_test_app_state_with_config};
+    use crate::{models::Authorization, session_storage::PlaybackSession};
+
+    async fn create_test_app_state(upstream_url: &str, proxy_unknown_paths: bool) -> AppState {
+        let state = create_test_app_state_with_config(AppConfig {
+            proxy_unknown_paths,
+            ..AppConfig::default()
+        })
+        .await;
+        state
+            .server_storage
+            .add_server("Upstream", upstream_url, 100, MediaStreamingMode::Redirect)
+            .await
+            .unwrap();
+        state
+    }
+
+    fn unknown_path_request() -> Request {
+        let uri: axum::http::Uri = "/Some/Unrouted/Endpoint".parse().unwrap();
+        Request::builder()
+            .uri(uri.clone())
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unknown_path_returns_not_found_when_proxying_is_disabled() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), false).await;
+
+        let result = unknown_path_handler(State(state), unknown_path_request()).await;
+
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn unknown_path_is_proxied_when_proxying_is_enabled() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("upstream"))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), true).await;
+
+        let response = unknown_path_handler(State(state), unknown_path_request())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"upstream");
+    }
+
+    #[tokio::test]
+    async fn proxied_responses_carry_a_request_id() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), true).await;
+
+        let first = proxy_handler(State(state.clone()), unknown_path_request()).await;
+        let second = proxy_handler(State(state), unknown_path_request()).await;
+
+        assert_eq!(first.status(), StatusCode::NOT_FOUND);
+        let first_id = first.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        let second_id = second.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert_eq!(first_id.len(), 32);
+        assert_ne!(first_id, second_id);
+    }
+
+    #[tokio::test]
+    async fn server_ids_round_trip_between_the_proxy_and_the_backend() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/System/Info/Public"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ServerName": "Upstream",
+                "Version": "10.10.7",
+                "Id": "upstream-server-id"
+            })))
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Name": "viewer",
+                "ServerId": "upstream-server-id",
+                "SessionInfo": { "ServerId": "upstream-server-id" }
+            })))
+            .mount(&upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .and(query_param("serverId", "upstream-server-id"))
+            .and(wiremock::matchers::body_json(serde_json::json!({
+                "ServerId": "upstream-server-id"
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), true).await;
+        state.server_storage.check_servers_health().await;
+        let proxy_server_id = state.config.read().await.server_id.clone();
+
+        let response = proxy_handler(State(state.clone()), unknown_path_request()).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["ServerId"], proxy_server_id);
+        assert_eq!(body["SessionInfo"]["ServerId"], proxy_server_id);
+
+        let uri: axum::http::Uri = format!("/Some/Unrouted/Endpoint?serverId={proxy_server_id}")
+            .parse()
+            .unwrap();
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::from(
+                serde_json::json!({ "ServerId": proxy_server_id }).to_string(),
+            ))
+            .unwrap();
+        let response = proxy_handler(State(state), request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn proxied_gets_are_retried_after_a_transient_upstream_failure() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(ResponseTemplate::new(502))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), true).await;
+        state.config.write().await.upstream_retries = 2;
+
+        let response = proxy_handler(State(state), unknown_path_request()).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn upstream_errors_name_the_server_when_details_are_enabled() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("Access token is invalid"))
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), true).await;
+
+        let response = proxy_handler(State(state.clone()), unknown_path_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!response
+            .headers()
+            .contains_key(error_response::UPSTREAM_SERVER_HEADER));
+
+        state.config.write().await.upstream_error_details = true;
+        let response = proxy_handler(State(state), unknown_path_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[error_response::UPSTREAM_SERVER_HEADER],
+            "Upstream"
+        );
+        assert_eq!(
+            response.headers()[error_response::UPSTREAM_STATUS_HEADER],
+            "401"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"Access token is invalid");
+    }
+
+    #[tokio::test]
+    async fn login_screen_renders_and_requests_get_503_without_servers() {
+        use tower::ServiceExt;
+
+        let state = create_test_app_state("http://upstream:8096", true).await;
+        state
+            .server_storage
+            .delete_server_by_name("Upstream")
+            .await
+            .unwrap();
+        let router = Router::new()
+            .route("/System/Info/Public", get(handlers::system::info_public))
+            .route(
+                "/Branding/Configuration",
+                get(handlers::branding::handle_branding),
+            )
+            .fallback(proxy_handler)
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                error_response::jellyfin_error_bodies,
+            ))
+            .with_state(state.clone());
+        let get_json = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let uri: axum::http::Uri = uri.parse().unwrap();
+                let response = router
+                    .oneshot(
+                        Request::builder()
+                            .uri(uri.clone())
+                            .extension(axum::extract::OriginalUri(uri))
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+
+        let (status, info) = get_json("/System/Info/Public").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(info["Id"], state.config.read().await.server_id);
+        assert_eq!(info["StartupWizardCompleted"], true);
+
+        let (status, branding) = get_json("/Branding/Configuration").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(branding["LoginDisclaimer"]
+            .as_str()
+            .unwrap()
+            .contains("No servers configured."));
+
+        let (status, problem) = get_json("/Items?userId=abc").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            problem["detail"],
+            "No server is configured in Jellyswarrm yet. Add one in the web UI at /ui."
+        );
+    }
+
+    #[tokio::test]
+    async fn head_requests_keep_upstream_headers_without_a_body() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-length", "27")
+                    .insert_header("content-type", "application/json")
+                    .insert_header("etag", "\"upstream-etag\""),
+            )
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), true).await;
+
+        let uri: axum::http::Uri = "/Some/Unrouted/Endpoint".parse().unwrap();
+        let request = Request::builder()
+            .method("HEAD")
+            .uri(uri.clone())
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+        let response = proxy_handler(State(state), request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "27");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(response.headers()[header::ETAG], "\"upstream-etag\"");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn responses_above_the_parse_limit_only_get_their_ids_rewritten() {
+        let original_id = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let image_tag = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        let upstream_body = format!(
+            r#"{{
+  "Items": [
+    {{
+      "Id": "{original_id}",
+      "ServerId": "upstream-server-id",
+      "ImageTags": {{ "Primary": "{image_tag}" }},
+      "BackdropImageTags": ["{image_tag}"],
+      "UserData": {{ "ItemId": "{original_id}" }},
+      "CanDelete": true
+    }}
+  ],
+  "TotalRecordCount": 1
+}}"#
+        );
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(upstream_body.clone(), "application/json"),
+            )
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), true).await;
+        state.config.write().await.json_full_parse_limit = 64;
+
+        let response = proxy_handler(State(state.clone()), unknown_path_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let server = state.server_storage.list_servers().await.unwrap().remove(0);
+        let virtual_id = |id: &str| {
+            let state = state.clone();
+            let server = server.clone();
+            let id = id.to_string();
+            async move {
+                state
+                    .media_storage
+                    .get_or_create_media_mapping(&id, &server)
+                    .await
+                    .unwrap()
+                    .virtual_media_id
+            }
+        };
+        let virtual_item_id = virtual_id(original_id).await;
+        let virtual_image_tag = virtual_id(image_tag).await;
+        let proxy_server_id = state.config.read().await.server_id.clone();
+        let expected = upstream_body
+            .replacen(original_id, &virtual_item_id, 1)
+            .replace(image_tag, &virtual_image_tag)
+            .replace("upstream-server-id", &proxy_server_id)
+            .replace(r#""CanDelete": true"#, r#""CanDelete": false"#);
+        assert_eq!(body, expected);
+    }
+
+    const KNOWN_PAYLOADS: [&str; 6] = [
+        include_str!("models/tests/files/items.json"),
+        include_str!("models/tests/files/item.json"),
+        include_str!("models/tests/files/episodes.json"),
+        include_str!("models/tests/files/series_nextup.json"),
+        include_str!("models/tests/files/userviews.json"),
+        include_str!("models/tests/files/livetv_playback_response.json"),
+    ];
+
+    #[tokio::test]
+    async fn streaming_rewrite_matches_the_full_pass_on_known_payloads() {
+        let state = create_test_app_state("http://upstream.invalid", true).await;
+        let server = state.server_storage.list_servers().await.unwrap().remove(0);
+
+        for payload in KNOWN_PAYLOADS {
+            let mut full: serde_json::Value = serde_json::from_str(payload).unwrap();
+            state
+                .process_response_json(
+                    &mut full,
+                    &server,
+                    ResponseProcessingProfile::BestEffortMedia,
+                    false,
+                    None,
+                )
+                .await
+                .unwrap();
+            let streamed = state
+                .rewrite_response_fields(
+                    payload.as_bytes(),
+                    &server,
+                    ResponseProcessingProfile::BestEffortMedia,
+                    None,
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            let streamed: serde_json::Value = serde_json::from_slice(&streamed).unwrap();
+            assert_eq!(streamed, full);
+        }
+    }
+
+    /// Compares both rewrite paths on a large listing. Run with
+    /// `cargo test --release -- --ignored --nocapture rewrite_throughput`.
+    #[tokio::test]
+    #[ignore]
+    async fn rewrite_throughput() {
+        let state = create_test_app_state("http://upstream.invalid", true).await;
+        let server = state.server_storage.list_servers().await.unwrap().remove(0);
+        let items: serde_json::Value = serde_json::from_str(KNOWN_PAYLOADS[0]).unwrap();
+        let items = items["Items"].as_array().unwrap();
+        let listing = serde_json::json!({
+            "Items": items.iter().cycle().take(5_000).collect::<Vec<_>>(),
+            "TotalRecordCount": 5_000,
+        });
+        let body = serde_json::to_vec(&listing).unwrap();
+        let rounds = 10;
+
+        let started = std::time::Instant::now();
+        for _ in 0..rounds {
+            let mut json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            state
+                .process_response_json(
+                    &mut json,
+                    &server,
+                    ResponseProcessingProfile::BestEffortMedia,
+                    false,
+                    None,
+                )
+                .await
+                .unwrap();
+            serde_json::to_vec(&json).unwrap();
+        }
+        let full = started.elapsed() / rounds;
+
+        let started = std::time::Instant::now();
+        for _ in 0..rounds {
+            state
+                .rewrite_response_fields(
+                    &body,
+                    &server,
+                    ResponseProcessingProfile::BestEffortMedia,
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        let streaming = started.elapsed() / rounds;
+
+        println!(
+            "{} byte listing: full pass {:?}, streaming pass {:?}",
+            body.len(),
+            full,
+            streaming
+        );
+    }
+
+    #[tokio::test]
+    async fn upstream_clients_use_http2_when_configured() {
+        let upstream = MockServer::start().await;
+        mount_public_info(&upstream).await;
+
+        for (prior_knowledge, version) in [
+            (false, reqwest::Version::HTTP_11),
+            (true, reqwest::Version::HTTP_2),
+        ] {
+            let config = AppConfig {
+                http2_prior_knowledge: prior_knowledge,
+                ..AppConfig::default()
+            };
+            let client = upstream_client_builder(&config, &[]).build().unwrap();
+            let response = client
+                .get(format!("{}/System/Info/Public", upstream.uri()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.version(), version);
+        }
+    }
+
+    #[test]
+    fn prefix_redirects_keep_the_query_string() {
+        let uri = "/Items?ParentId=x&Recursive=true".parse().unwrap();
+        assert_eq!(
+            prefixed_redirect_target("jellyfin", &uri),
+            "/jellyfin/Items?ParentId=x&Recursive=true"
+        );
+
+        let uri = "/jellyfin/?x=1".parse().unwrap();
+        assert_eq!(prefixed_redirect_target("jellyfin", &uri), "/jellyfin?x=1");
+
+        let uri = "/web/".parse().unwrap();
+        assert_eq!(prefixed_redirect_target("jellyfin", &uri), "/jellyfin/web");
+    }
+
+    #[test]
+    fn upstream_ca_bundle_must_contain_certificates() {
+        assert!(load_upstream_ca_bundle(&AppConfig::default())
+            .unwrap()
+            .is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let missing = AppConfig {
+            upstream_ca_bundle: dir.path().join("missing.pem").display().to_string(),
+            ..AppConfig::default()
+        };
+        assert!(load_upstream_ca_bundle(&missing).is_err());
+
+        let not_pem = dir.path().join("ca.pem");
+        std::fs::write(&not_pem, "not a certificate").unwrap();
+        let not_pem = AppConfig {
+            upstream_ca_bundle: not_pem.display().to_string(),
+            ..AppConfig::default()
+        };
+        assert!(load_upstream_ca_bundle(&not_pem).is_err());
+    }
+
+    #[tokio::test]
+    async fn proxied_requests_are_counted_per_server() {
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/Some/Unrouted/Endpoint"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&upstream)
+            .await;
+        let state = create_test_app_state(&upstream.uri(), true).await;
+
+        proxy_handler(State(state.clone()), unknown_path_request()).await;
+
+        let snapshot = state.metrics.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].server, "Upstream");
+        assert_eq!(snapshot[0].requests, 1);
+        assert_eq!(snapshot[0].errors, 1);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[tokio::test]
+    async fn prometheus_metrics_report_active_sessions() {
+        let upstream = MockServer::start().await;
+        let state = create_test_app_state(&upstream.uri(), false).await;
+        state
+            .play_sessions
+            .track_stream("user", "play-session", 8_000_000)
+            .await;
+        state
+            .metrics
+            .record("Upstream", Duration::from_millis(20), Some(200));
+
+        let response = metrics::prometheus_metrics(State(state))
+            .await
+            .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("jellyswarrm_active_sessions 1\n"));
+        assert!(body.contains("jellyswarrm_play_sessions 0\n"));
+        assert!(body.contains(
+            "jellyswarrm_upstream_requests_total{server=\"Upstream\",status=\"200\"} 1\n"
+        ));
+    }
+
+    async fn mount_public_info(upstream: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path("/System/Info/Public"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ServerName": "Upstream",
+                "Version": "10.10.7"
+            })))
+            .mount(upstream)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn query_progress_reports_follow_their_play_session() {
+        let preferred = MockServer::start().await;
+        let playing = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/Users/Playing-user-id/PlayingItems/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb/Progress",
+            ))
+            .and(query_param("PositionTicks", "123456789"))
+            .and(query_param("PlaySessionId", "play-session"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&playing)
+            .await;
+        let state = create_test_app_state_with_config(AppConfig::default()).await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        add_server_with_session(&state, &user, "Upstream", &preferred, 100).await;
+        let server = add_server_with_session(&state, &user, "Playing", &playing, 50).await;
+        state
+            .play_sessions
+            .add_session(PlaybackSession {
+                session_id: "play-session".to_string(),
+                item_id: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+                user_id: user.id.clone(),
+                server_id: server.id,
+                original_user_id: "Playing-user-id".to_string(),
+                original_item_id: "cccccccccccccccccccccccccccccccc".to_string(),
+            })
+            .await;
+        state.server_storage.check_servers_health().await;
+
+        let auth_header = Authorization {
+            client: "Jellyfin Web".to_string(),
+            device: "Firefox".to_string(),
+            device_id: "web-device-id".to_string(),
+            version: "10.10.7".to_string(),
+            token: Some(user.virtual_key.clone()),
+        }
+        .to_header_value();
+        let uri: axum::http::Uri = format!(
+            "/Users/{}/PlayingItems/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb/Progress?PositionTicks=123456789&PlaySessionId=play-session",
+            user.id
+        )
+        .parse()
+        .unwrap();
+        let request = Request::builder()
+            .uri(uri.clone())
+            .header(header::HOST, "localhost")
+            .header(header::AUTHORIZATION, auth_header)
+            .extension(axum::extract::OriginalUri(uri))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = proxy_handler(State(state), request).await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let preferred_requests = preferred.received_requests().await.unwrap();
+        assert!(preferred_requests
+            .iter()
+            .all(|request| request.url.path() == "/System/Info/Public"));
+    }
+}
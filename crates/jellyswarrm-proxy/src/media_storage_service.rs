@@ -1,11 +1,13 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
-use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use jellyfin_api::JellyfinClient;
+use sqlx::{sqlite::SqliteRow, QueryBuilder, Row, Sqlite, SqliteConnection, SqlitePool};
 use tracing::{debug, error, info, trace};
 use uuid::Uuid;
 
 #[cfg(test)]
-use crate::config::MediaStreamingMode;
+use crate::config::{AuthorizationHeaderMode, MediaStreamingMode};
 use crate::models::generate_token;
 use crate::server_id::ServerId;
 use crate::server_storage::Server;
@@ -40,20 +42,31 @@ impl<'r> sqlx::FromRow<'r, SqliteRow> for MediaMapping {
 pub struct MediaStorageService {
     pool: SqlitePool,
     original_mapping_cache: Cache<String, MediaMapping>,
+    virtual_mapping_cache: Cache<String, MediaMapping>,
     mapping_with_server_cache: Cache<String, (MediaMapping, Server)>,
 }
 
 impl MediaStorageService {
     pub fn new(pool: SqlitePool) -> Self {
+        Self::with_cache(pool, 100_000, Duration::from_secs(60 * 30))
+    }
+
+    /// Create the service with mapping caches holding up to `capacity` entries
+    /// each for at most `ttl`.
+    pub fn with_cache(pool: SqlitePool, capacity: u64, ttl: Duration) -> Self {
         Self {
             pool,
             original_mapping_cache: Cache::builder()
-                .time_to_live(Duration::from_secs(60 * 30))
-                .max_capacity(100_000)
+                .time_to_live(ttl)
+                .max_capacity(capacity)
+                .build(),
+            virtual_mapping_cache: Cache::builder()
+                .time_to_live(ttl)
+                .max_capacity(capacity)
                 .build(),
             mapping_with_server_cache: Cache::builder()
-                .time_to_live(Duration::from_secs(60 * 30))
-                .max_capacity(10_000)
+                .time_to_live(ttl)
+                .max_capacity(capacity)
                 .build(),
         }
     }
@@ -116,6 +129,9 @@ impl MediaStorageService {
         .await?;
 
         if let Some(row) = inserted {
+            self.virtual_mapping_cache
+                .invalidate(&row.virtual_media_id)
+                .await;
             debug!(
                 "Created new media mapping: {} -> {} ({})",
                 &original_media_id,
@@ -137,6 +153,239 @@ impl MediaStorageService {
         Err(sqlx::Error::RowNotFound)
     }
 
+    /// Get or create the mappings of many original ids on one server at once. Ids that
+    /// are already mapped are read in a few batched queries and all missing ones are
+    /// written in a single transaction, instead of one round trip per id. The results
+    /// are cached, so later [`Self::get_or_create_media_mapping`] calls for the same
+    /// ids don't touch the database.
+    pub async fn get_or_create_media_mappings(
+        &self,
+        original_media_ids: &[&str],
+        server: &Server,
+    ) -> Result<HashMap<String, MediaMapping>, sqlx::Error> {
+        let mut mappings = HashMap::new();
+        let mut uncached = Vec::new();
+        for original_media_id in original_media_ids {
+            let original_media_id = Self::normalize_uuid(original_media_id);
+            if mappings.contains_key(&original_media_id) || uncached.contains(&original_media_id) {
+                continue;
+            }
+            let key = format!("{}|{}", original_media_id, server.id);
+            match self.original_mapping_cache.get(&key).await {
+                Some(cached) => {
+                    mappings.insert(original_media_id, cached);
+                }
+                None => uncached.push(original_media_id),
+            }
+        }
+        if uncached.is_empty() {
+            return Ok(mappings);
+        }
+
+        let mut found = self.mappings_by_original(&uncached, server.id).await?;
+        let missing = uncached
+            .iter()
+            .filter(|original_media_id| !found.contains_key(*original_media_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            let rows = missing
+                .iter()
+                .map(|original_media_id| (generate_token(), original_media_id.clone(), server))
+                .collect::<Vec<_>>();
+            self.bulk_upsert_mappings(&rows).await?;
+            // Rows that lost a race keep the virtual id the other writer gave them.
+            found.extend(self.mappings_by_original(&missing, server.id).await?);
+            debug!(
+                "Created {} media mappings for {} in one batch",
+                missing.len(),
+                server.url.as_str()
+            );
+        }
+
+        for (original_media_id, mapping) in found {
+            let key = format!("{}|{}", original_media_id, server.id);
+            self.original_mapping_cache
+                .insert(key, mapping.clone())
+                .await;
+            mappings.insert(original_media_id, mapping);
+        }
+        Ok(mappings)
+    }
+
+    /// List every media mapping, oldest first.
+    pub async fn list_media_mappings(&self) -> Result<Vec<MediaMapping>, sqlx::Error> {
+        sqlx::query_as::<_, MediaMapping>(
+            r#"
+            SELECT id, virtual_media_id, original_media_id, server_id, server_url, created_at
+            FROM media_mappings
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Restore an exported media mapping, keeping its virtual id. A mapping whose
+    /// virtual id or original id is already taken is skipped. Returns whether the
+    /// mapping was inserted.
+    pub async fn import_media_mapping(
+        &self,
+        conn: &mut SqliteConnection,
+        virtual_media_id: &str,
+        original_media_id: &str,
+        server: &Server,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO media_mappings (virtual_media_id, original_media_id, server_id, server_url, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(virtual_media_id)
+        .bind(Self::normalize_uuid(original_media_id))
+        .bind(server.id.as_i64())
+        .bind(server.url.as_str())
+        .bind(chrono::Utc::now())
+        .execute(&mut *conn)
+        .await?;
+        if result.rows_affected() > 0 {
+            self.virtual_mapping_cache
+                .invalidate(virtual_media_id)
+                .await;
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Insert `(virtual id, original id, server)` mappings in a single transaction.
+    /// A row whose original id is already mapped on its server is skipped rather
+    /// than replaced, so virtual ids that were handed out to clients stay valid.
+    pub async fn bulk_upsert_mappings(
+        &self,
+        mappings: &[(String, String, &Server)],
+    ) -> Result<(), sqlx::Error> {
+        // Five bound values per row keeps every statement below SQLite's oldest
+        // limit of 999 variables.
+        const ROWS_PER_STATEMENT: usize = 150;
+
+        if mappings.is_empty() {
+            return Ok(());
+        }
+
+        let now = chrono::Utc::now();
+        let mut tx = self.pool.begin().await?;
+        for chunk in mappings.chunks(ROWS_PER_STATEMENT) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO media_mappings \
+                 (virtual_media_id, original_media_id, server_id, server_url, created_at) ",
+            );
+            query.push_values(
+                chunk,
+                |mut row, (virtual_media_id, original_media_id, server)| {
+                    row.push_bind(virtual_media_id)
+                        .push_bind(Self::normalize_uuid(original_media_id))
+                        .push_bind(server.id.as_i64())
+                        .push_bind(server.url.as_str())
+                        .push_bind(now);
+                },
+            );
+            query.push(" ON CONFLICT DO NOTHING");
+            query.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        for (virtual_media_id, _, _) in mappings {
+            self.virtual_mapping_cache
+                .invalidate(virtual_media_id)
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn mappings_by_original(
+        &self,
+        original_media_ids: &[String],
+        server_id: ServerId,
+    ) -> Result<HashMap<String, MediaMapping>, sqlx::Error> {
+        const IDS_PER_QUERY: usize = 500;
+
+        let mut mappings = HashMap::new();
+        for chunk in original_media_ids.chunks(IDS_PER_QUERY) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "SELECT id, virtual_media_id, original_media_id, server_id, server_url, created_at \
+                 FROM media_mappings WHERE server_id = ",
+            );
+            query.push_bind(server_id.as_i64());
+            query.push(" AND original_media_id IN (");
+            let mut ids = query.separated(", ");
+            for original_media_id in chunk {
+                ids.push_bind(original_media_id);
+            }
+            ids.push_unseparated(")");
+
+            for mapping in query
+                .build_query_as::<MediaMapping>()
+                .fetch_all(&self.pool)
+                .await?
+            {
+                mappings.insert(mapping.original_media_id.clone(), mapping);
+            }
+        }
+        Ok(mappings)
+    }
+
+    /// Find the server that owns a media id no mapping knows by asking each of
+    /// `servers` in turn, with the given token, for the item. The first server that
+    /// has it gets a mapping for it, which is returned. Every miss costs a round trip
+    /// to a backend, so an id one of the servers was already found to own is answered
+    /// from the stored mapping.
+    pub async fn probe_media_owner(
+        &self,
+        media_id: &str,
+        servers: &[(Server, String)],
+    ) -> Result<Option<MediaMapping>, sqlx::Error> {
+        for (server, _) in servers {
+            if let Some(mapping) = self
+                .get_media_mapping_by_original(media_id, server.id)
+                .await?
+            {
+                return Ok(Some(mapping));
+            }
+        }
+
+        for (server, token) in servers {
+            let client = match JellyfinClient::new(
+                server.url.as_str(),
+                crate::config::CLIENT_INFO.clone(),
+            ) {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Failed to create client for {}: {}", server.name, e);
+                    continue;
+                }
+            };
+            client.with_token(token.clone()).await;
+
+            match client.get_item(media_id).await {
+                Ok(_) => {
+                    info!("Media id {} belongs to server {}", media_id, server.name);
+                    return self
+                        .get_or_create_media_mapping(media_id, server)
+                        .await
+                        .map(Some);
+                }
+                Err(jellyfin_api::error::JellyfinApiError::NotFound) => {}
+                Err(e) => debug!(
+                    "Server {} could not be asked about media id {}: {}",
+                    server.name, media_id, e
+                ),
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn normalize_uuid(s: &str) -> String {
         match Uuid::parse_str(s) {
             Ok(uuid) => uuid.simple().to_string(),
@@ -151,6 +400,11 @@ impl MediaStorageService {
     ) -> Result<Option<MediaMapping>, sqlx::Error> {
         let virtual_media_id = Self::normalize_uuid(virtual_media_id);
 
+        if let Some(cached) = self.virtual_mapping_cache.get(&virtual_media_id).await {
+            trace!("Cache hit for virtual media mapping: {}", virtual_media_id);
+            return Ok(Some(cached));
+        }
+
         let mapping = sqlx::query_as::<_, MediaMapping>(
             r#"
             SELECT id, virtual_media_id, original_media_id, server_id, server_url, created_at
@@ -158,10 +412,15 @@ impl MediaStorageService {
             WHERE virtual_media_id = ?
             "#,
         )
-        .bind(virtual_media_id)
+        .bind(&virtual_media_id)
         .fetch_optional(&self.pool)
         .await?;
 
+        if let Some(mapping) = &mapping {
+            self.virtual_mapping_cache
+                .insert(virtual_media_id, mapping.clone())
+                .await;
+        }
         Ok(mapping)
     }
 
@@ -218,6 +477,9 @@ impl MediaStorageService {
                 s.url as server_url_full,
                 s.priority,
                 s.media_streaming_mode,
+                s.authorization_header_mode,
+                s.max_connections,
+                s.backend_id,
                 s.created_at as server_created_at,
                 s.updated_at as server_updated_at
             FROM media_mappings m
@@ -274,7 +536,10 @@ impl MediaStorageService {
                     self.original_mapping_cache.invalidate_all();
                 }
             }
-            // Also invalidate the mapping_with_server_cache
+            // Also invalidate the virtual id keyed caches
+            self.virtual_mapping_cache
+                .invalidate(virtual_media_id)
+                .await;
             self.mapping_with_server_cache
                 .invalidate(virtual_media_id)
                 .await;
@@ -308,6 +573,7 @@ impl MediaStorageService {
             );
         }
         self.original_mapping_cache.invalidate_all();
+        self.virtual_mapping_cache.invalidate_all();
         self.mapping_with_server_cache.invalidate_all();
         Ok(deleted_count)
     }
@@ -342,6 +608,9 @@ mod tests {
             url: ServerUrl::parse("http://localhost:8096").unwrap(),
             priority: 100,
             media_streaming_mode: MediaStreamingMode::Redirect,
+            authorization_header_mode: AuthorizationHeaderMode::Normalize,
+            max_connections: None,
+            backend_id: None,
             created_at: now,
             updated_at: now,
         }
@@ -435,4 +704,104 @@ mod tests {
             .unwrap()
             .is_none());
     }
+
+    #[tokio::test]
+    async fn virtual_lookups_are_served_from_cache() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        let service = MediaStorageService::with_cache(pool.clone(), 10, Duration::from_secs(60));
+        let server = create_test_server(&pool).await;
+
+        let mapping = service
+            .get_or_create_media_mapping("movie-123", &server)
+            .await
+            .unwrap();
+        assert!(service
+            .get_media_mapping_by_virtual(&mapping.virtual_media_id)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Remove the row behind the service's back; a cached lookup never sees it.
+        sqlx::query("DELETE FROM media_mappings")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let cached = service
+            .get_media_mapping_by_virtual(&mapping.virtual_media_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached.original_media_id, "movie-123");
+    }
+
+    #[tokio::test]
+    async fn batched_mappings_are_written_in_one_transaction() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let commits = Arc::new(AtomicUsize::new(0));
+        let counter = commits.clone();
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .after_connect(move |connection, _| {
+                let counter = counter.clone();
+                Box::pin(async move {
+                    connection.lock_handle().await?.set_commit_hook(move || {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        true
+                    });
+                    Ok(())
+                })
+            })
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        let service = MediaStorageService::new(pool.clone());
+        let server = create_test_server(&pool).await;
+        let existing = service
+            .get_or_create_media_mapping("item-0", &server)
+            .await
+            .unwrap();
+
+        // More rows than fit in one INSERT statement.
+        let originals = (0..400).map(|i| format!("item-{i}")).collect::<Vec<_>>();
+        let ids = originals.iter().map(String::as_str).collect::<Vec<_>>();
+        let before = commits.load(Ordering::SeqCst);
+        let mappings = service
+            .get_or_create_media_mappings(&ids, &server)
+            .await
+            .unwrap();
+
+        assert_eq!(commits.load(Ordering::SeqCst) - before, 1);
+        assert_eq!(mappings.len(), 400);
+        assert_eq!(
+            mappings["item-0"].virtual_media_id,
+            existing.virtual_media_id
+        );
+        let found = service
+            .get_media_mapping_with_server(&mappings["item-399"].virtual_media_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.0.original_media_id, "item-399");
+        assert_eq!(found.1.id, server.id);
+
+        // Everything is mapped and cached now, so nothing is written again.
+        let before = commits.load(Ordering::SeqCst);
+        service
+            .get_or_create_media_mappings(&ids, &server)
+            .await
+            .unwrap();
+        for id in &ids[..10] {
+            service
+                .get_or_create_media_mapping(id, &server)
+                .await
+                .unwrap();
+        }
+        assert_eq!(commits.load(Ordering::SeqCst), before);
+    }
 }
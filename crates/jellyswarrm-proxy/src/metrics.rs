@@ -0,0 +1,338 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// Upper bounds, in milliseconds, of the latency histogram buckets. Slower requests
+/// land in a final open-ended bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Status label of requests that never got a response from the backend.
+const TRANSPORT_ERROR_STATUS: &str = "error";
+
+#[derive(Default)]
+struct ServerMetrics {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    /// Requests per response status; `None` counts transport errors.
+    statuses: RwLock<BTreeMap<Option<u16>, AtomicU64>>,
+    latency_sum_micros: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+}
+
+/// Per-server request counters for requests forwarded by the generic proxy handler.
+#[derive(Default)]
+pub struct ProxyMetrics {
+    servers: RwLock<BTreeMap<String, Arc<ServerMetrics>>>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct LatencyBucket {
+    /// Upper bound in milliseconds, `None` for the open-ended bucket.
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServerMetricsSnapshot {
+    pub server: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub statuses: BTreeMap<String, u64>,
+    pub latency_sum_ms: f64,
+    pub latency: Vec<LatencyBucket>,
+}
+
+impl ProxyMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one forwarded request with the status the backend answered with, or
+    /// `None` when it couldn't be reached. Transport errors and `5xx` responses
+    /// count as errors.
+    pub fn record(&self, server_name: &str, latency: Duration, status: Option<u16>) {
+        let server = self.server(server_name);
+        server.requests.fetch_add(1, Ordering::Relaxed);
+        if status.is_none_or(|status| status >= 500) {
+            server.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        server.count_status(status);
+
+        let latency_micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        server
+            .latency_sum_micros
+            .fetch_add(latency_micros, Ordering::Relaxed);
+        let latency_ms = latency_micros / 1000;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| latency_ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        server.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn server(&self, server_name: &str) -> Arc<ServerMetrics> {
+        if let Some(server) = self
+            .servers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(server_name)
+        {
+            return server.clone();
+        }
+
+        self.servers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(server_name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub fn snapshot(&self) -> Vec<ServerMetricsSnapshot> {
+        let servers = self
+            .servers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        servers
+            .iter()
+            .map(|(name, server)| ServerMetricsSnapshot {
+                server: name.clone(),
+                requests: server.requests.load(Ordering::Relaxed),
+                errors: server.errors.load(Ordering::Relaxed),
+                statuses: server.status_counts(),
+                latency_sum_ms: server.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+                latency: server
+                    .latency_buckets
+                    .iter()
+                    .enumerate()
+                    .map(|(index, count)| LatencyBucket {
+                        le_ms: LATENCY_BUCKETS_MS.get(index).copied(),
+                        count: count.load(Ordering::Relaxed),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Render the counters in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut output = String::new();
+
+        let _ = writeln!(
+            output,
+            "# HELP jellyswarrm_upstream_requests_total Requests forwarded to each server by response status."
+        );
+        let _ = writeln!(output, "# TYPE jellyswarrm_upstream_requests_total counter");
+        for server in &snapshot {
+            let label = escape_label(&server.server);
+            for (status, count) in &server.statuses {
+                let _ = writeln!(
+                    output,
+                    "jellyswarrm_upstream_requests_total{{server=\"{label}\",status=\"{status}\"}} {count}"
+                );
+            }
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP jellyswarrm_upstream_errors_total Forwarded requests that failed or returned a 5xx status."
+        );
+        let _ = writeln!(output, "# TYPE jellyswarrm_upstream_errors_total counter");
+        for server in &snapshot {
+            let _ = writeln!(
+                output,
+                "jellyswarrm_upstream_errors_total{{server=\"{}\"}} {}",
+                escape_label(&server.server),
+                server.errors
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP jellyswarrm_upstream_latency_seconds Latency of forwarded requests."
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE jellyswarrm_upstream_latency_seconds histogram"
+        );
+        for server in &snapshot {
+            let label = escape_label(&server.server);
+            let mut cumulative = 0;
+            for bucket in &server.latency {
+                cumulative += bucket.count;
+                let le = bucket
+                    .le_ms
+                    .map(|bound| (bound as f64 / 1000.0).to_string())
+                    .unwrap_or_else(|| "+Inf".to_string());
+                let _ = writeln!(
+                    output,
+                    "jellyswarrm_upstream_latency_seconds_bucket{{server=\"{label}\",le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                output,
+                "jellyswarrm_upstream_latency_seconds_sum{{server=\"{label}\"}} {}",
+                server.latency_sum_ms / 1000.0
+            );
+            let _ = writeln!(
+                output,
+                "jellyswarrm_upstream_latency_seconds_count{{server=\"{label}\"}} {}",
+                server.requests
+            );
+        }
+
+        output
+    }
+}
+
+impl ServerMetrics {
+    fn count_status(&self, status: Option<u16>) {
+        if let Some(count) = self
+            .statuses
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&status)
+        {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        self.statuses
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(status)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn status_counts(&self) -> BTreeMap<String, u64> {
+        self.statuses
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(status, count)| {
+                let status = status.map_or_else(
+                    || TRANSPORT_ERROR_STATUS.to_string(),
+                    |status| status.to_string(),
+                );
+                (status, count.load(Ordering::Relaxed))
+            })
+            .collect()
+    }
+}
+
+/// Serve the counters to Prometheus scrapers, together with the number of
+/// playback sessions that are currently streaming and of stored play sessions.
+#[cfg(feature = "prometheus")]
+pub async fn prometheus_metrics(
+    axum::extract::State(state): axum::extract::State<crate::AppState>,
+) -> impl axum::response::IntoResponse {
+    let mut output = state.metrics.to_prometheus();
+    let active_sessions = state.play_sessions.active_stream_count().await;
+    let _ = writeln!(
+        output,
+        "# HELP jellyswarrm_active_sessions Playback sessions that reported progress recently."
+    );
+    let _ = writeln!(output, "# TYPE jellyswarrm_active_sessions gauge");
+    let _ = writeln!(output, "jellyswarrm_active_sessions {active_sessions}");
+    let play_sessions = state.play_sessions.len().await;
+    let _ = writeln!(
+        output,
+        "# HELP jellyswarrm_play_sessions Play sessions kept to route playback requests."
+    );
+    let _ = writeln!(output, "# TYPE jellyswarrm_play_sessions gauge");
+    let _ = writeln!(output, "jellyswarrm_play_sessions {play_sessions}");
+
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        output,
+    )
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_counted_per_server() {
+        let metrics = ProxyMetrics::new();
+        metrics.record("Alpha", Duration::from_millis(3), Some(200));
+        metrics.record("Alpha", Duration::from_millis(40), None);
+        metrics.record("Beta", Duration::from_secs(30), Some(404));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].server, "Alpha");
+        assert_eq!(snapshot[0].requests, 2);
+        assert_eq!(snapshot[0].errors, 1);
+        assert_eq!(
+            snapshot[0].statuses,
+            BTreeMap::from([("200".to_string(), 1), ("error".to_string(), 1)])
+        );
+        assert_eq!(snapshot[0].latency_sum_ms, 43.0);
+        assert_eq!(
+            snapshot[0].latency[0],
+            LatencyBucket {
+                le_ms: Some(5),
+                count: 1
+            }
+        );
+        assert_eq!(snapshot[0].latency[3].count, 1);
+        assert_eq!(snapshot[1].server, "Beta");
+        assert_eq!(
+            snapshot[1].latency.last(),
+            Some(&LatencyBucket {
+                le_ms: None,
+                count: 1
+            })
+        );
+    }
+
+    #[test]
+    fn prometheus_histogram_buckets_are_cumulative() {
+        let metrics = ProxyMetrics::new();
+        metrics.record("Living \"Room\"", Duration::from_millis(3), Some(200));
+        metrics.record("Living \"Room\"", Duration::from_millis(200), Some(503));
+
+        let output = metrics.to_prometheus();
+        assert!(output.contains(
+            "jellyswarrm_upstream_requests_total{server=\"Living \\\"Room\\\"\",status=\"200\"} 1\n"
+        ));
+        assert!(output.contains(
+            "jellyswarrm_upstream_requests_total{server=\"Living \\\"Room\\\"\",status=\"503\"} 1\n"
+        ));
+        assert!(output
+            .contains("jellyswarrm_upstream_errors_total{server=\"Living \\\"Room\\\"\"} 1\n"));
+        assert!(output.contains(
+            "jellyswarrm_upstream_latency_seconds_bucket{server=\"Living \\\"Room\\\"\",le=\"0.005\"} 1\n"
+        ));
+        assert!(output.contains(
+            "jellyswarrm_upstream_latency_seconds_bucket{server=\"Living \\\"Room\\\"\",le=\"0.25\"} 2\n"
+        ));
+        assert!(output.contains(
+            "jellyswarrm_upstream_latency_seconds_bucket{server=\"Living \\\"Room\\\"\",le=\"+Inf\"} 2\n"
+        ));
+    }
+}
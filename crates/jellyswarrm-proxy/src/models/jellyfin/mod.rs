@@ -48,7 +48,6 @@ pub struct PlaybackResponse {
     pub extra: std::collections::HashMap<String, serde_json::Value>,
 }
 
-#[allow(dead_code)]
 fn serialize_playback_rate<S>(value: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -308,6 +307,22 @@ impl ItemsResponseVariants {
         }
     }
 
+    /// Shared access to the inner items of either variant.
+    pub fn items(&self) -> &[MediaItem] {
+        match self {
+            ItemsResponseVariants::WithCount(w) => &w.items,
+            ItemsResponseVariants::Bare(v) => v,
+        }
+    }
+
+    /// Mutable access to the inner items of either variant.
+    pub fn items_mut(&mut self) -> &mut Vec<MediaItem> {
+        match self {
+            ItemsResponseVariants::WithCount(w) => &mut w.items,
+            ItemsResponseVariants::Bare(v) => v,
+        }
+    }
+
     /// Consume self and return the inner items as a plain `Vec`.
     pub fn into_items(self) -> Vec<MediaItem> {
         match self {
@@ -454,6 +469,7 @@ pub struct MediaSource {
     pub transcoding_sub_protocol: Option<String>,
     pub transcoding_url: Option<String>,
     pub stream_url: Option<String>,
+    pub direct_stream_url: Option<String>,
     pub transcoding_container: Option<String>,
     pub default_audio_stream_index: Option<i32>,
     pub default_subtitle_stream_index: Option<i32>,
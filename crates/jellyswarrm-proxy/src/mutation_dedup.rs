@@ -0,0 +1,380 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{Extensions, HeaderMap, Method, StatusCode, Version},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
+use tracing::{debug, error};
+
+use crate::{
+    rate_limit::is_streaming_path,
+    request_preprocessing::resolve_request_identity_from_headers_uri, AppState,
+};
+
+/// Header a client can send to name a mutation explicitly instead of having it
+/// recognized by its path, query and body.
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Requests with larger (or unknown size) bodies, such as image uploads, are never
+/// deduplicated, as their body would have to be buffered to be compared.
+const MAX_DEDUP_BODY_BYTES: u64 = 64 * 1024;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum RequestFingerprint {
+    IdempotencyKey(String),
+    Request {
+        method: Method,
+        uri: String,
+        body: Bytes,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct MutationKey {
+    user_id: String,
+    device_id: String,
+    path: String,
+    request: RequestFingerprint,
+}
+
+impl MutationKey {
+    /// Whether `other` was sent by the same device of the same user to the same path.
+    fn same_target(&self, other: &MutationKey) -> bool {
+        self.user_id == other.user_id
+            && self.device_id == other.device_id
+            && self.path == other.path
+    }
+}
+
+/// Buffered response of a mutation, handed to the duplicates of the request.
+struct CachedResponse {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    extensions: Extensions,
+    body: Bytes,
+}
+
+impl CachedResponse {
+    fn to_response(&self) -> Response {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.version_mut() = self.version;
+        *response.headers_mut() = self.headers.clone();
+        *response.extensions_mut() = self.extensions.clone();
+        response
+    }
+}
+
+struct MutationEntry {
+    started_at: Instant,
+    response: Arc<OnceCell<CachedResponse>>,
+}
+
+/// Mutations seen within the deduplication window, keyed by the device of the user
+/// that sent them and what they changed.
+#[derive(Default)]
+pub struct MutationDeduplicator {
+    entries: Mutex<HashMap<MutationKey, MutationEntry>>,
+}
+
+impl MutationDeduplicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The response slot of `key`: the one of an identical mutation started within
+    /// `window`, or a new one. A new mutation of the same path supersedes the earlier
+    /// ones, so favoriting an item again after unfavoriting it isn't answered with
+    /// the first favorite's cached response.
+    fn slot(
+        &self,
+        key: MutationKey,
+        window: Duration,
+        now: Instant,
+    ) -> Arc<OnceCell<CachedResponse>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, entry| now.duration_since(entry.started_at) < window);
+        if let Some(entry) = entries.get(&key) {
+            return entry.response.clone();
+        }
+
+        entries.retain(|other, _| !key.same_target(other));
+        let response = Arc::new(OnceCell::new());
+        entries.insert(
+            key,
+            MutationEntry {
+                started_at: now,
+                response: response.clone(),
+            },
+        );
+        response
+    }
+
+    /// Drop the entry of `key` if it still holds `slot`, so the next identical
+    /// request is sent to the backend again.
+    fn forget(&self, key: &MutationKey, slot: &Arc<OnceCell<CachedResponse>>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries
+            .get(key)
+            .is_some_and(|entry| Arc::ptr_eq(&entry.response, slot))
+        {
+            entries.remove(key);
+        }
+    }
+}
+
+/// Middleware that coalesces identical `POST` and `DELETE` requests a device of a
+/// user sends within `dedup_mutations_window_ms`: the first one is forwarded and
+/// the others get its response. Server errors aren't kept, so a client retrying
+/// after one still reaches the backend.
+pub async fn dedup_mutations(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let window_ms = state.dedup_mutations_window_ms().await;
+    if window_ms == 0
+        || !matches!(*req.method(), Method::POST | Method::DELETE)
+        || is_streaming_path(req.uri().path())
+        || req
+            .body()
+            .size_hint()
+            .upper()
+            .is_none_or(|size| size > MAX_DEDUP_BODY_BYTES)
+    {
+        return next.run(req).await;
+    }
+
+    let identity =
+        match resolve_request_identity_from_headers_uri(req.headers(), req.uri(), &state).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                error!(
+                    "Failed to resolve identity for deduplication of {}: {}",
+                    req.uri(),
+                    e
+                );
+                return next.run(req).await;
+            }
+        };
+    let (Some(user), Some(device)) = (identity.user, identity.device) else {
+        return next.run(req).await;
+    };
+
+    let (parts, body) = req.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_DEDUP_BODY_BYTES as usize).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read body of {}: {}", parts.uri, e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    let request = match parts
+        .headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(idempotency_key) => RequestFingerprint::IdempotencyKey(idempotency_key.to_string()),
+        None => RequestFingerprint::Request {
+            method: parts.method.clone(),
+            uri: parts.uri.to_string(),
+            body: body.clone(),
+        },
+    };
+    let key = MutationKey {
+        user_id: user.id,
+        device_id: device.device_id,
+        path: parts.uri.path().to_string(),
+        request,
+    };
+
+    let slot = state.mutation_dedup.slot(
+        key.clone(),
+        Duration::from_millis(window_ms),
+        Instant::now(),
+    );
+    let path = parts.uri.path().to_string();
+    let mut forwarded = false;
+    let cached = slot
+        .get_or_init(|| async {
+            forwarded = true;
+            let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+            let (parts, body) = response.into_parts();
+            let body = axum::body::to_bytes(body, usize::MAX)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to buffer response of {}: {}", path, e);
+                    Bytes::new()
+                });
+            CachedResponse {
+                status: parts.status,
+                version: parts.version,
+                headers: parts.headers,
+                extensions: parts.extensions,
+                body,
+            }
+        })
+        .await;
+
+    if forwarded && cached.status.is_server_error() {
+        state.mutation_dedup.forget(&key, &slot);
+    } else if !forwarded {
+        debug!(
+            "Answering duplicate {} from device '{}' with the response of the first request",
+            path, device.device
+        );
+    }
+    cached.to_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_test_app_state_with_config;
+    use crate::{config::AppConfig, models::Authorization};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    async fn create_test_app_state() -> AppState {
+        create_test_app_state_with_config(AppConfig {
+            dedup_mutations_window_ms: 60_000,
+            ..AppConfig::default()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn identical_favorite_posts_within_the_window_reach_the_backend_once() {
+        let state = create_test_app_state().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let upstream_calls = Arc::new(AtomicUsize::new(0));
+        let calls = upstream_calls.clone();
+        let router = axum::Router::new()
+            .route(
+                "/{*path}",
+                axum::routing::post(move || {
+                    let calls = calls.clone();
+                    async move {
+                        let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                        format!(r#"{{"IsFavorite":true,"Call":{call}}}"#)
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                dedup_mutations,
+            ))
+            .with_state(state);
+
+        let request = |uri: &str| {
+            let authorization = Authorization {
+                client: "Jellyfin Web".to_string(),
+                device: "Firefox".to_string(),
+                device_id: "tv".to_string(),
+                version: "10.10.7".to_string(),
+                token: Some(user.virtual_key.clone()),
+            };
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("authorization", authorization.to_header_value())
+                .body(Body::empty())
+                .unwrap()
+        };
+        let body = |response: Response| async move {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let (first, second) = tokio::join!(
+            router
+                .clone()
+                .oneshot(request("/Users/user/FavoriteItems/item-1")),
+            router
+                .clone()
+                .oneshot(request("/Users/user/FavoriteItems/item-1")),
+        );
+        let third = router
+            .clone()
+            .oneshot(request("/Users/user/FavoriteItems/item-1"))
+            .await
+            .unwrap();
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 1);
+        for response in [first.unwrap(), second.unwrap(), third] {
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(body(response).await, r#"{"IsFavorite":true,"Call":1}"#);
+        }
+
+        let other = router
+            .oneshot(request("/Users/user/FavoriteItems/item-2"))
+            .await
+            .unwrap();
+        assert_eq!(body(other).await, r#"{"IsFavorite":true,"Call":2}"#);
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn a_different_mutation_of_the_path_clears_the_cached_one() {
+        let state = create_test_app_state().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let favorite = Arc::new(Mutex::new(false));
+        let (set, unset) = (favorite.clone(), favorite.clone());
+        let router = axum::Router::new()
+            .route(
+                "/UserFavoriteItems/{id}",
+                axum::routing::post(move || {
+                    let favorite = set.clone();
+                    async move {
+                        *favorite.lock().unwrap() = true;
+                        r#"{"IsFavorite":true}"#
+                    }
+                })
+                .delete(move || {
+                    let favorite = unset.clone();
+                    async move {
+                        *favorite.lock().unwrap() = false;
+                        r#"{"IsFavorite":false}"#
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                dedup_mutations,
+            ))
+            .with_state(state);
+
+        let request = |method: &str| {
+            let authorization = Authorization {
+                client: "Jellyfin Web".to_string(),
+                device: "Firefox".to_string(),
+                device_id: "tv".to_string(),
+                version: "10.10.7".to_string(),
+                token: Some(user.virtual_key.clone()),
+            };
+            Request::builder()
+                .method(method)
+                .uri("/UserFavoriteItems/item-1")
+                .header("authorization", authorization.to_header_value())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for (method, expected) in [("POST", true), ("DELETE", false), ("POST", true)] {
+            let response = router.clone().oneshot(request(method)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(*favorite.lock().unwrap(), expected);
+        }
+    }
+}
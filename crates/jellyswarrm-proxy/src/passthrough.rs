@@ -0,0 +1,166 @@
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::{debug, error};
+
+use crate::{
+    handlers::videos::proxy_request, proxy_headers::is_hop_by_hop_header,
+    url_helper::join_server_url, AppState,
+};
+
+/// With `disable_id_remapping` on, forward every client request unchanged to the
+/// highest-priority server and return its response as it came, so no id is ever
+/// translated. The management UI keeps working. Meant for troubleshooting only:
+/// clients talk to a single backend and log in with its own accounts.
+pub async fn passthrough_without_remapping(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !state.id_remapping_disabled().await {
+        return next.run(req).await;
+    }
+
+    let ui_route = format!("/{}", state.config.read().await.ui_route);
+    let path = req.uri().path();
+    if path == ui_route || path.starts_with(&format!("{ui_route}/")) {
+        return next.run(req).await;
+    }
+
+    match forward_unchanged(&state, req).await {
+        Ok(response) => response,
+        Err(status) => status.into_response(),
+    }
+}
+
+async fn forward_unchanged(state: &AppState, req: Request) -> Result<Response, StatusCode> {
+    let server = match state.server_storage.get_best_server().await {
+        Ok(Some(server)) => server,
+        Ok(None) => {
+            error!("No server to pass {} through to", req.uri());
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        Err(e) => {
+            error!("Failed to get best server: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let (parts, body) = req.into_parts();
+    let mut url = join_server_url(&server.url, parts.uri.path());
+    url.set_query(parts.uri.query());
+    debug!("Passing {} {} through to {}", parts.method, parts.uri, url);
+
+    let body = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+        error!("Failed to read request body: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let mut request = state
+        .streaming_reqwest_client
+        .request(parts.method, url)
+        .body(body);
+    for (name, value) in &parts.headers {
+        if name != axum::http::header::HOST && !is_hop_by_hop_header(name) {
+            request = request.header(name, value);
+        }
+    }
+    let request = request.build().map_err(|e| {
+        error!("Failed to build passthrough request: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    proxy_request(&state.streaming_reqwest_client, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, MediaStreamingMode};
+    use crate::test_support::create_test_app_state_with_config;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+    use wiremock::{
+        matchers::{header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn create_test_app_state() -> AppState {
+        create_test_app_state_with_config(AppConfig {
+            disable_id_remapping: true,
+            ..AppConfig::default()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn ids_pass_through_untouched_when_remapping_is_disabled() {
+        let state = create_test_app_state().await;
+        let upstream = MockServer::start().await;
+        let item_id = "0123456789abcdef0123456789abcdef";
+        let parent_id = "fedcba9876543210fedcba9876543210";
+        let body = serde_json::json!({
+            "Id": item_id,
+            "ParentId": parent_id,
+            "ServerId": "backend-server-id",
+            "Name": "Heat"
+        });
+        Mock::given(method("GET"))
+            .and(path(format!("/Items/{item_id}")))
+            .and(query_param("ParentId", parent_id))
+            .and(header("X-Emby-Token", "backend-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body.clone()))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        state
+            .server_storage
+            .add_server("Home", &upstream.uri(), 100, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+
+        let app = Router::new()
+            .route("/{*path}", get(|| async { StatusCode::IM_A_TEAPOT }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                passthrough_without_remapping,
+            ))
+            .with_state(state.clone());
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/Items/{item_id}?ParentId={parent_id}"))
+                    .header("X-Emby-Token", "backend-token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let returned: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(returned, body);
+        upstream.verify().await;
+
+        let ui_route = state.config.read().await.ui_route.to_string();
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/{ui_route}/servers"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    }
+}
@@ -60,8 +60,14 @@ pub static RESPONSE_MEDIA_ID_FIELDS: LazyLock<FieldMatcher> = LazyLock::new(|| {
     ])
 });
 
-pub static DELIVERY_URL_FIELDS: LazyLock<FieldMatcher> =
-    LazyLock::new(|| FieldMatcher::new(&["DeliveryUrl", "TranscodingUrl", "StreamUrl"]));
+pub static DELIVERY_URL_FIELDS: LazyLock<FieldMatcher> = LazyLock::new(|| {
+    FieldMatcher::new(&[
+        "DeliveryUrl",
+        "TranscodingUrl",
+        "StreamUrl",
+        "DirectStreamUrl",
+    ])
+});
 
 pub static DISABLED_BOOL_FIELDS: LazyLock<FieldMatcher> =
     LazyLock::new(|| FieldMatcher::new(&["CanDelete", "CanDownload"]));
@@ -0,0 +1,305 @@
+use std::ops::Range;
+
+use serde_json::Value;
+
+/// The kinds of token [`scan_tokens`] offers to its selector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// An object key.
+    Key,
+    /// A string value.
+    String,
+    /// A `true` literal.
+    True,
+}
+
+/// Where a token sits in a JSON document.
+pub struct TokenPosition<'a> {
+    /// The object key the value belongs to. For array elements this is the key of
+    /// the array, and for object keys it is the key itself.
+    pub key: &'a str,
+    pub in_array: bool,
+    pub kind: TokenKind,
+    /// Keys of the enclosing containers, outermost first; `None` for array
+    /// elements and the document root.
+    pub ancestors: &'a [Option<String>],
+}
+
+/// A token picked by [`scan_tokens`], with what the selector tagged it as.
+pub struct ScannedToken<T> {
+    /// Byte range of the token in the document, including the quotes of strings.
+    pub range: Range<usize>,
+    /// The decoded string, or `true` for literals.
+    pub value: String,
+    pub tag: T,
+}
+
+struct Frame {
+    is_object: bool,
+    key: Option<String>,
+    expects_key: bool,
+}
+
+/// Walk a JSON document without building it in memory and collect the object
+/// keys, string values and `true` literals `select` tags. Keys are passed on as
+/// written, so the selector decides how to compare them.
+///
+/// Returns `None` when the document is not well-formed enough to be scanned.
+pub fn scan_tokens<T>(
+    body: &[u8],
+    mut select: impl FnMut(&TokenPosition<'_>) -> Option<T>,
+) -> Option<Vec<ScannedToken<T>>> {
+    let mut stack: Vec<Frame> = Vec::new();
+    // Keys of the containers on `stack`, kept alongside so they can be lent out.
+    let mut ancestors: Vec<Option<String>> = Vec::new();
+    let mut current_key: Option<String> = None;
+    let mut selected = Vec::new();
+    let mut index = 0;
+
+    while index < body.len() {
+        match body[index] {
+            b'{' | b'[' => {
+                let is_object = body[index] == b'{';
+                let key = current_key.take();
+                ancestors.push(key.clone());
+                stack.push(Frame {
+                    is_object,
+                    key,
+                    expects_key: is_object,
+                });
+                index += 1;
+            }
+            b'}' | b']' => {
+                stack.pop()?;
+                ancestors.pop();
+                current_key = None;
+                index += 1;
+            }
+            b',' => {
+                let frame = stack.last_mut()?;
+                frame.expects_key = frame.is_object;
+                current_key = None;
+                index += 1;
+            }
+            b'"' => {
+                let end = string_end(body, index)?;
+                let range = index..end;
+                index = end;
+
+                let Some(frame) = stack.last_mut() else {
+                    continue;
+                };
+                if frame.expects_key {
+                    frame.expects_key = false;
+                    let key = decode_string(&body[range.clone()])?;
+                    let position = TokenPosition {
+                        key: &key,
+                        in_array: false,
+                        kind: TokenKind::Key,
+                        ancestors: &ancestors,
+                    };
+                    if let Some(tag) = select(&position) {
+                        selected.push(ScannedToken {
+                            range,
+                            value: key.clone(),
+                            tag,
+                        });
+                    }
+                    current_key = Some(key);
+                    continue;
+                }
+
+                if let Some(position) =
+                    value_position(frame, &current_key, TokenKind::String, &ancestors)
+                {
+                    if let Some(tag) = select(&position) {
+                        let value = decode_string(&body[range.clone()])?;
+                        selected.push(ScannedToken { range, value, tag });
+                    }
+                }
+            }
+            b't' if body[index..].starts_with(b"true") => {
+                let range = index..index + 4;
+                index = range.end;
+
+                let Some(frame) = stack.last() else {
+                    continue;
+                };
+                if let Some(position) =
+                    value_position(frame, &current_key, TokenKind::True, &ancestors)
+                {
+                    if let Some(tag) = select(&position) {
+                        let value = "true".to_string();
+                        selected.push(ScannedToken { range, value, tag });
+                    }
+                }
+            }
+            _ => index += 1,
+        }
+    }
+
+    stack.is_empty().then_some(selected)
+}
+
+fn value_position<'a>(
+    frame: &'a Frame,
+    current_key: &'a Option<String>,
+    kind: TokenKind,
+    ancestors: &'a [Option<String>],
+) -> Option<TokenPosition<'a>> {
+    let (key, in_array) = if frame.is_object {
+        (current_key.as_deref(), false)
+    } else {
+        (frame.key.as_deref(), true)
+    };
+    Some(TokenPosition {
+        key: key?,
+        in_array,
+        kind,
+        ancestors,
+    })
+}
+
+/// Index just past the closing quote of the string starting at `start`.
+fn string_end(body: &[u8], start: usize) -> Option<usize> {
+    let mut index = start + 1;
+    while index < body.len() {
+        match body[index] {
+            b'\\' => index += 2,
+            b'"' => return Some(index + 1),
+            _ => index += 1,
+        }
+    }
+    None
+}
+
+fn decode_string(token: &[u8]) -> Option<String> {
+    if !token.contains(&b'\\') {
+        return std::str::from_utf8(&token[1..token.len() - 1])
+            .ok()
+            .map(str::to_string);
+    }
+    serde_json::from_slice(token).ok()
+}
+
+/// Replace the tokens at the ranges of `replacements`, which must be in
+/// document order and not overlap, with the given values encoded as JSON.
+pub fn splice_tokens(body: &[u8], replacements: &[(Range<usize>, Value)]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(body.len());
+    let mut copied = 0;
+    for (range, value) in replacements {
+        output.extend_from_slice(&body[copied..range.start]);
+        // Serializing a value without maps can't fail.
+        output.extend(serde_json::to_vec(value).unwrap_or_default());
+        copied = range.end;
+    }
+    output.extend_from_slice(&body[copied..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selected_values(body: &str, field: &str) -> Vec<String> {
+        scan_tokens(body.as_bytes(), |position| {
+            (position.kind == TokenKind::String && position.key.eq_ignore_ascii_case(field))
+                .then_some(())
+        })
+        .unwrap()
+        .into_iter()
+        .map(|scanned| scanned.value)
+        .collect()
+    }
+
+    #[test]
+    fn values_are_matched_by_their_key_at_any_depth() {
+        let body = r#"{"Items":[{"Id":"a","Name":"Id","UserData":{"Key":"x","Id":"b"}},
+            {"id":"c","Tags":["Id"],"Chapters":[]}],"Id":"d"}"#;
+        assert_eq!(selected_values(body, "Id"), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn array_elements_use_the_key_of_their_array() {
+        let body = r#"{"ArtistIds":["a","b"],"Other":[{"ArtistIds":"c"}],"Name":"ArtistIds"}"#;
+        let scanned = scan_tokens(body.as_bytes(), |position| {
+            (position.key == "ArtistIds" && position.in_array).then_some(())
+        })
+        .unwrap();
+        let values = scanned
+            .iter()
+            .map(|scanned| scanned.value.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(values, ["a", "b"]);
+    }
+
+    #[test]
+    fn escaped_strings_are_decoded_and_spliced() {
+        let body = r#"{"Name":"say \"hi\"","Id":"abc"}"#;
+        let scanned = scan_tokens(body.as_bytes(), |position| {
+            (position.kind == TokenKind::String).then_some(())
+        })
+        .unwrap();
+        assert_eq!(scanned[0].value, "say \"hi\"");
+        assert_eq!(scanned[1].value, "abc");
+
+        let spliced = splice_tokens(
+            body.as_bytes(),
+            &[(scanned[1].range.clone(), Value::from("new \"id\""))],
+        );
+        let spliced: Value = serde_json::from_slice(&spliced).unwrap();
+        assert_eq!(spliced["Id"], "new \"id\"");
+        assert_eq!(spliced["Name"], "say \"hi\"");
+    }
+
+    #[test]
+    fn object_keys_are_offered_with_their_enclosing_keys() {
+        let body = r#"{"Trickplay":{"abc":{"320":{"Width":320}}},"Name":"Trickplay"}"#;
+        let scanned = scan_tokens(body.as_bytes(), |position| {
+            let parent = position.ancestors.last().cloned().flatten();
+            (position.kind == TokenKind::Key && parent.as_deref() == Some("Trickplay"))
+                .then_some(())
+        })
+        .unwrap();
+        assert_eq!(scanned.len(), 1);
+        assert_eq!(scanned[0].value, "abc");
+
+        let spliced = splice_tokens(
+            body.as_bytes(),
+            &[(scanned[0].range.clone(), Value::from("xyz"))],
+        );
+        assert_eq!(
+            String::from_utf8(spliced).unwrap(),
+            body.replace(r#""abc""#, r#""xyz""#)
+        );
+    }
+
+    #[test]
+    fn true_literals_can_be_replaced() {
+        let body = r#"{"CanDelete": true,"Flags":[true],"Name":"true","IsHD":false}"#;
+        let scanned = scan_tokens(body.as_bytes(), |position| {
+            (position.kind == TokenKind::True).then_some(position.key.to_string())
+        })
+        .unwrap();
+        let keys = scanned
+            .iter()
+            .map(|scanned| scanned.tag.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["CanDelete", "Flags"]);
+
+        let spliced = splice_tokens(
+            body.as_bytes(),
+            &[(scanned[0].range.clone(), Value::Bool(false))],
+        );
+        assert_eq!(
+            String::from_utf8(spliced).unwrap(),
+            r#"{"CanDelete": false,"Flags":[true],"Name":"true","IsHD":false}"#
+        );
+    }
+
+    #[test]
+    fn truncated_documents_are_rejected() {
+        assert!(scan_tokens(br#"{"Id":"a""#, |_| Some(())).is_none());
+        assert!(scan_tokens(br#"{"Id":"a"#, |_| Some(())).is_none());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod field_matcher;
 mod json_processor;
+pub mod json_scanner;
 pub mod request_analyzer;
 pub mod request_processor;
 pub mod response_processor;
@@ -28,17 +28,28 @@ pub struct RequestBodyAnalysisResult {
     pub found_session_ids: Vec<String>,
     pub found_user_ids: Vec<String>,
     pub servers: Vec<Server>,
+    /// Servers of the tracked play sessions named in the body.
+    pub play_session_servers: Vec<Server>,
     pub users: Vec<User>,
 }
 
 impl RequestBodyAnalysisResult {
-    /// Returns the server with the highest occurance in the servers vector, or None if the vector is empty.
+    /// Returns the server of a tracked play session in the body, as that is where the
+    /// playback was started. Otherwise the server with the highest occurance in the
+    /// servers vector, or None if the vector is empty.
     pub fn get_server(&self) -> Option<Server> {
-        if self.servers.is_empty() {
+        if let Some(server) = Self::most_common_server(&self.play_session_servers) {
+            return Some(server);
+        }
+        Self::most_common_server(&self.servers)
+    }
+
+    fn most_common_server(servers: &[Server]) -> Option<Server> {
+        if servers.is_empty() {
             return None;
         }
         let mut server_count = std::collections::HashMap::new();
-        for server in &self.servers {
+        for server in servers {
             *server_count.entry(server).or_insert(0) += 1;
         }
         let (most_common_server, _) = server_count.into_iter().max_by_key(|&(_, count)| count)?;
@@ -100,7 +111,7 @@ impl JsonAnalyzer<RequestAnalysisContext, RequestBodyAnalysisResult> for Request
                         .get_server_by_id(play_session.server_id)
                         .await?
                     {
-                        accumulator.servers.push(server);
+                        accumulator.play_session_servers.push(server);
                     } else {
                         self.data_context
                             .play_sessions
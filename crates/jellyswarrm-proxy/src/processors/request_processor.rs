@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use serde_json::Value;
 use tracing::{debug, info};
 
-use crate::processors::field_matcher::{ID_FIELDS, SESSION_FIELDS, USER_FIELDS};
+use crate::processors::field_matcher::{ID_FIELDS, SERVER_ID_FIELDS, SESSION_FIELDS, USER_FIELDS};
 use crate::processors::json_processor::{
     JsonProcessingContext, JsonProcessingResult, JsonProcessor,
 };
@@ -94,6 +94,27 @@ impl JsonProcessor<RequestProcessingContext> for RequestProcessor {
                 }
             }
         }
+        // The proxy's own server id stands for the backend the request goes to
+        else if SERVER_ID_FIELDS.contains(&json_context.key) {
+            if let Value::String(ref server_id) = value {
+                let proxy_server_id = self.data_context.config.read().await.server_id.clone();
+                if *server_id == proxy_server_id {
+                    if let Some(upstream_server_id) = self
+                        .data_context
+                        .server_storage
+                        .upstream_server_id(context.server.id)
+                        .await
+                    {
+                        debug!(
+                            "Replacing server ID {} -> {} in payload",
+                            server_id, upstream_server_id
+                        );
+                        *value = Value::String(upstream_server_id);
+                        result = result.mark_modified();
+                    }
+                }
+            }
+        }
         // Handle any other request-specific transformations
         else {
             // Handle any other request-specific transformations
@@ -112,7 +133,7 @@ mod tests {
 
     use super::*;
     use crate::{
-        config::{AppConfig, MediaStreamingMode, MIGRATOR},
+        config::{AppConfig, AuthorizationHeaderMode, MediaStreamingMode, MIGRATOR},
         media_storage_service::MediaStorageService,
         processors::process_json,
         server_id::ServerId,
@@ -151,6 +172,9 @@ mod tests {
             url: ServerUrl::parse("http://server.example:8096").unwrap(),
             priority: 0,
             media_streaming_mode: MediaStreamingMode::Redirect,
+            authorization_header_mode: AuthorizationHeaderMode::Normalize,
+            max_connections: None,
+            backend_id: None,
             created_at: now,
             updated_at: now,
         }
@@ -1,6 +1,8 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
-use serde_json::{Map, Value};
-use tracing::debug;
+use serde_json::{json, Map, Value};
+use tracing::{debug, warn};
 
 use crate::{
     processors::{
@@ -10,12 +12,17 @@ use crate::{
             NAME_FIELDS, RESPONSE_MEDIA_ID_FIELDS, SERVER_ID_FIELDS,
         },
         json_processor::{JsonProcessingContext, JsonProcessingResult, JsonProcessor},
+        json_scanner::{scan_tokens, splice_tokens, TokenKind, TokenPosition},
         url_processor::UrlProcessor,
     },
     server_storage::Server,
     DataContext,
 };
 
+/// Field added to federated media items naming the server they come from when
+/// `tag_source_server` is enabled.
+pub const SOURCE_SERVER_FIELD: &str = "JellyswarrmServer";
+
 pub struct ResponseProcessor {
     pub data_context: DataContext,
     url_processor: UrlProcessor,
@@ -38,16 +45,196 @@ impl ResponseProcessor {
             .map_err(|e| format!("failed to create media mapping for {id}: {e}"))
     }
 
+    /// Create the mappings of the media ids in `payload` in one batch before the JSON
+    /// walk rewrites them, so a large browse costs one write transaction rather than
+    /// one per item. Ids this misses are still mapped one by one during the walk.
+    pub async fn prefetch_media_mappings(
+        &self,
+        payload: &Value,
+        context: &ResponseProcessingContext,
+    ) -> Result<(), sqlx::Error> {
+        if !context.rewrites_media_fields() {
+            return Ok(());
+        }
+
+        let mut ids = Vec::new();
+        collect_media_ids(payload, false, false, &mut ids);
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        self.data_context
+            .media_storage
+            .get_or_create_media_mappings(&ids, &context.server)
+            .await
+            .map(|_| ())
+    }
+
     async fn remap_delivery_url(
         &self,
         value: &str,
         context: &ResponseProcessingContext,
     ) -> Result<Option<String>, String> {
         self.url_processor
-            .server_to_client_delivery_url(value, &context.server, context.proxy_api_key.as_deref())
+            .server_to_client_delivery_url(
+                value,
+                &context.server,
+                context.proxy_api_key.as_deref(),
+                &context.proxy_server_id,
+                context.public_base_url.as_ref(),
+            )
             .await
             .map_err(|e| e.to_string())
     }
+
+    /// Remap the media ids, server ids, delivery URLs and disabled flags of a raw
+    /// JSON body without parsing it into a tree. Used for responses too large to
+    /// process in full and in streaming mode, so item names are passed on as the
+    /// backend sent them.
+    ///
+    /// Returns `None` when nothing was remapped or the body couldn't be scanned.
+    pub async fn rewrite_known_fields(
+        &self,
+        body: &[u8],
+        context: &ResponseProcessingContext,
+    ) -> Result<Option<Vec<u8>>, String> {
+        if context.profile == ResponseProcessingProfile::Disabled {
+            return Ok(None);
+        }
+
+        let rewrites_media_fields = context.rewrites_media_fields();
+        let Some(fields) = scan_tokens(body, |position| {
+            known_field(position, rewrites_media_fields)
+        }) else {
+            warn!("Skipping lightweight response rewrite because the body could not be scanned");
+            return Ok(None);
+        };
+
+        // One write transaction for every new id; the lookups below are then cached.
+        let media_ids = fields
+            .iter()
+            .filter(|field| matches!(field.tag, KnownField::MediaId))
+            .map(|field| field.value.as_str())
+            .collect::<Vec<_>>();
+        self.data_context
+            .media_storage
+            .get_or_create_media_mappings(&media_ids, &context.server)
+            .await
+            .map_err(|e| format!("failed to create media mappings: {e}"))?;
+
+        let mut virtual_ids: HashMap<String, String> = HashMap::new();
+        let mut replacements = Vec::with_capacity(fields.len());
+        for field in fields {
+            let replacement = match field.tag {
+                KnownField::MediaId => match virtual_ids.get(&field.value) {
+                    Some(virtual_id) => virtual_id.clone(),
+                    None => {
+                        let virtual_id =
+                            self.virtual_media_id(&field.value, &context.server).await?;
+                        virtual_ids.insert(field.value, virtual_id.clone());
+                        virtual_id
+                    }
+                }
+                .into(),
+                KnownField::ServerId => context.proxy_server_id.as_str().into(),
+                KnownField::DeliveryUrl => {
+                    match self.remap_delivery_url(&field.value, context).await? {
+                        Some(remapped) => remapped.into(),
+                        None => continue,
+                    }
+                }
+                KnownField::DisabledFlag => Value::Bool(false),
+            };
+            replacements.push((field.range, replacement));
+        }
+
+        if replacements.is_empty() {
+            return Ok(None);
+        }
+        debug!(
+            "Rewrote {} fields without a full parse ({} distinct media ids)",
+            replacements.len(),
+            virtual_ids.len()
+        );
+        Ok(Some(splice_tokens(body, &replacements)))
+    }
+}
+
+/// Fields remapped by [`ResponseProcessor::rewrite_known_fields`].
+enum KnownField {
+    MediaId,
+    ServerId,
+    DeliveryUrl,
+    DisabledFlag,
+}
+
+fn known_field(position: &TokenPosition<'_>, rewrites_media_fields: bool) -> Option<KnownField> {
+    match position.kind {
+        TokenKind::Key => {
+            return (rewrites_media_fields && is_media_id_map_key(position.ancestors))
+                .then_some(KnownField::MediaId);
+        }
+        TokenKind::True => {
+            return (rewrites_media_fields
+                && !position.in_array
+                && DISABLED_BOOL_FIELDS.contains(position.key))
+            .then_some(KnownField::DisabledFlag);
+        }
+        TokenKind::String => {}
+    }
+
+    if rewrites_media_fields {
+        if position.in_array {
+            return MEDIA_ID_ARRAY_FIELDS
+                .contains(position.key)
+                .then_some(KnownField::MediaId);
+        }
+
+        let parent = position.ancestors.last().and_then(Option::as_deref);
+        if parent.is_some_and(|parent| MEDIA_ID_MAP_VALUE_FIELDS.contains(parent)) {
+            return Some(KnownField::MediaId);
+        }
+
+        let is_legacy_unmapped = |key: &str, ancestor: &str| {
+            position.key.eq_ignore_ascii_case(key)
+                && position
+                    .ancestors
+                    .iter()
+                    .flatten()
+                    .any(|segment| segment.eq_ignore_ascii_case(ancestor))
+        };
+        if RESPONSE_MEDIA_ID_FIELDS.contains(position.key)
+            && !is_legacy_unmapped("ItemId", "UserData")
+            && !is_legacy_unmapped("Etag", "MediaSources")
+        {
+            return Some(KnownField::MediaId);
+        }
+    }
+
+    if position.in_array {
+        None
+    } else if DELIVERY_URL_FIELDS.contains(position.key) {
+        Some(KnownField::DeliveryUrl)
+    } else if rewrites_media_fields && SERVER_ID_FIELDS.contains(position.key) {
+        Some(KnownField::ServerId)
+    } else {
+        None
+    }
+}
+
+/// Whether keys of the object at `ancestors` are media ids, as
+/// [`should_remap_map_key`] decides for a parsed document.
+fn is_media_id_map_key(ancestors: &[Option<String>]) -> bool {
+    let mut keys = ancestors.iter().flatten();
+    if ancestors
+        .last()
+        .and_then(Option::as_deref)
+        .is_some_and(|parent| MEDIA_ID_MAP_KEY_FIELDS.contains(parent))
+    {
+        return true;
+    }
+    keys.position(|key| MEDIA_ID_NESTED_MAP_KEY_FIELDS.contains(key))
+        .is_some_and(|_| keys.next().is_some())
 }
 
 pub struct ResponseProcessingContext {
@@ -57,6 +244,10 @@ pub struct ResponseProcessingContext {
     pub profile: ResponseProcessingProfile,
     pub should_change_name: bool,
     pub can_change_item_names: bool,
+    pub tag_source_server: bool,
+    /// Where absolute backend URLs are pointed instead; they become root-relative
+    /// when it is unknown.
+    pub public_base_url: Option<url::Url>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -154,6 +345,15 @@ impl JsonProcessor<ResponseProcessingContext> for ResponseProcessor {
                     Err(e) => result = result.add_error(e),
                 }
             }
+            if should_tag_source_server(json_context, context) {
+                result = result.add_field(
+                    SOURCE_SERVER_FIELD.to_string(),
+                    json!({
+                        "Name": context.server.name,
+                        "Id": context.server.id.to_string(),
+                    }),
+                );
+            }
         } else if DELIVERY_URL_FIELDS.contains(&json_context.key) {
             if let Some(delivery_url) = value.as_str().map(str::to_string) {
                 match self.remap_delivery_url(&delivery_url, context).await {
@@ -188,6 +388,54 @@ impl JsonProcessor<ResponseProcessingContext> for ResponseProcessor {
     }
 }
 
+/// The media ids [`ResponseProcessor::process`] will rewrite in `value`, except the
+/// keys of nested maps such as `ImageBlurHashes`.
+fn collect_media_ids<'a>(
+    value: &'a Value,
+    in_user_data: bool,
+    in_media_sources: bool,
+    ids: &mut Vec<&'a str>,
+) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if MEDIA_ID_ARRAY_FIELDS.contains(key) {
+                    if let Some(items) = value.as_array() {
+                        ids.extend(items.iter().filter_map(Value::as_str));
+                    }
+                } else if MEDIA_ID_MAP_VALUE_FIELDS.contains(key) {
+                    if let Some(map) = value.as_object() {
+                        ids.extend(map.values().filter_map(Value::as_str));
+                    }
+                } else if MEDIA_ID_MAP_KEY_FIELDS.contains(key) {
+                    if let Some(map) = value.as_object() {
+                        ids.extend(map.keys().map(String::as_str));
+                    }
+                } else if RESPONSE_MEDIA_ID_FIELDS.contains(key) {
+                    let is_legacy = (in_user_data && key.eq_ignore_ascii_case("ItemId"))
+                        || (in_media_sources && key.eq_ignore_ascii_case("Etag"));
+                    if let Some(id) = value.as_str().filter(|_| !is_legacy) {
+                        ids.push(id);
+                    }
+                }
+
+                collect_media_ids(
+                    value,
+                    in_user_data || key.eq_ignore_ascii_case("UserData"),
+                    in_media_sources || key.eq_ignore_ascii_case("MediaSources"),
+                    ids,
+                );
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_media_ids(item, in_user_data, in_media_sources, ids);
+            }
+        }
+        _ => {}
+    }
+}
+
 fn should_remap_map_value(parent_path: &str) -> bool {
     MEDIA_ID_MAP_VALUE_FIELDS.contains(last_segment(parent_path))
 }
@@ -235,6 +483,15 @@ fn should_change_name(
         && !is_live_tv_item(json_context.parent_object.as_ref())
 }
 
+fn should_tag_source_server(
+    json_context: &JsonProcessingContext,
+    context: &ResponseProcessingContext,
+) -> bool {
+    context.tag_source_server
+        && json_context.key.eq_ignore_ascii_case("Id")
+        && is_media_item_root_path(&json_context.parent_path)
+}
+
 fn is_media_item_root_path(parent_path: &str) -> bool {
     parent_path.is_empty()
         || (parent_path.starts_with('[') && !parent_path.contains('.'))
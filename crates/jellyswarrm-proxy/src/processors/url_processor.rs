@@ -5,7 +5,7 @@ use crate::{
     media_storage_service::MediaMapping,
     server_id::ServerId,
     server_storage::Server,
-    url_helper::{contains_id, is_id_like, replace_id},
+    url_helper::{contains_id, is_id_like, join_server_url, replace_id},
     user_authorization_service::AuthorizationSession,
     virtual_library_service::{VirtualLibraryAccessScope, VirtualLibraryResolution},
     DataContext,
@@ -13,6 +13,7 @@ use crate::{
 
 pub static MEDIA_ID_PATH_TAGS: &[&str] = &[
     "Items",
+    "Collections",
     "Audio",
     "Shows",
     "Videos",
@@ -45,6 +46,11 @@ pub static MEDIA_ID_QUERY_TAGS: &[&str] = &[
 pub static USER_ID_PATH_TAGS: &[&str] = &["Users"];
 pub static USER_ID_QUERY_TAGS: &[&str] = &["UserId"];
 pub static API_KEY_QUERY_TAGS: &[&str] = &["api_key", "ApiKey"];
+pub static DEVICE_ID_QUERY_TAGS: &[&str] = &["DeviceId"];
+pub static PARENT_ID_QUERY_TAGS: &[&str] = &["ParentId"];
+pub static PLAY_SESSION_ID_QUERY_TAGS: &[&str] = &["PlaySessionId", "SessionId"];
+pub static MAX_STREAMING_BITRATE_QUERY_TAGS: &[&str] = &["MaxStreamingBitrate"];
+pub static SERVER_ID_QUERY_TAGS: &[&str] = &["ServerId"];
 
 pub struct UrlProcessor {
     data_context: DataContext,
@@ -74,27 +80,102 @@ impl UrlProcessor {
         self.replace_session_query_values(&mut pairs, session);
         self.replace_media_ids_in_query(&mut pairs, access_scope, required_server_id)
             .await;
+        if let Some(server_id) = required_server_id {
+            self.replace_server_id_in_query(&mut pairs, server_id).await;
+        }
 
         url.query_pairs_mut().clear().extend_pairs(pairs);
     }
 
+    /// Rewrite a backend delivery URL (stream, subtitle, trickplay, ...) so it points
+    /// back at the proxy. A `ServerId` in it is replaced with the proxy's own id, so a
+    /// resource keeps the same URL whichever backend served it.
+    ///
+    /// Absolute URLs on the backend point at `public_base_url`, or become root-relative
+    /// when it is unknown, as clients often can't reach the backend themselves.
+    /// Absolute URLs on any other host, such as external subtitles served by a third
+    /// party, are left alone.
     pub async fn server_to_client_delivery_url(
         &self,
         value: &str,
         server: &Server,
         proxy_api_key: Option<&str>,
+        proxy_server_id: &str,
+        public_base_url: Option<&url::Url>,
     ) -> Result<Option<String>> {
-        let Some((mut url, style)) = parse_delivery_url(value) else {
+        let Some((mut url, mut style)) = parse_delivery_url(value) else {
             return Ok(None);
         };
+        let was_absolute = matches!(style, DeliveryUrlStyle::Absolute);
+        if was_absolute {
+            let Some(relative) = strip_server_base(&url, server) else {
+                return Ok(None);
+            };
+            url = relative;
+            style = DeliveryUrlStyle::RootRelative;
+        }
 
         self.remap_delivery_url_path(&mut url, server).await?;
-        self.remap_delivery_url_query(&mut url, server, proxy_api_key)
+        self.remap_delivery_url_query(&mut url, server, proxy_api_key, proxy_server_id)
             .await?;
 
+        // Absolute backend URLs stay absolute when the proxy's public address is known.
+        if let Some(base) = public_base_url.filter(|_| was_absolute) {
+            let mut public = join_server_url(base, url.path());
+            public.set_query(url.query());
+            public.set_fragment(url.fragment());
+            return Ok(Some(public.to_string()));
+        }
+
         Ok(Some(format_delivery_url(url, style)))
     }
 
+    /// Media ids in the path or query of a client URL that neither a media mapping
+    /// nor a virtual library knows.
+    pub async fn unmapped_client_media_ids(&self, url: &url::Url) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for &path_segment in MEDIA_ID_PATH_TAGS {
+            if let Some(media_id) = contains_id(url, path_segment) {
+                ids.push(media_id);
+            }
+        }
+        for (key, value) in url.query_pairs() {
+            if matches_case_insensitive(&key, MEDIA_ID_QUERY_TAGS) {
+                ids.extend(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|id| is_id_like(id))
+                        .map(str::to_string),
+                );
+            }
+        }
+
+        let mut unmapped = Vec::new();
+        for id in ids {
+            if unmapped.contains(&id)
+                || self
+                    .data_context
+                    .media_storage
+                    .get_media_mapping_by_virtual(&id)
+                    .await?
+                    .is_some()
+            {
+                continue;
+            }
+            if let VirtualLibraryResolution::Unknown = self
+                .data_context
+                .virtual_library_service
+                .resolve(&id, None)
+                .await?
+            {
+                unmapped.push(id);
+            }
+        }
+
+        Ok(unmapped)
+    }
+
     pub async fn server_from_client_url(
         &self,
         url: &url::Url,
@@ -104,7 +185,11 @@ impl UrlProcessor {
             return Ok(Some(server));
         }
 
-        self.server_from_query_media_ids(url, access_scope).await
+        if let Some(server) = self.server_from_query_media_ids(url, access_scope).await? {
+            return Ok(Some(server));
+        }
+
+        self.server_from_query_play_session(url, access_scope).await
     }
 
     fn replace_user_ids_in_path(&self, url: &mut url::Url, session: &Option<AuthorizationSession>) {
@@ -183,6 +268,33 @@ impl UrlProcessor {
         }
     }
 
+    /// Swap the proxy's own `ServerId` for the one of the server the request goes to.
+    async fn replace_server_id_in_query(
+        &self,
+        pairs: &mut [(String, String)],
+        server_id: ServerId,
+    ) {
+        let proxy_server_id = self.data_context.config.read().await.server_id.clone();
+        for (name, value) in pairs {
+            if !matches_case_insensitive(name, SERVER_ID_QUERY_TAGS) || *value != proxy_server_id {
+                continue;
+            }
+
+            if let Some(upstream_server_id) = self
+                .data_context
+                .server_storage
+                .upstream_server_id(server_id)
+                .await
+            {
+                debug!(
+                    "Replacing server ID in query: {} -> {}",
+                    value, upstream_server_id
+                );
+                *value = upstream_server_id;
+            }
+        }
+    }
+
     async fn resolve_client_media_id_list(
         &self,
         value: &str,
@@ -245,6 +357,7 @@ impl UrlProcessor {
         url: &mut url::Url,
         server: &Server,
         proxy_api_key: Option<&str>,
+        proxy_server_id: &str,
     ) -> Result<()> {
         let Some(query) = url.query() else {
             return Ok(());
@@ -261,6 +374,11 @@ impl UrlProcessor {
                 } else {
                     value.into_owned()
                 }
+            } else if matches_case_insensitive(&key, SERVER_ID_QUERY_TAGS) {
+                if value != proxy_server_id {
+                    changed = true;
+                }
+                proxy_server_id.to_string()
             } else if matches_case_insensitive(&key, MEDIA_ID_QUERY_TAGS) {
                 let remapped = self.remap_delivery_url_query_value(&value, server).await?;
                 if remapped != value {
@@ -402,6 +520,39 @@ impl UrlProcessor {
         Ok(None)
     }
 
+    /// Resolve the backend from a known `PlaySessionId` query value, as sent by
+    /// clients reporting playback through query parameters.
+    async fn server_from_query_play_session(
+        &self,
+        url: &url::Url,
+        access_scope: Option<&VirtualLibraryAccessScope>,
+    ) -> Result<Option<Server>> {
+        let Some(play_session_id) = find_query_value(url, PLAY_SESSION_ID_QUERY_TAGS) else {
+            return Ok(None);
+        };
+        let Some(play_session) = self
+            .data_context
+            .play_sessions
+            .get_session(&play_session_id)
+            .await
+        else {
+            return Ok(None);
+        };
+
+        let server = self
+            .data_context
+            .server_storage
+            .get_server_by_id(play_session.server_id)
+            .await?;
+        if let Some(server) = &server {
+            debug!(
+                "Found server for play session {}: {} ({})",
+                play_session_id, server.name, server.url
+            );
+        }
+        Ok(server.filter(|server| server_is_allowed(server.id, access_scope, None)))
+    }
+
     async fn server_from_client_media_id(
         &self,
         media_id: &str,
@@ -476,6 +627,27 @@ fn parse_delivery_url(value: &str) -> Option<(url::Url, DeliveryUrlStyle)> {
         .map(|url| (url, style))
 }
 
+/// The part of `url` below the backend's base URL, on a placeholder host, or `None`
+/// when `url` points somewhere else.
+fn strip_server_base(url: &url::Url, server: &Server) -> Option<url::Url> {
+    let base = server.url.as_url();
+    if url.origin() != base.origin() {
+        return None;
+    }
+
+    let base_path = base.path().trim_end_matches('/');
+    let path = url.path().strip_prefix(base_path)?;
+    if !path.is_empty() && !path.starts_with('/') {
+        return None;
+    }
+
+    let mut relative = url::Url::parse("http://localhost").ok()?;
+    relative.set_path(path);
+    relative.set_query(url.query());
+    relative.set_fragment(url.fragment());
+    Some(relative)
+}
+
 fn format_delivery_url(url: url::Url, style: DeliveryUrlStyle) -> String {
     match style {
         DeliveryUrlStyle::Absolute => url.to_string(),
@@ -500,12 +672,21 @@ fn relative_url_from_parts(url: &url::Url) -> String {
     value
 }
 
+/// All query parameter keys are matched through this helper, as clients disagree on
+/// casing (`UserId`, `userId`, `userid`, ...).
 pub fn matches_case_insensitive(value: &str, candidates: &[&str]) -> bool {
     candidates
         .iter()
         .any(|candidate| value.eq_ignore_ascii_case(candidate))
 }
 
+/// Returns the value of the first query parameter whose key matches one of `candidates`.
+pub fn find_query_value(url: &url::Url, candidates: &[&str]) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| matches_case_insensitive(key, candidates))
+        .map(|(_, value)| value.into_owned())
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -518,10 +699,227 @@ mod tests {
         media_storage_service::MediaStorageService,
         server_storage::ServerStorageService,
         session_storage::SessionStorage,
+        user_authorization_service::Device,
         user_authorization_service::UserAuthorizationService,
         virtual_library_service::VirtualLibraryService,
     };
 
+    async fn create_test_processor() -> (UrlProcessor, MediaStorageService, Server) {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        let server_storage = ServerStorageService::new(pool.clone());
+        let server_id = server_storage
+            .add_server(
+                "Upstream",
+                "http://upstream:8096",
+                100,
+                crate::config::MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+        let server = server_storage
+            .get_server_by_id(server_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let media_storage = MediaStorageService::new(pool.clone());
+        let virtual_libraries =
+            VirtualLibraryService::new(pool.clone(), server_storage.clone(), media_storage.clone());
+        let processor = UrlProcessor::new(DataContext {
+            user_authorization: Arc::new(UserAuthorizationService::new(pool)),
+            server_storage: Arc::new(server_storage),
+            media_storage: Arc::new(media_storage.clone()),
+            virtual_library_service: Arc::new(virtual_libraries),
+            play_sessions: Arc::new(SessionStorage::new()),
+            config: Arc::new(tokio::sync::RwLock::new(AppConfig::default())),
+        });
+
+        (processor, media_storage, server)
+    }
+
+    fn test_session() -> AuthorizationSession {
+        let now = chrono::Utc::now();
+        AuthorizationSession {
+            id: 1,
+            user_id: "proxy-user".to_string(),
+            mapping_id: 1,
+            server_url: "http://upstream:8096".to_string(),
+            device: Device {
+                client: "Test".to_string(),
+                device: "Test".to_string(),
+                device_id: "device".to_string(),
+                version: "1.0".to_string(),
+            },
+            jellyfin_token: "upstream-token".to_string(),
+            original_user_id: "upstream-user".to_string(),
+            expires_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[tokio::test]
+    async fn session_query_keys_are_matched_case_insensitively() {
+        let (processor, _, _) = create_test_processor().await;
+        let session = Some(test_session());
+
+        for key in ["UserId", "userId", "userid", "USERID"] {
+            let mut url =
+                url::Url::parse(&format!("http://localhost/Items?{key}=proxy-user")).unwrap();
+            processor
+                .client_to_server_url(&mut url, &session, None, None)
+                .await;
+            assert_eq!(
+                find_query_value(&url, USER_ID_QUERY_TAGS).as_deref(),
+                Some("upstream-user"),
+                "{key} was not recognized"
+            );
+        }
+
+        for key in ["api_key", "ApiKey", "apiKey", "apikey", "API_KEY"] {
+            let mut url =
+                url::Url::parse(&format!("http://localhost/Items?{key}=proxy-key")).unwrap();
+            processor
+                .client_to_server_url(&mut url, &session, None, None)
+                .await;
+            assert_eq!(
+                find_query_value(&url, API_KEY_QUERY_TAGS).as_deref(),
+                Some("upstream-token"),
+                "{key} was not recognized"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn media_id_query_keys_are_matched_case_insensitively() {
+        let (processor, media_storage, server) = create_test_processor().await;
+        let original_id = "11111111111111111111111111111111";
+        let mapping = media_storage
+            .get_or_create_media_mapping(original_id, &server)
+            .await
+            .unwrap();
+
+        for key in [
+            "ParentId",
+            "parentId",
+            "parentid",
+            "ItemId",
+            "itemId",
+            "SeriesId",
+            "seriesId",
+            "MediaSourceId",
+            "mediaSourceId",
+            "SeasonId",
+            "seasonId",
+            "StartItemId",
+            "startItemId",
+            "Ids",
+            "ids",
+            "IDs",
+            "PersonIds",
+            "personIds",
+        ] {
+            let mut url = url::Url::parse(&format!(
+                "http://localhost/Items?{key}={}",
+                mapping.virtual_media_id
+            ))
+            .unwrap();
+            processor
+                .client_to_server_url(&mut url, &None, None, None)
+                .await;
+            assert_eq!(
+                find_query_value(&url, &[key]).as_deref(),
+                Some(original_id),
+                "{key} was not recognized"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn delivery_urls_carry_the_proxy_server_id() {
+        let (processor, media_storage, server) = create_test_processor().await;
+        let original_id = "11111111111111111111111111111111";
+
+        let mut urls = Vec::new();
+        for delivery_url in [
+            format!("/Videos/{original_id}/master.m3u8?ServerId=upstream-server&api_key=upstream-token"),
+            format!("/Videos/{original_id}/master.m3u8?serverId=other-upstream-id&api_key=upstream-token"),
+        ] {
+            urls.push(
+                processor
+                    .server_to_client_delivery_url(
+                        &delivery_url,
+                        &server,
+                        Some("proxy-key"),
+                        "proxy-server",
+                        None,
+                    )
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+
+        let virtual_id = media_storage
+            .get_or_create_media_mapping(original_id, &server)
+            .await
+            .unwrap()
+            .virtual_media_id;
+        assert_eq!(
+            urls[0],
+            format!("/Videos/{virtual_id}/master.m3u8?ServerId=proxy-server&api_key=proxy-key")
+        );
+        let second = url::Url::parse(&format!("http://localhost{}", urls[1])).unwrap();
+        assert_eq!(
+            find_query_value(&second, SERVER_ID_QUERY_TAGS).as_deref(),
+            Some("proxy-server")
+        );
+        assert!(urls[1].starts_with(&format!("/Videos/{virtual_id}/")));
+    }
+
+    #[tokio::test]
+    async fn absolute_delivery_urls_point_at_the_public_base_url() {
+        let (processor, media_storage, server) = create_test_processor().await;
+        let original_id = "11111111111111111111111111111111";
+        let delivery_url =
+            format!("http://upstream:8096/Videos/{original_id}/stream.mp4?api_key=upstream-token");
+        let public_base = url::Url::parse("https://media.example.com/jellyfin").unwrap();
+
+        let public = processor
+            .server_to_client_delivery_url(
+                &delivery_url,
+                &server,
+                Some("proxy-key"),
+                "proxy-server",
+                Some(&public_base),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let relative = processor
+            .server_to_client_delivery_url(
+                &format!("/Videos/{original_id}/stream.mp4"),
+                &server,
+                Some("proxy-key"),
+                "proxy-server",
+                Some(&public_base),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let virtual_id = media_storage
+            .get_or_create_media_mapping(original_id, &server)
+            .await
+            .unwrap()
+            .virtual_media_id;
+        assert_eq!(
+            public,
+            format!("https://media.example.com/jellyfin/Videos/{virtual_id}/stream.mp4?api_key=proxy-key")
+        );
+        assert_eq!(relative, format!("/Videos/{virtual_id}/stream.mp4"));
+    }
+
     #[tokio::test]
     async fn empty_virtual_library_does_not_force_a_routing_server() {
         let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
@@ -531,7 +929,10 @@ mod tests {
         let virtual_libraries =
             VirtualLibraryService::new(pool.clone(), server_storage.clone(), media_storage.clone());
         let library = virtual_libraries
-            .create_group("Empty library")
+            .create_group(
+                "Empty library",
+                crate::config::DeduplicationStrategy::default(),
+            )
             .await
             .unwrap();
         let processor = UrlProcessor::new(DataContext {
@@ -1,4 +1,42 @@
-use axum::http::{HeaderMap, HeaderName};
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use url::Url;
+
+use crate::{url_helper, AppState};
+
+tokio::task_local! {
+    static PUBLIC_BASE_URL: Option<Url>;
+}
+
+/// Middleware that works out the public base URL of each request once, see
+/// [`url_helper::public_base_url`], for [`current_public_base_url`].
+pub async fn resolve_public_base_url(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let trust_forwarded_headers = state.trust_forwarded_headers().await;
+    let base_url = {
+        let config = state.config.read().await;
+        url_helper::public_base_url(
+            req.headers(),
+            config.public_base_url.as_ref().map(|url| url.as_url()),
+            config.url_prefix.as_deref(),
+            trust_forwarded_headers,
+        )
+    };
+    PUBLIC_BASE_URL.scope(base_url, next.run(req)).await
+}
+
+/// Public base URL of the request being handled. `None` outside of a request, in
+/// tasks it spawns and when the address clients use isn't known.
+pub fn current_public_base_url() -> Option<Url> {
+    PUBLIC_BASE_URL.try_with(Clone::clone).ok().flatten()
+}
 
 pub fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
     headers.remove(hyper::header::CONNECTION);
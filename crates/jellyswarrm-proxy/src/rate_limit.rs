@@ -0,0 +1,252 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, error};
+
+use crate::{request_preprocessing::resolve_request_identity_from_headers_uri, AppState};
+
+/// Number of tracked clients above which buckets that have refilled completely are
+/// dropped, as they behave exactly like a client that was never seen.
+const PRUNE_THRESHOLD: usize = 1024;
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token buckets of the clients talking to the proxy, keyed by `(user_id, device_id)`.
+#[derive(Default)]
+pub struct ClientRateLimiter {
+    buckets: Mutex<HashMap<(String, String), TokenBucket>>,
+}
+
+impl ClientRateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take one token from the bucket of `key`. Returns how long the client has to
+    /// wait for the next token when the bucket is empty.
+    pub fn check(
+        &self,
+        key: (String, String),
+        per_second: f64,
+        burst: u32,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let capacity = f64::from(burst.max(1));
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            let full_after = Duration::from_secs_f64(capacity / per_second);
+            buckets.retain(|_, bucket| now.duration_since(bucket.refilled_at) < full_after);
+        }
+
+        let bucket = buckets.entry(key).or_insert(TokenBucket {
+            tokens: capacity,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+/// Whether `path` carries media data. Streams hold a connection open and seek with
+/// bursts of range requests, so they are never limited.
+pub(crate) fn is_streaming_path(path: &str) -> bool {
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    if segments.next().is_some_and(|root| {
+        root.eq_ignore_ascii_case("Videos") || root.eq_ignore_ascii_case("Audio")
+    }) {
+        return true;
+    }
+
+    path.split('/').any(|segment| {
+        let segment = segment.to_ascii_lowercase();
+        segment == "download"
+            || segment == "livestreamfiles"
+            || segment.starts_with("hls")
+            || segment == "stream"
+            || segment.starts_with("stream.")
+    })
+}
+
+/// Middleware that answers `429 Too Many Requests` once a device of a user exceeds
+/// `client_rate_limit_per_second`. Requests without a known user or device are left
+/// to the other checks.
+pub async fn limit_client_requests(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let per_second = state.client_rate_limit_per_second().await;
+    if per_second <= 0.0 || is_streaming_path(req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    let identity =
+        match resolve_request_identity_from_headers_uri(req.headers(), req.uri(), &state).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                error!(
+                    "Failed to resolve identity for rate limiting of {}: {}",
+                    req.uri(),
+                    e
+                );
+                return next.run(req).await;
+            }
+        };
+    let (Some(user), Some(device)) = (identity.user, identity.device) else {
+        return next.run(req).await;
+    };
+
+    let burst = state.client_rate_limit_burst().await;
+    if let Err(wait) = state.rate_limiter.check(
+        (user.id, device.device_id.clone()),
+        per_second,
+        burst,
+        Instant::now(),
+    ) {
+        debug!(
+            "Rate limiting {} from device '{}' ({}) of user '{}'",
+            req.uri().path(),
+            device.device,
+            device.client,
+            user.original_username
+        );
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+        )
+            .into_response();
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_test_app_state_with_config;
+    use crate::{config::AppConfig, models::Authorization};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    async fn create_test_app_state() -> AppState {
+        create_test_app_state_with_config(AppConfig {
+            client_rate_limit_per_second: 1.0,
+            client_rate_limit_burst: 2,
+            ..AppConfig::default()
+        })
+        .await
+    }
+
+    fn key(device_id: &str) -> (String, String) {
+        ("user".to_string(), device_id.to_string())
+    }
+
+    #[test]
+    fn buckets_allow_a_burst_and_then_refill_over_time() {
+        let limiter = ClientRateLimiter::new();
+        let start = Instant::now();
+
+        assert!(limiter.check(key("tv"), 2.0, 3, start).is_ok());
+        assert!(limiter.check(key("tv"), 2.0, 3, start).is_ok());
+        assert!(limiter.check(key("tv"), 2.0, 3, start).is_ok());
+        assert_eq!(
+            limiter.check(key("tv"), 2.0, 3, start),
+            Err(Duration::from_millis(500))
+        );
+        assert!(limiter.check(key("phone"), 2.0, 3, start).is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check(key("tv"), 2.0, 3, later).is_ok());
+        assert!(limiter.check(key("tv"), 2.0, 3, later).is_err());
+    }
+
+    #[test]
+    fn streaming_paths_are_exempt() {
+        assert!(is_streaming_path("/Videos/abc/stream.mkv"));
+        assert!(is_streaming_path("/videos/abc/master.m3u8"));
+        assert!(is_streaming_path("/Audio/abc/universal"));
+        assert!(is_streaming_path("/Items/abc/Download"));
+        assert!(is_streaming_path("/LiveTv/LiveStreamFiles/abc/stream.ts"));
+        assert!(!is_streaming_path("/Sessions"));
+        assert!(!is_streaming_path("/Sessions/Playing/Progress"));
+        assert!(!is_streaming_path("/Users/abc/Items"));
+    }
+
+    #[tokio::test]
+    async fn clients_over_the_limit_get_429_with_retry_after() {
+        let state = create_test_app_state().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let router = axum::Router::new()
+            .route("/{*path}", axum::routing::get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                limit_client_requests,
+            ))
+            .with_state(state);
+
+        let request = |uri: &str, device_id: &str| {
+            let authorization = Authorization {
+                client: "Jellyfin Web".to_string(),
+                device: "Firefox".to_string(),
+                device_id: device_id.to_string(),
+                version: "10.10.7".to_string(),
+                token: Some(user.virtual_key.clone()),
+            };
+            Request::builder()
+                .uri(uri)
+                .header("authorization", authorization.to_header_value())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for _ in 0..2 {
+            let response = router
+                .clone()
+                .oneshot(request("/Sessions", "tv"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = router
+            .clone()
+            .oneshot(request("/Sessions", "tv"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        let response = router
+            .clone()
+            .oneshot(request("/Videos/abc/stream", "tv"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router.oneshot(request("/Sessions", "phone")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,21 +1,53 @@
-use axum::extract::{OriginalUri, Request};
+use axum::extract::{OriginalUri, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 
 use anyhow::{anyhow, Result};
 use axum::http;
 use http_body_util::BodyExt;
+use hyper::StatusCode;
 use std::fmt;
-use tracing::{debug, error};
+use tracing::{debug, error, instrument, warn};
 
+use crate::config::{AuthorizationHeaderMode, UnauthenticatedAuditMode, UnmappedMediaIdMode};
 use crate::models::Authorization;
 use crate::processors::analyze_json;
 use crate::processors::request_analyzer::{RequestAnalysisContext, RequestBodyAnalysisResult};
+use crate::processors::url_processor::{
+    find_query_value, matches_case_insensitive, API_KEY_QUERY_TAGS, DEVICE_ID_QUERY_TAGS,
+    MEDIA_ID_QUERY_TAGS, PARENT_ID_QUERY_TAGS,
+};
 use crate::proxy_headers::remove_hop_by_hop_headers;
 use crate::server_storage::Server;
-use crate::url_helper::join_server_url;
+use crate::url_helper::{join_server_url, replace_id};
 use crate::user_authorization_service::{AuthorizationSession, Device, User};
-use crate::virtual_library_service::VirtualLibraryAccessScope;
+use crate::virtual_library_service::{
+    normalize_library_id, VirtualLibraryAccessScope, VirtualLibraryResolution,
+};
 use crate::AppState;
 
+/// Raised when a request needs a backend but no server has been configured yet.
+#[derive(Debug, thiserror::Error)]
+#[error("No server available")]
+pub struct NoServerAvailable;
+
+/// Raised when a request names a media id nothing is known about and
+/// `unmapped_media_id` is `NotFound`.
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown media id {0}")]
+pub struct UnmappedMediaId(pub String);
+
+/// Status to answer with when `preprocess_request` fails.
+pub fn preprocess_error_status(error: &anyhow::Error) -> StatusCode {
+    if error.is::<NoServerAvailable>() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else if error.is::<UnmappedMediaId>() {
+        StatusCode::NOT_FOUND
+    } else {
+        StatusCode::BAD_REQUEST
+    }
+}
+
 pub struct RequestIdentity {
     pub auth: Option<JellyfinAuthorization>,
     pub user: Option<User>,
@@ -33,17 +65,11 @@ pub async fn resolve_request_identity_from_headers_uri(
     let mut request = reqwest::Request::new(reqwest::Method::GET, url);
     request.headers_mut().extend(headers.clone());
 
-    let auth = JellyfinAuthorization::from_request(&request);
+    let auth =
+        JellyfinAuthorization::from_request(&request, state.legacy_emby_auth_enabled().await);
     let mut device = auth.as_ref().and_then(|a| a.get_device(request.headers()));
     if device.is_none() {
-        let query_device_id = request.url().query_pairs().find_map(|(k, v)| {
-            if k.eq_ignore_ascii_case("deviceid") {
-                Some(v.to_string())
-            } else {
-                None
-            }
-        });
-        if let Some(device_id) = query_device_id {
+        if let Some(device_id) = find_query_value(request.url(), DEVICE_ID_QUERY_TAGS) {
             let ua_device = request
                 .headers()
                 .get("user-agent")
@@ -67,6 +93,98 @@ pub async fn resolve_request_identity_from_headers_uri(
     Ok(RequestIdentity { auth, user, device })
 }
 
+/// Top-level path segments whose endpoints always act on behalf of a user.
+static USER_SCOPED_PATH_ROOTS: &[&str] = &[
+    "Users",
+    "UserViews",
+    "UserItems",
+    "UserFavoriteItems",
+    "UserPlayedItems",
+    "Sessions",
+];
+
+/// Endpoints below `/Users` that clients call before they have a token.
+static PUBLIC_USER_PATHS: &[&str] = &[
+    "AuthenticateByName",
+    "AuthenticateWithQuickConnect",
+    "Public",
+    "ForgotPassword",
+];
+
+pub fn is_user_scoped_path(path: &str) -> bool {
+    let mut segments = path.trim_matches('/').split('/');
+    let Some(root) = segments.next() else {
+        return false;
+    };
+    if !matches_case_insensitive(root, USER_SCOPED_PATH_ROOTS) {
+        return false;
+    }
+
+    !(root.eq_ignore_ascii_case("Users")
+        && segments
+            .next()
+            .is_some_and(|segment| matches_case_insensitive(segment, PUBLIC_USER_PATHS)))
+}
+
+/// Returns `true` when the request hit a user-scoped endpoint without a resolvable user
+/// and was logged according to `audit_unauthenticated`.
+pub async fn audit_unauthenticated_request(
+    headers: &http::HeaderMap,
+    method: &http::Method,
+    uri: &http::Uri,
+    state: &AppState,
+) -> bool {
+    if state.audit_unauthenticated_mode().await == UnauthenticatedAuditMode::Off
+        || !is_user_scoped_path(uri.path())
+    {
+        return false;
+    }
+
+    let identity = match resolve_request_identity_from_headers_uri(headers, uri, state).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            error!("Failed to resolve identity for audit of {}: {}", uri, e);
+            return false;
+        }
+    };
+    if identity.user.is_some() {
+        return false;
+    }
+
+    let user_agent = headers
+        .get(http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("<none>");
+    warn!(
+        "Unauthenticated request to user-scoped endpoint: {} {} (credentials: {}, user-agent: {})",
+        method,
+        uri.path(),
+        if identity.auth.is_some() {
+            "unknown token"
+        } else {
+            "missing"
+        },
+        user_agent
+    );
+    true
+}
+
+/// Middleware that logs, and in `Block` mode rejects, unauthenticated requests to
+/// user-scoped endpoints.
+pub async fn audit_unauthenticated(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let flagged =
+        audit_unauthenticated_request(req.headers(), req.method(), req.uri(), &state).await;
+    if flagged && state.audit_unauthenticated_mode().await == UnauthenticatedAuditMode::Block {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    next.run(req).await
+}
+
 #[derive(Clone)]
 pub enum JellyfinAuthorization {
     Authorization(Authorization),
@@ -144,47 +262,56 @@ impl JellyfinAuthorization {
         }
     }
 
-    pub fn from_request(req: &reqwest::Request) -> Option<Self> {
+    /// Read the credentials of a request. Clients occasionally send several schemes at
+    /// once, so a scheme carrying a token wins over one without, and a tokenless
+    /// `Authorization`/`X-Emby-Authorization` header keeps its device info but takes
+    /// the token from the other headers or the query. With `enable_legacy_emby_auth`,
+    /// those headers may also use the `Emby ` prefix of older Emby clients.
+    pub fn from_request(req: &reqwest::Request, enable_legacy_emby_auth: bool) -> Option<Self> {
         let headers = req.headers();
-        if let Some(auth_header) = headers.get("authorization") {
-            if let Ok(auth_str) = auth_header.to_str() {
-                if let Ok(auth) = Authorization::parse(auth_str) {
-                    return Some(JellyfinAuthorization::Authorization(auth));
-                }
-            }
-        }
-
-        if let Some(auth_header) = headers.get("x-emby-authorization") {
-            if let Ok(auth_str) = auth_header.to_str() {
-                if let Ok(auth) = Authorization::parse(auth_str) {
-                    return Some(JellyfinAuthorization::XEmbyAuthorization(auth));
-                }
-            }
+        let header_value = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        let header_auths: Vec<Self> = [
+            header_value("authorization")
+                .and_then(|value| {
+                    Authorization::parse_with_legacy(value, enable_legacy_emby_auth).ok()
+                })
+                .map(JellyfinAuthorization::Authorization),
+            header_value("x-emby-authorization")
+                .and_then(|value| {
+                    Authorization::parse_with_legacy(value, enable_legacy_emby_auth).ok()
+                })
+                .map(JellyfinAuthorization::XEmbyAuthorization),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if let Some(auth) = header_auths.iter().find(|auth| auth.token().is_some()) {
+            return Some(auth.clone());
         }
 
-        if let Some(token_header) = headers.get("X-MediaBrowser-Token") {
-            if let Ok(token_str) = token_header.to_str() {
-                return Some(JellyfinAuthorization::XMediaBrowser(token_str.to_string()));
-            }
-        }
-
-        if let Some(token_header) = headers.get("x-emby-token") {
-            if let Ok(token_str) = token_header.to_str() {
-                return Some(JellyfinAuthorization::XEmbyToken(token_str.to_string()));
-            }
-        }
+        let token_auth = header_value("X-MediaBrowser-Token")
+            .map(|token| JellyfinAuthorization::XMediaBrowser(token.to_string()))
+            .or_else(|| {
+                header_value("x-emby-token")
+                    .map(|token| JellyfinAuthorization::XEmbyToken(token.to_string()))
+            })
+            .or_else(|| {
+                find_query_value(req.url(), API_KEY_QUERY_TAGS).map(JellyfinAuthorization::ApiKey)
+            });
 
-        if let Some(auth) = req.url().query_pairs().find_map(|(k, v)| {
-            if (k == "api_key") | (k == "ApiKey") {
-                Some(JellyfinAuthorization::ApiKey(v.to_string()))
-            } else {
-                None
+        match (header_auths.into_iter().next(), token_auth) {
+            (Some(mut header_auth), Some(token_auth)) => {
+                if let JellyfinAuthorization::Authorization(auth)
+                | JellyfinAuthorization::XEmbyAuthorization(auth) = &mut header_auth
+                {
+                    auth.token = token_auth.token();
+                }
+                Some(header_auth)
             }
-        }) {
-            return Some(auth);
+            (header_auth, token_auth) => header_auth.or(token_auth),
         }
-
-        None
     }
 }
 
@@ -214,7 +341,8 @@ pub async fn extract_request_infos(
 )> {
     let request = axum_to_reqwest(req).await?;
 
-    let auth = JellyfinAuthorization::from_request(&request);
+    let auth =
+        JellyfinAuthorization::from_request(&request, state.legacy_emby_auth_enabled().await);
 
     if let Some(auth) = &auth {
         debug!("Extracted authorization: {:?}", auth);
@@ -301,6 +429,16 @@ pub async fn extract_request_infos(
             }
         }
 
+        // A backend token picks the session it belongs to over the priority order.
+        if let Some(token) = auth.as_ref().and_then(|auth| auth.token()) {
+            if let Some(index) = filtered_sessions
+                .iter()
+                .position(|(session, _)| session.jellyfin_token == token)
+            {
+                filtered_sessions[..=index].rotate_right(1);
+            }
+        }
+
         if !filtered_sessions.is_empty() {
             Some(filtered_sessions)
         } else {
@@ -313,10 +451,13 @@ pub async fn extract_request_infos(
     Ok((request, auth, user, sessions, request_body_result))
 }
 
+#[instrument(level = "debug", skip_all, fields(path = %req.uri().path()))]
 pub async fn preprocess_request(req: Request, state: &AppState) -> Result<PreprocessedRequest> {
     debug!("Preprocessing request: {:?}", req.uri());
     let (mut request, auth, user, sessions, request_body_result) =
         extract_request_infos(req, state).await?;
+    *request.timeout_mut() = Some(state.upstream_timeout().await);
+    resolve_unmapped_media_ids(state, &mut request, &sessions).await?;
     let original_request = request
         .try_clone()
         .ok_or_else(|| anyhow!("failed to clone preprocessed request body"))?;
@@ -345,7 +486,7 @@ pub async fn preprocess_request(req: Request, state: &AppState) -> Result<Prepro
     )
     .await?;
 
-    let new_auth = remap_authorization(&auth, &session).await?;
+    let new_auth = remap_authorization(&auth, &session, &server).await?;
 
     apply_to_request(
         &mut request,
@@ -370,6 +511,89 @@ pub async fn preprocess_request(req: Request, state: &AppState) -> Result<Prepro
     })
 }
 
+/// Apply the `unmapped_media_id` fallback to media ids in the request URL that no
+/// mapping knows. When probing finds the owning server, the id is swapped for the
+/// virtual id of its new mapping, so routing and id translation then work as usual.
+async fn resolve_unmapped_media_ids(
+    state: &AppState,
+    request: &mut reqwest::Request,
+    sessions: &Option<Vec<(AuthorizationSession, Server)>>,
+) -> Result<()> {
+    let mode = state.unmapped_media_id_mode().await;
+    if mode == UnmappedMediaIdMode::PassThrough {
+        return Ok(());
+    }
+
+    let unmapped = state
+        .processors
+        .url_processor
+        .unmapped_client_media_ids(request.url())
+        .await?;
+    let Some(first) = unmapped.first() else {
+        return Ok(());
+    };
+
+    if mode == UnmappedMediaIdMode::NotFound {
+        debug!("Rejecting request for unmapped media id {}", first);
+        return Err(UnmappedMediaId(first.clone()).into());
+    }
+
+    let mut candidates = sessions
+        .iter()
+        .flatten()
+        .map(|(session, server)| (server.clone(), session.jellyfin_token.clone()))
+        .collect::<Vec<_>>();
+    candidates.sort_by(|(left, _), (right, _)| {
+        right
+            .priority
+            .cmp(&left.priority)
+            .then_with(|| left.name.cmp(&right.name))
+    });
+
+    for media_id in unmapped {
+        let Some(mapping) = state
+            .media_storage
+            .probe_media_owner(&media_id, &candidates)
+            .await?
+        else {
+            debug!("No server claims unmapped media id {}", media_id);
+            continue;
+        };
+        replace_client_media_id(request.url_mut(), &media_id, &mapping.virtual_media_id);
+    }
+
+    Ok(())
+}
+
+fn replace_client_media_id(url: &mut url::Url, media_id: &str, virtual_media_id: &str) {
+    *url = replace_id(url.clone(), media_id, virtual_media_id);
+
+    let Some(query) = url.query() else {
+        return;
+    };
+    let pairs = url::form_urlencoded::parse(query.as_bytes())
+        .map(|(key, value)| {
+            let value = if matches_case_insensitive(&key, MEDIA_ID_QUERY_TAGS) {
+                value
+                    .split(',')
+                    .map(|id| {
+                        if id.trim() == media_id {
+                            virtual_media_id
+                        } else {
+                            id
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            } else {
+                value.into_owned()
+            };
+            (key.into_owned(), value)
+        })
+        .collect::<Vec<_>>();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+}
+
 pub async fn apply_to_request(
     request: &mut reqwest::Request,
     server: &Server,
@@ -430,12 +654,9 @@ pub fn apply_authorization_header(
                         .insert(reqwest::header::AUTHORIZATION, value);
                 }
             }
-            // Map XEmbyAuthorization to Authorization header
             JellyfinAuthorization::XEmbyAuthorization(auth) => {
                 if let Ok(value) = reqwest::header::HeaderValue::from_str(&auth.to_header_value()) {
-                    request
-                        .headers_mut()
-                        .insert(reqwest::header::AUTHORIZATION, value);
+                    request.headers_mut().insert("X-Emby-Authorization", value);
                 }
             }
             JellyfinAuthorization::XMediaBrowser(token) => {
@@ -461,13 +682,21 @@ pub fn apply_host_header(request: &mut reqwest::Request, server: &Server) {
     }
 }
 
+/// Swap the client's credentials for the session's upstream ones.
+///
+/// The outgoing credentials use the scheme the client sent, except that
+/// `X-Emby-Authorization` is normalized to `Authorization` unless the server is
+/// configured to preserve it. A bare `X-Emby-Token` carries no client details,
+/// so it always stays a token header.
 pub async fn remap_authorization(
     auth: &Option<JellyfinAuthorization>,
     session: &Option<AuthorizationSession>,
+    server: &Server,
 ) -> Result<Option<JellyfinAuthorization>> {
     let Some(auth) = auth else {
         return Ok(None);
     };
+    let preserve_header = server.authorization_header_mode == AuthorizationHeaderMode::Preserve;
 
     let remapped_session = if let Some(session) = session {
         match auth {
@@ -482,9 +711,13 @@ pub async fn remap_authorization(
                 let token = session.jellyfin_token.clone();
                 Some(JellyfinAuthorization::ApiKey(token))
             }
-            JellyfinAuthorization::XEmbyToken(_) => Some(JellyfinAuthorization::Authorization(
-                session.to_authorization(),
-            )),
+            JellyfinAuthorization::XEmbyToken(_) => {
+                let token = session.jellyfin_token.clone();
+                Some(JellyfinAuthorization::XEmbyToken(token))
+            }
+            JellyfinAuthorization::XEmbyAuthorization(_) if preserve_header => Some(
+                JellyfinAuthorization::XEmbyAuthorization(session.to_authorization()),
+            ),
             JellyfinAuthorization::XEmbyAuthorization(_) => Some(
                 JellyfinAuthorization::Authorization(session.to_authorization()),
             ),
@@ -495,6 +728,13 @@ pub async fn remap_authorization(
     debug!("Remapped authorization to: {:?}", remapped_session);
     Ok(remapped_session)
 }
+
+/// Pick the backend a request goes to: a pinned library, then the media ids or
+/// `PlaySessionId` in the URL, then the ids and play sessions in the body. Playback
+/// reports that only carry a `PlaySessionId` thereby reach the server that answered
+/// the `PlaybackInfo` request. Without any of those, the user's first session on a
+/// server with a non-negative priority or the best server is used.
+#[instrument(level = "debug", skip_all)]
 pub async fn resolve_server(
     sessions: &Option<Vec<(AuthorizationSession, Server)>>,
     request_body_result: &Option<RequestBodyAnalysisResult>,
@@ -502,7 +742,10 @@ pub async fn resolve_server(
     request: &reqwest::Request,
     access_scope: Option<&VirtualLibraryAccessScope>,
 ) -> Result<(Server, Option<AuthorizationSession>)> {
-    let mut request_server = server_from_request_media_ids(state, request, access_scope).await?;
+    let mut request_server = server_from_pinned_library(state, request, access_scope).await?;
+    if request_server.is_none() {
+        request_server = server_from_request_media_ids(state, request, access_scope).await?;
+    }
 
     if request_server.is_none() {
         if let Some(request_body_result) = request_body_result {
@@ -529,7 +772,13 @@ pub async fn resolve_server(
             }
         }
 
-        let Some((session, server)) = sessions.first() else {
+        // Servers with a negative priority only serve requests that target them, unless
+        // the user has no other server.
+        let Some((session, server)) = sessions
+            .iter()
+            .find(|(_, server)| server.is_auto_selectable())
+            .or_else(|| sessions.first())
+        else {
             return Err(anyhow!("no authorization sessions available"));
         };
         return Ok((server.clone(), Some(session.clone())));
@@ -545,10 +794,87 @@ pub async fn resolve_server(
     }
 
     let server = state.server_storage.get_best_server().await?;
-    let server = server.ok_or_else(|| anyhow!("No server available"))?;
+    let server = server.ok_or(NoServerAvailable)?;
     Ok((server, None))
 }
 
+/// Route a request whose `ParentId` names a pinned library to that library's
+/// server. The pin only applies when that server can serve the id: for merged
+/// libraries it has to hold a member, for mapped ids it has to own the mapping.
+async fn server_from_pinned_library(
+    state: &AppState,
+    request: &reqwest::Request,
+    access_scope: Option<&VirtualLibraryAccessScope>,
+) -> Result<Option<Server>> {
+    let pins = state.pinned_libraries().await;
+    if pins.is_empty() {
+        return Ok(None);
+    }
+    let Some(parent_id) = find_query_value(request.url(), PARENT_ID_QUERY_TAGS) else {
+        return Ok(None);
+    };
+
+    let mapping = state
+        .media_storage
+        .get_media_mapping_by_virtual(&parent_id)
+        .await?;
+    let library_name = match state
+        .virtual_library_service
+        .resolve(&parent_id, access_scope)
+        .await?
+    {
+        VirtualLibraryResolution::Unknown => None,
+        VirtualLibraryResolution::Empty(library) => Some(library.name().to_string()),
+        VirtualLibraryResolution::Resolved(resolved) => Some(resolved.library.name().to_string()),
+    };
+
+    let parent_id = normalize_library_id(&parent_id);
+    let Some(pin) = pins.iter().find(|pin| {
+        let pinned_id = normalize_library_id(pin.library.trim());
+        pinned_id == parent_id
+            || mapping
+                .as_ref()
+                .is_some_and(|mapping| mapping.original_media_id == pinned_id)
+            || library_name
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(pin.library.trim()))
+    }) else {
+        return Ok(None);
+    };
+
+    let Some(server) = state.server_storage.get_server_by_name(&pin.server).await? else {
+        warn!(
+            "Library {} is pinned to unknown server {}",
+            pin.library, pin.server
+        );
+        return Ok(None);
+    };
+    if !access_scope.is_none_or(|scope| scope.allows(server.id)) {
+        return Ok(None);
+    }
+    if mapping
+        .as_ref()
+        .is_some_and(|mapping| mapping.server_id != server.id)
+    {
+        return Ok(None);
+    }
+    if library_name.is_some()
+        && state
+            .virtual_library_service
+            .routing_target(&parent_id, access_scope, Some(server.id))
+            .await?
+            .is_none()
+    {
+        return Ok(None);
+    }
+
+    debug!(
+        "Using server {} pinned for library {}",
+        server.name, pin.library
+    );
+    Ok(Some(server))
+}
+
 async fn server_from_request_media_ids(
     state: &AppState,
     request: &reqwest::Request,
@@ -574,10 +900,26 @@ pub async fn get_user_from_request(
         return Ok(None);
     };
 
-    let user = state
+    if let Some(user) = state
         .user_authorization
         .get_user_by_virtual_key(&token)
-        .await?;
+        .await?
+    {
+        return Ok(Some(user));
+    }
+
+    // Clients that reuse a backend token instead of the virtual key.
+    let user = state
+        .user_authorization
+        .get_user_by_jellyfin_token(&token)
+        .await?
+        .map(|(user, session)| {
+            debug!(
+                "Resolved user {} from the backend token of session {}",
+                user.original_username, session.id
+            );
+            user
+        });
 
     Ok(user)
 }
@@ -597,9 +939,20 @@ pub async fn axum_to_reqwest(req: Request) -> Result<reqwest::Request> {
         .path_and_query(path_and_query.to_string())
         .build()?;
 
-    // First extract parts and body separately
-    let (parts, body) = req.into_parts();
+    // First extract parts and body separately. The body is always buffered so the
+    // analyzer can read it, even when the client sent it chunked.
+    let (mut parts, body) = req.into_parts();
     let body_bytes = body.collect().await?.to_bytes();
+    if parts
+        .headers
+        .remove(http::header::TRANSFER_ENCODING)
+        .is_some()
+        || !body_bytes.is_empty()
+    {
+        parts
+            .headers
+            .insert(http::header::CONTENT_LENGTH, body_bytes.len().into());
+    }
 
     let mut http_req = http::Request::from_parts(parts, reqwest::Body::from(body_bytes));
     *http_req.uri_mut() = uri_with_host;
@@ -616,10 +969,18 @@ pub fn body_to_json(request: &reqwest::Request) -> Option<serde_json::Value> {
         .get(reqwest::header::CONTENT_TYPE)
         .and_then(|v| v.to_str().ok())
     {
-        if content_type.contains("application/json") {
+        if content_type
+            .to_ascii_lowercase()
+            .contains("application/json")
+        {
             if let Some(body) = request.body() {
-                // Clone the body bytes since we need to read them
-                let body_bytes = body.as_bytes().unwrap_or(&[]);
+                let Some(body_bytes) = body.as_bytes() else {
+                    warn!(
+                        "JSON request body to {} is not buffered; skipping body analysis",
+                        request.url().path()
+                    );
+                    return None;
+                };
                 if !body_bytes.is_empty() {
                     match serde_json::from_slice(body_bytes) {
                         Ok(json_value) => return Some(json_value),
@@ -648,6 +1009,7 @@ mod tests {
     use crate::processors::url_processor::{
         matches_case_insensitive, MEDIA_ID_PATH_TAGS, MEDIA_ID_QUERY_TAGS,
     };
+    use crate::test_support::{add_server_with_session, create_test_app_state, web_authorization};
     use crate::url_helper::contains_id;
 
     #[test]
@@ -666,44 +1028,222 @@ mod tests {
         assert!(matches_case_insensitive("ItemId", MEDIA_ID_QUERY_TAGS));
     }
 
-    use crate::config::{AppConfig, MIGRATOR};
-    use crate::handlers::quick_connect::QuickConnectStorage;
-    use crate::media_storage_service::MediaStorageService;
-    use crate::server_storage::ServerStorageService;
-    use crate::session_storage::SessionStorage;
-    use crate::user_authorization_service::UserAuthorizationService;
-    use crate::virtual_library_service::VirtualLibraryService;
-    use crate::{DataContext, ProxyProcessors};
-    use sqlx::SqlitePool;
-    use std::sync::Arc;
-
-    async fn create_test_app_state() -> AppState {
-        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&pool).await.unwrap();
-        let server_storage = ServerStorageService::new(pool.clone());
-        let media_storage = MediaStorageService::new(pool.clone());
-
-        let data_context = DataContext {
-            user_authorization: Arc::new(UserAuthorizationService::new(pool.clone())),
-            server_storage: Arc::new(server_storage.clone()),
-            media_storage: Arc::new(media_storage.clone()),
-            virtual_library_service: Arc::new(VirtualLibraryService::new(
-                pool,
-                server_storage,
-                media_storage,
-            )),
-            play_sessions: Arc::new(SessionStorage::new()),
-            config: Arc::new(tokio::sync::RwLock::new(AppConfig::default())),
+    use crate::config::{MediaStreamingMode, PinnedLibrary};
+
+    #[tokio::test]
+    async fn pinned_libraries_route_browse_requests_to_their_server() {
+        let state = create_test_app_state().await;
+        let mut servers = Vec::new();
+        for (name, priority) in [("Shows", 100), ("Movies", 50)] {
+            let server_id = state
+                .server_storage
+                .add_server(
+                    name,
+                    &format!("http://{}:8096", name.to_lowercase()),
+                    priority,
+                    MediaStreamingMode::Proxy,
+                )
+                .await
+                .unwrap();
+            servers.push(server_id);
+        }
+        let group = state
+            .virtual_library_service
+            .create_group("Films", crate::config::DeduplicationStrategy::default())
+            .await
+            .unwrap();
+        for (server_id, library_id) in servers.iter().zip([
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+        ]) {
+            state
+                .virtual_library_service
+                .add_member(&group.virtual_id, *server_id, library_id, "Films")
+                .await
+                .unwrap();
+        }
+
+        let resolve = |parent_id: &str| {
+            let url =
+                url::Url::parse(&format!("http://localhost/Items?ParentId={parent_id}")).unwrap();
+            let request = reqwest::Request::new(reqwest::Method::GET, url);
+            let state = state.clone();
+            async move {
+                resolve_server(&None, &None, &state, &request, None)
+                    .await
+                    .unwrap()
+                    .0
+                    .name
+            }
         };
-        let processors = ProxyProcessors::new(data_context.clone());
-
-        AppState::new(
-            reqwest::Client::new(),
-            reqwest::Client::new(),
-            data_context,
-            processors,
-            QuickConnectStorage::new(),
+
+        assert_eq!(resolve(&group.virtual_id).await, "Shows");
+        assert_eq!(resolve("cccccccccccccccccccccccccccccccc").await, "Shows");
+
+        state.config.write().await.pinned_libraries = vec![
+            PinnedLibrary {
+                library: "films".to_string(),
+                server: "Movies".to_string(),
+            },
+            PinnedLibrary {
+                library: "cccccccc-cccc-cccc-cccc-cccccccccccc".to_string(),
+                server: "Movies".to_string(),
+            },
+        ];
+        assert_eq!(resolve(&group.virtual_id).await, "Movies");
+        assert_eq!(resolve("cccccccccccccccccccccccccccccccc").await, "Movies");
+    }
+
+    #[test]
+    fn tokenless_authorization_header_takes_the_token_of_another_scheme() {
+        let url = url::Url::parse("http://localhost/Items").unwrap();
+        let mut request = reqwest::Request::new(reqwest::Method::GET, url);
+        request.headers_mut().insert(
+            "authorization",
+            r#"MediaBrowser Client="Jellyfin Web", Device="Firefox", DeviceId="web-device-id", Version="10.10.7""#
+                .parse()
+                .unwrap(),
+        );
+        request
+            .headers_mut()
+            .insert("x-emby-token", "secret".parse().unwrap());
+
+        let auth = JellyfinAuthorization::from_request(&request, false).unwrap();
+
+        assert_eq!(auth.token().as_deref(), Some("secret"));
+        let device = auth.get_device(request.headers()).unwrap();
+        assert_eq!(device.device_id, "web-device-id");
+        assert_eq!(device.client, "Jellyfin Web");
+
+        request.headers_mut().insert(
+            "x-emby-authorization",
+            r#"MediaBrowser Client="Infuse", Device="AppleTV", DeviceId="tv-device-id", Version="8.0", Token="header-token""#
+                .parse()
+                .unwrap(),
+        );
+        let auth = JellyfinAuthorization::from_request(&request, false).unwrap();
+        assert!(matches!(auth, JellyfinAuthorization::XEmbyAuthorization(_)));
+        assert_eq!(auth.token().as_deref(), Some("header-token"));
+    }
+
+    #[test]
+    fn api_key_query_parameter_is_matched_case_insensitively() {
+        for key in ["api_key", "ApiKey", "apiKey", "apikey", "API_KEY"] {
+            let url = url::Url::parse(&format!("http://localhost/Items?{key}=secret")).unwrap();
+            let request = reqwest::Request::new(reqwest::Method::GET, url);
+
+            let auth = JellyfinAuthorization::from_request(&request, false);
+
+            assert!(
+                matches!(auth, Some(JellyfinAuthorization::ApiKey(ref token)) if token == "secret"),
+                "{key} was not recognized"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_identity_reads_device_id_query_case_insensitively() {
+        let state = create_test_app_state().await;
+
+        for key in ["DeviceId", "deviceId", "deviceid"] {
+            let uri: http::Uri = format!("/Videos/abc/stream?{key}=device-1")
+                .parse()
+                .unwrap();
+
+            let identity =
+                resolve_request_identity_from_headers_uri(&http::HeaderMap::new(), &uri, &state)
+                    .await
+                    .unwrap();
+
+            assert_eq!(
+                identity.device.map(|device| device.device_id).as_deref(),
+                Some("device-1"),
+                "{key} was not recognized"
+            );
+        }
+    }
+
+    #[test]
+    fn user_scoped_paths_exclude_public_login_endpoints() {
+        assert!(is_user_scoped_path("/Users/abc/Items"));
+        assert!(is_user_scoped_path("/users/Me"));
+        assert!(is_user_scoped_path("/UserViews"));
+        assert!(is_user_scoped_path("/UserItems/Resume"));
+        assert!(is_user_scoped_path("/Sessions/Playing"));
+        assert!(!is_user_scoped_path("/Users/AuthenticateByName"));
+        assert!(!is_user_scoped_path("/users/authenticatewithquickconnect"));
+        assert!(!is_user_scoped_path("/Users/Public"));
+        assert!(!is_user_scoped_path("/System/Info/Public"));
+        assert!(!is_user_scoped_path("/Videos/abc/stream"));
+    }
+
+    async fn audited_request_status(
+        mode: UnauthenticatedAuditMode,
+        uri: &str,
+    ) -> (bool, http::StatusCode) {
+        use tower::ServiceExt;
+
+        let state = create_test_app_state().await;
+        state.config.write().await.audit_unauthenticated = mode;
+        let uri: http::Uri = uri.parse().unwrap();
+
+        let flagged = audit_unauthenticated_request(
+            &http::HeaderMap::new(),
+            &http::Method::GET,
+            &uri,
+            &state,
         )
+        .await;
+
+        let router = axum::Router::new()
+            .route("/{*path}", axum::routing::get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                audit_unauthenticated,
+            ))
+            .with_state(state);
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        (flagged, response.status())
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_user_route_is_logged_and_passed_through() {
+        let (flagged, status) =
+            audited_request_status(UnauthenticatedAuditMode::Log, "/Users/abc/Items").await;
+
+        assert!(flagged);
+        assert_eq!(status, http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_user_route_is_blocked_when_configured() {
+        let (flagged, status) =
+            audited_request_status(UnauthenticatedAuditMode::Block, "/Users/abc/Items").await;
+
+        assert!(flagged);
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn audit_ignores_public_routes_and_disabled_mode() {
+        let (flagged, status) =
+            audited_request_status(UnauthenticatedAuditMode::Block, "/Users/Public").await;
+        assert!(!flagged);
+        assert_eq!(status, http::StatusCode::OK);
+
+        let (flagged, status) =
+            audited_request_status(UnauthenticatedAuditMode::Off, "/Users/abc/Items").await;
+        assert!(!flagged);
+        assert_eq!(status, http::StatusCode::OK);
     }
 
     #[tokio::test]
@@ -825,4 +1365,331 @@ mod tests {
 
         assert_eq!(identity.user.unwrap().id, caller.id);
     }
+
+    #[tokio::test]
+    async fn legacy_emby_authorization_is_accepted_only_when_enabled() {
+        let state = create_test_app_state().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("theater", &"password123".into())
+            .await
+            .unwrap();
+
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::AUTHORIZATION,
+            http::HeaderValue::from_str(&format!(
+                r#"Emby Client="Emby Theater", Device="Living Room", DeviceId="theater-1", Version="3.0.20", Token="{}""#,
+                user.virtual_key
+            ))
+            .unwrap(),
+        );
+        let uri: http::Uri = "/UserViews".parse().unwrap();
+
+        let identity = resolve_request_identity_from_headers_uri(&headers, &uri, &state)
+            .await
+            .unwrap();
+        assert!(identity.auth.is_none());
+        assert!(identity.user.is_none());
+
+        state.config.write().await.enable_legacy_emby_auth = true;
+        let identity = resolve_request_identity_from_headers_uri(&headers, &uri, &state)
+            .await
+            .unwrap();
+        assert_eq!(identity.user.unwrap().id, user.id);
+        assert_eq!(identity.device.unwrap().client, "Emby Theater");
+    }
+
+    const AUTH_HEADERS: [&str; 4] = [
+        "authorization",
+        "x-emby-authorization",
+        "x-emby-token",
+        "x-mediabrowser-token",
+    ];
+
+    /// Forward a request whose credentials use `scheme` (a header name, or
+    /// `api_key` for the query parameter) and return it as sent upstream.
+    async fn forwarded_request(mode: AuthorizationHeaderMode, scheme: &str) -> reqwest::Request {
+        let state = create_test_app_state().await;
+        let upstream = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/System/Info/Public"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({ "ServerName": "Emby", "Version": "4.8.0.0" }),
+                ),
+            )
+            .mount(&upstream)
+            .await;
+
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let server_id = state
+            .server_storage
+            .add_server("Emby", &upstream.uri(), 100, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+        state
+            .server_storage
+            .update_server_authorization_header_mode(server_id, mode)
+            .await
+            .unwrap();
+        let server = state
+            .server_storage
+            .get_server_by_id(server_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let client_auth = Authorization {
+            client: "Emby Theater".to_string(),
+            device: "Living Room".to_string(),
+            device_id: "emby-device".to_string(),
+            version: "3.0.0".to_string(),
+            token: None,
+        };
+        state
+            .user_authorization
+            .add_server_mapping(&user.id, &server, "viewer", &"password".into(), None)
+            .await
+            .unwrap();
+        state
+            .user_authorization
+            .store_authorization_session(
+                &user.id,
+                &server,
+                &client_auth,
+                "upstream-token".to_string(),
+                "upstream-user".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        state.server_storage.check_servers_health().await;
+
+        let full_header = Authorization {
+            token: Some(user.virtual_key.clone()),
+            ..client_auth
+        }
+        .to_header_value();
+        let uri: http::Uri = match scheme {
+            "api_key" => format!("/System/Info?api_key={}", user.virtual_key),
+            _ => "/System/Info".to_string(),
+        }
+        .parse()
+        .unwrap();
+        let mut request = Request::builder()
+            .uri(uri.clone())
+            .header(http::header::HOST, "localhost")
+            .extension(axum::extract::OriginalUri(uri));
+        match scheme {
+            "Authorization" | "X-Emby-Authorization" => {
+                request = request.header(scheme, full_header);
+            }
+            "X-Emby-Token" | "X-MediaBrowser-Token" => {
+                request = request.header(scheme, user.virtual_key.clone());
+            }
+            _ => {}
+        }
+        let request = request.body(axum::body::Body::empty()).unwrap();
+
+        preprocess_request(request, &state).await.unwrap().request
+    }
+
+    #[tokio::test]
+    async fn unmapped_media_ids_follow_the_configured_fallback() {
+        let state = create_test_app_state().await;
+        let user = state
+            .user_authorization
+            .get_or_create_user("viewer", &"password".into())
+            .await
+            .unwrap();
+        let item_id = "dddddddddddddddddddddddddddddddd";
+        let mut upstreams = Vec::new();
+        for (name, priority, item_status) in [("First", 200, 404), ("Second", 100, 200)] {
+            let upstream = wiremock::MockServer::start().await;
+            wiremock::Mock::given(wiremock::matchers::path(format!("/Items/{item_id}")))
+                .respond_with(wiremock::ResponseTemplate::new(item_status).set_body_json(
+                    serde_json::json!({ "Name": "Heat", "Id": item_id, "Type": "Movie" }),
+                ))
+                .expect(1)
+                .mount(&upstream)
+                .await;
+            let server = add_server_with_session(&state, &user, name, &upstream, priority).await;
+            upstreams.push((upstream, server));
+        }
+        state.server_storage.check_servers_health().await;
+
+        let header = web_authorization(Some(user.virtual_key.clone())).to_header_value();
+        let preprocess = |item_id: &str| {
+            let uri: http::Uri = format!("/Items/{item_id}").parse().unwrap();
+            let request = Request::builder()
+                .uri(uri.clone())
+                .header(http::header::HOST, "localhost")
+                .header(http::header::AUTHORIZATION, header.clone())
+                .extension(axum::extract::OriginalUri(uri))
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let state = state.clone();
+            async move { preprocess_request(request, &state).await }
+        };
+
+        let passed_through = preprocess(item_id).await.unwrap();
+        assert_eq!(passed_through.server.name, "First");
+        assert_eq!(
+            passed_through.request.url().path(),
+            format!("/Items/{item_id}")
+        );
+
+        state.config.write().await.unmapped_media_id = UnmappedMediaIdMode::NotFound;
+        let error = preprocess(item_id).await.err().unwrap();
+        assert_eq!(preprocess_error_status(&error), StatusCode::NOT_FOUND);
+
+        state.config.write().await.unmapped_media_id = UnmappedMediaIdMode::ProbeServers;
+        let probed = preprocess(item_id).await.unwrap();
+        assert_eq!(probed.server.name, "Second");
+        assert_eq!(probed.request.url().path(), format!("/Items/{item_id}"));
+        let mapping = state
+            .media_storage
+            .get_media_mapping_by_original(item_id, upstreams[1].1.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            probed.original_request.url().path(),
+            format!("/Items/{}", mapping.virtual_media_id)
+        );
+
+        assert_eq!(preprocess(item_id).await.unwrap().server.name, "Second");
+        for (upstream, _) in &upstreams {
+            upstream.verify().await;
+        }
+    }
+
+    fn forwarded_auth_headers(request: &reqwest::Request) -> Vec<(&'static str, String)> {
+        AUTH_HEADERS
+            .into_iter()
+            .filter_map(|name| {
+                let value = request.headers().get(name)?;
+                Some((name, value.to_str().unwrap().to_string()))
+            })
+            .collect()
+    }
+
+    async fn forwarded_auth_headers_for(
+        mode: AuthorizationHeaderMode,
+        scheme: &str,
+    ) -> Vec<(&'static str, String)> {
+        forwarded_auth_headers(&forwarded_request(mode, scheme).await)
+    }
+
+    #[tokio::test]
+    async fn emby_authorization_is_preserved_for_configured_servers() {
+        let headers =
+            forwarded_auth_headers_for(AuthorizationHeaderMode::Preserve, "X-Emby-Authorization")
+                .await;
+
+        assert_eq!(headers.len(), 1);
+        let (name, value) = &headers[0];
+        assert_eq!(*name, "x-emby-authorization");
+        assert!(value.contains("upstream-token"));
+        assert!(value.contains("emby-device"));
+    }
+
+    #[tokio::test]
+    async fn emby_authorization_is_normalized_by_default() {
+        let headers =
+            forwarded_auth_headers_for(AuthorizationHeaderMode::Normalize, "X-Emby-Authorization")
+                .await;
+
+        assert_eq!(headers.len(), 1);
+        let (name, value) = &headers[0];
+        assert_eq!(*name, "authorization");
+        assert!(value.contains("upstream-token"));
+    }
+
+    #[tokio::test]
+    async fn remapped_credentials_keep_the_client_scheme() {
+        for mode in [
+            AuthorizationHeaderMode::Normalize,
+            AuthorizationHeaderMode::Preserve,
+        ] {
+            for scheme in ["Authorization", "X-MediaBrowser-Token", "X-Emby-Token"] {
+                let headers = forwarded_auth_headers_for(mode, scheme).await;
+
+                assert_eq!(headers.len(), 1, "{scheme} in {mode} mode");
+                let (name, value) = &headers[0];
+                assert_eq!(*name, scheme.to_ascii_lowercase(), "{mode} mode");
+                assert!(value.contains("upstream-token"), "{scheme}: {value}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn api_key_credentials_stay_in_the_query() {
+        let request = forwarded_request(AuthorizationHeaderMode::Normalize, "api_key").await;
+
+        assert!(forwarded_auth_headers(&request).is_empty());
+        let api_key = find_query_value(request.url(), API_KEY_QUERY_TAGS);
+        assert_eq!(api_key.as_deref(), Some("upstream-token"));
+    }
+
+    #[tokio::test]
+    async fn forwarded_requests_carry_the_configured_timeout() {
+        let request = forwarded_request(AuthorizationHeaderMode::Normalize, "X-Emby-Token").await;
+
+        assert_eq!(
+            request.timeout(),
+            Some(&std::time::Duration::from_secs(
+                crate::config::AppConfig::default().timeout
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn chunked_json_body_is_buffered_for_analysis() {
+        let chunks = [
+            r#"{"UserId": "user-1", "#,
+            r#""ItemIds": ["11111111111111111111111111111111"]}"#,
+        ]
+        .map(|chunk| Ok::<_, std::io::Error>(axum::body::Bytes::from_static(chunk.as_bytes())));
+        let uri: http::Uri = "/Sessions/Playing".parse().unwrap();
+        let request = Request::builder()
+            .method(http::Method::POST)
+            .uri(uri.clone())
+            .header(
+                http::header::CONTENT_TYPE,
+                "Application/JSON; charset=utf-8",
+            )
+            .header(http::header::TRANSFER_ENCODING, "chunked")
+            .extension(axum::extract::OriginalUri(uri))
+            .body(axum::body::Body::from_stream(futures_util::stream::iter(
+                chunks,
+            )))
+            .unwrap();
+
+        let request = axum_to_reqwest(request).await.unwrap();
+
+        assert!(request
+            .headers()
+            .get(http::header::TRANSFER_ENCODING)
+            .is_none());
+        let body_len = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .unwrap()
+            .len();
+        assert_eq!(
+            request.headers().get(http::header::CONTENT_LENGTH).unwrap(),
+            &body_len.to_string()
+        );
+        assert_eq!(
+            body_to_json(&request),
+            Some(serde_json::json!({
+                "UserId": "user-1",
+                "ItemIds": ["11111111111111111111111111111111"]
+            }))
+        );
+    }
 }
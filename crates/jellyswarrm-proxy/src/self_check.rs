@@ -0,0 +1,214 @@
+use std::{fmt, net::IpAddr};
+
+use tracing::{error, info, warn};
+
+use crate::{config::AppConfig, server_storage::ServerStorageService};
+
+const DEFAULT_PASSWORD: &str = "jellyswarrm";
+
+/// A setup mistake found by the startup self-check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetupWarning {
+    NoServers,
+    MissingServerAdmin { server: String },
+    PermissiveCorsOnPublicBind { host: String },
+    InsecureCookiesOverTls,
+    DefaultAdminPassword,
+}
+
+impl fmt::Display for SetupWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupWarning::NoServers => write!(
+                f,
+                "No servers are configured. Add one in the admin UI or under `preconfigured_servers`."
+            ),
+            SetupWarning::MissingServerAdmin { server } => write!(
+                f,
+                "Server '{server}' has no admin credentials, so users can't be synced to it. Add them in the admin UI."
+            ),
+            SetupWarning::PermissiveCorsOnPublicBind { host } => write!(
+                f,
+                "The API accepts requests from any origin and listens on '{host}'. Bind `host` to a loopback address or put Jellyswarrm behind a reverse proxy that restricts origins."
+            ),
+            SetupWarning::InsecureCookiesOverTls => write!(
+                f,
+                "`public_address` uses HTTPS but the session cookie is not marked Secure. Set `secure_cookies = true`."
+            ),
+            SetupWarning::DefaultAdminPassword => write!(
+                f,
+                "The admin account still uses the default password. Change `password`."
+            ),
+        }
+    }
+}
+
+/// Check the configuration and the stored servers for common setup mistakes.
+pub async fn collect_warnings(
+    config: &AppConfig,
+    server_storage: &ServerStorageService,
+) -> Vec<SetupWarning> {
+    let mut warnings = Vec::new();
+
+    match server_storage.list_servers().await {
+        Ok(servers) if servers.is_empty() => warnings.push(SetupWarning::NoServers),
+        Ok(servers) => {
+            for server in servers {
+                match server_storage.get_server_admin(server.id).await {
+                    Ok(Some(_)) => {}
+                    Ok(None) => warnings.push(SetupWarning::MissingServerAdmin {
+                        server: server.name,
+                    }),
+                    Err(e) => error!("Failed to check admin of server {}: {}", server.name, e),
+                }
+            }
+        }
+        Err(e) => error!("Failed to list servers for the self-check: {}", e),
+    }
+
+    if is_public_bind(&config.host) {
+        warnings.push(SetupWarning::PermissiveCorsOnPublicBind {
+            host: config.host.clone(),
+        });
+    }
+
+    let serves_tls = config
+        .public_address
+        .get(..8)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://"));
+    if serves_tls && !config.secure_cookies {
+        warnings.push(SetupWarning::InsecureCookiesOverTls);
+    }
+
+    if config.password.as_str() == DEFAULT_PASSWORD {
+        warnings.push(SetupWarning::DefaultAdminPassword);
+    }
+
+    warnings
+}
+
+/// Log a warning for every setup mistake found by [`collect_warnings`].
+pub async fn run(config: &AppConfig, server_storage: &ServerStorageService) {
+    let warnings = collect_warnings(config, server_storage).await;
+    if warnings.is_empty() {
+        info!("Startup self-check found no problems");
+    }
+    for warning in warnings {
+        warn!("Self-check: {}", warning);
+    }
+}
+
+fn is_public_bind(host: &str) -> bool {
+    if host.eq_ignore_ascii_case("localhost") {
+        return false;
+    }
+    host.trim_matches(['[', ']'])
+        .parse::<IpAddr>()
+        .map_or(true, |ip| !ip.is_loopback())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{MediaStreamingMode, MIGRATOR},
+        encryption::EncryptedPassword,
+    };
+    use sqlx::SqlitePool;
+
+    async fn server_storage() -> ServerStorageService {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        ServerStorageService::new(pool)
+    }
+
+    fn well_configured() -> AppConfig {
+        AppConfig {
+            host: "127.0.0.1".to_string(),
+            password: "a-better-password".into(),
+            ..AppConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn default_setup_reports_missing_servers_and_default_credentials() {
+        let warnings = collect_warnings(&AppConfig::default(), &server_storage().await).await;
+
+        assert_eq!(
+            warnings,
+            [
+                SetupWarning::NoServers,
+                SetupWarning::PermissiveCorsOnPublicBind {
+                    host: "0.0.0.0".to_string()
+                },
+                SetupWarning::DefaultAdminPassword,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn servers_without_admin_credentials_are_reported() {
+        let storage = server_storage().await;
+        let with_admin = storage
+            .add_server(
+                "Main",
+                "http://main:8096",
+                100,
+                MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+        storage
+            .add_server(
+                "Other",
+                "http://other:8096",
+                50,
+                MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+        storage
+            .add_server_admin(
+                with_admin,
+                "admin",
+                &EncryptedPassword::from_raw("encrypted".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let warnings = collect_warnings(&well_configured(), &storage).await;
+
+        assert_eq!(
+            warnings,
+            [SetupWarning::MissingServerAdmin {
+                server: "Other".to_string()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn tls_without_secure_cookies_is_reported() {
+        let storage = server_storage().await;
+        let mut config = AppConfig {
+            public_address: "HTTPS://media.example.com".to_string(),
+            ..well_configured()
+        };
+
+        let warnings = collect_warnings(&config, &storage).await;
+        assert!(warnings.contains(&SetupWarning::InsecureCookiesOverTls));
+
+        config.secure_cookies = true;
+        let warnings = collect_warnings(&config, &storage).await;
+        assert!(!warnings.contains(&SetupWarning::InsecureCookiesOverTls));
+    }
+
+    #[test]
+    fn loopback_hosts_are_not_public() {
+        assert!(!is_public_bind("localhost"));
+        assert!(!is_public_bind("127.0.0.1"));
+        assert!(!is_public_bind("[::1]"));
+        assert!(is_public_bind("0.0.0.0"));
+        assert!(is_public_bind("::"));
+        assert!(is_public_bind("192.168.1.10"));
+    }
+}
@@ -1,18 +1,21 @@
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use sqlx::{sqlite::SqliteRow, Row, Sqlite, SqliteConnection, SqlitePool, Transaction};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use jellyfin_api::{
     client::{ClientInfo, JellyfinClient},
+    error::JellyfinApiError as JellyfinError,
     models::PublicSystemInfo,
 };
 
-use crate::config::MediaStreamingMode;
+use crate::config::{AuthorizationHeaderMode, MediaStreamingMode};
 use crate::encryption::EncryptedPassword;
+use crate::instance_transfer::ExportedServer;
 use crate::server_id::ServerId;
 use crate::server_url::ServerUrl;
 
@@ -23,11 +26,23 @@ pub struct Server {
     pub url: ServerUrl,
     pub priority: i32,
     pub media_streaming_mode: MediaStreamingMode,
+    pub authorization_header_mode: AuthorizationHeaderMode,
+    /// Overrides `max_connections_per_server` for this server.
+    pub max_connections: Option<u32>,
+    /// The `Id` the backend reported in its public system info, once it was reachable.
+    pub backend_id: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
 impl Server {
+    /// Whether the server may be picked when a request names no server. Servers with a
+    /// negative priority are only used for requests that target them through their
+    /// media ids, and still appear in federated listings.
+    pub fn is_auto_selectable(&self) -> bool {
+        self.priority >= 0
+    }
+
     pub(crate) fn from_row(row: SqliteRow) -> Result<Self, sqlx::Error> {
         Self::from_row_ref(&row)
     }
@@ -42,6 +57,12 @@ impl Server {
                 .try_get::<String, _>("media_streaming_mode")?
                 .parse()
                 .unwrap_or(MediaStreamingMode::Redirect),
+            authorization_header_mode: row
+                .try_get::<String, _>("authorization_header_mode")?
+                .parse()
+                .unwrap_or(AuthorizationHeaderMode::Normalize),
+            max_connections: row.try_get("max_connections")?,
+            backend_id: row.try_get("backend_id")?,
             created_at: row.try_get("created_at")?,
             updated_at: row.try_get("updated_at")?,
         })
@@ -57,6 +78,12 @@ impl Server {
                 .try_get::<String, _>("media_streaming_mode")?
                 .parse()
                 .unwrap_or(MediaStreamingMode::Redirect),
+            authorization_header_mode: row
+                .try_get::<String, _>("authorization_header_mode")?
+                .parse()
+                .unwrap_or(AuthorizationHeaderMode::Normalize),
+            max_connections: row.try_get("max_connections")?,
+            backend_id: row.try_get("backend_id")?,
             created_at: row.try_get("server_created_at")?,
             updated_at: row.try_get("server_updated_at")?,
         })
@@ -96,24 +123,87 @@ impl<'r> sqlx::FromRow<'r, SqliteRow> for ServerAdmin {
     }
 }
 
+/// Oldest Jellyfin version the proxy is known to work with.
+pub const MIN_JELLYFIN_VERSION: &str = "10.9.0";
+/// How long a checked status is reused by [`ServerStorageService::current_server_status`].
+const STATUS_CACHE_TTL: Duration = Duration::from_secs(30);
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ServerHealthStatus {
     /// Server is healthy
     Healthy(PublicSystemInfo),
-    /// Server is unhealthy with reason
+    /// Server is unreachable or failing, with reason
     Unhealthy(String),
+    /// Server answers, but not like a Jellyfin server
+    NotJellyfin(String),
 }
 
 impl ServerHealthStatus {
     pub fn is_healthy(&self) -> bool {
         matches!(self, ServerHealthStatus::Healthy(_))
     }
+
+    /// Whether the server is a Jellyfin server older than [`MIN_JELLYFIN_VERSION`].
+    /// Emby servers have their own version scheme and are never reported.
+    pub fn is_outdated(&self) -> bool {
+        let ServerHealthStatus::Healthy(info) = self else {
+            return false;
+        };
+        let is_emby = info
+            .product_name
+            .as_deref()
+            .is_some_and(|product| product.contains("Emby"));
+        !is_emby
+            && info
+                .version
+                .as_deref()
+                .is_some_and(|version| version_parts(version) < version_parts(MIN_JELLYFIN_VERSION))
+    }
+}
+
+/// The configured server that is the backend with `backend_id`, if any.
+pub fn find_server_by_backend_id<'a>(
+    servers: &'a [Server],
+    backend_id: &str,
+) -> Option<&'a Server> {
+    servers
+        .iter()
+        .find(|server| server.backend_id.as_deref() == Some(backend_id))
+}
+
+/// Every server that is the same backend as a server before it in `servers`, paired
+/// with that earlier server.
+pub fn duplicate_backends(servers: &[Server]) -> Vec<(&Server, &Server)> {
+    servers
+        .iter()
+        .enumerate()
+        .filter_map(|(index, server)| {
+            let backend_id = server.backend_id.as_deref()?;
+            find_server_by_backend_id(&servers[..index], backend_id)
+                .map(|existing| (server, existing))
+        })
+        .collect()
+}
+
+pub(crate) fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct CheckedStatus {
+    checked_at: Instant,
+    /// How long the server took to answer the check.
+    latency: Duration,
+    status: ServerHealthStatus,
 }
 
 #[derive(Debug, Clone)]
 pub struct ServerStorageService {
     pool: SqlitePool,
-    health_status: Arc<RwLock<HashMap<ServerId, ServerHealthStatus>>>,
+    health_status: Arc<RwLock<HashMap<ServerId, CheckedStatus>>>,
     pub http_client: reqwest::Client,
     pub client_info: ClientInfo,
 }
@@ -183,7 +273,7 @@ impl ServerStorageService {
     pub async fn get_server_by_name(&self, name: &str) -> Result<Option<Server>, sqlx::Error> {
         let row = sqlx::query(
             r#"
-            SELECT id, name, url, priority, media_streaming_mode, created_at, updated_at
+            SELECT id, name, url, priority, media_streaming_mode, authorization_header_mode, max_connections, backend_id, created_at, updated_at
             FROM servers 
             WHERE name = ?
             "#,
@@ -198,7 +288,7 @@ impl ServerStorageService {
     pub async fn get_server_by_id(&self, id: ServerId) -> Result<Option<Server>, sqlx::Error> {
         let row = sqlx::query(
             r#"
-            SELECT id, name, url, priority, media_streaming_mode, created_at, updated_at
+            SELECT id, name, url, priority, media_streaming_mode, authorization_header_mode, max_connections, backend_id, created_at, updated_at
             FROM servers 
             WHERE id = ?
             "#,
@@ -213,7 +303,7 @@ impl ServerStorageService {
     pub async fn list_servers(&self) -> Result<Vec<Server>, sqlx::Error> {
         let rows = sqlx::query(
             r#"
-            SELECT id, name, url, priority, media_streaming_mode, created_at, updated_at
+            SELECT id, name, url, priority, media_streaming_mode, authorization_header_mode, max_connections, backend_id, created_at, updated_at
             FROM servers 
             ORDER BY priority DESC, name ASC
             "#,
@@ -270,6 +360,75 @@ impl ServerStorageService {
         Ok(result.rows_affected() > 0)
     }
 
+    pub async fn update_server_authorization_header_mode(
+        &self,
+        server_id: ServerId,
+        authorization_header_mode: AuthorizationHeaderMode,
+    ) -> Result<bool, sqlx::Error> {
+        let now = chrono::Utc::now();
+
+        let result = sqlx::query(
+            r#"
+            UPDATE servers
+            SET authorization_header_mode = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(authorization_header_mode.to_string())
+        .bind(now)
+        .bind(server_id.as_i64())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn update_server_max_connections(
+        &self,
+        server_id: ServerId,
+        max_connections: Option<u32>,
+    ) -> Result<bool, sqlx::Error> {
+        let now = chrono::Utc::now();
+
+        let result = sqlx::query(
+            r#"
+            UPDATE servers
+            SET max_connections = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(max_connections)
+        .bind(now)
+        .bind(server_id.as_i64())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn update_server_backend_id(
+        &self,
+        server_id: ServerId,
+        backend_id: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let now = chrono::Utc::now();
+
+        let result = sqlx::query(
+            r#"
+            UPDATE servers
+            SET backend_id = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(backend_id)
+        .bind(now)
+        .bind(server_id.as_i64())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn delete_server(&self, server_id: ServerId) -> Result<bool, sqlx::Error> {
         let result = sqlx::query(
             r#"
@@ -304,9 +463,11 @@ impl ServerStorageService {
         let service = self.clone();
         tokio::spawn(async move {
             info!("Starting server health check loop");
+            service.check_servers_health().await;
+            service.warn_about_duplicate_backends().await;
             loop {
-                service.check_servers_health().await;
                 tokio::time::sleep(tokio::time::Duration::from_secs(wait_time_secs)).await;
+                service.check_servers_health().await;
             }
         });
     }
@@ -320,64 +481,193 @@ impl ServerStorageService {
             }
         };
 
-        let statuses: Vec<(ServerId, ServerHealthStatus)> =
-            futures_util::stream::iter(servers.into_iter().map(|server| {
-                let http_client = self.http_client.clone();
-                let client_info = self.client_info.clone();
-                async move {
-                    let client = match JellyfinClient::new_with_client(
-                        server.url.as_str(),
-                        client_info,
-                        http_client,
-                    ) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            error!("Failed to create client for server {}: {}", server.name, e);
-                            return (server.id, ServerHealthStatus::Unhealthy(e.to_string()));
-                        }
-                    };
-
-                    let status = match client.get_public_system_info().await {
-                        Ok(info) => ServerHealthStatus::Healthy(info),
-                        Err(e) => ServerHealthStatus::Unhealthy(e.to_string()),
-                    };
-
-                    (server.id, status)
-                }
+        let statuses: Vec<(Server, ServerHealthStatus, Duration)> =
+            futures_util::stream::iter(servers.into_iter().map(|server| async move {
+                let started = Instant::now();
+                let status = self.check_server(&server).await;
+                (server, status, started.elapsed())
             }))
             .buffer_unordered(5)
             .collect()
             .await;
 
+        for (server, status, latency) in statuses {
+            self.record_backend_id(&server, &status).await;
+            self.store_status(server.id, status, latency).await;
+        }
+    }
+
+    /// Log a warning for every pair of servers that are the same backend, which makes
+    /// federation show everything on it twice.
+    pub async fn warn_about_duplicate_backends(&self) {
+        let servers = match self.list_servers().await {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to list servers for duplicate check: {}", e);
+                return;
+            }
+        };
+
+        for (server, existing) in duplicate_backends(&servers) {
+            warn!(
+                "Server '{}' ({}) is the same backend as server '{}' ({}); its items will show up twice",
+                server.name, server.url, existing.name, existing.url
+            );
+        }
+    }
+
+    /// Remember the `Id` a healthy server reported, so servers added later can be
+    /// checked against it even while it is unreachable.
+    async fn record_backend_id(&self, server: &Server, status: &ServerHealthStatus) {
+        let ServerHealthStatus::Healthy(PublicSystemInfo {
+            id: Some(backend_id),
+            ..
+        }) = status
+        else {
+            return;
+        };
+        if server.backend_id.as_ref() == Some(backend_id) {
+            return;
+        }
+        if let Err(e) = self.update_server_backend_id(server.id, backend_id).await {
+            error!(
+                "Failed to store backend id of server {}: {}",
+                server.name, e
+            );
+        }
+    }
+
+    async fn check_server(&self, server: &Server) -> ServerHealthStatus {
+        let client = match JellyfinClient::new_with_client(
+            server.url.as_str(),
+            self.client_info.clone(),
+            self.http_client.clone(),
+        ) {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to create client for server {}: {}", server.name, e);
+                return ServerHealthStatus::Unhealthy(e.to_string());
+            }
+        };
+
+        match client.get_public_system_info().await {
+            Ok(info) => ServerHealthStatus::Healthy(info),
+            Err(JellyfinError::NotJellyfin(reason)) => ServerHealthStatus::NotJellyfin(reason),
+            Err(e) => ServerHealthStatus::Unhealthy(e.to_string()),
+        }
+    }
+
+    /// The `Id` the backend at `url` reports in its public system info, if it
+    /// can be reached.
+    pub async fn fetch_backend_id(&self, url: &str) -> Option<String> {
+        let client = JellyfinClient::new_with_client(
+            url,
+            self.client_info.clone(),
+            self.http_client.clone(),
+        )
+        .ok()?;
+        client.get_public_system_info().await.ok()?.id
+    }
+
+    async fn store_status(
+        &self,
+        server_id: ServerId,
+        status: ServerHealthStatus,
+        latency: Duration,
+    ) {
         let mut lock = self.health_status.write().await;
-        for (server_id, status) in statuses {
-            if let Some(old_status) = lock.get(&server_id) {
-                if *old_status == status {
-                    continue; // No change
-                } else {
-                    info!(
-                        "Server ID {} health status changed: {:?} -> {:?}",
-                        server_id, old_status, status
-                    );
-                }
+        if let Some(old) = lock.get(&server_id) {
+            if old.status != status {
+                info!(
+                    "Server ID {} health status changed: {:?} -> {:?}",
+                    server_id, old.status, status
+                );
             }
-            lock.insert(server_id, status);
         }
+        lock.insert(
+            server_id,
+            CheckedStatus {
+                checked_at: Instant::now(),
+                latency,
+                status,
+            },
+        );
+    }
+
+    /// The status of `server`, checked again when the last check is older than a
+    /// few seconds, for views that show it right after a server was changed.
+    pub async fn current_server_status(&self, server: &Server) -> ServerHealthStatus {
+        if let Some(checked) = self.health_status.read().await.get(&server.id) {
+            if checked.checked_at.elapsed() < STATUS_CACHE_TTL {
+                return checked.status.clone();
+            }
+        }
+
+        let started = Instant::now();
+        let status = self.check_server(server).await;
+        self.record_backend_id(server, &status).await;
+        self.store_status(server.id, status.clone(), started.elapsed())
+            .await;
+        status
+    }
+
+    /// How long the last health check of a server took to get an answer, if it got one.
+    pub async fn server_latency(&self, server_id: ServerId) -> Option<Duration> {
+        let health = self.health_status.read().await;
+        let checked = health.get(&server_id)?;
+        (!matches!(checked.status, ServerHealthStatus::Unhealthy(_))).then_some(checked.latency)
+    }
+
+    /// Number of servers whose last health check found them healthy.
+    pub async fn healthy_server_count(&self) -> usize {
+        let health = self.health_status.read().await;
+        health
+            .values()
+            .filter(|checked| checked.status.is_healthy())
+            .count()
+    }
+
+    /// Whether the database answers a trivial query.
+    pub async fn database_reachable(&self) -> bool {
+        sqlx::query("SELECT 1").execute(&self.pool).await.is_ok()
+    }
+
+    /// Write a consistent snapshot of the database to `path` with `VACUUM INTO`. It
+    /// only reads, so requests keep being served while the backup is written.
+    pub async fn backup_database(&self, path: &std::path::Path) -> Result<(), sqlx::Error> {
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
     }
 
     pub async fn server_status(&self, server_id: ServerId) -> ServerHealthStatus {
         let health = self.health_status.read().await;
         health
             .get(&server_id)
-            .unwrap_or(&ServerHealthStatus::Unhealthy(
+            .map(|checked| checked.status.clone())
+            .unwrap_or(ServerHealthStatus::Unhealthy(
                 "Unknown Server Status".to_string(),
             ))
-            .clone()
+    }
+
+    /// The `ServerId` a server reported in its last successful health check.
+    pub async fn upstream_server_id(&self, server_id: ServerId) -> Option<String> {
+        match self.server_status(server_id).await {
+            ServerHealthStatus::Healthy(info) => info.id,
+            ServerHealthStatus::Unhealthy(_) | ServerHealthStatus::NotJellyfin(_) => None,
+        }
     }
 
     /// Get the best available server (highest priority, healthy, active)
     pub async fn get_best_server(&self) -> Result<Option<Server>, sqlx::Error> {
-        let servers = self.list_servers().await?;
+        let servers = self
+            .list_servers()
+            .await?
+            .into_iter()
+            .filter(Server::is_auto_selectable)
+            .collect::<Vec<_>>();
 
         if servers.is_empty() {
             return Ok(None);
@@ -445,6 +735,122 @@ impl ServerStorageService {
         Ok(row)
     }
 
+    pub async fn list_server_admins(&self) -> Result<Vec<ServerAdmin>, sqlx::Error> {
+        sqlx::query_as::<_, ServerAdmin>(
+            r#"
+            SELECT id, server_id, username, password, created_at, updated_at
+            FROM server_admins
+            ORDER BY server_id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Start a transaction on the database shared by the storage services, so a change
+    /// spanning them (like an import) is applied completely or not at all.
+    pub async fn begin(&self) -> Result<Transaction<'static, Sqlite>, sqlx::Error> {
+        self.pool.begin().await
+    }
+
+    /// Insert an exported server, or update the settings of the server with the same
+    /// URL. Returns the server as stored in this database.
+    pub async fn import_server(
+        &self,
+        conn: &mut SqliteConnection,
+        server: &ExportedServer,
+    ) -> Result<Server, sqlx::Error> {
+        let url = ServerUrl::parse(&server.url).map_err(|_| {
+            sqlx::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid URL format: {}", server.url),
+            ))
+        })?;
+        let now = chrono::Utc::now();
+
+        let existing = sqlx::query_scalar::<_, i64>("SELECT id FROM servers WHERE url = ?")
+            .bind(url.as_str())
+            .fetch_optional(&mut *conn)
+            .await?;
+        let server_id = match existing {
+            Some(id) => {
+                sqlx::query(
+                    r#"
+                    UPDATE servers
+                    SET name = ?, priority = ?, media_streaming_mode = ?, authorization_header_mode = ?,
+                        max_connections = ?, backend_id = COALESCE(backend_id, ?), updated_at = ?
+                    WHERE id = ?
+                    "#,
+                )
+                .bind(&server.name)
+                .bind(server.priority)
+                .bind(server.media_streaming_mode.to_string())
+                .bind(server.authorization_header_mode.to_string())
+                .bind(server.max_connections)
+                .bind(&server.backend_id)
+                .bind(now)
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+                id
+            }
+            None => sqlx::query(
+                r#"
+                INSERT INTO servers (name, url, priority, media_streaming_mode, authorization_header_mode, max_connections, backend_id, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&server.name)
+            .bind(url.as_str())
+            .bind(server.priority)
+            .bind(server.media_streaming_mode.to_string())
+            .bind(server.authorization_header_mode.to_string())
+            .bind(server.max_connections)
+            .bind(&server.backend_id)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?
+            .last_insert_rowid(),
+        };
+
+        let row = sqlx::query(
+            r#"
+            SELECT id, name, url, priority, media_streaming_mode, authorization_header_mode, max_connections, backend_id, created_at, updated_at
+            FROM servers
+            WHERE id = ?
+            "#,
+        )
+        .bind(server_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        Server::from_row(row)
+    }
+
+    pub async fn import_server_admin(
+        &self,
+        conn: &mut SqliteConnection,
+        server_id: ServerId,
+        username: &str,
+        password: &EncryptedPassword,
+    ) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now();
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO server_admins (server_id, username, password, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(server_id.as_i64())
+        .bind(username)
+        .bind(password)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
     pub async fn delete_server_admin(&self, server_id: ServerId) -> Result<bool, sqlx::Error> {
         let result = sqlx::query(
             r#"
@@ -524,4 +930,150 @@ mod tests {
         let server = service.get_server_by_id(server_id).await.unwrap().unwrap();
         assert_eq!(server.media_streaming_mode, MediaStreamingMode::Proxy);
     }
+
+    #[tokio::test]
+    async fn best_server_skips_negative_priorities() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        let service = ServerStorageService::new(pool);
+
+        service
+            .add_server(
+                "slow",
+                "http://slow:8096",
+                -10,
+                MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+        assert!(service.get_best_server().await.unwrap().is_none());
+
+        service
+            .add_server(
+                "default",
+                "http://default:8096",
+                5,
+                MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+        // Neither server was checked, so the fallback applies, which skips them too.
+        let best = service.get_best_server().await.unwrap().unwrap();
+        assert_eq!(best.name, "default");
+
+        // Negative priorities are still listed.
+        let servers = service.list_servers().await.unwrap();
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[1].priority, -10);
+    }
+
+    #[tokio::test]
+    async fn current_status_is_cached_and_tells_non_jellyfin_servers_apart() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        let service = ServerStorageService::new(pool);
+
+        let mut upstreams = Vec::new();
+        let mut statuses = Vec::new();
+        for (name, body) in [
+            (
+                "Old",
+                serde_json::json!({ "ServerName": "Old", "Version": "10.8.13" }),
+            ),
+            (
+                "Emby",
+                serde_json::json!({ "ProductName": "Emby Server", "Version": "4.8.0.0" }),
+            ),
+            ("Other", serde_json::json!({ "status": "ok" })),
+        ] {
+            let upstream = MockServer::start().await;
+            Mock::given(method("GET"))
+                .and(path("/System/Info/Public"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body))
+                .expect(1)
+                .mount(&upstream)
+                .await;
+            let server_id = service
+                .add_server(name, &upstream.uri(), 100, MediaStreamingMode::Proxy)
+                .await
+                .unwrap();
+            let server = service.get_server_by_id(server_id).await.unwrap().unwrap();
+
+            let status = service.current_server_status(&server).await;
+            assert_eq!(service.current_server_status(&server).await, status);
+            assert_eq!(service.server_status(server_id).await, status);
+            statuses.push(status);
+            upstream.verify().await;
+            upstreams.push(upstream);
+        }
+
+        assert!(statuses[0].is_healthy());
+        assert!(statuses[0].is_outdated());
+        assert!(statuses[1].is_healthy());
+        assert!(!statuses[1].is_outdated());
+        assert!(matches!(statuses[2], ServerHealthStatus::NotJellyfin(_)));
+    }
+
+    #[tokio::test]
+    async fn servers_reporting_the_same_backend_id_are_detected() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        let service = ServerStorageService::new(pool);
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/System/Info/Public"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ServerName": "Home",
+                "Version": "10.10.7",
+                "Id": "backend-1"
+            })))
+            .mount(&upstream)
+            .await;
+        let home = service
+            .add_server("Home", &upstream.uri(), 100, MediaStreamingMode::Proxy)
+            .await
+            .unwrap();
+        let other = service
+            .add_server(
+                "Other",
+                "http://other.local:8096",
+                50,
+                MediaStreamingMode::Proxy,
+            )
+            .await
+            .unwrap();
+        service.check_servers_health().await;
+
+        let servers = service.list_servers().await.unwrap();
+        assert_eq!(
+            find_server_by_backend_id(&servers, "backend-1").map(|server| server.id),
+            Some(home)
+        );
+        assert!(duplicate_backends(&servers).is_empty());
+        assert_eq!(
+            service.fetch_backend_id(&upstream.uri()).await.as_deref(),
+            Some("backend-1")
+        );
+
+        service
+            .update_server_backend_id(other, "backend-1")
+            .await
+            .unwrap();
+        let servers = service.list_servers().await.unwrap();
+        let duplicates = duplicate_backends(&servers);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].0.name, "Other");
+        assert_eq!(duplicates[0].1.name, "Home");
+    }
 }
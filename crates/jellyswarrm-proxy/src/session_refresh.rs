@@ -0,0 +1,266 @@
+//! Background refresh of backend tokens.
+//!
+//! Authorization sessions get an `expires_at` when `session_lifetime_secs` is set. Before
+//! a session runs out, it is logged in again with the stored mapping credentials, so
+//! long-lived clients don't start getting `401`s from the backend.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use jellyfin_api::{error::JellyfinApiError, models::AuthResponse, ClientInfo, JellyfinClient};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    encryption::{HashedPassword, Password},
+    server_storage::Server,
+    user_authorization_service::AuthorizationSession,
+    AppState,
+};
+
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What to do with a session on a refresh pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshDecision {
+    /// Doesn't expire, has already expired or isn't within the window yet.
+    NotDue,
+    /// Log in again with the mapping credentials.
+    Refresh,
+    /// Expiring, but there are no usable credentials to log in with.
+    Relogin,
+}
+
+pub fn refresh_decision(
+    expires_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    window: chrono::Duration,
+    has_credentials: bool,
+) -> RefreshDecision {
+    match expires_at {
+        Some(expires_at) if expires_at > now && expires_at - now <= window => {
+            if has_credentials {
+                RefreshDecision::Refresh
+            } else {
+                RefreshDecision::Relogin
+            }
+        }
+        _ => RefreshDecision::NotDue,
+    }
+}
+
+pub fn start_session_refresh_loop(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(REFRESH_CHECK_INTERVAL).await;
+            let window_secs = state.config.read().await.session_refresh_window_secs;
+            if window_secs > 0 {
+                refresh_expiring_sessions(&state, chrono::Duration::seconds(window_secs as i64))
+                    .await;
+            }
+        }
+    });
+}
+
+async fn refresh_expiring_sessions(state: &AppState, window: chrono::Duration) {
+    let now = Utc::now();
+    let sessions = match state
+        .user_authorization
+        .list_sessions_expiring_before(now + window)
+        .await
+    {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            error!("Failed to list expiring sessions: {}", e);
+            return;
+        }
+    };
+    if sessions.is_empty() {
+        return;
+    }
+    debug!("Refreshing {} expiring sessions", sessions.len());
+
+    for (session, server) in sessions {
+        let password = match mapping_password(state, &session, &server).await {
+            Ok(password) => password,
+            Err(e) => {
+                error!(
+                    "Failed to load credentials of session {}: {}",
+                    session.id, e
+                );
+                continue;
+            }
+        };
+
+        match refresh_decision(session.expires_at, now, window, password.is_some()) {
+            RefreshDecision::NotDue => {}
+            RefreshDecision::Refresh => {
+                if let Some((username, password)) = password {
+                    refresh_session(state, &session, &server, &username, &password).await;
+                }
+            }
+            RefreshDecision::Relogin => {
+                warn!(
+                    "No usable credentials to refresh session {} on server '{}', the user has to log in again",
+                    session.id, server.name
+                );
+                expire_session(state, &session).await;
+            }
+        }
+    }
+}
+
+/// The mapped username and decrypted password of the session's mapping.
+async fn mapping_password(
+    state: &AppState,
+    session: &AuthorizationSession,
+    server: &Server,
+) -> Result<Option<(String, Password)>, sqlx::Error> {
+    let Some(mapping) = state
+        .user_authorization
+        .get_server_mapping_by_server_id(&session.user_id, server.id)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Some(user) = state
+        .user_authorization
+        .get_user_by_id(&session.user_id)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let admin_password = state.get_admin_password().await;
+    let admin_password_hash: HashedPassword = (&admin_password).into();
+    let password = state
+        .user_authorization
+        .try_decrypt_server_mapping_password(
+            &mapping,
+            &user.original_password_hash,
+            &admin_password_hash,
+            None,
+            Some(&admin_password),
+        );
+
+    Ok(password.map(|password| (mapping.mapped_username, password)))
+}
+
+async fn refresh_session(
+    state: &AppState,
+    session: &AuthorizationSession,
+    server: &Server,
+    username: &str,
+    password: &Password,
+) {
+    let client_info = ClientInfo {
+        client: session.device.client.clone(),
+        device: session.device.device.clone(),
+        device_id: session.device.device_id.clone(),
+        version: session.device.version.clone(),
+    };
+    let client = match JellyfinClient::new_with_client(
+        server.url.as_str(),
+        client_info,
+        state.reqwest_client.clone(),
+    ) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "Failed to create client for server '{}': {}",
+                server.name, e
+            );
+            return;
+        }
+    };
+
+    match client
+        .authenticate_by_name_typed::<AuthResponse>(username, password.as_str())
+        .await
+    {
+        Ok(response) => {
+            let expires_at = state
+                .user_authorization
+                .session_lifetime()
+                .map(|lifetime| Utc::now() + lifetime);
+            match state
+                .user_authorization
+                .update_session_token(session.id, &response.access_token, expires_at)
+                .await
+            {
+                Ok(_) => info!(
+                    "Refreshed session {} on server '{}'",
+                    session.id, server.name
+                ),
+                Err(e) => error!("Failed to store refreshed session {}: {}", session.id, e),
+            }
+        }
+        Err(JellyfinApiError::Unauthorized) => {
+            warn!(
+                "Server '{}' rejected the credentials of session {}, the user has to log in again",
+                server.name, session.id
+            );
+            expire_session(state, session).await;
+        }
+        Err(e) => {
+            // Try again on the next pass while the session is still valid.
+            warn!(
+                "Failed to refresh session {} on server '{}': {}",
+                session.id, server.name, e
+            );
+        }
+    }
+}
+
+async fn expire_session(state: &AppState, session: &AuthorizationSession) {
+    if let Err(e) = state.user_authorization.expire_session(session.id).await {
+        error!("Failed to expire session {}: {}", session.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_are_refreshed_only_within_the_window() {
+        let now = Utc::now();
+        let window = chrono::Duration::hours(1);
+
+        assert_eq!(
+            refresh_decision(None, now, window, true),
+            RefreshDecision::NotDue
+        );
+        assert_eq!(
+            refresh_decision(Some(now + chrono::Duration::hours(2)), now, window, true),
+            RefreshDecision::NotDue
+        );
+        assert_eq!(
+            refresh_decision(Some(now + chrono::Duration::minutes(30)), now, window, true),
+            RefreshDecision::Refresh
+        );
+        assert_eq!(
+            refresh_decision(Some(now + window), now, window, true),
+            RefreshDecision::Refresh
+        );
+        // Already expired sessions are left to the login flow.
+        assert_eq!(
+            refresh_decision(Some(now - chrono::Duration::minutes(1)), now, window, true),
+            RefreshDecision::NotDue
+        );
+    }
+
+    #[test]
+    fn expiring_sessions_without_credentials_need_a_new_login() {
+        let now = Utc::now();
+        let window = chrono::Duration::hours(1);
+
+        assert_eq!(
+            refresh_decision(Some(now + chrono::Duration::minutes(5)), now, window, false),
+            RefreshDecision::Relogin
+        );
+        assert_eq!(
+            refresh_decision(Some(now + chrono::Duration::hours(2)), now, window, false),
+            RefreshDecision::NotDue
+        );
+    }
+}
@@ -1,10 +1,21 @@
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use tokio::sync::{RwLock, RwLockWriteGuard};
+use tracing::debug;
 
 use crate::server_id::ServerId;
 
 const PLAYBACK_SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+const PLAYBACK_SESSION_CAPACITY: usize = 10_000;
+/// Streams are refreshed by playback progress reports; one that hasn't reported for
+/// this long no longer counts against its user's bandwidth cap. Bitrates negotiated by
+/// `PlaybackInfo` whose playback never started are forgotten after the same time.
+const ACTIVE_STREAM_TTL: Duration = Duration::from_secs(15 * 60);
+/// How often expired play sessions and streams are dropped when nobody looks them up.
+const REAP_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
 #[derive(Clone)]
 pub struct PlaybackSession {
@@ -12,11 +23,26 @@ pub struct PlaybackSession {
     pub item_id: String,    // ID of the media item being played
     pub user_id: String,
     pub server_id: ServerId,
+    /// Id of the user on the backend that answered the `PlaybackInfo` request.
+    pub original_user_id: String,
+    /// Id of the media source on that backend.
+    pub original_item_id: String,
 }
 
 pub struct SessionStorage {
     sessions: RwLock<Vec<TrackedPlaybackSession>>,
     session_ttl: Duration,
+    session_capacity: usize,
+    active_streams: RwLock<Vec<ActiveStream>>,
+    planned_streams: RwLock<Vec<ActiveStream>>,
+    stream_ttl: Duration,
+}
+
+struct ActiveStream {
+    user_id: String,
+    play_session_id: String,
+    bitrate: i64,
+    updated_at: Instant,
 }
 
 struct TrackedPlaybackSession {
@@ -36,9 +62,20 @@ impl SessionStorage {
     }
 
     pub fn with_session_ttl(session_ttl: Duration) -> Self {
+        Self::with_limits(session_ttl, PLAYBACK_SESSION_CAPACITY)
+    }
+
+    /// Storage whose play sessions expire `session_ttl` after they were last added,
+    /// keeping at most `session_capacity` of them. Past that, the least recently
+    /// added sessions are evicted even if they haven't expired yet.
+    pub fn with_limits(session_ttl: Duration, session_capacity: usize) -> Self {
         SessionStorage {
             sessions: RwLock::new(Vec::new()),
             session_ttl,
+            session_capacity: session_capacity.max(1),
+            active_streams: RwLock::new(Vec::new()),
+            planned_streams: RwLock::new(Vec::new()),
+            stream_ttl: ACTIVE_STREAM_TTL,
         }
     }
 
@@ -56,6 +93,25 @@ impl SessionStorage {
             session,
             updated_at: Instant::now(),
         });
+
+        // Sessions are kept in the order they were last added, so the oldest go first.
+        if sessions.len() > self.session_capacity {
+            let evicted = sessions.len() - self.session_capacity;
+            sessions.drain(..evicted);
+            debug!(
+                "Evicted {} play sessions over the capacity of {}",
+                evicted, self.session_capacity
+            );
+        }
+    }
+
+    /// Number of play sessions that haven't expired.
+    pub async fn len(&self) -> usize {
+        self.live_sessions().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
     }
 
     pub async fn get_session(&self, session_id: &str) -> Option<PlaybackSession> {
@@ -105,6 +161,120 @@ impl SessionStorage {
         sessions.retain(|tracked| tracked.session.server_id != server_id);
     }
 
+    /// Drop play sessions and streams that outlived their TTL.
+    pub async fn reap_expired(&self) {
+        let sessions = self.live_sessions().await.len();
+        let streams = self.live_streams().await.len();
+        Self::prune_stale_streams(&mut *self.planned_streams.write().await, self.stream_ttl);
+        debug!(
+            "Reaped expired play sessions, {} sessions and {} streams remain",
+            sessions, streams
+        );
+    }
+
+    /// Periodically reap expired entries, so sessions of clients that never report
+    /// back don't pile up between lookups.
+    pub fn start_reaper_loop(self: &Arc<Self>) {
+        let storage = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(REAP_INTERVAL).await;
+                storage.reap_expired().await;
+            }
+        });
+    }
+
+    /// Sum of the bitrates of a user's active streams, optionally leaving out one
+    /// play session (e.g. the one that is being renegotiated).
+    pub async fn active_bitrate_for_user(
+        &self,
+        user_id: &str,
+        excluded_play_session_id: Option<&str>,
+    ) -> i64 {
+        let streams = self.live_streams().await;
+        streams
+            .iter()
+            .filter(|stream| stream.user_id == user_id)
+            .filter(|stream| Some(stream.play_session_id.as_str()) != excluded_play_session_id)
+            .map(|stream| stream.bitrate)
+            .sum()
+    }
+
+    /// Number of streams that started or reported progress within the stream TTL.
+    pub async fn active_stream_count(&self) -> usize {
+        self.live_streams().await.len()
+    }
+
+    pub async fn track_stream(&self, user_id: &str, play_session_id: &str, bitrate: i64) {
+        let mut streams = self.live_streams().await;
+        streams.retain(|stream| stream.play_session_id != play_session_id);
+        streams.push(ActiveStream {
+            user_id: user_id.to_string(),
+            play_session_id: play_session_id.to_string(),
+            bitrate,
+            updated_at: Instant::now(),
+        });
+    }
+
+    /// Remember the bitrate a `PlaybackInfo` request negotiated for a play session. It
+    /// only counts against the user's cap once the client reports that playback started.
+    pub async fn plan_stream(&self, user_id: &str, play_session_id: &str, bitrate: i64) {
+        let mut planned = self.planned_streams.write().await;
+        Self::prune_stale_streams(&mut planned, self.stream_ttl);
+        planned.retain(|stream| stream.play_session_id != play_session_id);
+        planned.push(ActiveStream {
+            user_id: user_id.to_string(),
+            play_session_id: play_session_id.to_string(),
+            bitrate,
+            updated_at: Instant::now(),
+        });
+    }
+
+    /// Playback of a play session started or progressed: its planned bitrate becomes
+    /// active, replacing the one it streamed with so far, or its active stream is
+    /// refreshed.
+    pub async fn start_stream(&self, play_session_id: &str) {
+        let planned = {
+            let mut planned = self.planned_streams.write().await;
+            planned
+                .iter()
+                .position(|stream| stream.play_session_id == play_session_id)
+                .map(|index| planned.remove(index))
+        };
+
+        let mut streams = self.live_streams().await;
+        if let Some(mut stream) = planned {
+            streams.retain(|active| active.play_session_id != play_session_id);
+            stream.updated_at = Instant::now();
+            streams.push(stream);
+        } else if let Some(stream) = streams
+            .iter_mut()
+            .find(|stream| stream.play_session_id == play_session_id)
+        {
+            stream.updated_at = Instant::now();
+        }
+    }
+
+    pub async fn release_stream(&self, play_session_id: &str) {
+        self.planned_streams
+            .write()
+            .await
+            .retain(|stream| stream.play_session_id != play_session_id);
+        let mut streams = self.active_streams.write().await;
+        streams.retain(|stream| stream.play_session_id != play_session_id);
+    }
+
+    async fn live_streams(&self) -> RwLockWriteGuard<'_, Vec<ActiveStream>> {
+        let mut streams = self.active_streams.write().await;
+        Self::prune_stale_streams(&mut streams, self.stream_ttl);
+        streams
+    }
+
+    fn prune_stale_streams(streams: &mut Vec<ActiveStream>, stream_ttl: Duration) {
+        let now = Instant::now();
+        streams.retain(|stream| now.duration_since(stream.updated_at) <= stream_ttl);
+    }
+
     async fn live_sessions(&self) -> RwLockWriteGuard<'_, Vec<TrackedPlaybackSession>> {
         let now = Instant::now();
         let mut sessions = self.sessions.write().await;
@@ -135,6 +305,8 @@ mod tests {
                 item_id: "item-1".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(1),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
         storage
@@ -143,6 +315,8 @@ mod tests {
                 item_id: "item-1".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(2),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
 
@@ -168,6 +342,8 @@ mod tests {
                 item_id: "shared-item".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(1),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
         storage
@@ -176,6 +352,8 @@ mod tests {
                 item_id: "shared-item".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(2),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
 
@@ -207,6 +385,8 @@ mod tests {
                 item_id: "item-1".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(1),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
 
@@ -229,6 +409,8 @@ mod tests {
                 item_id: "stale-item".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(1),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
 
@@ -240,6 +422,8 @@ mod tests {
                 item_id: "fresh-item".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(2),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
 
@@ -257,6 +441,8 @@ mod tests {
                 item_id: "item-1".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(1),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
         storage
@@ -265,6 +451,8 @@ mod tests {
                 item_id: "item-2".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(2),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
 
@@ -284,6 +472,8 @@ mod tests {
                 item_id: "shared-item".to_string(),
                 user_id: "user-1".to_string(),
                 server_id: ServerId::new(1),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
         storage
@@ -292,6 +482,8 @@ mod tests {
                 item_id: "shared-item".to_string(),
                 user_id: "user-2".to_string(),
                 server_id: ServerId::new(2),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
             })
             .await;
 
@@ -300,4 +492,140 @@ mod tests {
         assert_eq!(sessions[0].session_id, "session-2");
         assert_eq!(sessions[1].session_id, "session-1");
     }
+
+    #[tokio::test]
+    async fn test_session_round_trip_keeps_original_ids_until_reaped() {
+        let storage = SessionStorage::with_session_ttl(Duration::from_millis(20));
+
+        storage
+            .add_session(PlaybackSession {
+                session_id: "play-session".to_string(),
+                item_id: "virtual-item".to_string(),
+                user_id: "proxy-user".to_string(),
+                server_id: ServerId::new(3),
+                original_user_id: "backend-user".to_string(),
+                original_item_id: "backend-item".to_string(),
+            })
+            .await;
+
+        let session = storage.get_session("play-session").await.unwrap();
+        assert_eq!(session.server_id, ServerId::new(3));
+        assert_eq!(session.item_id, "virtual-item");
+        assert_eq!(session.user_id, "proxy-user");
+        assert_eq!(session.original_user_id, "backend-user");
+        assert_eq!(session.original_item_id, "backend-item");
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        storage.reap_expired().await;
+        assert!(storage.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sessions_expire_after_their_ttl() {
+        let storage = SessionStorage::with_limits(Duration::from_millis(20), 10);
+
+        storage
+            .add_session(PlaybackSession {
+                session_id: "session-1".to_string(),
+                item_id: "item-1".to_string(),
+                user_id: "user-1".to_string(),
+                server_id: ServerId::new(1),
+                original_user_id: "original-user".to_string(),
+                original_item_id: "original-item".to_string(),
+            })
+            .await;
+        assert_eq!(storage.len().await, 1);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        assert_eq!(storage.len().await, 0);
+        assert!(storage.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_capacity_evicts_the_oldest_sessions() {
+        let storage = SessionStorage::with_limits(PLAYBACK_SESSION_TTL, 2);
+
+        for index in 1..=3 {
+            storage
+                .add_session(PlaybackSession {
+                    session_id: format!("session-{index}"),
+                    item_id: "item-1".to_string(),
+                    user_id: "user-1".to_string(),
+                    server_id: ServerId::new(1),
+                    original_user_id: "original-user".to_string(),
+                    original_item_id: "original-item".to_string(),
+                })
+                .await;
+        }
+
+        assert_eq!(storage.len().await, 2);
+        assert!(storage.get_session("session-1").await.is_none());
+        assert!(storage.get_session("session-2").await.is_some());
+        assert!(storage.get_session("session-3").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_active_bitrate_sums_streams_per_user() {
+        let storage = SessionStorage::new();
+
+        storage.track_stream("user-1", "play-1", 4_000_000).await;
+        storage.track_stream("user-1", "play-2", 3_000_000).await;
+        storage.track_stream("user-2", "play-3", 8_000_000).await;
+        // Renegotiating a play session replaces its bitrate.
+        storage.track_stream("user-1", "play-2", 2_000_000).await;
+
+        assert_eq!(
+            storage.active_bitrate_for_user("user-1", None).await,
+            6_000_000
+        );
+        assert_eq!(
+            storage
+                .active_bitrate_for_user("user-1", Some("play-1"))
+                .await,
+            2_000_000
+        );
+
+        storage.release_stream("play-1").await;
+        assert_eq!(
+            storage.active_bitrate_for_user("user-1", None).await,
+            2_000_000
+        );
+        assert_eq!(
+            storage.active_bitrate_for_user("user-2", None).await,
+            8_000_000
+        );
+    }
+
+    #[tokio::test]
+    async fn planned_streams_count_once_playback_starts() {
+        let storage = SessionStorage::new();
+
+        storage.plan_stream("user-1", "play-1", 4_000_000).await;
+        assert_eq!(storage.active_bitrate_for_user("user-1", None).await, 0);
+        assert_eq!(storage.active_stream_count().await, 0);
+
+        storage.start_stream("play-1").await;
+        assert_eq!(
+            storage.active_bitrate_for_user("user-1", None).await,
+            4_000_000
+        );
+
+        // Renegotiating replaces the bitrate once the client reports progress again.
+        storage.plan_stream("user-1", "play-1", 2_000_000).await;
+        assert_eq!(
+            storage.active_bitrate_for_user("user-1", None).await,
+            4_000_000
+        );
+        storage.start_stream("play-1").await;
+        assert_eq!(
+            storage.active_bitrate_for_user("user-1", None).await,
+            2_000_000
+        );
+
+        storage.plan_stream("user-1", "play-2", 1_000_000).await;
+        storage.release_stream("play-2").await;
+        storage.start_stream("play-2").await;
+        assert_eq!(storage.active_stream_count().await, 1);
+    }
 }
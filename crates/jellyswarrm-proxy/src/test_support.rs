@@ -0,0 +1,126 @@
+//! Fixtures shared by the test modules.
+
+use std::sync::Arc;
+
+use sqlx::SqlitePool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{
+    config::{AppConfig, MediaStreamingMode, MIGRATOR},
+    handlers::quick_connect::QuickConnectStorage,
+    media_storage_service::MediaStorageService,
+    models::Authorization,
+    server_storage::{Server, ServerStorageService},
+    session_storage::SessionStorage,
+    user_authorization_service::{User, UserAuthorizationService},
+    virtual_library_service::VirtualLibraryService,
+    AppState, DataContext, ProxyProcessors,
+};
+
+/// An `AppState` on a fresh in-memory database with the default config.
+pub(crate) async fn create_test_app_state() -> AppState {
+    create_test_app_state_with_config(AppConfig::default()).await
+}
+
+/// An `AppState` on a fresh in-memory database with `config`.
+pub(crate) async fn create_test_app_state_with_config(config: AppConfig) -> AppState {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    create_test_app_state_with_pool(pool, config).await
+}
+
+/// An `AppState` on `pool`, migrated, with `config`. For tests that need the
+/// database in a file.
+pub(crate) async fn create_test_app_state_with_pool(
+    pool: SqlitePool,
+    config: AppConfig,
+) -> AppState {
+    MIGRATOR.run(&pool).await.unwrap();
+    let server_storage = ServerStorageService::new(pool.clone());
+    let media_storage = MediaStorageService::new(pool.clone());
+
+    let data_context = DataContext {
+        user_authorization: Arc::new(UserAuthorizationService::new(pool.clone())),
+        server_storage: Arc::new(server_storage.clone()),
+        media_storage: Arc::new(media_storage.clone()),
+        virtual_library_service: Arc::new(VirtualLibraryService::new(
+            pool,
+            server_storage,
+            media_storage,
+        )),
+        play_sessions: Arc::new(SessionStorage::new()),
+        config: Arc::new(tokio::sync::RwLock::new(config)),
+    };
+    let processors = ProxyProcessors::new(data_context.clone());
+
+    AppState::new(
+        reqwest::Client::new(),
+        reqwest::Client::new(),
+        data_context,
+        processors,
+        QuickConnectStorage::new(),
+    )
+}
+
+/// The authorization of a Jellyfin Web client in Firefox, carrying `token`.
+pub(crate) fn web_authorization(token: Option<String>) -> Authorization {
+    Authorization {
+        client: "Jellyfin Web".to_string(),
+        device: "Firefox".to_string(),
+        device_id: "web-device-id".to_string(),
+        version: "10.10.7".to_string(),
+        token,
+    }
+}
+
+/// Add `upstream` as a proxied server called `name`, map `user` to it as
+/// "viewer" and store a web session whose token is `{name}-token` and whose
+/// upstream user id is `{name}-user-id`.
+pub(crate) async fn add_server_with_session(
+    state: &AppState,
+    user: &User,
+    name: &str,
+    upstream: &MockServer,
+    priority: i32,
+) -> Server {
+    Mock::given(method("GET"))
+        .and(path("/System/Info/Public"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "ServerName": name,
+            "Version": "10.10.7"
+        })))
+        .mount(upstream)
+        .await;
+
+    let server_id = state
+        .server_storage
+        .add_server(name, &upstream.uri(), priority, MediaStreamingMode::Proxy)
+        .await
+        .unwrap();
+    let server = state
+        .server_storage
+        .get_server_by_id(server_id)
+        .await
+        .unwrap()
+        .unwrap();
+    state
+        .user_authorization
+        .add_server_mapping(&user.id, &server, "viewer", &"password".into(), None)
+        .await
+        .unwrap();
+    state
+        .user_authorization
+        .store_authorization_session(
+            &user.id,
+            &server,
+            &web_authorization(None),
+            format!("{name}-token"),
+            format!("{name}-user-id"),
+            None,
+        )
+        .await
+        .unwrap();
+    server
+}
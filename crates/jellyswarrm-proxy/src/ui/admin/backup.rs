@@ -0,0 +1,100 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tokio_util::io::ReaderStream;
+use tracing::error;
+
+use crate::{
+    database_backup::{backup_dir, create_backup},
+    AppState,
+};
+
+/// Write a database backup to `backup_dir` and download it.
+pub async fn download_backup(State(state): State<AppState>) -> Response {
+    let (dir, retention) = {
+        let config = state.config.read().await;
+        (backup_dir(&config.backup_dir), config.backup_retention)
+    };
+    let path = match create_backup(&state.server_storage, &dir, retention).await {
+        Ok(path) => path,
+        Err(e) => {
+            error!("Database backup failed: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Backup failed").into_response();
+        }
+    };
+
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Failed to open database backup {}: {}", path.display(), e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Backup failed").into_response();
+        }
+    };
+    let length = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            error!("Failed to read database backup {}: {}", path.display(), e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Backup failed").into_response();
+        }
+    };
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "jellyswarrm.db".to_string());
+    (
+        [
+            (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::test_support::create_test_app_state_with_pool;
+    use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
+
+    #[tokio::test]
+    async fn backups_download_with_their_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(dir.path().join("jellyswarrm.db"))
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+        let state = create_test_app_state_with_pool(
+            pool,
+            AppConfig {
+                backup_dir: dir.path().join("backups").to_string_lossy().into_owned(),
+                ..AppConfig::default()
+            },
+        )
+        .await;
+
+        let response = download_backup(State(state)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let length = response.headers()[header::CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), length);
+        assert!(body.starts_with(b"SQLite format 3\0"));
+    }
+}
@@ -10,10 +10,11 @@ use serde::Deserialize;
 use tracing::{error, info};
 
 use crate::{
-    config::CLIENT_INFO,
+    config::{DeduplicationStrategy, CLIENT_INFO},
     duplicate_policy::DuplicatePolicy,
     encryption::{decrypt_password, HashedPassword},
     server_id::ServerId,
+    ui::admin::servers::{is_valid_priority, PRIORITY_RANGE_MESSAGE},
     virtual_library_service::{normalize_library_id, LibraryGroupMemberRecord},
     AppState,
 };
@@ -58,6 +59,7 @@ pub struct LibraryGroupMemberView {
     pub server_name: String,
     pub original_library_id: String,
     pub library_name: String,
+    pub priority: Option<i32>,
 }
 
 pub struct DiscoveredLibraryView {
@@ -72,6 +74,9 @@ pub struct DiscoveredLibraryView {
 #[derive(Deserialize)]
 pub struct CreateGroupForm {
     pub name: String,
+    /// Empty or missing uses `default_dedup_strategy`.
+    #[serde(default)]
+    pub dedup_strategy: String,
 }
 
 #[derive(Deserialize)]
@@ -88,6 +93,13 @@ pub struct RemoveMemberForm {
     pub library_id: String,
 }
 
+/// An empty priority hands the library back to its server's priority.
+#[derive(Deserialize)]
+pub struct UpdateMemberPriorityForm {
+    pub library_id: String,
+    pub priority: String,
+}
+
 #[derive(Deserialize)]
 pub struct UpdateGroupPolicyForm {
     pub duplicate_policy: String,
@@ -125,7 +137,7 @@ pub async fn library_groups_list(State(state): State<AppState>) -> impl IntoResp
     }
 }
 
-async fn render_library_groups_list(state: &AppState) -> Result<String, String> {
+pub(crate) async fn render_library_groups_list(state: &AppState) -> Result<String, String> {
     if state.merge_libraries_enabled().await {
         return Ok(
             "<article><p>Disable <strong>Merge Libraries Across Servers</strong> in Settings to use custom library groups.</p></article>".to_string(),
@@ -153,6 +165,7 @@ async fn render_library_groups_list(state: &AppState) -> Result<String, String>
                 server_name: String::new(),
                 original_library_id: member.original_library_id,
                 library_name: member.library_name,
+                priority: member.priority,
             })
             .collect::<Vec<_>>();
 
@@ -343,7 +356,7 @@ async fn discover_libraries(state: &AppState) -> Vec<DiscoveredLibrary> {
     discovered
 }
 
-fn library_groups_blocked_response() -> Response {
+pub(crate) fn library_groups_blocked_response() -> Response {
     (
         StatusCode::BAD_REQUEST,
         Html("<div class=\"alert alert-error\">Disable <strong>Merge Libraries Across Servers</strong> in Settings to use custom library groups.</div>"),
@@ -367,9 +380,24 @@ pub async fn create_group(
             .into_response();
     }
 
+    let dedup_strategy = if form.dedup_strategy.trim().is_empty() {
+        state.default_dedup_strategy().await
+    } else {
+        match form.dedup_strategy.parse::<DeduplicationStrategy>() {
+            Ok(strategy) => strategy,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Html(format!("<div class=\"alert alert-error\">{e}</div>")),
+                )
+                    .into_response()
+            }
+        }
+    };
+
     match state
         .virtual_library_service
-        .create_group(form.name.trim())
+        .create_group(form.name.trim(), dedup_strategy)
         .await
     {
         Ok(group) => {
@@ -591,3 +619,154 @@ pub async fn remove_member(
         }
     }
 }
+
+/// Update the priority of one library within its group
+pub async fn update_member_priority(
+    State(state): State<AppState>,
+    Path((group_virtual_id, server_id)): Path<(String, ServerId)>,
+    Form(form): Form<UpdateMemberPriorityForm>,
+) -> Response {
+    if state.merge_libraries_enabled().await {
+        return library_groups_blocked_response();
+    }
+
+    let priority = match form.priority.trim() {
+        "" => None,
+        value => match value.parse::<i32>() {
+            Ok(priority) if is_valid_priority(priority) => Some(priority),
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Html(format!(
+                        "<div class=\"alert alert-error\">{PRIORITY_RANGE_MESSAGE}</div>"
+                    )),
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    match state
+        .virtual_library_service
+        .update_member_priority(&group_virtual_id, server_id, &form.library_id, priority)
+        .await
+    {
+        Ok(true) => {
+            info!(
+                "Updated priority of library {} from server {} in group {} to {:?}",
+                form.library_id, server_id, group_virtual_id, priority
+            );
+            match render_library_groups_list(&state).await {
+                Ok(html) => Html(html).into_response(),
+                Err(message) => (StatusCode::INTERNAL_SERVER_ERROR, message).into_response(),
+            }
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Html("<div class=\"alert alert-error\">Library assignment not found</div>"),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to update library priority: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("<div class=\"alert alert-error\">Failed to update priority</div>"),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, MediaStreamingMode};
+    use crate::test_support::create_test_app_state_with_config;
+
+    async fn create(state: &AppState, name: &str, dedup_strategy: &str) -> DeduplicationStrategy {
+        let response = create_group(
+            State(state.clone()),
+            Form(CreateGroupForm {
+                name: name.to_string(),
+                dedup_strategy: dedup_strategy.to_string(),
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let groups = state.virtual_library_service.list_groups().await.unwrap();
+        groups
+            .into_iter()
+            .find(|group| group.name == name)
+            .unwrap()
+            .dedup_strategy
+    }
+
+    #[tokio::test]
+    async fn new_groups_use_the_configured_default_strategy() {
+        let state = create_test_app_state_with_config(AppConfig {
+            merge_libraries: false,
+            default_dedup_strategy: DeduplicationStrategy::NameYear,
+            ..AppConfig::default()
+        })
+        .await;
+
+        assert_eq!(
+            create(&state, "Films", "").await,
+            DeduplicationStrategy::NameYear
+        );
+        assert_eq!(
+            create(&state, "Anime", "off").await,
+            DeduplicationStrategy::Off
+        );
+    }
+
+    #[tokio::test]
+    async fn member_priorities_use_the_server_priority_range() {
+        let state = create_test_app_state_with_config(AppConfig {
+            merge_libraries: false,
+            ..AppConfig::default()
+        })
+        .await;
+        let server_id = state
+            .server_storage
+            .add_server(
+                "Main",
+                "http://127.0.0.1:1",
+                100,
+                MediaStreamingMode::Redirect,
+            )
+            .await
+            .unwrap();
+        let libraries = &state.virtual_library_service;
+        let group = libraries
+            .create_group("Films", DeduplicationStrategy::default())
+            .await
+            .unwrap();
+        libraries
+            .add_member(&group.virtual_id, server_id, "library-a", "Films")
+            .await
+            .unwrap();
+
+        let update = |priority: &str| {
+            update_member_priority(
+                State(state.clone()),
+                Path((group.virtual_id.clone(), server_id)),
+                Form(UpdateMemberPriorityForm {
+                    library_id: "library-a".to_string(),
+                    priority: priority.to_string(),
+                }),
+            )
+        };
+        let stored =
+            || async { libraries.list_members(&group.virtual_id).await.unwrap()[0].priority };
+
+        assert_eq!(update("-5").await.status(), StatusCode::OK);
+        assert_eq!(stored().await, Some(-5));
+
+        for invalid in ["0", "1000", "-1000"] {
+            assert_eq!(update(invalid).await.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(stored().await, Some(-5));
+    }
+}
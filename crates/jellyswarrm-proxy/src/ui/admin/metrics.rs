@@ -0,0 +1,34 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct MetricsQuery {
+    format: Option<String>,
+}
+
+/// Per-server request counts and latencies as JSON, or in the Prometheus text
+/// format with `?format=prometheus`.
+pub async fn get_metrics(
+    State(state): State<AppState>,
+    Query(query): Query<MetricsQuery>,
+) -> Response {
+    if query
+        .format
+        .is_some_and(|format| format.eq_ignore_ascii_case("prometheus"))
+    {
+        return (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            state.metrics.to_prometheus(),
+        )
+            .into_response();
+    }
+
+    Json(state.metrics.snapshot()).into_response()
+}
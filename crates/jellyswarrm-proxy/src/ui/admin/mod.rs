@@ -1,4 +1,7 @@
+pub mod backup;
 pub mod libraries;
+pub mod metrics;
 pub mod servers;
 pub mod settings;
+pub mod transfer;
 pub mod users;
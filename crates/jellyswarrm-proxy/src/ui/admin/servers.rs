@@ -5,17 +5,23 @@ use axum::{
     response::{Html, IntoResponse, Response},
     Form,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use tracing::{error, info};
 
 use crate::{
-    config::MediaStreamingMode,
+    config::{AuthorizationHeaderMode, MediaStreamingMode},
     encryption::{encrypt_password, Password},
+    federated_users::{ServerSyncResult, UserSyncResult},
     server_id::ServerId,
-    server_storage::Server,
+    server_storage::{find_server_by_backend_id, Server, ServerStorageService},
     AppState,
 };
 
+/// Upper bound for a connection test, so an unreachable URL doesn't keep the admin
+/// page waiting on every retry and redirect of the HTTP client.
+const CONNECTION_TEST_TIMEOUT: Duration = Duration::from_secs(15);
+
 #[derive(Template)]
 #[template(path = "admin/servers.html")]
 pub struct ServersPageTemplate {
@@ -27,6 +33,7 @@ pub struct ServerWithAdmin {
     pub has_admin: bool,
     pub is_redirect: bool,
     pub is_proxy: bool,
+    pub preserves_auth_header: bool,
 }
 
 #[derive(Template)]
@@ -34,6 +41,14 @@ pub struct ServerWithAdmin {
 pub struct ServerListTemplate {
     pub servers: Vec<ServerWithAdmin>,
     pub ui_route: String,
+    pub sync_report: Option<UsersSyncReport>,
+}
+
+/// Outcome of provisioning all proxy users onto one server.
+pub struct UsersSyncReport {
+    pub server_name: String,
+    pub failure: Option<ServerSyncResult>,
+    pub results: Vec<UserSyncResult>,
 }
 
 #[derive(Deserialize)]
@@ -54,13 +69,63 @@ pub struct UpdateMediaStreamingModeForm {
     pub media_streaming_mode: String,
 }
 
+#[derive(Deserialize)]
+pub struct UpdateAuthorizationHeaderModeForm {
+    pub authorization_header_mode: String,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMaxConnectionsForm {
+    /// Empty to use `max_connections_per_server`.
+    pub max_connections: String,
+}
+
 #[derive(Deserialize)]
 pub struct AddServerAdminForm {
     pub username: String,
     pub password: Password,
 }
 
+#[derive(Deserialize)]
+pub struct TestServerConnectionForm {
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Password>,
+}
+
+/// Outcome of checking a server before it is saved.
+#[derive(Debug, Serialize)]
+pub struct ConnectionTestResult {
+    pub success: bool,
+    pub server_name: Option<String>,
+    pub version: Option<String>,
+    /// Whether the given credentials belong to an administrator, when some were given.
+    pub is_admin: Option<bool>,
+    pub error: Option<String>,
+}
+
+impl ConnectionTestResult {
+    fn failed(error: impl Into<String>) -> Self {
+        Self {
+            success: false,
+            server_name: None,
+            version: None,
+            is_admin: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
 async fn render_server_list(state: &AppState) -> Result<String, String> {
+    render_server_list_with_report(state, None).await
+}
+
+async fn render_server_list_with_report(
+    state: &AppState,
+    sync_report: Option<UsersSyncReport>,
+) -> Result<String, String> {
     match state.server_storage.list_servers().await {
         Ok(servers) => {
             let mut servers_with_admin = Vec::new();
@@ -72,17 +137,21 @@ async fn render_server_list(state: &AppState) -> Result<String, String> {
                     .unwrap_or(None)
                     .is_some();
                 let is_redirect = server.media_streaming_mode == MediaStreamingMode::Redirect;
+                let preserves_auth_header =
+                    server.authorization_header_mode == AuthorizationHeaderMode::Preserve;
                 servers_with_admin.push(ServerWithAdmin {
                     server,
                     has_admin,
                     is_redirect,
                     is_proxy: !is_redirect,
+                    preserves_auth_header,
                 });
             }
 
             let template = ServerListTemplate {
                 servers: servers_with_admin,
                 ui_route: state.get_ui_route().await,
+                sync_report,
             };
 
             template.render().map_err(|e| e.to_string())
@@ -139,10 +208,12 @@ pub async fn add_server(
             .into_response();
     }
 
-    if form.priority < 1 || form.priority > 999 {
+    if !is_valid_priority(form.priority) {
         return (
             StatusCode::BAD_REQUEST,
-            Html("<div class=\"alert alert-error\">Priority must be between 1 and 999</div>"),
+            Html(format!(
+                "<div class=\"alert alert-error\">{PRIORITY_RANGE_MESSAGE}</div>"
+            )),
         )
             .into_response();
     }
@@ -158,6 +229,26 @@ pub async fn add_server(
         }
     };
 
+    // Refuse a second entry for a backend that is already configured under
+    // another name or URL. Unreachable servers can still be added.
+    if let Some(backend_id) = state.server_storage.fetch_backend_id(form.url.trim()).await {
+        match state.server_storage.list_servers().await {
+            Ok(servers) => {
+                if let Some(existing) = find_server_by_backend_id(&servers, &backend_id) {
+                    return (
+                        StatusCode::CONFLICT,
+                        Html(format!(
+                            "<div class=\"alert alert-error\">This Jellyfin server is already configured as '{}'</div>",
+                            html_escape(&existing.name)
+                        )),
+                    )
+                        .into_response();
+                }
+            }
+            Err(e) => error!("Failed to list servers for duplicate check: {}", e),
+        }
+    }
+
     // Try to add the server
     match state
         .server_storage
@@ -216,6 +307,150 @@ pub async fn add_server(
     }
 }
 
+/// Check that a server answers like Jellyfin and, when credentials are given, that
+/// they log in as an administrator, without saving anything.
+pub async fn test_server_connection(
+    State(state): State<AppState>,
+    Form(form): Form<TestServerConnectionForm>,
+) -> Response {
+    let credentials = form
+        .username
+        .as_deref()
+        .map(str::trim)
+        .filter(|username| !username.is_empty())
+        .map(|username| {
+            (
+                username,
+                form.password.clone().unwrap_or_else(|| Password::from("")),
+            )
+        });
+    let result = check_server_connection(
+        &state.server_storage,
+        form.url.trim(),
+        credentials
+            .as_ref()
+            .map(|(username, password)| (*username, password)),
+    )
+    .await;
+
+    let html = match &result {
+        ConnectionTestResult {
+            success: true,
+            server_name,
+            version,
+            is_admin,
+            ..
+        } => {
+            let admin = match is_admin {
+                Some(true) => ", administrator login works",
+                _ => "",
+            };
+            format!(
+                "<div class=\"alert alert-success\">Connected to '{}' (Jellyfin {}){}</div>",
+                html_escape(server_name.as_deref().unwrap_or("unnamed server")),
+                html_escape(version.as_deref().unwrap_or("unknown version")),
+                admin
+            )
+        }
+        ConnectionTestResult { error, .. } => format!(
+            "<div class=\"alert alert-error\">{}</div>",
+            html_escape(error.as_deref().unwrap_or("Connection test failed"))
+        ),
+    };
+    Html(html).into_response()
+}
+
+async fn check_server_connection(
+    server_storage: &ServerStorageService,
+    url: &str,
+    credentials: Option<(&str, &Password)>,
+) -> ConnectionTestResult {
+    if url.is_empty() {
+        return ConnectionTestResult::failed("Server URL cannot be empty");
+    }
+
+    let client = match jellyfin_api::JellyfinClient::new_with_client(
+        url,
+        crate::config::CLIENT_INFO.clone(),
+        server_storage.http_client.clone(),
+    ) {
+        Ok(client) => client,
+        Err(_) => return ConnectionTestResult::failed("Invalid URL format"),
+    };
+
+    let check = async {
+        let info = match client.get_public_system_info().await {
+            Ok(info) => info,
+            Err(jellyfin_api::error::JellyfinApiError::NotJellyfin(reason)) => {
+                return ConnectionTestResult::failed(format!(
+                    "The server answered, but not like a Jellyfin server: {reason}"
+                ))
+            }
+            Err(e) => return ConnectionTestResult::failed(format!("Connection error: {e}")),
+        };
+
+        let is_admin = match credentials {
+            Some((username, password)) => {
+                match client
+                    .authenticate_by_name(username, password.as_str())
+                    .await
+                {
+                    Ok(user) => {
+                        let is_admin = user.policy.is_some_and(|p| p.is_administrator);
+                        if !is_admin {
+                            return ConnectionTestResult {
+                                is_admin: Some(false),
+                                ..ConnectionTestResult::failed(
+                                    "User is not an administrator on this server",
+                                )
+                            };
+                        }
+                        Some(true)
+                    }
+                    Err(jellyfin_api::error::JellyfinApiError::Unauthorized) => {
+                        return ConnectionTestResult::failed("Invalid credentials")
+                    }
+                    Err(e) => return ConnectionTestResult::failed(format!("Login failed: {e}")),
+                }
+            }
+            None => None,
+        };
+
+        ConnectionTestResult {
+            success: true,
+            server_name: info.server_name,
+            version: info.version,
+            is_admin,
+            error: None,
+        }
+    };
+
+    match tokio::time::timeout(CONNECTION_TEST_TIMEOUT, check).await {
+        Ok(result) => result,
+        Err(_) => ConnectionTestResult::failed(format!(
+            "The server did not answer within {} seconds",
+            CONNECTION_TEST_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+pub(crate) const PRIORITY_RANGE_MESSAGE: &str =
+    "Priority must be between 1 and 999, or between -999 and -1 for a server that is never picked by default";
+
+/// Priorities from 1 to 999, or from -999 to -1 for servers that are only used when a
+/// request targets them.
+pub(crate) fn is_valid_priority(priority: i32) -> bool {
+    priority != 0 && (-999..=999).contains(&priority)
+}
+
+pub(crate) fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
 /// Update server media streaming mode
 pub async fn update_server_media_streaming_mode(
     State(state): State<AppState>,
@@ -261,6 +496,104 @@ pub async fn update_server_media_streaming_mode(
     }
 }
 
+/// Update which authorization header is forwarded to a server
+pub async fn update_server_authorization_header_mode(
+    State(state): State<AppState>,
+    Path(server_id): Path<ServerId>,
+    Form(form): Form<UpdateAuthorizationHeaderModeForm>,
+) -> Response {
+    let authorization_header_mode = match form
+        .authorization_header_mode
+        .parse::<AuthorizationHeaderMode>()
+    {
+        Ok(mode) => mode,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("<div class=\"alert alert-error\">Invalid authorization header mode</div>"),
+            )
+                .into_response()
+        }
+    };
+
+    match state
+        .server_storage
+        .update_server_authorization_header_mode(server_id, authorization_header_mode)
+        .await
+    {
+        Ok(true) => {
+            info!(
+                "Updated server {} authorization header mode to {}",
+                server_id, authorization_header_mode
+            );
+            get_server_list(State(state)).await.into_response()
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Html("<div class=\"alert alert-error\">Server not found</div>"),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to update server authorization header mode: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html(
+                    "<div class=\"alert alert-error\">Failed to update authorization header mode</div>",
+                ),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Update how many requests may be in flight to a server at once
+pub async fn update_server_max_connections(
+    State(state): State<AppState>,
+    Path(server_id): Path<ServerId>,
+    Form(form): Form<UpdateMaxConnectionsForm>,
+) -> Response {
+    let max_connections = match form.max_connections.trim() {
+        "" => None,
+        value => match value.parse::<u32>() {
+            Ok(limit) => Some(limit),
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Html("<div class=\"alert alert-error\">Invalid connection limit</div>"),
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    match state
+        .server_storage
+        .update_server_max_connections(server_id, max_connections)
+        .await
+    {
+        Ok(true) => {
+            info!(
+                "Updated server {} connection limit to {:?}",
+                server_id, max_connections
+            );
+            get_server_list(State(state)).await.into_response()
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Html("<div class=\"alert alert-error\">Server not found</div>"),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to update server connection limit: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("<div class=\"alert alert-error\">Failed to update connection limit</div>"),
+            )
+                .into_response()
+        }
+    }
+}
+
 /// Delete a server
 pub async fn delete_server(
     State(state): State<AppState>,
@@ -298,10 +631,12 @@ pub async fn update_server_priority(
     Path(server_id): Path<ServerId>,
     Form(form): Form<UpdatePriorityForm>,
 ) -> Response {
-    if form.priority < 1 || form.priority > 999 {
+    if !is_valid_priority(form.priority) {
         return (
             StatusCode::BAD_REQUEST,
-            Html("<div class=\"alert alert-error\">Priority must be between 1 and 999</div>"),
+            Html(format!(
+                "<div class=\"alert alert-error\">{PRIORITY_RANGE_MESSAGE}</div>"
+            )),
         )
             .into_response();
     }
@@ -430,7 +765,7 @@ pub async fn add_server_admin(
                 }
             }
         }
-        Err(jellyfin_api::error::Error::AuthenticationFailed(_)) => {
+        Err(jellyfin_api::error::JellyfinApiError::Unauthorized) => {
             (
                 StatusCode::OK,
                 Html("<div style=\"background-color: #e74c3c; color: white; padding: 0.75rem; border-radius: 0.25rem; margin-bottom: 1rem;\">Invalid credentials</div>"),
@@ -476,3 +811,132 @@ pub async fn delete_server_admin(
         }
     }
 }
+
+/// Provision all proxy users onto a server with admin credentials
+pub async fn sync_users_to_server(
+    State(state): State<AppState>,
+    Path(server_id): Path<ServerId>,
+) -> Response {
+    let server_name = match state.server_storage.get_server_by_id(server_id).await {
+        Ok(Some(server)) => server.name,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Html("<div class=\"alert alert-error\">Server not found</div>"),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("Failed to get server: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("<div class=\"alert alert-error\">Database error</div>"),
+            )
+                .into_response();
+        }
+    };
+
+    let report = match state
+        .federated_users
+        .sync_all_users_to_server(server_id)
+        .await
+    {
+        Ok(results) => {
+            info!("Synced {} users to server {}", results.len(), server_name);
+            UsersSyncReport {
+                server_name,
+                failure: None,
+                results,
+            }
+        }
+        Err(failure) => UsersSyncReport {
+            server_name,
+            failure: Some(failure),
+            results: Vec::new(),
+        },
+    };
+
+    match render_server_list_with_report(&state, Some(report)).await {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            error!("Failed to render server list: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Error").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::SqlitePool;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn mount_backend(upstream: &MockServer, is_administrator: bool) {
+        Mock::given(method("GET"))
+            .and(path("/System/Info/Public"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ServerName": "Media Vault",
+                "Version": "10.10.7",
+                "Id": "backend-1"
+            })))
+            .mount(upstream)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/Users/AuthenticateByName"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "AccessToken": "token",
+                "ServerId": "backend-1",
+                "User": {
+                    "Name": "admin",
+                    "Id": "admin-id",
+                    "Policy": { "IsAdministrator": is_administrator }
+                }
+            })))
+            .mount(upstream)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn connection_test_reports_the_server_and_admin_login() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let storage = ServerStorageService::new(pool);
+        let upstream = MockServer::start().await;
+        mount_backend(&upstream, true).await;
+
+        let result = check_server_connection(&storage, &upstream.uri(), None).await;
+        assert!(result.success);
+        assert_eq!(result.server_name.as_deref(), Some("Media Vault"));
+        assert_eq!(result.version.as_deref(), Some("10.10.7"));
+        assert_eq!(result.is_admin, None);
+
+        let password = Password::from("secret");
+        let result =
+            check_server_connection(&storage, &upstream.uri(), Some(("admin", &password))).await;
+        assert!(result.success);
+        assert_eq!(result.is_admin, Some(true));
+    }
+
+    #[tokio::test]
+    async fn connection_test_fails_for_non_admins_and_unreachable_servers() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let storage = ServerStorageService::new(pool);
+        let upstream = MockServer::start().await;
+        mount_backend(&upstream, false).await;
+
+        let password = Password::from("secret");
+        let result =
+            check_server_connection(&storage, &upstream.uri(), Some(("viewer", &password))).await;
+        assert!(!result.success);
+        assert_eq!(result.is_admin, Some(false));
+
+        let result = check_server_connection(&storage, "http://127.0.0.1:9", None).await;
+        assert!(!result.success);
+        assert!(result.error.unwrap().starts_with("Connection error"));
+
+        let result = check_server_connection(&storage, "not a url", None).await;
+        assert_eq!(result.error.as_deref(), Some("Invalid URL format"));
+    }
+}
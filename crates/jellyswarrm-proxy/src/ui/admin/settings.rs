@@ -1,14 +1,20 @@
 use askama::Template;
 use axum::{
     extract::State,
-    http::StatusCode,
-    response::{Html, IntoResponse},
-    Form,
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Form, Json,
 };
 use serde::Deserialize;
+use serde_json::json;
 use tracing::error;
 
-use crate::{config::save_config, AppState};
+use crate::{
+    config::{admin_password_from_env, load_config, save_config, AppConfig, ReloadSummary},
+    encryption::Password,
+    ui::admin::servers::html_escape,
+    AppState,
+};
 
 #[derive(Template)]
 #[template(path = "admin/settings.html")]
@@ -22,7 +28,7 @@ pub struct SettingsFormTemplate {
     pub server_id: String,
     pub public_address: String,
     pub server_name: String,
-    pub include_server_name_in_media: bool,
+    pub include_server_name_in_media: String,
     pub auto_create_users_on_login: bool,
     pub merge_libraries: bool,
     pub ui_route: String,
@@ -47,7 +53,7 @@ pub async fn settings_form(State(state): State<AppState>) -> impl IntoResponse {
         server_id: cfg.server_id,
         public_address: cfg.public_address,
         server_name: cfg.server_name,
-        include_server_name_in_media: cfg.include_server_name_in_media,
+        include_server_name_in_media: cfg.include_server_name_in_media.to_string(),
         auto_create_users_on_login: cfg.auto_create_users_on_login,
         merge_libraries: cfg.merge_libraries,
         ui_route: state.get_ui_route().await,
@@ -65,9 +71,8 @@ pub async fn settings_form(State(state): State<AppState>) -> impl IntoResponse {
 pub struct SaveForm {
     pub public_address: String,
     pub server_name: String,
-    // When the checkbox is unchecked the field is absent; default to false.
-    #[serde(default)]
-    pub include_server_name_in_media: bool,
+    pub include_server_name_in_media: String,
+    // When a checkbox is unchecked the field is absent; default to false.
     #[serde(default)]
     pub auto_create_users_on_login: bool,
     #[serde(default)]
@@ -89,7 +94,9 @@ pub async fn save_settings(
         let mut cfg = state.config.write().await;
         cfg.public_address = form.public_address.trim().to_string();
         cfg.server_name = form.server_name.trim().to_string();
-        cfg.include_server_name_in_media = form.include_server_name_in_media;
+        if let Ok(mode) = form.include_server_name_in_media.parse() {
+            cfg.include_server_name_in_media = mode;
+        }
         cfg.auto_create_users_on_login = form.auto_create_users_on_login;
         cfg.merge_libraries = form.merge_libraries;
         if let Err(e) = save_config(&cfg) {
@@ -100,11 +107,217 @@ pub async fn save_settings(
     settings_form(State(state)).await.into_response()
 }
 
-pub async fn reload_config(State(state): State<AppState>) -> impl IntoResponse {
-    let new_cfg = crate::config::load_config();
+/// Validate a reloaded config and take over the settings that apply while running.
+pub async fn apply_reloaded_config(
+    state: &AppState,
+    reloaded: AppConfig,
+) -> Result<ReloadSummary, Vec<String>> {
+    if let Err(errors) = reloaded.validate() {
+        return Err(errors.iter().map(ToString::to_string).collect());
+    }
+    let mut cfg = state.config.write().await;
+    cfg.reload_from(reloaded).map_err(|e| {
+        error!("Failed to apply reloaded config: {}", e);
+        vec!["The reloaded config could not be applied".to_string()]
+    })
+}
+
+/// Reload the config file. Answers htmx with a message and other clients with the
+/// [`ReloadSummary`] as JSON.
+pub async fn reload_config(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let result = apply_reloaded_config(&state, load_config()).await;
+    if !headers.contains_key("HX-Request") {
+        return match result {
+            Ok(summary) => Json(summary).into_response(),
+            Err(errors) => {
+                (StatusCode::BAD_REQUEST, Json(json!({ "errors": errors }))).into_response()
+            }
+        };
+    }
+
+    match result {
+        Ok(summary) => {
+            let mut message = "Configuration reloaded".to_string();
+            if !summary.applied.is_empty() {
+                message.push_str(&format!(". Applied: {}", summary.applied.join(", ")));
+            }
+            if !summary.requires_restart.is_empty() {
+                message.push_str(&format!(
+                    ". Restart to apply: {}",
+                    summary.requires_restart.join(", ")
+                ));
+            }
+            Html(format!("<div class=\"alert\">{message}</div>")).into_response()
+        }
+        Err(errors) => Html(format!(
+            "<div class=\"alert alert-error\">The config file was not reloaded: {}</div>",
+            html_escape(&errors.join(" "))
+        ))
+        .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AdminPasswordForm {
+    pub old_password: Password,
+    pub new_password: Password,
+}
+
+/// Re-encrypt the server mappings stored with the admin password after it changed.
+/// Works both before and after the new password was written to the config file: if
+/// the config still holds the old password, the new one is persisted first, so the
+/// mappings are never re-encrypted with a password the proxy would lose on restart.
+pub async fn rotate_admin_password(
+    State(state): State<AppState>,
+    Form(form): Form<AdminPasswordForm>,
+) -> impl IntoResponse {
+    if form.new_password.as_str().is_empty() {
+        return Html("<div class=\"alert alert-error\">The new password is required</div>")
+            .into_response();
+    }
+
+    let mut cfg = state.config.write().await;
+    if cfg.password != form.old_password && cfg.password != form.new_password {
+        return Html(
+            "<div class=\"alert alert-error\">Neither password matches the configured admin password</div>",
+        )
+        .into_response();
+    }
+
+    let persist_new_password = cfg.password == form.old_password;
+    if persist_new_password && admin_password_from_env() {
+        return Html(
+            "<div class=\"alert alert-error\">The admin password is set through JELLYSWARRM_PASSWORD. Change the variable and restart the proxy first, then re-encrypt the mappings.</div>",
+        )
+        .into_response();
+    }
+
+    let previous_cfg = cfg.clone();
+    if persist_new_password {
+        let mut updated = cfg.clone();
+        updated.password = form.new_password.clone();
+        if let Err(e) = save_config(&updated) {
+            error!("Failed to save the new admin password: {}", e);
+            return Html(
+                "<div class=\"alert alert-error\">Failed to save the new admin password; nothing was changed</div>",
+            )
+            .into_response();
+        }
+    }
+
+    let reencrypted = match state
+        .user_authorization
+        .reencrypt_admin_mappings(&form.old_password, &form.new_password)
+        .await
     {
-        let mut cfg = state.config.write().await;
-        *cfg = new_cfg;
+        Ok(count) => count,
+        Err(e) => {
+            error!("Failed to re-encrypt server mappings: {}", e);
+            if persist_new_password {
+                if let Err(e) = save_config(&previous_cfg) {
+                    error!("Failed to restore the previous admin password: {}", e);
+                }
+            }
+            return Html(
+                "<div class=\"alert alert-error\">Failed to re-encrypt server mappings</div>",
+            )
+            .into_response();
+        }
+    };
+
+    if persist_new_password {
+        cfg.password = form.new_password;
+    }
+
+    Html(format!(
+        "<div class=\"alert alert-success\">Re-encrypted {} server mappings with the new admin password</div>",
+        reencrypted
+    ))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerNameSuffixMode;
+    use crate::test_support::create_test_app_state;
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    async fn allowed_origin(router: &Router, origin: &str) -> String {
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::OPTIONS)
+                    .uri("/Items")
+                    .header(header::ORIGIN, origin)
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn reloaded_settings_reach_later_requests() {
+        let state = create_test_app_state().await;
+        let router = Router::new().route("/Items", get(|| async { "ok" })).layer(
+            axum::middleware::from_fn_with_state(state.clone(), crate::cors::reloadable_cors),
+        );
+        assert_eq!(allowed_origin(&router, "https://app.example").await, "*");
+
+        let running = state.config.read().await.clone();
+        let reloaded = AppConfig {
+            include_server_name_in_media: ServerNameSuffixMode::Never,
+            cors_allowed_origins: vec!["https://app.example".to_string()],
+            timeout: running.timeout + 10,
+            port: running.port + 1,
+            ..running.clone()
+        };
+        let summary = apply_reloaded_config(&state, reloaded).await.unwrap();
+
+        assert_eq!(summary.requires_restart, ["port"]);
+        assert!(summary.applied.iter().any(|key| key == "timeout"));
+        assert_eq!(
+            state.upstream_timeout().await,
+            std::time::Duration::from_secs(running.timeout + 10)
+        );
+        assert_eq!(
+            state.server_name_suffix_mode().await,
+            ServerNameSuffixMode::Never
+        );
+        assert_eq!(state.config.read().await.port, running.port);
+        assert_eq!(
+            allowed_origin(&router, "https://app.example").await,
+            "https://app.example"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_reloads_change_nothing() {
+        let state = create_test_app_state().await;
+        let reloaded = AppConfig {
+            timeout: 0,
+            include_server_name_in_media: ServerNameSuffixMode::Never,
+            ..AppConfig::default()
+        };
+
+        let errors = apply_reloaded_config(&state, reloaded).await.unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            state.server_name_suffix_mode().await,
+            ServerNameSuffixMode::Always
+        );
     }
-    Html("<div class=\"alert\">Configuration reloaded</div>")
 }
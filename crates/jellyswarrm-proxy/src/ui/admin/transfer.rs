@@ -0,0 +1,84 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Form, Json,
+};
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use crate::{
+    instance_transfer::{InstanceExport, InstanceTransfer},
+    AppState,
+};
+
+/// Largest import document accepted; media mappings make exports of big libraries
+/// much larger than axum's default body limit.
+pub const IMPORT_BODY_LIMIT: usize = 256 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct ExportForm {
+    #[serde(default)]
+    pub passphrase: String,
+}
+
+/// Download servers, users and mappings as a JSON document. Secrets are sealed with
+/// the passphrase, or left out when it's empty.
+pub async fn export_instance(
+    State(state): State<AppState>,
+    Form(form): Form<ExportForm>,
+) -> Response {
+    let passphrase = Some(form.passphrase.as_str()).filter(|p| !p.is_empty());
+    match InstanceTransfer::from_state(&state)
+        .export(passphrase)
+        .await
+    {
+        Ok(export) => {
+            let filename = format!(
+                "jellyswarrm-export-{}.json",
+                export.exported_at.format("%Y%m%d-%H%M%S")
+            );
+            (
+                [(
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{filename}\""),
+                )],
+                Json(export),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("Failed to export instance: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Export failed").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImportRequest {
+    #[serde(default)]
+    pub passphrase: Option<String>,
+    pub export: InstanceExport,
+}
+
+/// Restore a document produced by [`export_instance`] and return what was imported.
+pub async fn import_instance(
+    State(state): State<AppState>,
+    Json(request): Json<ImportRequest>,
+) -> Response {
+    match InstanceTransfer::from_state(&state)
+        .import(&request.export, request.passphrase.as_deref())
+        .await
+    {
+        Ok(summary) => Json(summary).into_response(),
+        Err(e) if e.is_client_error() => {
+            warn!("Rejected instance import: {}", e);
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+        Err(e) => {
+            error!("Failed to import instance: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
@@ -421,7 +421,7 @@ pub async fn add_mapping(
                 form.user_id, server.name, form.mapped_username
             );
         }
-        Err(jellyfin_api::error::Error::AuthenticationFailed(_)) => {
+        Err(jellyfin_api::error::JellyfinApiError::Unauthorized) => {
             info!(
                 "Mapping validation failed for local user '{}' on server '{}' as mapped user '{}': invalid credentials.",
                 form.user_id, server.name, form.mapped_username
@@ -1,6 +1,6 @@
 use axum::{
     body::Body,
-    extract::Path,
+    extract::{DefaultBodyLimit, Path},
     middleware,
     response::{IntoResponse, Response},
     routing::{get, post},
@@ -112,6 +112,10 @@ pub fn ui_routes() -> axum::Router<AppState> {
         .route("/servers", get(admin::servers::servers_page))
         .route("/servers", post(admin::servers::add_server))
         .route("/servers/list", get(admin::servers::get_server_list))
+        .route(
+            "/servers/test",
+            post(admin::servers::test_server_connection),
+        )
         .route(
             "/servers/{id}",
             axum::routing::delete(admin::servers::delete_server),
@@ -124,6 +128,14 @@ pub fn ui_routes() -> axum::Router<AppState> {
             "/servers/{id}/media-streaming-mode",
             axum::routing::patch(admin::servers::update_server_media_streaming_mode),
         )
+        .route(
+            "/servers/{id}/authorization-header-mode",
+            axum::routing::patch(admin::servers::update_server_authorization_header_mode),
+        )
+        .route(
+            "/servers/{id}/max-connections",
+            axum::routing::patch(admin::servers::update_server_max_connections),
+        )
         .route(
             "/servers/{id}/admin",
             post(admin::servers::add_server_admin),
@@ -132,6 +144,10 @@ pub fn ui_routes() -> axum::Router<AppState> {
             "/servers/{id}/admin",
             axum::routing::delete(admin::servers::delete_server_admin),
         )
+        .route(
+            "/servers/{id}/sync-users",
+            post(admin::servers::sync_users_to_server),
+        )
         .route("/libraries", get(admin::libraries::libraries_page))
         .route(
             "/libraries/list",
@@ -147,6 +163,10 @@ pub fn ui_routes() -> axum::Router<AppState> {
             "/libraries/groups/{group_virtual_id}/remove",
             post(admin::libraries::remove_member),
         )
+        .route(
+            "/libraries/groups/{group_virtual_id}/sources/{server_id}/priority",
+            axum::routing::patch(admin::libraries::update_member_priority),
+        )
         .route(
             "/libraries/groups/{virtual_id}/policy",
             post(admin::libraries::update_group_policy),
@@ -160,6 +180,19 @@ pub fn ui_routes() -> axum::Router<AppState> {
         .route("/settings/form", get(admin::settings::settings_form))
         .route("/settings/save", post(admin::settings::save_settings))
         .route("/settings/reload", post(admin::settings::reload_config))
+        .route(
+            "/settings/admin-password",
+            post(admin::settings::rotate_admin_password),
+        )
+        .route("/settings/backup", post(admin::backup::download_backup))
+        .route("/settings/export", post(admin::transfer::export_instance))
+        .route(
+            "/settings/import",
+            post(admin::transfer::import_instance)
+                .layer(DefaultBodyLimit::max(admin::transfer::IMPORT_BODY_LIMIT)),
+        )
+        // Metrics
+        .route("/metrics", get(admin::metrics::get_metrics))
         .route_layer(middleware::from_fn(require_admin));
 
     Router::new()
@@ -187,6 +220,14 @@ pub fn ui_routes() -> axum::Router<AppState> {
             "/user/media/server/{server_id}/library/{library_id}/items",
             get(user::media::get_library_items),
         )
+        .route(
+            "/user/media/merged/preview",
+            post(user::media::preview_merged_library),
+        )
+        .route(
+            "/user/media/merged/{virtual_id}",
+            axum::routing::patch(user::media::update_merged_library),
+        )
         .route(
             "/user/media/image/{server_id}/{item_id}",
             get(user::media::proxy_media_image),
@@ -6,12 +6,20 @@ use axum::{
 };
 use tracing::error;
 
-use crate::{server_id::ServerId, server_storage::ServerHealthStatus, AppState};
+use crate::{
+    server_id::ServerId,
+    server_storage::{ServerHealthStatus, MIN_JELLYFIN_VERSION},
+    AppState,
+};
 
 #[derive(Template)]
 #[template(path = "admin/server_status.html")]
 pub struct ServerStatusTemplate {
     pub error_message: Option<String>,
+    /// The server answered, but not like Jellyfin does.
+    pub not_jellyfin: bool,
+    /// The server is older than [`crate::server_storage::MIN_JELLYFIN_VERSION`].
+    pub outdated: bool,
     pub server_version: Option<String>,
 }
 
@@ -22,36 +30,38 @@ pub async fn check_server_status(
 ) -> impl IntoResponse {
     // Get the server details first
     match state.server_storage.get_server_by_id(server_id).await {
-        Ok(Some(_)) => match state.server_storage.server_status(server_id).await {
-            ServerHealthStatus::Healthy(info) => {
-                let template = ServerStatusTemplate {
+        Ok(Some(server)) => {
+            let status = state.server_storage.current_server_status(&server).await;
+            let outdated = status.is_outdated();
+            let template = match status {
+                ServerHealthStatus::Healthy(info) => ServerStatusTemplate {
                     error_message: None,
+                    not_jellyfin: false,
+                    outdated,
                     server_version: info.version,
-                };
-
-                match template.render() {
-                    Ok(html) => Html(html).into_response(),
-                    Err(e) => {
-                        error!("Failed to render status template: {}", e);
-                        (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-                    }
-                }
-            }
-            ServerHealthStatus::Unhealthy(e) => {
-                let template = ServerStatusTemplate {
+                },
+                ServerHealthStatus::Unhealthy(e) => ServerStatusTemplate {
                     error_message: Some(format!("Error: {}", e)),
+                    not_jellyfin: false,
+                    outdated,
+                    server_version: None,
+                },
+                ServerHealthStatus::NotJellyfin(e) => ServerStatusTemplate {
+                    error_message: Some(format!("Not a Jellyfin server: {}", e)),
+                    not_jellyfin: true,
+                    outdated,
                     server_version: None,
-                };
+                },
+            };
 
-                match template.render() {
-                    Ok(html) => Html(html).into_response(),
-                    Err(e) => {
-                        error!("Failed to render status template: {}", e);
-                        (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
-                    }
+            match template.render() {
+                Ok(html) => Html(html).into_response(),
+                Err(e) => {
+                    error!("Failed to render status template: {}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
                 }
             }
-        },
+        }
         Ok(None) => (
             StatusCode::NOT_FOUND,
             Html("<span style=\"color: #dc3545;\">Server not found</span>"),
@@ -3,7 +3,12 @@ use std::sync::Arc;
 use jellyfin_api::JellyfinClient;
 use tracing::info;
 
-use crate::{config::CLIENT_STORAGE, encryption::HashedPassword, server_storage::Server, AppState};
+use crate::{
+    config::CLIENT_STORAGE,
+    encryption::HashedPassword,
+    server_storage::{Server, ServerHealthStatus},
+    AppState,
+};
 
 pub async fn authenticate_user_on_server(
     state: &AppState,
@@ -31,9 +36,12 @@ pub async fn authenticate_user_on_server(
         .map_err(|e| format!("Failed to get client from storage: {}", e))?;
 
     // Always check public system info first to get version and name
-    let public_info = match client.get_public_system_info().await {
-        Ok(info) => info,
-        Err(_) => return Err("Server offline or unreachable".to_string()),
+    let public_info = match state.server_storage.current_server_status(server).await {
+        ServerHealthStatus::Healthy(info) => info,
+        ServerHealthStatus::Unhealthy(_) => return Err("Server offline or unreachable".to_string()),
+        ServerHealthStatus::NotJellyfin(_) => {
+            return Err("Server does not respond like a Jellyfin server".to_string())
+        }
     };
 
     // Check for mapping and try to authenticate
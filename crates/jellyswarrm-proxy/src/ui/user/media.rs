@@ -4,16 +4,31 @@ use axum::{
     extract::{Path, Query, State},
     http::{header, StatusCode},
     response::{Html, IntoResponse, Response},
+    Form, Json,
 };
-use jellyfin_api::models::{BaseItem, IncludeItemTypes};
+use jellyfin_api::models::{BaseItem, IncludeBaseItemFields, IncludeItemTypes};
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
 use crate::{
+    config::DeduplicationStrategy,
+    duplicate_policy::{
+        preview_version_merge, DuplicatePolicy, TaggedMediaItem, VersionMergePreview,
+    },
+    models::MediaItem,
     server_id::ServerId,
-    ui::{auth::AuthenticatedUser, user::common::authenticate_user_on_server},
+    ui::{
+        admin::libraries::library_groups_blocked_response,
+        auth::{AuthenticatedUser, User, UserRole},
+        user::common::authenticate_user_on_server,
+    },
+    virtual_library_service::LibraryGroup,
     AppState,
 };
 
+/// Page size used when counting the items of a library for a merge preview.
+const PREVIEW_PAGE_SIZE: i32 = 500;
+
 pub struct ServerInfo {
     pub id: ServerId,
     pub name: String,
@@ -55,6 +70,54 @@ pub struct Pagination {
     pub page: Option<i32>,
 }
 
+/// A library that would become part of a merged library.
+#[derive(Deserialize)]
+pub struct AddSourceRequest {
+    pub server_id: ServerId,
+    pub library_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct MergedPreviewRequest {
+    pub sources: Vec<AddSourceRequest>,
+    pub strategy: DeduplicationStrategy,
+    /// How many merged titles to list as examples.
+    #[serde(default)]
+    pub sample_size: usize,
+}
+
+#[derive(Serialize)]
+pub struct SourcePreview {
+    pub server_id: ServerId,
+    pub library_id: String,
+    pub item_count: usize,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MergedPreviewResponse {
+    pub sources: Vec<SourcePreview>,
+    #[serde(flatten)]
+    pub merge: VersionMergePreview,
+}
+
+/// The merged library after an edit, without the admin controls of the group list.
+#[derive(Template)]
+#[template(path = "user/merged_library.html")]
+pub struct MergedLibraryTemplate {
+    pub group: LibraryGroup,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMergedLibraryForm {
+    pub name: String,
+    pub duplicate_policy: String,
+    pub preferred_server_id: Option<String>,
+    /// Empty or missing keeps the group's current strategy.
+    #[serde(default)]
+    pub dedup_strategy: String,
+}
+
 pub async fn get_user_media(
     State(state): State<AppState>,
     AuthenticatedUser(user): AuthenticatedUser,
@@ -101,7 +164,7 @@ pub async fn get_server_libraries(
     if let Ok((client, jellyfin_user, _)) =
         authenticate_user_on_server(&state, &user, &server).await
     {
-        match client.get_media_folders(Some(&jellyfin_user.id)).await {
+        match client.get_views(&jellyfin_user.id).await {
             Ok(folders) => {
                 let mut libraries = Vec::new();
                 for folder in folders {
@@ -269,6 +332,7 @@ pub async fn proxy_media_image(
     match state
         .reqwest_client
         .get(&image_url)
+        .timeout(state.upstream_timeout().await)
         .header(header::AUTHORIZATION, auth_header)
         .send()
         .await
@@ -292,3 +356,292 @@ pub async fn proxy_media_image(
         Err(_) => StatusCode::BAD_GATEWAY.into_response(),
     }
 }
+
+/// Dry run of merging the given libraries: counts their items and how many of them
+/// `strategy` would collapse, using the same matching as merged libraries. Nothing
+/// is stored.
+pub async fn preview_merged_library(
+    State(state): State<AppState>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Json(request): Json<MergedPreviewRequest>,
+) -> impl IntoResponse {
+    let mut sources = Vec::new();
+    let mut items = Vec::new();
+    for source in request.sources {
+        match fetch_source_items(&state, &user, &source).await {
+            Ok(source_items) => {
+                sources.push(SourcePreview {
+                    server_id: source.server_id,
+                    library_id: source.library_id,
+                    item_count: source_items.len(),
+                    error: None,
+                });
+                items.extend(source_items);
+            }
+            Err(e) => {
+                error!(
+                    "Failed to fetch library {} of server {} for merge preview: {}",
+                    source.library_id, source.server_id, e
+                );
+                sources.push(SourcePreview {
+                    server_id: source.server_id,
+                    library_id: source.library_id,
+                    item_count: 0,
+                    error: Some(e),
+                });
+            }
+        }
+    }
+
+    let titles = state.title_normalizer().await;
+    Json(MergedPreviewResponse {
+        sources,
+        merge: preview_version_merge(items, request.strategy, &titles, request.sample_size),
+    })
+}
+
+async fn fetch_source_items(
+    state: &AppState,
+    user: &User,
+    source: &AddSourceRequest,
+) -> Result<Vec<TaggedMediaItem>, String> {
+    let server = match state
+        .server_storage
+        .get_server_by_id(source.server_id)
+        .await
+    {
+        Ok(Some(server)) => server,
+        Ok(None) => return Err("Server not found".to_string()),
+        Err(e) => return Err(format!("Database error: {e}")),
+    };
+    let (client, jellyfin_user, _) = authenticate_user_on_server(state, user, &server).await?;
+
+    let mut items = Vec::new();
+    loop {
+        let response = client
+            .get_items(
+                &jellyfin_user.id,
+                Some(&source.library_id),
+                true,
+                Some(vec![
+                    IncludeItemTypes::Movie,
+                    IncludeItemTypes::Episode,
+                    IncludeItemTypes::Video,
+                    IncludeItemTypes::MusicVideo,
+                ]),
+                Some(PREVIEW_PAGE_SIZE),
+                Some(items.len() as i32),
+                None,
+                None,
+                Some(vec![IncludeBaseItemFields::ProviderIds]),
+            )
+            .await
+            .map_err(|e| format!("Failed to fetch items: {e}"))?;
+        let page_len = response.items.len();
+        for item in response.items {
+            let item = serde_json::to_value(item)
+                .and_then(serde_json::from_value::<MediaItem>)
+                .map_err(|e| format!("Unexpected item: {e}"))?;
+            items.push(TaggedMediaItem {
+                item,
+                server: server.clone(),
+            });
+        }
+        if page_len == 0 || items.len() >= response.total_record_count.max(0) as usize {
+            return Ok(items);
+        }
+    }
+}
+
+/// Whether `user` may change a merged library. Library groups are created by admins
+/// and shared by every user, so only admins can change them.
+fn may_edit_library_group(user: &User) -> bool {
+    user.role == UserRole::Admin
+}
+
+/// Rename a merged library and change its duplicate policy and deduplication strategy
+/// in one go, answering with the updated library.
+pub async fn update_merged_library(
+    State(state): State<AppState>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Path(virtual_id): Path<String>,
+    Form(form): Form<UpdateMergedLibraryForm>,
+) -> Response {
+    if !may_edit_library_group(&user) {
+        return (
+            StatusCode::FORBIDDEN,
+            Html("<div class=\"alert alert-error\">Only admins can change merged libraries</div>"),
+        )
+            .into_response();
+    }
+    if state.merge_libraries_enabled().await {
+        return library_groups_blocked_response();
+    }
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("<div class=\"alert alert-error\">Display name is required</div>"),
+        )
+            .into_response();
+    }
+    let Ok(policy) = form.duplicate_policy.parse::<DuplicatePolicy>() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("<div class=\"alert alert-error\">Invalid duplicate policy</div>"),
+        )
+            .into_response();
+    };
+    let dedup_strategy = if form.dedup_strategy.trim().is_empty() {
+        None
+    } else {
+        match form.dedup_strategy.parse::<DeduplicationStrategy>() {
+            Ok(strategy) => Some(strategy),
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Html("<div class=\"alert alert-error\">Invalid deduplication strategy</div>"),
+                )
+                    .into_response()
+            }
+        }
+    };
+    let preferred_server_id = form
+        .preferred_server_id
+        .filter(|value| !value.trim().is_empty())
+        .and_then(|value| value.parse::<i64>().ok())
+        .map(ServerId::new);
+
+    let libraries = &state.virtual_library_service;
+    let mut updated = libraries.rename_group(&virtual_id, name).await;
+    if matches!(updated, Ok(true)) {
+        updated = libraries
+            .update_group_policy(&virtual_id, policy, preferred_server_id)
+            .await;
+    }
+    if let (Ok(true), Some(strategy)) = (&updated, dedup_strategy) {
+        updated = libraries
+            .update_group_dedup_strategy(&virtual_id, strategy)
+            .await;
+    }
+    let group = match updated {
+        Ok(true) => libraries.get_group(&virtual_id).await,
+        Ok(false) => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    match group {
+        Ok(Some(group)) => match (MergedLibraryTemplate { group }).render() {
+            Ok(html) => Html(html).into_response(),
+            Err(e) => {
+                error!("Failed to render merged library: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to render merged library",
+                )
+                    .into_response()
+            }
+        },
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Html("<div class=\"alert alert-error\">Merged library not found</div>"),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to update merged library {}: {}", virtual_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("<div class=\"alert alert-error\">Failed to update merged library</div>"),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::test_support::create_test_app_state_with_config;
+
+    fn user(id: &str, role: UserRole) -> User {
+        User {
+            id: id.to_string(),
+            username: id.to_string(),
+            password_hash: crate::encryption::HashedPassword::from(
+                crate::encryption::Password::from("password"),
+            ),
+            role,
+        }
+    }
+
+    /// Edit a merged library as `editor`, answering with the response and the group
+    /// afterwards.
+    async fn edit_as(editor: &User, dedup_strategy: &str) -> (Response, LibraryGroup) {
+        let state = create_test_app_state_with_config(AppConfig {
+            merge_libraries: false,
+            ..AppConfig::default()
+        })
+        .await;
+        let group = state
+            .virtual_library_service
+            .create_group("Films", DeduplicationStrategy::ProviderIds)
+            .await
+            .unwrap();
+
+        let response = update_merged_library(
+            State(state.clone()),
+            AuthenticatedUser(editor.clone()),
+            Path(group.virtual_id.clone()),
+            Form(UpdateMergedLibraryForm {
+                name: "Movies".to_string(),
+                duplicate_policy: DuplicatePolicy::ServerPriority.to_string(),
+                preferred_server_id: None,
+                dedup_strategy: dedup_strategy.to_string(),
+            }),
+        )
+        .await;
+
+        let group = state
+            .virtual_library_service
+            .get_group(&group.virtual_id)
+            .await
+            .unwrap()
+            .unwrap();
+        (response, group)
+    }
+
+    #[tokio::test]
+    async fn admins_may_edit_merged_libraries() {
+        let (response, group) = edit_as(&user("admin", UserRole::Admin), "name_year").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(group.name, "Movies");
+        assert_eq!(group.duplicate_policy, DuplicatePolicy::ServerPriority);
+        assert_eq!(group.dedup_strategy, DeduplicationStrategy::NameYear);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert!(html.contains("Movies"));
+        assert!(!html.contains("hx-delete"));
+    }
+
+    #[tokio::test]
+    async fn a_blank_strategy_keeps_the_current_one() {
+        let (_, group) = edit_as(&user("admin", UserRole::Admin), "").await;
+
+        assert_eq!(group.dedup_strategy, DeduplicationStrategy::ProviderIds);
+    }
+
+    #[tokio::test]
+    async fn users_may_not_edit_merged_libraries() {
+        let (response, group) = edit_as(&user("viewer", UserRole::User), "off").await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(group.name, "Films");
+        assert_eq!(group.dedup_strategy, DeduplicationStrategy::ProviderIds);
+    }
+}
@@ -8,7 +8,12 @@ use axum::{
 use serde::Deserialize;
 use tracing::error;
 
-use crate::{encryption::Password, ui::auth::AuthenticatedUser, AppState};
+use crate::{
+    encryption::Password,
+    federated_users::{ServerSyncResult, SyncStatus},
+    ui::auth::AuthenticatedUser,
+    AppState,
+};
 
 #[derive(Template)]
 #[template(path = "user/user_profile.html")]
@@ -17,6 +22,15 @@ pub struct UserProfileTemplate {
     pub ui_route: String,
 }
 
+/// Outcome of a password change, including the backends it was passed on to.
+#[derive(Template)]
+#[template(path = "user/password_change_result.html")]
+pub struct PasswordChangeResultTemplate {
+    pub results: Vec<ServerSyncResult>,
+    pub has_failures: bool,
+    pub logout_url: String,
+}
+
 #[derive(Deserialize)]
 pub struct ChangePasswordForm {
     pub current_password: Password,
@@ -81,24 +95,29 @@ pub async fn post_user_password(
                 .await
             {
                 Ok(_) => {
-                    let logout_url = format!("/{}/logout", state.get_ui_route().await);
-                    (
-                        StatusCode::OK,
-                        Html(format!(r#"
-                            <div role="alert" style="background-color: #2e7d32; color: white; padding: 0.75rem; border-radius: 0.25rem;">
-                                <i class="fas fa-check-circle" style="margin-right: 0.5rem;"></i> Password updated successfully
-                            </div>
-                            <script>
-                                document.getElementById("password_form").reset();
-                                setTimeout(function() {{
-                                    alert("Password changed successfully. You will be logged out.");
-                                    window.location.href = "{}";
-                                }}, 100);
-                            </script>
-                        "#, logout_url)),
-                    )
-                        .into_response()
-                },
+                    let results = state
+                        .federated_users
+                        .propagate_password_change(
+                            &user.id,
+                            &form.current_password,
+                            &form.new_password,
+                        )
+                        .await;
+                    let template = PasswordChangeResultTemplate {
+                        has_failures: results.iter().any(|result| {
+                            !matches!(result.status, SyncStatus::PasswordUpdated)
+                        }),
+                        results,
+                        logout_url: format!("/{}/logout", state.get_ui_route().await),
+                    };
+                    match template.render() {
+                        Ok(html) => Html(html).into_response(),
+                        Err(e) => {
+                            error!("Failed to render password change result: {}", e);
+                            (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response()
+                        }
+                    }
+                }
                 Err(e) => {
                     error!("Failed to update password: {}", e);
                     (
@@ -162,7 +162,7 @@ pub async fn connect_server(
                 }
             }
         }
-        Err(jellyfin_api::error::Error::AuthenticationFailed(_)) => {
+        Err(jellyfin_api::error::JellyfinApiError::Unauthorized) => {
             (
                 StatusCode::OK,
                 Html("<div style=\"background-color: #e74c3c; color: white; padding: 0.75rem; border-radius: 0.25rem; margin-bottom: 1rem;\">Invalid credentials</div>"),
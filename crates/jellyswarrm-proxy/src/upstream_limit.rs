@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::server_id::ServerId;
+
+/// Semaphores bounding the number of requests in flight to each backend.
+#[derive(Default)]
+pub struct UpstreamLimiter {
+    semaphores: Mutex<HashMap<ServerId, (u32, Arc<Semaphore>)>>,
+}
+
+impl UpstreamLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The semaphore of `server_id` with `limit` permits. When the limit changed, a
+    /// new one replaces it; requests holding permits of the old one finish normally.
+    fn semaphore(&self, server_id: ServerId, limit: u32) -> Arc<Semaphore> {
+        let mut semaphores = self.semaphores.lock().unwrap_or_else(|e| e.into_inner());
+        match semaphores.get(&server_id) {
+            Some((current, semaphore)) if *current == limit => semaphore.clone(),
+            _ => {
+                let semaphore = Arc::new(Semaphore::new(limit as usize));
+                semaphores.insert(server_id, (limit, semaphore.clone()));
+                semaphore
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for one of the `limit` slots of `server_id`. Returns
+    /// `Ok(None)` without waiting when `limit` is `0`, and `Err(())` when no slot
+    /// became free in time. The slot is released when the permit is dropped.
+    pub async fn acquire(
+        &self,
+        server_id: ServerId,
+        limit: u32,
+        timeout: Duration,
+    ) -> Result<Option<OwnedSemaphorePermit>, ()> {
+        if limit == 0 {
+            return Ok(None);
+        }
+
+        let semaphore = self.semaphore(server_id, limit);
+        match tokio::time::timeout(timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            Ok(Err(_)) | Err(_) => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn slots_are_limited_per_server_and_released_on_drop() {
+        let limiter = UpstreamLimiter::new();
+        let first = ServerId::new(1);
+        let second = ServerId::new(2);
+        let timeout = Duration::from_millis(20);
+
+        let held = limiter.acquire(first, 1, timeout).await.unwrap();
+        assert!(held.is_some());
+        assert!(limiter.acquire(first, 1, timeout).await.is_err());
+        assert!(limiter.acquire(second, 1, timeout).await.unwrap().is_some());
+        assert!(limiter.acquire(first, 0, timeout).await.unwrap().is_none());
+
+        drop(held);
+        assert!(limiter.acquire(first, 1, timeout).await.unwrap().is_some());
+    }
+}
@@ -1,3 +1,4 @@
+use axum::http::HeaderMap;
 use url::Url;
 use uuid::Uuid;
 
@@ -28,6 +29,44 @@ pub fn join_server_url(server_url: &Url, request_path: &str) -> Url {
     new_url
 }
 
+/// The URL clients reach the proxy under, for the absolute URLs it generates.
+///
+/// A configured `public_base_url` wins. Otherwise, when `trust_forwarded_headers`
+/// says the proxy sits behind another reverse proxy, it is built from
+/// `X-Forwarded-Proto` (default `http`) and `X-Forwarded-Host`, followed by the
+/// `url_prefix`. Without either there is no reliable public address and `None` is
+/// returned: any client can send the forwarded headers.
+pub fn public_base_url(
+    headers: &HeaderMap,
+    configured: Option<&Url>,
+    url_prefix: Option<&str>,
+    trust_forwarded_headers: bool,
+) -> Option<Url> {
+    if let Some(configured) = configured {
+        return Some(configured.clone());
+    }
+    if !trust_forwarded_headers {
+        return None;
+    }
+
+    // Chained proxies append their own values, the first one is what the client sent.
+    let forwarded = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let host = forwarded("x-forwarded-host")?;
+    let scheme = forwarded("x-forwarded-proto").unwrap_or("http");
+    let base = Url::parse(&format!("{scheme}://{host}")).ok()?;
+    match url_prefix {
+        Some(prefix) => Some(join_server_url(&base, &format!("/{prefix}"))),
+        None => Some(base),
+    }
+}
+
 pub fn contains_id(url: &Url, name: &str) -> Option<String> {
     let segments: Vec<&str> = match url.path_segments() {
         Some(segments) => segments.collect(),
@@ -92,6 +131,60 @@ mod tests {
         assert_eq!(result.as_str(), "http://server.com/jellyfin/Users/123");
     }
 
+    #[test]
+    fn test_public_base_url_from_forwarded_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(public_base_url(&headers, None, None, true), None);
+
+        headers.insert(
+            "x-forwarded-host",
+            "media.example.com, 10.0.0.2".parse().unwrap(),
+        );
+        assert_eq!(
+            public_base_url(&headers, None, None, true)
+                .unwrap()
+                .as_str(),
+            "http://media.example.com/"
+        );
+
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        assert_eq!(
+            public_base_url(&headers, None, Some("jellyfin"), true)
+                .unwrap()
+                .as_str(),
+            "https://media.example.com/jellyfin"
+        );
+    }
+
+    #[test]
+    fn test_public_base_url_prefers_the_configured_url() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-host", "internal:8096".parse().unwrap());
+        headers.insert("x-forwarded-proto", "http".parse().unwrap());
+        let configured = Url::parse("https://media.example.com/swarm").unwrap();
+
+        assert_eq!(
+            public_base_url(&headers, Some(&configured), Some("jellyfin"), true),
+            Some(configured.clone())
+        );
+        assert_eq!(
+            public_base_url(&HeaderMap::new(), Some(&configured), None, false),
+            Some(configured)
+        );
+    }
+
+    #[test]
+    fn test_public_base_url_ignores_forwarded_headers_unless_trusted() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-host", "attacker.example".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+
+        assert_eq!(
+            public_base_url(&headers, None, Some("jellyfin"), false),
+            None
+        );
+    }
+
     #[test]
     fn test_is_id_like() {
         assert!(is_id_like("0123456789abcdef0123456789abcdef"));
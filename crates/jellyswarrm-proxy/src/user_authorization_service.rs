@@ -1,4 +1,5 @@
-use sqlx::{sqlite::SqliteRow, FromRow, Row, SqlitePool};
+use sha2::{Digest, Sha256};
+use sqlx::{sqlite::SqliteRow, FromRow, Row, SqliteConnection, SqlitePool};
 use tracing::{debug, error, info, warn};
 
 use crate::encryption::{
@@ -96,6 +97,23 @@ pub fn normalize_device(value: &str) -> String {
     value.trim().to_lowercase().replace("+", " ")
 }
 
+/// User agent patterns for clients that need special handling, as
+/// `(regex, client name, device when the UA carries no platform)`.
+/// Capture group 1 is the version, optional group 2 the parenthetical platform.
+const KNOWN_CLIENT_USER_AGENTS: &[(&str, &str, &str)] = &[
+    (r"^Kodi/([^\s(]+)(?:\s*\(([^)]+)\))?", "Kodi", "Unknown"),
+    (
+        r"^Infuse(?:-[A-Za-z]+)?/([^\s(]+)(?:\s*\(([^)]+)\))?",
+        "Infuse",
+        "Unknown",
+    ),
+    (
+        r"^Findroid/([^\s(]+)(?:\s*\(([^)]+)\))?",
+        "Findroid",
+        "Android",
+    ),
+];
+
 fn is_android_tv_client(client: &str) -> bool {
     normalize_device(client).contains("android tv")
 }
@@ -162,44 +180,54 @@ impl Device {
         Device {
             client,
             device,
-            device_id: "unknown-device-id".to_string(),
+            device_id: Self::device_id_from_useragent(user_agent),
             version,
         }
     }
 
+    /// Derive a deterministic device id from the full user agent string so that
+    /// token-only clients keep the same id across requests.
+    fn device_id_from_useragent(user_agent: &str) -> String {
+        let digest = Sha256::digest(user_agent.trim().as_bytes());
+        format!("ua-{}", &hex::encode(digest)[..32])
+    }
+
     /// Parse user agent string to extract client, version, and device information
     /// Examples:
     /// - "Switchfin/0.7.4 (Linux)" -> ("Switchfin", "0.7.4", "Linux")
     /// - "Jellyfin Web/10.8.13" -> ("Jellyfin Web", "10.8.13", "Unknown")
     /// - "Mozilla/5.0 (Windows NT 10.0; Win64; x64)" -> ("Mozilla", "5.0", "Windows")
+    /// - "Kodi/20.2 (Linux; Android 11.0; SHIELD Android TV)" -> ("Kodi", "20.2", "Android TV")
+    /// - "Infuse-Direct/7.7.5" -> ("Infuse", "7.7.5", "Unknown")
     fn parse_user_agent(user_agent: &str) -> (String, String, String) {
         let user_agent = user_agent.trim();
 
+        // Known clients whose user agents don't fit the generic patterns below, either
+        // because the product name carries a suffix (Infuse-Direct, Infuse-Library) or
+        // because the platform has to be inferred from the client itself (Findroid).
+        for (pattern, client, default_device) in KNOWN_CLIENT_USER_AGENTS {
+            if let Some(captures) = regex::Regex::new(pattern)
+                .ok()
+                .and_then(|re| re.captures(user_agent))
+            {
+                let version = captures
+                    .get(1)
+                    .map_or("0.0.0".to_string(), |m| m.as_str().to_string());
+                let device = captures
+                    .get(2)
+                    .map(|m| Self::platform_from_device_info(m.as_str()))
+                    .unwrap_or_else(|| default_device.to_string());
+                return (client.to_string(), version, device);
+            }
+        }
+
         // Pattern 1: "Client/Version (Device)" - e.g., "Switchfin/0.7.4 (Linux)"
         if let Some(captures) = regex::Regex::new(r"^([^/]+)/([^\s\(]+)\s*\(([^)]+)\)")
             .ok()
             .and_then(|re| re.captures(user_agent))
         {
             let device_info = captures.get(3).map_or("Unknown".to_string(), |m| {
-                let device_str = m.as_str();
-                // Clean up common OS patterns from device info
-                if device_str.contains("Windows") {
-                    "Windows".to_string()
-                } else if device_str.contains("Mac") || device_str.contains("Darwin") {
-                    "macOS".to_string()
-                } else if device_str.contains("Linux") && !device_str.contains("Android") {
-                    "Linux".to_string()
-                } else if device_str.contains("Android") {
-                    "Android".to_string()
-                } else if device_str.contains("iPhone")
-                    || device_str.contains("iPad")
-                    || device_str.contains("iOS")
-                {
-                    "iOS".to_string()
-                } else {
-                    // For simple cases like "(Linux)" just return as-is
-                    device_str.to_string()
-                }
+                Self::platform_from_device_info(m.as_str())
             });
 
             return (
@@ -236,6 +264,33 @@ impl Device {
             "Unknown".to_string(),
         )
     }
+
+    /// Reduce the parenthetical platform part of a user agent to a short OS name,
+    /// e.g. "Windows NT 10.0; Win64; x64" -> "Windows".
+    fn platform_from_device_info(device_str: &str) -> String {
+        let lowered = device_str.to_lowercase();
+        if lowered.contains("android tv") || lowered.contains("androidtv") {
+            "Android TV".to_string()
+        } else if device_str.contains("tvOS") || device_str.contains("Apple TV") {
+            "tvOS".to_string()
+        } else if device_str.contains("Windows") {
+            "Windows".to_string()
+        } else if device_str.contains("Mac") || device_str.contains("Darwin") {
+            "macOS".to_string()
+        } else if device_str.contains("Linux") && !device_str.contains("Android") {
+            "Linux".to_string()
+        } else if device_str.contains("Android") {
+            "Android".to_string()
+        } else if device_str.contains("iPhone")
+            || device_str.contains("iPad")
+            || device_str.contains("iOS")
+        {
+            "iOS".to_string()
+        } else {
+            // For simple cases like "(Linux)" just return as-is
+            device_str.to_string()
+        }
+    }
 }
 
 impl AuthorizationSession {
@@ -271,9 +326,43 @@ impl AuthorizationSession {
     }
 }
 
+/// Columns of an authorization session joined with its server, read by
+/// `AuthorizationSession::from_user_sessions_row` and `Server::from_session_join_row`.
+const SESSIONS_WITH_SERVERS_SELECT: &str = r#"
+    SELECT
+        auth.id as auth_id,
+        auth.user_id as auth_user_id,
+        auth.mapping_id as auth_mapping_id,
+        sm.server_url as auth_server_url,
+        auth.client,
+        auth.device,
+        auth.device_id,
+        auth.version,
+        auth.jellyfin_token,
+        auth.original_user_id,
+        auth.expires_at,
+        auth.created_at as auth_created_at,
+        auth.updated_at as auth_updated_at,
+
+        s.id as server_id,
+        s.name as server_name,
+        s.url as server_url_full,
+        s.priority,
+        s.media_streaming_mode,
+        s.authorization_header_mode,
+        s.max_connections,
+        s.backend_id,
+        s.created_at as server_created_at,
+        s.updated_at as server_updated_at
+    FROM authorization_sessions auth
+    JOIN server_mappings sm ON auth.mapping_id = sm.id
+    JOIN servers s ON sm.server_id = s.id"#;
+
 #[derive(Debug, Clone)]
 pub struct UserAuthorizationService {
     pool: SqlitePool,
+    /// How long new authorization sessions stay valid when the caller gives no expiry.
+    session_lifetime: Option<chrono::Duration>,
 }
 
 #[cfg(test)]
@@ -305,7 +394,17 @@ impl<'a> From<&'a &'a str> for ServerReference<'a> {
 
 impl UserAuthorizationService {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            session_lifetime: None,
+        }
+    }
+
+    /// Let new authorization sessions expire after `lifetime`, `None` keeps them until
+    /// they are deleted.
+    pub fn with_session_lifetime(mut self, lifetime: Option<chrono::Duration>) -> Self {
+        self.session_lifetime = lifetime;
+        self
     }
 
     fn normalized_username_key(username: &str) -> String {
@@ -506,6 +605,37 @@ impl UserAuthorizationService {
         Ok(user)
     }
 
+    /// Get the user and session a backend token was issued to. Some clients send the
+    /// Jellyfin token of a server instead of the user's virtual key.
+    pub async fn get_user_by_jellyfin_token(
+        &self,
+        jellyfin_token: &str,
+    ) -> Result<Option<(User, AuthorizationSession)>, sqlx::Error> {
+        let session = sqlx::query_as::<_, AuthorizationSession>(
+            r#"
+            SELECT auth.id, auth.user_id, auth.mapping_id, sm.server_url, auth.client, auth.device,
+                   auth.device_id, auth.version, auth.jellyfin_token, auth.original_user_id,
+                   auth.expires_at, auth.created_at, auth.updated_at
+            FROM authorization_sessions auth
+            JOIN server_mappings sm ON auth.mapping_id = sm.id
+            WHERE auth.jellyfin_token = ?
+            AND (auth.expires_at IS NULL OR auth.expires_at > ?)
+            ORDER BY auth.updated_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(jellyfin_token)
+        .bind(chrono::Utc::now())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(session) = session else {
+            return Ok(None);
+        };
+        let user = self.get_user_by_id(&session.user_id).await?;
+        Ok(user.map(|user| (user, session)))
+    }
+
     /// Get user by virtual id
     pub async fn get_user_by_id(&self, id: &str) -> Result<Option<User>, sqlx::Error> {
         let user = sqlx::query_as::<_, User>(
@@ -522,6 +652,28 @@ impl UserAuthorizationService {
         Ok(user)
     }
 
+    /// Get the user owning the mapping for `mapped_username` on a server (case-insensitive)
+    pub async fn get_user_by_mapped_username(
+        &self,
+        server_id: ServerId,
+        mapped_username: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT u.id, u.virtual_key, u.original_username, u.original_password_hash, u.created_at, u.updated_at
+            FROM users u
+            JOIN server_mappings sm ON sm.user_id = u.id
+            WHERE sm.server_id = ? AND lower(trim(sm.mapped_username)) = lower(trim(?))
+            "#,
+        )
+        .bind(server_id.as_i64())
+        .bind(mapped_username)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
     /// Get user by credentials
     pub async fn get_user_by_credentials(
         &self,
@@ -682,14 +834,41 @@ impl UserAuthorizationService {
         user_password_plain: Option<&Password>,
         admin_password_plain: Option<&Password>,
     ) -> Password {
+        if let Some(decrypted) = self.try_decrypt_server_mapping_password(
+            mapping,
+            user_password,
+            admin_password,
+            user_password_plain,
+            admin_password_plain,
+        ) {
+            return decrypted;
+        }
+
+        // If decryption fails, assume it's plaintext (legacy or fallback)
+        warn!(
+            "Failed to decrypt password for mapping {}. Assuming plaintext.",
+            mapping.id
+        );
+        mapping.mapped_password.clone().into_inner().into()
+    }
+
+    /// Decrypt a server mapping password, or `None` if none of the keys fit.
+    pub fn try_decrypt_server_mapping_password(
+        &self,
+        mapping: &ServerMapping,
+        user_password: &HashedPassword,
+        admin_password: &HashedPassword,
+        user_password_plain: Option<&Password>,
+        admin_password_plain: Option<&Password>,
+    ) -> Option<Password> {
         // Try user password first
         if let Ok(decrypted) = decrypt_password(&mapping.mapped_password, user_password) {
-            return decrypted;
+            return Some(decrypted);
         }
 
         // Try admin password
         if let Ok(decrypted) = decrypt_password(&mapping.mapped_password, admin_password) {
-            return decrypted;
+            return Some(decrypted);
         }
 
         // Backward compatibility: try raw user password key material if available
@@ -698,7 +877,7 @@ impl UserAuthorizationService {
                 &mapping.mapped_password,
                 user_password_plain.as_str(),
             ) {
-                return decrypted;
+                return Some(decrypted);
             }
         }
 
@@ -708,16 +887,11 @@ impl UserAuthorizationService {
                 &mapping.mapped_password,
                 admin_password_plain.as_str(),
             ) {
-                return decrypted;
+                return Some(decrypted);
             }
         }
 
-        // If decryption fails, assume it's plaintext (legacy or fallback)
-        warn!(
-            "Failed to decrypt password for mapping {}. Assuming plaintext.",
-            mapping.id
-        );
-        mapping.mapped_password.clone().into_inner().into()
+        None
     }
 
     /// Get server mapping
@@ -770,6 +944,131 @@ impl UserAuthorizationService {
         Ok(mappings)
     }
 
+    /// List the server mappings of all users
+    pub async fn list_all_server_mappings(&self) -> Result<Vec<ServerMapping>, sqlx::Error> {
+        sqlx::query_as::<_, ServerMapping>(
+            r#"
+            SELECT id, user_id, server_id, server_url, mapped_username, mapped_password, created_at, updated_at
+            FROM server_mappings
+            ORDER BY user_id, server_id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Restore an exported user. A user with the same normalized username is kept
+    /// and only gets the exported password hash, when there is one. A new user keeps
+    /// its exported id and virtual key, or gets a new key without one, but needs a
+    /// password hash; without one it is skipped. Returns the id of the user in this
+    /// database, if there is one.
+    pub async fn import_user(
+        &self,
+        conn: &mut SqliteConnection,
+        id: &str,
+        virtual_key: Option<&str>,
+        username: &str,
+        password_hash: Option<&HashedPassword>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let username_key = Self::normalized_username_key(username);
+        let now = chrono::Utc::now();
+
+        let existing = sqlx::query_scalar::<_, String>(
+            "SELECT id FROM users WHERE lower(trim(original_username)) = lower(trim(?))",
+        )
+        .bind(&username_key)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        match (existing, password_hash) {
+            (Some(existing), Some(password_hash)) => {
+                sqlx::query(
+                    "UPDATE users SET original_password_hash = ?, updated_at = ? WHERE id = ?",
+                )
+                .bind(password_hash)
+                .bind(now)
+                .bind(&existing)
+                .execute(&mut *conn)
+                .await?;
+                Ok(Some(existing))
+            }
+            (Some(existing), None) => Ok(Some(existing)),
+            (None, Some(password_hash)) => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO users (id, virtual_key, original_username, original_password_hash, created_at, updated_at)
+                    VALUES (?, ?, ?, ?, ?, ?)
+                    "#,
+                )
+                .bind(id)
+                .bind(virtual_key.map_or_else(generate_token, str::to_string))
+                .bind(&username_key)
+                .bind(password_hash)
+                .bind(now)
+                .bind(now)
+                .execute(&mut *conn)
+                .await?;
+                Ok(Some(id.to_string()))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Restore an exported server mapping. The stored password is taken as-is, so it
+    /// has to be encrypted the way this instance expects. Without a password only an
+    /// existing mapping's username is updated. Returns whether a mapping was written.
+    pub async fn import_server_mapping(
+        &self,
+        conn: &mut SqliteConnection,
+        user_id: &str,
+        server: &Server,
+        mapped_username: &str,
+        mapped_password: Option<&EncryptedPassword>,
+    ) -> Result<bool, sqlx::Error> {
+        let now = chrono::Utc::now();
+        let result = match mapped_password {
+            Some(mapped_password) => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO server_mappings
+                    (user_id, server_id, server_url, mapped_username, mapped_password, created_at, updated_at)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)
+                    ON CONFLICT(user_id, server_id) DO UPDATE SET
+                        server_url = excluded.server_url,
+                        mapped_username = excluded.mapped_username,
+                        mapped_password = excluded.mapped_password,
+                        updated_at = excluded.updated_at
+                    "#,
+                )
+                .bind(user_id)
+                .bind(server.id.as_i64())
+                .bind(server.url.as_str())
+                .bind(mapped_username)
+                .bind(mapped_password)
+                .bind(now)
+                .bind(now)
+                .execute(&mut *conn)
+                .await?
+            }
+            None => {
+                sqlx::query(
+                    r#"
+                    UPDATE server_mappings
+                    SET mapped_username = ?, updated_at = ?
+                    WHERE user_id = ? AND server_id = ?
+                    "#,
+                )
+                .bind(mapped_username)
+                .bind(now)
+                .bind(user_id)
+                .bind(server.id.as_i64())
+                .execute(&mut *conn)
+                .await?
+            }
+        };
+        Ok(result.rows_affected() > 0)
+    }
+
     /// Store authorization session
     #[cfg(not(test))]
     pub async fn store_authorization_session(
@@ -831,6 +1130,8 @@ impl UserAuthorizationService {
         expires_at: Option<chrono::DateTime<chrono::Utc>>,
     ) -> Result<i64, sqlx::Error> {
         let now = chrono::Utc::now();
+        let expires_at =
+            expires_at.or_else(|| self.session_lifetime.map(|lifetime| now + lifetime));
 
         // Find mapping to obtain mapping_id (required for referential integrity & cascade deletes)
         let mapping = self
@@ -924,37 +1225,12 @@ impl UserAuthorizationService {
         user_id: &str,
         device: Option<Device>,
     ) -> Result<Vec<(AuthorizationSession, Server)>, sqlx::Error> {
-        let query = String::from(
-            r#"
-    SELECT
-        auth.id as auth_id,
-        auth.user_id as auth_user_id,
-        auth.mapping_id as auth_mapping_id,
-        sm.server_url as auth_server_url,
-        auth.client,
-        auth.device,
-        auth.device_id,
-        auth.version,
-        auth.jellyfin_token,
-        auth.original_user_id,
-        auth.expires_at,
-        auth.created_at as auth_created_at,
-        auth.updated_at as auth_updated_at,
-
-        s.id as server_id,
-        s.name as server_name,
-        s.url as server_url_full,
-        s.priority,
-        s.media_streaming_mode,
-        s.created_at as server_created_at,
-        s.updated_at as server_updated_at
-    FROM authorization_sessions auth
-    JOIN server_mappings sm ON auth.mapping_id = sm.id
-    JOIN servers s ON sm.server_id = s.id
+        let query = format!(
+            r#"{SESSIONS_WITH_SERVERS_SELECT}
     WHERE auth.user_id = ?
     AND (auth.expires_at IS NULL OR auth.expires_at > ?)
     ORDER BY s.priority DESC, s.name ASC
-"#,
+"#
         );
 
         let rows = sqlx::query(&query)
@@ -988,6 +1264,84 @@ impl UserAuthorizationService {
         Ok(sessions)
     }
 
+    /// Sessions that are still valid but expire before `deadline`, soonest first.
+    pub async fn list_sessions_expiring_before(
+        &self,
+        deadline: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<(AuthorizationSession, Server)>, sqlx::Error> {
+        let query = format!(
+            r#"{SESSIONS_WITH_SERVERS_SELECT}
+    WHERE auth.expires_at IS NOT NULL
+    AND auth.expires_at > ?
+    AND auth.expires_at <= ?
+    ORDER BY auth.expires_at ASC
+"#
+        );
+
+        let rows = sqlx::query(&query)
+            .bind(chrono::Utc::now())
+            .bind(deadline)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok((
+                    AuthorizationSession::from_user_sessions_row(&row)?,
+                    Server::from_session_join_row(&row)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Replace the backend token of a session after it was refreshed.
+    pub async fn update_session_token(
+        &self,
+        session_id: i64,
+        jellyfin_token: &str,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            UPDATE authorization_sessions
+            SET jellyfin_token = ?, expires_at = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(jellyfin_token)
+        .bind(expires_at)
+        .bind(chrono::Utc::now())
+        .bind(session_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Expire a session now, so the user has to log in again to use its server.
+    pub async fn expire_session(&self, session_id: i64) -> Result<bool, sqlx::Error> {
+        let now = chrono::Utc::now();
+        let res = sqlx::query(
+            r#"
+            UPDATE authorization_sessions
+            SET expires_at = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(now)
+        .bind(now)
+        .bind(session_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Session lifetime given to refreshed sessions.
+    pub fn session_lifetime(&self) -> Option<chrono::Duration> {
+        self.session_lifetime
+    }
+
     /// Rebind Android TV authorization sessions to a new device ID when the client rotates
     /// from username-derived to user-id-derived device IDs after login.
     ///
@@ -1155,6 +1509,21 @@ impl UserAuthorizationService {
         Ok(users)
     }
 
+    /// Numbers of users, server mappings and authorization sessions.
+    pub async fn record_counts(&self) -> Result<(i64, i64, i64), sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM users) AS users,
+                (SELECT COUNT(*) FROM server_mappings) AS mappings,
+                (SELECT COUNT(*) FROM authorization_sessions) AS sessions
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok((row.get("users"), row.get("mappings"), row.get("sessions")))
+    }
+
     /// Delete a user
     pub async fn delete_user(&self, user_id: &str) -> Result<bool, sqlx::Error> {
         let res = sqlx::query("DELETE FROM users WHERE id = ?")
@@ -1269,6 +1638,78 @@ impl UserAuthorizationService {
         Ok(true)
     }
 
+    /// Re-encrypt the server mappings that were encrypted with the admin password after
+    /// the admin password changed. Mappings encrypted with their user's own password
+    /// can't be decrypted with the old admin password and are left untouched, as are
+    /// plaintext ones. Returns the number of re-encrypted mappings.
+    pub async fn reencrypt_admin_mappings(
+        &self,
+        old_admin_password: &Password,
+        new_admin_password: &Password,
+    ) -> Result<u64, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        let mappings = sqlx::query_as::<_, ServerMapping>(
+            r#"
+            SELECT id, user_id, server_id, server_url, mapped_username, mapped_password, created_at, updated_at
+            FROM server_mappings
+            "#,
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        let old_admin_hash: HashedPassword = old_admin_password.into();
+        let new_admin_hash: HashedPassword = new_admin_password.into();
+        let now = chrono::Utc::now();
+        let mut reencrypted = 0;
+
+        for mapping in mappings {
+            let decrypted =
+                decrypt_password(&mapping.mapped_password, &old_admin_hash).or_else(|_| {
+                    decrypt_password_with_key_material(
+                        &mapping.mapped_password,
+                        old_admin_password.as_str(),
+                    )
+                });
+            let Ok(decrypted) = decrypted else {
+                continue;
+            };
+
+            let new_encrypted_password = match encrypt_password(&decrypted, &new_admin_hash) {
+                Ok(p) => p,
+                Err(e) => {
+                    error!(
+                        "Failed to encrypt password during admin key rotation: {}",
+                        e
+                    );
+                    return Err(sqlx::Error::Protocol(format!("Encryption failed: {}", e)));
+                }
+            };
+
+            sqlx::query(
+                r#"
+                UPDATE server_mappings
+                SET mapped_password = ?, updated_at = ?
+                WHERE id = ?
+                "#,
+            )
+            .bind(new_encrypted_password)
+            .bind(now)
+            .bind(mapping.id)
+            .execute(&mut *transaction)
+            .await?;
+            reencrypted += 1;
+        }
+
+        transaction.commit().await?;
+
+        info!(
+            "Re-encrypted {} server mappings with the new admin password",
+            reencrypted
+        );
+        Ok(reencrypted)
+    }
+
     /// Verify user password
     pub async fn verify_user_password(
         &self,
@@ -1345,7 +1786,7 @@ impl UserAuthorizationService {
     pub async fn get_mapped_servers(&self, user_id: &str) -> Result<Vec<Server>, sqlx::Error> {
         let rows = sqlx::query(
             r#"
-            SELECT s.id, s.name, s.url, s.priority, s.media_streaming_mode, s.created_at, s.updated_at
+            SELECT s.id, s.name, s.url, s.priority, s.media_streaming_mode, s.authorization_header_mode, s.max_connections, s.backend_id, s.created_at, s.updated_at
             FROM servers s
             JOIN server_mappings sm ON s.id = sm.server_id
             WHERE sm.user_id = ?
@@ -1448,6 +1889,65 @@ mod tests {
         assert_eq!(device.device, "Unknown");
     }
 
+    #[test]
+    fn test_device_from_useragent_known_clients() {
+        let cases = [
+            (
+                "Kodi/20.2 (Linux; Android 11.0; SHIELD Android TV Build/RQ1A.210105.003) Android/11.0.0 Sys_CPU/aarch64 App_Bitness/64 Version/20.2-(20.2.0)-Git:20230629-5f418d0b13",
+                ("Kodi", "20.2", "Android TV"),
+            ),
+            (
+                "Kodi/21.0 (Windows NT 10.0.22631; Win64; x64) App_Bitness/64 Version/21.0-(21.0.0)-Git:20240406-b3d4cd4b73",
+                ("Kodi", "21.0", "Windows"),
+            ),
+            (
+                "Kodi/20.2 (X11; Linux x86_64) Ubuntu/22.04 App_Bitness/64 Version/20.2-(20.2.0)-Git:20230629-5f418d0b13",
+                ("Kodi", "20.2", "Linux"),
+            ),
+            (
+                "Kodi/20.2 (Linux; Android 13; Pixel 7 Build/TQ3A.230805.001) Android/13.0.0 Sys_CPU/aarch64",
+                ("Kodi", "20.2", "Android"),
+            ),
+            (
+                "Kodi/20.2 (Mac OS X; 13.4.1) App_Bitness/64 Version/20.2-(20.2.0)-Git:20230629-5f418d0b13",
+                ("Kodi", "20.2", "macOS"),
+            ),
+            ("Infuse-Direct/7.7.5", ("Infuse", "7.7.5", "Unknown")),
+            (
+                "Infuse-Library/7.7.5 (Apple TV; tvOS 17.2)",
+                ("Infuse", "7.7.5", "tvOS"),
+            ),
+            ("Infuse/7.6.3 (iPhone; iOS 17.1)", ("Infuse", "7.6.3", "iOS")),
+            ("Findroid/0.15.2", ("Findroid", "0.15.2", "Android")),
+            (
+                "Findroid/0.15.2 (Android TV; BRAVIA 4K)",
+                ("Findroid", "0.15.2", "Android TV"),
+            ),
+        ];
+
+        for (user_agent, (client, version, device)) in cases {
+            let parsed = Device::from_useragent(user_agent);
+            assert_eq!(parsed.client, client, "client for {user_agent}");
+            assert_eq!(parsed.version, version, "version for {user_agent}");
+            assert_eq!(parsed.device, device, "device for {user_agent}");
+        }
+    }
+
+    #[test]
+    fn test_device_from_useragent_derives_stable_device_id() {
+        let ua = "Switchfin/0.7.4 (Linux)";
+        let first = Device::from_useragent(ua);
+        let second = Device::from_useragent(ua);
+        assert_eq!(first.device_id, second.device_id);
+        assert!(Device::has_known_device_id(&first.device_id));
+        assert!(first.device_id.starts_with("ua-"));
+
+        let other = Device::from_useragent("Switchfin/0.7.5 (Linux)");
+        assert_ne!(first.device_id, other.device_id);
+        assert!(!first.matches(&other));
+        assert!(first.matches(&second));
+    }
+
     #[tokio::test]
     async fn test_get_or_create_user_uses_stable_username_identity() {
         let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
@@ -3136,4 +3636,215 @@ mod tests {
             .1;
         assert_eq!(sessions_after.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_reencrypt_admin_mappings_leaves_user_encrypted_mappings_alone() {
+        let (pool, service) = setup_service().await;
+        let admin_server = insert_test_server(&pool, "Server 1", "http://localhost:8096").await;
+        let user_server = insert_test_server(&pool, "Server 2", "http://localhost:8097").await;
+
+        let user_password: Password = "testpass".into();
+        let old_admin: Password = "old-admin".into();
+        let new_admin: Password = "new-admin".into();
+        let user = service
+            .get_or_create_user("testuser", &user_password)
+            .await
+            .unwrap();
+
+        service
+            .add_server_mapping(
+                &user.id,
+                "http://localhost:8096",
+                "mappeduser",
+                &"admin-encrypted".into(),
+                Some(&(&old_admin).into()),
+            )
+            .await
+            .unwrap();
+        service
+            .add_server_mapping(
+                &user.id,
+                "http://localhost:8097",
+                "mappeduser",
+                &"user-encrypted".into(),
+                Some(&(&user_password).into()),
+            )
+            .await
+            .unwrap();
+        let user_mapping_before = service
+            .get_server_mapping_by_server_id(&user.id, user_server)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let reencrypted = service
+            .reencrypt_admin_mappings(&old_admin, &new_admin)
+            .await
+            .unwrap();
+        assert_eq!(reencrypted, 1);
+
+        let admin_mapping = service
+            .get_server_mapping_by_server_id(&user.id, admin_server)
+            .await
+            .unwrap()
+            .unwrap();
+        let new_admin_hash: HashedPassword = (&new_admin).into();
+        assert_eq!(
+            decrypt_password(&admin_mapping.mapped_password, &new_admin_hash)
+                .unwrap()
+                .as_str(),
+            "admin-encrypted"
+        );
+        assert!(decrypt_password(&admin_mapping.mapped_password, &(&old_admin).into()).is_err());
+
+        let user_mapping = service
+            .get_server_mapping_by_server_id(&user.id, user_server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            user_mapping.mapped_password,
+            user_mapping_before.mapped_password
+        );
+
+        // Running it again with the old password finds nothing left to rotate.
+        assert_eq!(
+            service
+                .reencrypt_admin_mappings(&old_admin, &new_admin)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_jellyfin_token() {
+        let (pool, service) = setup_service().await;
+        insert_test_server(&pool, "Server 1", "http://localhost:8096").await;
+
+        let user = service
+            .get_or_create_user("testuser", &"testpass".into())
+            .await
+            .unwrap();
+        service
+            .add_server_mapping(
+                &user.id,
+                "http://localhost:8096",
+                "mappeduser",
+                &"mappedpass".into(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let auth = Authorization {
+            client: "Jellyfin Web".to_string(),
+            device: "Firefox".to_string(),
+            device_id: "device-1".to_string(),
+            version: "10.0.0".to_string(),
+            token: None,
+        };
+        service
+            .store_authorization_session(
+                &user.id,
+                "http://localhost:8096",
+                &auth,
+                "backend-token".to_string(),
+                "orig-user-1".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let (found_user, session) = service
+            .get_user_by_jellyfin_token("backend-token")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found_user.id, user.id);
+        assert_eq!(session.original_user_id, "orig-user-1");
+        assert_eq!(session.server_url, "http://localhost:8096");
+
+        assert!(service
+            .get_user_by_jellyfin_token(&user.virtual_key)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_session_lifetime_and_expiring_sessions() {
+        let (pool, service) = setup_service().await;
+        let service = service.with_session_lifetime(Some(chrono::Duration::minutes(30)));
+        insert_test_server(&pool, "Server 1", "http://localhost:8096").await;
+
+        let user = service
+            .get_or_create_user("testuser", &"testpass".into())
+            .await
+            .unwrap();
+        service
+            .add_server_mapping(
+                &user.id,
+                "http://localhost:8096",
+                "mappeduser",
+                &"mappedpass".into(),
+                None,
+            )
+            .await
+            .unwrap();
+        let auth = Authorization {
+            client: "Jellyfin Web".to_string(),
+            device: "Firefox".to_string(),
+            device_id: "device-1".to_string(),
+            version: "10.0.0".to_string(),
+            token: None,
+        };
+        let session_id = service
+            .store_authorization_session(
+                &user.id,
+                "http://localhost:8096",
+                &auth,
+                "token-1".to_string(),
+                "orig-user-1".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let now = chrono::Utc::now();
+        assert!(service
+            .list_sessions_expiring_before(now + chrono::Duration::minutes(10))
+            .await
+            .unwrap()
+            .is_empty());
+        let expiring = service
+            .list_sessions_expiring_before(now + chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(expiring.len(), 1);
+        assert_eq!(expiring[0].0.id, session_id);
+
+        service
+            .update_session_token(
+                session_id,
+                "token-2",
+                Some(now + chrono::Duration::hours(2)),
+            )
+            .await
+            .unwrap();
+        assert!(service
+            .list_sessions_expiring_before(now + chrono::Duration::hours(1))
+            .await
+            .unwrap()
+            .is_empty());
+        let sessions = service.get_user_sessions(&user.id, None).await.unwrap();
+        assert_eq!(sessions[0].0.jellyfin_token, "token-2");
+
+        service.expire_session(session_id).await.unwrap();
+        assert!(service
+            .get_user_sessions(&user.id, None)
+            .await
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -5,7 +5,8 @@ use tracing::debug;
 use uuid::Uuid;
 
 use crate::{
-    duplicate_policy::{DuplicatePolicy, DuplicatePolicyConfig},
+    config::DeduplicationStrategy,
+    duplicate_policy::{DuplicatePolicy, DuplicatePolicyConfig, BOX_SET_MERGE_KEY_PREFIX},
     media_storage_service::{MediaMapping, MediaStorageService},
     server_id::ServerId,
     server_storage::{Server, ServerStorageService},
@@ -64,6 +65,8 @@ pub struct LibraryGroup {
     pub sort_order: i32,
     pub duplicate_policy: DuplicatePolicy,
     pub preferred_server_id: Option<ServerId>,
+    /// How versions of the same title are matched across the group's libraries.
+    pub dedup_strategy: DeduplicationStrategy,
 }
 
 #[derive(Debug, Clone)]
@@ -72,6 +75,8 @@ pub struct LibraryGroupMemberRecord {
     pub server_id: ServerId,
     pub original_library_id: String,
     pub library_name: String,
+    /// Takes the place of the server's priority within the group.
+    pub priority: Option<i32>,
 }
 
 #[derive(Debug, Clone)]
@@ -94,6 +99,20 @@ impl VirtualLibrary {
         }
     }
 
+    /// Whether this is a collapsed box set rather than a merged library.
+    pub fn is_box_set(&self) -> bool {
+        matches!(self, Self::Automatic(library) if library.collection_type.starts_with(BOX_SET_MERGE_KEY_PREFIX))
+    }
+
+    /// The strategy a library group was created with. Automatic libraries follow
+    /// `merge_library_versions`.
+    pub fn dedup_strategy(&self) -> Option<DeduplicationStrategy> {
+        match self {
+            Self::Automatic(_) => None,
+            Self::Configured(group) => Some(group.dedup_strategy),
+        }
+    }
+
     pub fn duplicate_config(&self) -> DuplicatePolicyConfig {
         match self {
             Self::Automatic(_) => DuplicatePolicyConfig {
@@ -290,6 +309,7 @@ impl VirtualLibraryService {
              FROM automatic_library_snapshots s \
              JOIN merged_libraries l ON l.virtual_id = s.automatic_virtual_id \
              WHERE s.access_scope_key = ? \
+               AND l.collection_type NOT LIKE ? \
                AND EXISTS ( \
                    SELECT 1 FROM automatic_library_members m \
                    WHERE m.automatic_virtual_id = s.automatic_virtual_id \
@@ -297,6 +317,7 @@ impl VirtualLibraryService {
                )",
         )
         .bind(access_scope.key())
+        .bind(format!("{BOX_SET_MERGE_KEY_PREFIX}%"))
         .fetch_all(&self.pool)
         .await?;
 
@@ -460,40 +481,30 @@ impl VirtualLibraryService {
     }
 
     pub async fn list_groups(&self) -> Result<Vec<LibraryGroup>, sqlx::Error> {
-        let rows: Vec<(String, String, i32, String, Option<i64>)> = sqlx::query_as(
-            "SELECT virtual_id, name, sort_order, duplicate_policy, preferred_server_id \
+        let rows: Vec<LibraryGroupRow> = sqlx::query_as(
+            "SELECT virtual_id, name, sort_order, duplicate_policy, preferred_server_id, dedup_strategy \
              FROM library_groups ORDER BY sort_order, name",
         )
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(rows
-            .into_iter()
-            .map(
-                |(virtual_id, name, sort_order, duplicate_policy, preferred_server_id)| {
-                    LibraryGroup {
-                        virtual_id,
-                        name,
-                        sort_order,
-                        duplicate_policy: duplicate_policy
-                            .parse()
-                            .unwrap_or(DuplicatePolicy::ServerPriority),
-                        preferred_server_id: preferred_server_id.map(ServerId::new),
-                    }
-                },
-            )
-            .collect())
+        Ok(rows.into_iter().map(library_group).collect())
     }
 
-    pub async fn create_group(&self, name: &str) -> Result<LibraryGroup, sqlx::Error> {
+    pub async fn create_group(
+        &self,
+        name: &str,
+        dedup_strategy: DeduplicationStrategy,
+    ) -> Result<LibraryGroup, sqlx::Error> {
         let virtual_id = Uuid::new_v4().simple().to_string();
         sqlx::query(
-            "INSERT INTO library_groups (virtual_id, name, sort_order, duplicate_policy) \
-             SELECT ?, ?, COALESCE(MAX(sort_order), -1) + 1, ? FROM library_groups",
+            "INSERT INTO library_groups (virtual_id, name, sort_order, duplicate_policy, dedup_strategy) \
+             SELECT ?, ?, COALESCE(MAX(sort_order), -1) + 1, ?, ? FROM library_groups",
         )
         .bind(&virtual_id)
         .bind(name.trim())
         .bind(DuplicatePolicy::ServerPriority.to_string())
+        .bind(dedup_strategy.to_string())
         .execute(&self.pool)
         .await?;
 
@@ -520,6 +531,21 @@ impl VirtualLibraryService {
         Ok(result.rows_affected() > 0)
     }
 
+    pub async fn update_group_dedup_strategy(
+        &self,
+        virtual_id: &str,
+        dedup_strategy: DeduplicationStrategy,
+    ) -> Result<bool, sqlx::Error> {
+        let virtual_id = normalize_library_id(virtual_id);
+        let result =
+            sqlx::query("UPDATE library_groups SET dedup_strategy = ? WHERE virtual_id = ?")
+                .bind(dedup_strategy.to_string())
+                .bind(virtual_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn rename_group(&self, virtual_id: &str, name: &str) -> Result<bool, sqlx::Error> {
         let virtual_id = normalize_library_id(virtual_id);
         let result = sqlx::query("UPDATE library_groups SET name = ? WHERE virtual_id = ?")
@@ -591,13 +617,37 @@ impl VirtualLibraryService {
         Ok(result.rows_affected() > 0)
     }
 
+    /// Set the priority of a library within its group, or go back to the server's
+    /// priority with `None`.
+    pub async fn update_member_priority(
+        &self,
+        group_virtual_id: &str,
+        server_id: ServerId,
+        original_library_id: &str,
+        priority: Option<i32>,
+    ) -> Result<bool, sqlx::Error> {
+        let group_virtual_id = normalize_library_id(group_virtual_id);
+        let original_library_id = normalize_library_id(original_library_id);
+        let result = sqlx::query(
+            "UPDATE library_group_members SET priority = ? \
+             WHERE group_virtual_id = ? AND server_id = ? AND original_library_id = ?",
+        )
+        .bind(priority)
+        .bind(group_virtual_id)
+        .bind(server_id.as_i64())
+        .bind(&original_library_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn list_members(
         &self,
         group_virtual_id: &str,
     ) -> Result<Vec<LibraryGroupMemberRecord>, sqlx::Error> {
         let group_virtual_id = normalize_library_id(group_virtual_id);
-        let rows: Vec<(String, i64, String, String)> = sqlx::query_as(
-            "SELECT group_virtual_id, server_id, original_library_id, library_name \
+        let rows: Vec<(String, i64, String, String, Option<i32>)> = sqlx::query_as(
+            "SELECT group_virtual_id, server_id, original_library_id, library_name, priority \
              FROM library_group_members WHERE group_virtual_id = ? \
              ORDER BY library_name, server_id, original_library_id",
         )
@@ -608,12 +658,13 @@ impl VirtualLibraryService {
         Ok(rows
             .into_iter()
             .map(
-                |(group_virtual_id, server_id, original_library_id, library_name)| {
+                |(group_virtual_id, server_id, original_library_id, library_name, priority)| {
                     LibraryGroupMemberRecord {
                         group_virtual_id,
                         server_id: ServerId::new(server_id),
                         original_library_id,
                         library_name,
+                        priority,
                     }
                 },
             )
@@ -666,13 +717,16 @@ impl VirtualLibraryService {
             if !server_is_allowed(record.server_id, access_scope, None) {
                 continue;
             }
-            let Some(server) = self
+            let Some(mut server) = self
                 .server_storage
                 .get_server_by_id(record.server_id)
                 .await?
             else {
                 continue;
             };
+            if let Some(priority) = record.priority {
+                server.priority = priority;
+            }
 
             let mapping = self
                 .media_storage
@@ -687,25 +741,35 @@ impl VirtualLibraryService {
 
     pub async fn get_group(&self, virtual_id: &str) -> Result<Option<LibraryGroup>, sqlx::Error> {
         let virtual_id = normalize_library_id(virtual_id);
-        let row: Option<(String, String, i32, String, Option<i64>)> = sqlx::query_as(
-            "SELECT virtual_id, name, sort_order, duplicate_policy, preferred_server_id \
+        let row: Option<LibraryGroupRow> = sqlx::query_as(
+            "SELECT virtual_id, name, sort_order, duplicate_policy, preferred_server_id, dedup_strategy \
              FROM library_groups WHERE virtual_id = ?",
         )
         .bind(&virtual_id)
         .fetch_optional(&self.pool)
         .await?;
 
-        Ok(row.map(
-            |(virtual_id, name, sort_order, duplicate_policy, preferred_server_id)| LibraryGroup {
-                virtual_id,
-                name,
-                sort_order,
-                duplicate_policy: duplicate_policy
-                    .parse()
-                    .unwrap_or(DuplicatePolicy::ServerPriority),
-                preferred_server_id: preferred_server_id.map(ServerId::new),
-            },
-        ))
+        Ok(row.map(library_group))
+    }
+}
+
+type LibraryGroupRow = (String, String, i32, String, Option<i64>, Option<String>);
+
+/// Groups created before strategies were stored per group use the type default.
+fn library_group(
+    (virtual_id, name, sort_order, duplicate_policy, preferred_server_id, dedup_strategy): LibraryGroupRow,
+) -> LibraryGroup {
+    LibraryGroup {
+        virtual_id,
+        name,
+        sort_order,
+        duplicate_policy: duplicate_policy
+            .parse()
+            .unwrap_or(DuplicatePolicy::ServerPriority),
+        preferred_server_id: preferred_server_id.map(ServerId::new),
+        dedup_strategy: dedup_strategy
+            .and_then(|strategy| strategy.parse().ok())
+            .unwrap_or_default(),
     }
 }
 
@@ -800,7 +864,11 @@ mod tests {
         }
 
         async fn configured_group(&self, members: &[(i64, &str)]) -> LibraryGroup {
-            let group = self.service.create_group("Anime").await.unwrap();
+            let group = self
+                .service
+                .create_group("Anime", DeduplicationStrategy::default())
+                .await
+                .unwrap();
             for &(server_id, original_id) in members {
                 self.service
                     .add_member(
@@ -879,7 +947,10 @@ mod tests {
     async fn create_group_and_assign_member() {
         let fixture = Fixture::new(&[(1, 100)]).await;
         let service = &fixture.service;
-        let group = service.create_group("Anime").await.unwrap();
+        let group = service
+            .create_group("Anime", DeduplicationStrategy::default())
+            .await
+            .unwrap();
         service
             .add_member(
                 &group.virtual_id,
@@ -914,7 +985,10 @@ mod tests {
     async fn library_grouping_is_configured_when_groups_exist() {
         let fixture = Fixture::new(&[]).await;
         let service = &fixture.service;
-        service.create_group("Anime").await.unwrap();
+        service
+            .create_group("Anime", DeduplicationStrategy::default())
+            .await
+            .unwrap();
 
         let grouping = service.library_grouping(false).await.unwrap();
 
@@ -925,7 +999,10 @@ mod tests {
     async fn library_grouping_is_automatic_when_enabled() {
         let fixture = Fixture::new(&[]).await;
         let service = &fixture.service;
-        service.create_group("Anime").await.unwrap();
+        service
+            .create_group("Anime", DeduplicationStrategy::default())
+            .await
+            .unwrap();
 
         let grouping = service.library_grouping(true).await.unwrap();
 
@@ -936,7 +1013,10 @@ mod tests {
     async fn resolve_returns_empty_for_known_empty_group() {
         let fixture = Fixture::new(&[]).await;
         let service = &fixture.service;
-        let group = service.create_group("Anime").await.unwrap();
+        let group = service
+            .create_group("Anime", DeduplicationStrategy::default())
+            .await
+            .unwrap();
 
         let resolution = service.resolve(&group.virtual_id, None).await.unwrap();
 
@@ -949,17 +1029,67 @@ mod tests {
     #[tokio::test]
     async fn create_group_defaults_to_server_priority() {
         let fixture = Fixture::new(&[]).await;
-        let group = fixture.service.create_group("Anime").await.unwrap();
+        let group = fixture
+            .service
+            .create_group("Anime", DeduplicationStrategy::default())
+            .await
+            .unwrap();
 
         assert_eq!(group.duplicate_policy, DuplicatePolicy::ServerPriority);
     }
 
+    #[tokio::test]
+    async fn groups_without_a_stored_strategy_use_the_default() {
+        let fixture = Fixture::new(&[]).await;
+        let service = &fixture.service;
+        let group = service
+            .create_group("Anime", DeduplicationStrategy::NameYear)
+            .await
+            .unwrap();
+        assert_eq!(group.dedup_strategy, DeduplicationStrategy::NameYear);
+
+        sqlx::query("UPDATE library_groups SET dedup_strategy = NULL")
+            .execute(&service.pool)
+            .await
+            .unwrap();
+        let group = service.get_group(&group.virtual_id).await.unwrap().unwrap();
+        assert_eq!(group.dedup_strategy, DeduplicationStrategy::default());
+    }
+
+    #[tokio::test]
+    async fn group_dedup_strategy_can_be_changed() {
+        let fixture = Fixture::new(&[]).await;
+        let service = &fixture.service;
+        let group = service
+            .create_group("Anime", DeduplicationStrategy::ProviderIds)
+            .await
+            .unwrap();
+
+        assert!(service
+            .update_group_dedup_strategy(&group.virtual_id, DeduplicationStrategy::Off)
+            .await
+            .unwrap());
+        assert!(!service
+            .update_group_dedup_strategy("missing", DeduplicationStrategy::Off)
+            .await
+            .unwrap());
+
+        let group = service.get_group(&group.virtual_id).await.unwrap().unwrap();
+        assert_eq!(group.dedup_strategy, DeduplicationStrategy::Off);
+    }
+
     #[tokio::test]
     async fn add_member_replaces_existing_source_assignment() {
         let fixture = Fixture::new(&[(1, 100)]).await;
         let service = &fixture.service;
-        let first = service.create_group("Anime").await.unwrap();
-        let second = service.create_group("Movies").await.unwrap();
+        let first = service
+            .create_group("Anime", DeduplicationStrategy::default())
+            .await
+            .unwrap();
+        let second = service
+            .create_group("Movies", DeduplicationStrategy::default())
+            .await
+            .unwrap();
         service
             .add_member(&first.virtual_id, ServerId::new(1), "library", "Anime")
             .await
@@ -980,7 +1110,10 @@ mod tests {
     async fn failed_reassignment_preserves_existing_assignment() {
         let fixture = Fixture::new(&[(1, 100)]).await;
         let service = &fixture.service;
-        let group = service.create_group("Anime").await.unwrap();
+        let group = service
+            .create_group("Anime", DeduplicationStrategy::default())
+            .await
+            .unwrap();
         service
             .add_member(&group.virtual_id, ServerId::new(1), "library", "Anime")
             .await
@@ -1044,7 +1177,10 @@ mod tests {
         let scope = scope("user", &[1]);
         let member = fixture.member(1, "member-id").await;
         fixture.snapshot(&automatic, &scope, &[member]).await;
-        service.create_group("Configured group").await.unwrap();
+        service
+            .create_group("Configured group", DeduplicationStrategy::default())
+            .await
+            .unwrap();
 
         let resolution = service
             .resolve(&automatic.virtual_id, Some(&scope))
@@ -1094,6 +1230,62 @@ mod tests {
         assert_eq!(target.server.id, ServerId::new(1));
     }
 
+    #[tokio::test]
+    async fn member_priority_overrides_server_priority() {
+        let fixture = Fixture::new(&[(1, 100), (2, 200)]).await;
+        let service = &fixture.service;
+        let group = fixture
+            .configured_group(&[(1, "library-a"), (2, "library-b")])
+            .await;
+        let scope = scope("user", &[1, 2]);
+        assert_eq!(
+            fixture
+                .route(&group.virtual_id, &scope, None)
+                .await
+                .server
+                .id,
+            ServerId::new(2)
+        );
+
+        assert!(service
+            .update_member_priority(&group.virtual_id, ServerId::new(1), "library-a", Some(300))
+            .await
+            .unwrap());
+        assert!(!service
+            .update_member_priority(&group.virtual_id, ServerId::new(1), "missing", Some(300))
+            .await
+            .unwrap());
+
+        let members = service.list_members(&group.virtual_id).await.unwrap();
+        let priorities = members
+            .iter()
+            .map(|member| (member.server_id.as_i64(), member.priority))
+            .collect::<Vec<_>>();
+        assert!(priorities.contains(&(1, Some(300))));
+        assert!(priorities.contains(&(2, None)));
+        assert_eq!(
+            fixture
+                .route(&group.virtual_id, &scope, None)
+                .await
+                .server
+                .id,
+            ServerId::new(1)
+        );
+
+        service
+            .update_member_priority(&group.virtual_id, ServerId::new(1), "library-a", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture
+                .route(&group.virtual_id, &scope, None)
+                .await
+                .server
+                .id,
+            ServerId::new(2)
+        );
+    }
+
     #[tokio::test]
     async fn automatic_memberships_are_isolated_by_server_set() {
         let fixture = Fixture::new(&[(1, 100), (2, 200), (3, 300)]).await;